@@ -0,0 +1,72 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Requests to the control socket of the vmm sandboxer, configured by `control_address`
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+/// Control socket of the vmm sandboxer suggested in its config
+pub const DEFAULT_CONTROL_ADDRESS: &str = "/run/kuasar-vmm-control.sock";
+
+/// Request to save the vm state of a running sandbox into a directory
+pub fn checkpoint_request(id: &str, path: &str, exit: bool) -> Value {
+    json!({"checkpoint": {"id": id, "path": path, "exit": exit}})
+}
+
+/// Request to restore a sandbox from a directory saved by checkpoint
+pub fn restore_request(path: &str) -> Value {
+    json!({"restore": {"path": path}})
+}
+
+/// Send the request to the control socket and return the sandbox id in the response
+pub fn send_request(address: &str, request: &Value) -> Result<String> {
+    let mut stream = UnixStream::connect(address).with_context(|| {
+        format!(
+            "Failed to connect to {}, is control_address of the sandboxer configured?",
+            address
+        )
+    })?;
+    let mut line = request.to_string();
+    line.push('\n');
+    stream
+        .write_all(line.as_bytes())
+        .context("Failed to send request")?;
+
+    let mut response = String::new();
+    BufReader::new(stream)
+        .read_line(&mut response)
+        .context("Failed to read response")?;
+    let response: Value = serde_json::from_str(&response)
+        .with_context(|| format!("Invalid response {:?}", response))?;
+    if let Some(error) = response["error"].as_str().filter(|e| !e.is_empty()) {
+        return Err(anyhow::anyhow!("{}", error));
+    }
+    Ok(response["id"].as_str().unwrap_or_default().to_string())
+}
+
+/// The sandboxer runs in another working directory, so the path is made absolute
+pub fn absolute_path(path: &str) -> Result<String> {
+    let path = Path::new(path);
+    if path.is_absolute() {
+        return Ok(path.display().to_string());
+    }
+    let cwd = std::env::current_dir().context("Failed to get current directory")?;
+    Ok(cwd.join(path).display().to_string())
+}
@@ -16,7 +16,11 @@ limitations under the License.
 
 //! Library for kuasarctl functionality
 
-pub use main::{list_available_pods, resolve_pod_id};
+pub use main::{list_available_pods, match_pod_id, resolve_pod_id};
+
+pub mod control;
+pub mod sandbox;
+pub mod transfer;
 
 // Include the main module
 mod main {
@@ -59,7 +63,11 @@ mod main {
     /// Returns an error if no match or multiple matches are found
     pub fn resolve_pod_id(socket_dir: &str, pod_prefix: &str) -> Result<String> {
         let available_pods = list_available_pods(socket_dir)?;
+        match_pod_id(&available_pods, socket_dir, pod_prefix)
+    }
 
+    /// Match a pod ID prefix against the pods found in `dir`
+    pub fn match_pod_id(available_pods: &[String], dir: &str, pod_prefix: &str) -> Result<String> {
         // First, try exact match
         if available_pods.contains(&pod_prefix.to_string()) {
             return Ok(pod_prefix.to_string());
@@ -76,7 +84,7 @@ mod main {
                 let error_msg = if available_pods.is_empty() {
                     format!(
                         "No pods found in {}. Please check if any pods are running.",
-                        socket_dir
+                        dir
                     )
                 } else {
                     format!(
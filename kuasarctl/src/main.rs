@@ -16,6 +16,14 @@ limitations under the License.
 
 use anyhow::{Context, Result};
 use clap::Parser;
+use kuasarctl::control::{
+    absolute_path, checkpoint_request, restore_request, send_request, DEFAULT_CONTROL_ADDRESS,
+};
+use kuasarctl::sandbox::{
+    affiliated_pids_of, cgroup_stats, format_age, format_bytes, list_sandbox_summaries,
+    load_sandbox, process_stats, resolve_sandbox_id, vmm_pid_of, DEFAULT_SANDBOXER_DIR,
+};
+use kuasarctl::transfer::{self, parse_cp_target, CpTarget, DEFAULT_TRANSFER_PORT};
 use log::{debug, error, info, warn};
 use nix::sys::termios::{tcgetattr, tcsetattr, LocalFlags, OutputFlags, SetArg};
 use nix::pty::Winsize;
@@ -29,7 +37,7 @@ use std::path::Path;
 use std::process;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 // TIOCGWINSZ ioctl number for getting terminal window size
 use nix::libc::{TIOCGWINSZ, c_ulong, ioctl as libc_ioctl};
@@ -38,6 +46,9 @@ const DEFAULT_DEBUG_PORT: u32 = 1025;
 const KUASAR_SOCKET_PREFIX: &str = "/run/kuasar";
 const CONNECT_TIMEOUT_SECS: u64 = 30;
 const MAX_CMD_LENGTH: usize = 4096;
+const SHORT_ID_LENGTH: usize = 13;
+// cgroup v1 reports a page aligned i64::MAX as the memory limit if it is not limited
+const UNLIMITED_MEMORY_THRESHOLD: u64 = 1 << 62;
 
 /// Get list of all available pod IDs from the socket directory
 pub fn list_available_pods(socket_dir: &str) -> Result<Vec<String>> {
@@ -193,6 +204,84 @@ enum Commands {
         #[arg(short = 'd', long = "socket-dir", default_value = KUASAR_SOCKET_PREFIX)]
         socket_dir: String,
     },
+    /// Copy a file between the host and a VM guest, use <pod_id>:<path> for the guest path
+    Cp {
+        /// Source, either a local path or <pod_id>:<guest path>
+        src: String,
+
+        /// Destination, either a local path or <pod_id>:<guest path>
+        dest: String,
+
+        /// File transfer port of the guest (default: 1026)
+        #[arg(short = 'p', long = "port", default_value_t = DEFAULT_TRANSFER_PORT)]
+        port: u32,
+
+        /// Socket directory path
+        #[arg(short = 'd', long = "socket-dir", default_value = KUASAR_SOCKET_PREFIX)]
+        socket_dir: String,
+
+        /// Do not report the progress
+        #[arg(short = 'q', long = "quiet")]
+        quiet: bool,
+    },
+    /// List the sandboxes managed by the vmm sandboxer
+    List {
+        /// Do not truncate the sandbox IDs
+        #[arg(long = "no-trunc")]
+        no_trunc: bool,
+
+        /// Working directory of the vmm sandboxer
+        #[arg(short = 'd', long = "sandbox-dir", default_value = DEFAULT_SANDBOXER_DIR)]
+        sandbox_dir: String,
+    },
+    /// Print the state of a sandbox dumped by the vmm sandboxer
+    Inspect {
+        /// Pod ID or prefix
+        pod_id: String,
+
+        /// Working directory of the vmm sandboxer
+        #[arg(short = 'd', long = "sandbox-dir", default_value = DEFAULT_SANDBOXER_DIR)]
+        sandbox_dir: String,
+    },
+    /// Show CPU and memory usage of the vmm processes and cgroup of a sandbox
+    Stats {
+        /// Pod ID or prefix
+        pod_id: String,
+
+        /// Working directory of the vmm sandboxer
+        #[arg(short = 'd', long = "sandbox-dir", default_value = DEFAULT_SANDBOXER_DIR)]
+        sandbox_dir: String,
+    },
+    /// Save the VM state of a running sandbox into a directory, QEMU and StratoVirt VMs with
+    /// hot attached devices can not be checkpointed
+    Checkpoint {
+        /// Pod ID or prefix
+        pod_id: String,
+
+        /// Directory to save the VM state and sandbox state
+        path: String,
+
+        /// Stop the VM after the checkpoint, the storages are kept for restoring
+        #[arg(long = "exit")]
+        exit: bool,
+
+        /// Control socket of the vmm sandboxer
+        #[arg(short = 'a', long = "address", default_value = DEFAULT_CONTROL_ADDRESS)]
+        address: String,
+
+        /// Working directory of the vmm sandboxer
+        #[arg(short = 'd', long = "sandbox-dir", default_value = DEFAULT_SANDBOXER_DIR)]
+        sandbox_dir: String,
+    },
+    /// Restore a sandbox from a directory saved by checkpoint
+    Restore {
+        /// Directory saved by checkpoint
+        path: String,
+
+        /// Control socket of the vmm sandboxer
+        #[arg(short = 'a', long = "address", default_value = DEFAULT_CONTROL_ADDRESS)]
+        address: String,
+    },
 }
 
 fn main() {
@@ -214,9 +303,247 @@ fn main() {
                 process::exit(1);
             }
         }
+        Commands::Cp {
+            src,
+            dest,
+            port,
+            socket_dir,
+            quiet,
+        } => {
+            if let Err(e) = cp_command(&src, &dest, port, &socket_dir, quiet) {
+                error!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+        Commands::List {
+            no_trunc,
+            sandbox_dir,
+        } => {
+            if let Err(e) = list_command(no_trunc, &sandbox_dir) {
+                error!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+        Commands::Inspect {
+            pod_id,
+            sandbox_dir,
+        } => {
+            if let Err(e) = inspect_command(&pod_id, &sandbox_dir) {
+                error!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+        Commands::Stats {
+            pod_id,
+            sandbox_dir,
+        } => {
+            if let Err(e) = stats_command(&pod_id, &sandbox_dir) {
+                error!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+        Commands::Checkpoint {
+            pod_id,
+            path,
+            exit,
+            address,
+            sandbox_dir,
+        } => {
+            if let Err(e) = checkpoint_command(&pod_id, &path, exit, &address, &sandbox_dir) {
+                error!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+        Commands::Restore { path, address } => {
+            if let Err(e) = restore_command(&path, &address) {
+                error!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+fn cp_command(src: &str, dest: &str, port: u32, socket_dir: &str, quiet: bool) -> Result<()> {
+    let (pod_id, guest_path, local_path, download) =
+        match (parse_cp_target(src), parse_cp_target(dest)) {
+            (CpTarget::Guest { pod_id, path }, CpTarget::Local(local)) => {
+                (pod_id, path, local, true)
+            }
+            (CpTarget::Local(local), CpTarget::Guest { pod_id, path }) => {
+                (pod_id, path, local, false)
+            }
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Exactly one of the source and destination should be <pod_id>:<guest path>"
+                ))
+            }
+        };
+    let resolved_pod_id = resolve_pod_id(socket_dir, &pod_id)?;
+    let socket_path = format!("{}/{}/task.socket", socket_dir, resolved_pod_id);
+    let stream = transfer::connect(&socket_path, port)?;
+    info!("Connected to socket: {}", socket_path);
+
+    let size = if download {
+        let progress = progress_reporter(&guest_path, quiet);
+        transfer::download(stream, &guest_path, &local_path, progress)?
+    } else {
+        let progress = progress_reporter(&local_path, quiet);
+        transfer::upload(stream, &local_path, &guest_path, progress)?
+    };
+    debug!("Copied {} bytes", size);
+    Ok(())
+}
+
+// Report the progress on stderr each time the percentage changes.
+fn progress_reporter(name: &str, quiet: bool) -> impl FnMut(u64, u64) {
+    let name = name.to_string();
+    let mut last_percent = None;
+    move |copied, total| {
+        if quiet {
+            return;
+        }
+        let percent = if total == 0 {
+            100
+        } else {
+            copied * 100 / total
+        };
+        if last_percent == Some(percent) {
+            return;
+        }
+        last_percent = Some(percent);
+        eprint!(
+            "\r{}: {} / {} ({}%)",
+            name,
+            format_bytes(copied),
+            format_bytes(total),
+            percent
+        );
+        if copied == total {
+            eprintln!();
+        }
     }
 }
 
+fn list_command(no_trunc: bool, sandbox_dir: &str) -> Result<()> {
+    let summaries = list_sandbox_summaries(sandbox_dir)?;
+    let now = SystemTime::now();
+    let id_width = if no_trunc {
+        summaries
+            .iter()
+            .map(|s| s.id.len())
+            .max()
+            .unwrap_or_default()
+            .max(SHORT_ID_LENGTH)
+    } else {
+        SHORT_ID_LENGTH
+    };
+
+    println!(
+        "{:<id_width$}  {:<16}  {:<8}  {:<8}  {:<16}  CONTAINERS",
+        "SANDBOX ID", "HYPERVISOR", "STATUS", "VMM PID", "CREATED"
+    );
+    for s in summaries {
+        let id = if no_trunc {
+            s.id.as_str()
+        } else {
+            &s.id[..s.id.len().min(SHORT_ID_LENGTH)]
+        };
+        let pid = s
+            .vmm_pid
+            .map(|p| p.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "{:<id_width$}  {:<16}  {:<8}  {:<8}  {:<16}  {}",
+            id,
+            s.hypervisor,
+            s.status,
+            pid,
+            format_age(s.created_at, now),
+            s.containers
+        );
+    }
+    Ok(())
+}
+
+fn checkpoint_command(
+    pod_id: &str,
+    path: &str,
+    exit: bool,
+    address: &str,
+    sandbox_dir: &str,
+) -> Result<()> {
+    let id = resolve_sandbox_id(sandbox_dir, pod_id)?;
+    let path = absolute_path(path)?;
+    send_request(address, &checkpoint_request(&id, &path, exit))?;
+    println!("{}", id);
+    Ok(())
+}
+
+fn restore_command(path: &str, address: &str) -> Result<()> {
+    let path = absolute_path(path)?;
+    let id = send_request(address, &restore_request(&path))?;
+    println!("{}", id);
+    Ok(())
+}
+
+fn inspect_command(pod_id: &str, sandbox_dir: &str) -> Result<()> {
+    let id = resolve_sandbox_id(sandbox_dir, pod_id)?;
+    let sandbox = load_sandbox(sandbox_dir, &id)?;
+    println!("{}", serde_json::to_string_pretty(&sandbox)?);
+    Ok(())
+}
+
+fn stats_command(pod_id: &str, sandbox_dir: &str) -> Result<()> {
+    let id = resolve_sandbox_id(sandbox_dir, pod_id)?;
+    let sandbox = load_sandbox(sandbox_dir, &id)?;
+
+    let mut pids: Vec<u32> = vmm_pid_of(&sandbox).into_iter().collect();
+    pids.extend(affiliated_pids_of(&sandbox));
+    println!("{:<8}  {:<20}  {:<12}  RSS", "PID", "PROCESS", "CPU TIME");
+    for pid in pids {
+        match process_stats(pid) {
+            Ok(stats) => {
+                let cpu_time = format!("{:.2}s", stats.cpu_seconds);
+                println!(
+                    "{:<8}  {:<20}  {:<12}  {}",
+                    stats.pid,
+                    stats.name,
+                    cpu_time,
+                    format_bytes(stats.rss_bytes)
+                );
+            }
+            Err(e) => warn!("Failed to get stats of process {}: {}", pid, e),
+        }
+    }
+
+    let cgroup = cgroup_stats(&sandbox, &id);
+    println!();
+    println!("Cgroup:       {}", cgroup.path);
+    println!(
+        "CPU usage:    {}",
+        cgroup
+            .cpu_usage_ns
+            .map(|ns| format!("{:.2}s", ns as f64 / 1e9))
+            .unwrap_or_else(|| "-".to_string())
+    );
+    println!(
+        "Memory usage: {}",
+        cgroup
+            .memory_usage_bytes
+            .map(format_bytes)
+            .unwrap_or_else(|| "-".to_string())
+    );
+    println!(
+        "Memory limit: {}",
+        cgroup
+            .memory_limit_bytes
+            .filter(|l| *l < UNLIMITED_MEMORY_THRESHOLD)
+            .map(format_bytes)
+            .unwrap_or_else(|| "unlimited".to_string())
+    );
+    Ok(())
+}
+
 fn exec_command(
     pod_id: String,
     command: Vec<String>,
@@ -0,0 +1,327 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Read the sandbox state dumped by the vmm sandboxer in its working directory
+
+use anyhow::{Context, Result};
+use log::warn;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::match_pod_id;
+
+/// Default working directory of the vmm sandboxer
+pub const DEFAULT_SANDBOXER_DIR: &str = "/run/kuasar-vmm";
+/// File the sandboxer dumps each sandbox into
+pub const SANDBOX_STATE_FILE: &str = "sandbox.json";
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const DEFAULT_CGROUP_PARENT_PATH: &str = "kuasar-vmm";
+
+/// Summary of a sandbox shown by `kuasarctl list`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SandboxSummary {
+    pub id: String,
+    pub hypervisor: String,
+    pub status: String,
+    pub vmm_pid: Option<u32>,
+    pub created_at: Option<SystemTime>,
+    pub containers: usize,
+}
+
+/// CPU and memory usage of a vmm related process
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessStats {
+    pub pid: u32,
+    pub name: String,
+    pub cpu_seconds: f64,
+    pub rss_bytes: u64,
+}
+
+/// CPU and memory usage of the sandbox cgroup
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CgroupStats {
+    pub path: String,
+    pub cpu_usage_ns: Option<u64>,
+    pub memory_usage_bytes: Option<u64>,
+    pub memory_limit_bytes: Option<u64>,
+}
+
+/// Get list of all sandbox IDs which have a state file in the sandboxer directory
+pub fn list_sandboxes(sandboxer_dir: &str) -> Result<Vec<String>> {
+    let dir = Path::new(sandboxer_dir);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut sandboxes = Vec::new();
+    for entry in fs::read_dir(dir)
+        .with_context(|| format!("Failed to read sandboxer directory {}", sandboxer_dir))?
+    {
+        let path = entry?.path();
+        if path.join(SANDBOX_STATE_FILE).is_file() {
+            if let Some(id) = path.file_name().and_then(|n| n.to_str()) {
+                sandboxes.push(id.to_string());
+            }
+        }
+    }
+
+    sandboxes.sort();
+    Ok(sandboxes)
+}
+
+/// Resolve a sandbox ID prefix to a full sandbox ID, the same way as `resolve_pod_id`
+pub fn resolve_sandbox_id(sandboxer_dir: &str, prefix: &str) -> Result<String> {
+    let sandboxes = list_sandboxes(sandboxer_dir)?;
+    match_pod_id(&sandboxes, sandboxer_dir, prefix)
+}
+
+/// Load the dumped `KuasarSandbox` of the sandbox
+pub fn load_sandbox(sandboxer_dir: &str, id: &str) -> Result<Value> {
+    let path = Path::new(sandboxer_dir).join(id).join(SANDBOX_STATE_FILE);
+    let content = fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_slice(&content).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Summarize the dumped sandbox, `dir_time` is used if the creation time is not recorded
+pub fn summarize(id: &str, sandbox: &Value, dir_time: Option<SystemTime>) -> SandboxSummary {
+    SandboxSummary {
+        id: id.to_string(),
+        hypervisor: hypervisor_of(sandbox),
+        status: status_of(&sandbox["status"]),
+        vmm_pid: vmm_pid_of(sandbox),
+        created_at: parse_time(&sandbox["data"]["created_at"]).or(dir_time),
+        containers: sandbox["containers"]
+            .as_object()
+            .map(|c| c.len())
+            .unwrap_or_default(),
+    }
+}
+
+/// Load and summarize all the sandboxes in the sandboxer directory,
+/// a sandbox whose state file can not be loaded, e.g. while it is being dumped,
+/// is shown with an unknown status instead of failing the whole list
+pub fn list_sandbox_summaries(sandboxer_dir: &str) -> Result<Vec<SandboxSummary>> {
+    let mut summaries = Vec::new();
+    for id in list_sandboxes(sandboxer_dir)? {
+        let sandbox = load_sandbox(sandboxer_dir, &id).unwrap_or_else(|e| {
+            warn!("Failed to load sandbox {}: {:#}", id, e);
+            Value::Null
+        });
+        let dir_time = fs::metadata(Path::new(sandboxer_dir).join(&id))
+            .and_then(|m| m.created().or_else(|_| m.modified()))
+            .ok();
+        summaries.push(summarize(&id, &sandbox, dir_time));
+    }
+    Ok(summaries)
+}
+
+/// Get the hypervisor recorded by the sandboxer when the sandbox is created
+pub fn hypervisor_of(sandbox: &Value) -> String {
+    sandbox["hypervisor"]
+        .as_str()
+        .filter(|h| !h.is_empty())
+        .unwrap_or("unknown")
+        .to_string()
+}
+
+/// Get the name of the `SandboxStatus`, which is serialized as "Created" or {"Running": pid}
+pub fn status_of(status: &Value) -> String {
+    match status {
+        Value::String(s) => s.to_string(),
+        Value::Object(o) => o
+            .keys()
+            .next()
+            .cloned()
+            .unwrap_or_else(|| "Unknown".to_string()),
+        _ => "Unknown".to_string(),
+    }
+}
+
+/// Get the vmm pid, from the running status first as some vms do not record it in pids
+pub fn vmm_pid_of(sandbox: &Value) -> Option<u32> {
+    sandbox["status"]["Running"]
+        .as_u64()
+        .filter(|pid| *pid > 0)
+        .or_else(|| sandbox["vm"]["pids"]["vmm_pid"].as_u64())
+        .map(|pid| pid as u32)
+}
+
+/// Get the pids of the processes running along with the vmm, such as virtiofsd
+pub fn affiliated_pids_of(sandbox: &Value) -> Vec<u32> {
+    sandbox["vm"]["pids"]["affiliated_pids"]
+        .as_array()
+        .map(|pids| {
+            pids.iter()
+                .filter_map(|p| p.as_u64())
+                .map(|p| p as u32)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// SystemTime is serialized by serde as {"secs_since_epoch": .., "nanos_since_epoch": ..}
+fn parse_time(value: &Value) -> Option<SystemTime> {
+    let secs = value["secs_since_epoch"]
+        .as_u64()
+        .or_else(|| value.as_u64())?;
+    let nanos = value["nanos_since_epoch"].as_u64().unwrap_or_default();
+    Some(UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_nanos(nanos))
+}
+
+/// Format the time elapsed since `time`, like "5 minutes ago"
+pub fn format_age(time: Option<SystemTime>, now: SystemTime) -> String {
+    let elapsed = match time.and_then(|t| now.duration_since(t).ok()) {
+        Some(d) => d.as_secs(),
+        None => return "-".to_string(),
+    };
+    let (value, unit) = match elapsed {
+        0..=59 => (elapsed, "second"),
+        60..=3599 => (elapsed / 60, "minute"),
+        3600..=86399 => (elapsed / 3600, "hour"),
+        _ => (elapsed / 86400, "day"),
+    };
+    if value == 1 {
+        format!("{} {} ago", value, unit)
+    } else {
+        format!("{} {}s ago", value, unit)
+    }
+}
+
+/// Read the CPU time and resident memory of the process from procfs
+pub fn process_stats(pid: u32) -> Result<ProcessStats> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid))
+        .with_context(|| format!("Failed to read stat of process {}", pid))?;
+    let (name, cpu_ticks) =
+        parse_proc_stat(&stat).ok_or_else(|| anyhow::anyhow!("Invalid stat of process {}", pid))?;
+    let status = fs::read_to_string(format!("/proc/{}/status", pid))
+        .with_context(|| format!("Failed to read status of process {}", pid))?;
+
+    // SAFETY: sysconf has no side effects
+    let ticks_per_second = unsafe { nix::libc::sysconf(nix::libc::_SC_CLK_TCK) };
+    let ticks_per_second = if ticks_per_second > 0 {
+        ticks_per_second as f64
+    } else {
+        100.0
+    };
+
+    Ok(ProcessStats {
+        pid,
+        name,
+        cpu_seconds: cpu_ticks as f64 / ticks_per_second,
+        rss_bytes: parse_vm_rss(&status).unwrap_or_default(),
+    })
+}
+
+/// Parse the command name and utime + stime in clock ticks from /proc/<pid>/stat
+pub fn parse_proc_stat(stat: &str) -> Option<(String, u64)> {
+    // the command name is in parentheses and may contain spaces
+    let start = stat.find('(')?;
+    let end = stat.rfind(')')?;
+    let name = stat[start + 1..end].to_string();
+    let fields: Vec<&str> = stat[end + 1..].split_whitespace().collect();
+    // utime and stime are the 14th and 15th fields, the first two are pid and comm
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    Some((name, utime + stime))
+}
+
+/// Parse the VmRSS in bytes from /proc/<pid>/status
+pub fn parse_vm_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+/// Get the path of the sandbox cgroup in the hierarchies, which is resolved by the cgroup driver
+/// of the sandboxer, the sandboxes dumped by older sandboxers are in the cgroupfs parent path
+pub fn sandbox_cgroup_path(sandbox: &Value, id: &str) -> String {
+    let cgroups = &sandbox["sandbox_cgroups"];
+    if let Some(path) = cgroups["sandbox_cgroup_path"]
+        .as_str()
+        .filter(|p| !p.is_empty())
+    {
+        return path.trim_matches('/').to_string();
+    }
+    let parent = cgroups["cgroup_parent_path"]
+        .as_str()
+        .filter(|p| !p.is_empty())
+        .unwrap_or(DEFAULT_CGROUP_PARENT_PATH)
+        .trim_matches('/');
+    format!("{}/{}", parent, id)
+}
+
+/// Read the CPU and memory usage of the sandbox cgroup, both cgroup v1 and v2 are supported
+pub fn cgroup_stats(sandbox: &Value, id: &str) -> CgroupStats {
+    let relative = sandbox_cgroup_path(sandbox, id);
+    let read = |path: String| -> Option<u64> {
+        let content = fs::read_to_string(path).ok()?;
+        content.trim().parse().ok()
+    };
+
+    if Path::new(CGROUP_ROOT).join("cgroup.controllers").exists() {
+        let dir = format!("{}/{}", CGROUP_ROOT, relative);
+        let cpu_usage_ns = fs::read_to_string(format!("{}/cpu.stat", dir))
+            .ok()
+            .and_then(|s| {
+                s.lines()
+                    .find_map(|l| l.strip_prefix("usage_usec "))
+                    .and_then(|v| v.trim().parse::<u64>().ok())
+            })
+            .map(|usec| usec * 1000);
+        CgroupStats {
+            cpu_usage_ns,
+            memory_usage_bytes: read(format!("{}/memory.current", dir)),
+            // "max" fails to parse, which means there is no limit
+            memory_limit_bytes: read(format!("{}/memory.max", dir)),
+            path: dir,
+        }
+    } else {
+        CgroupStats {
+            path: format!("{}/<subsystem>/{}", CGROUP_ROOT, relative),
+            cpu_usage_ns: read(format!(
+                "{}/cpuacct/{}/cpuacct.usage",
+                CGROUP_ROOT, relative
+            )),
+            memory_usage_bytes: read(format!(
+                "{}/memory/{}/memory.usage_in_bytes",
+                CGROUP_ROOT, relative
+            )),
+            memory_limit_bytes: read(format!(
+                "{}/memory/{}/memory.limit_in_bytes",
+                CGROUP_ROOT, relative
+            )),
+        }
+    }
+}
+
+/// Format bytes in a human readable way, like "1.5 GiB"
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
@@ -0,0 +1,242 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Copy files between the host and the guest through the file transfer port of the task agent
+//!
+//! The protocol is line based, one file per connection:
+//!
+//! - `GET <path>\n` is replied with `OK <size> <mode in octal>\n` and the content
+//! - `PUT <size> <mode in octal> <path>\n` is replied with `OK\n`, then the content is sent,
+//!   and `DONE\n` is replied after it is written
+//!
+//! Any failure is replied with `ERR <message>\n`.
+
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Guest vsock port of the file transfer service
+pub const DEFAULT_TRANSFER_PORT: u32 = 1026;
+
+const MAX_LINE_LENGTH: usize = 8192;
+const BUFFER_SIZE: usize = 64 * 1024;
+const CONNECT_TIMEOUT_SECS: u64 = 30;
+
+/// Source or destination of `kuasarctl cp`
+#[derive(Debug, PartialEq)]
+pub enum CpTarget {
+    Local(String),
+    Guest { pod_id: String, path: String },
+}
+
+/// Parse `<pod>:/guest/path` as a guest path, anything else is a local path
+pub fn parse_cp_target(arg: &str) -> CpTarget {
+    if let Some((pod_id, path)) = arg.split_once(':') {
+        // a local path like "./a:/b" is not mistaken for a pod as pod IDs have no "/"
+        if !pod_id.is_empty() && !pod_id.contains('/') && path.starts_with('/') {
+            return CpTarget::Guest {
+                pod_id: pod_id.to_string(),
+                path: path.to_string(),
+            };
+        }
+    }
+    CpTarget::Local(arg.to_string())
+}
+
+/// Connect to the guest vsock `port` through the hybrid vsock socket of the vm
+pub fn connect(socket_path: &str, port: u32) -> Result<UnixStream> {
+    let mut stream = UnixStream::connect(socket_path)
+        .with_context(|| format!("Failed to connect to socket {}", socket_path))?;
+    stream.set_read_timeout(Some(Duration::from_secs(CONNECT_TIMEOUT_SECS)))?;
+    stream
+        .write_all(format!("CONNECT {}\n", port).as_bytes())
+        .context("Failed to send CONNECT command")?;
+    let reply = read_line(&mut stream)?;
+    if !reply.starts_with("OK") {
+        return Err(anyhow::anyhow!(
+            "Failed to connect to guest port {}: {}",
+            port,
+            reply
+        ));
+    }
+    stream.set_read_timeout(None)?;
+    Ok(stream)
+}
+
+/// Copy the guest file `guest_path` to `local_path`, which can be an existing directory.
+/// `progress` is called with the bytes copied and the total bytes, returns the bytes copied.
+pub fn download<S, F>(
+    mut stream: S,
+    guest_path: &str,
+    local_path: &str,
+    mut progress: F,
+) -> Result<u64>
+where
+    S: Read + Write,
+    F: FnMut(u64, u64),
+{
+    let mut dest = PathBuf::from(local_path);
+    if dest.is_dir() {
+        dest = dest.join(file_name(guest_path)?);
+    }
+
+    writeln!(stream, "GET {}", guest_path).context("Failed to send GET request")?;
+    let reply = read_line(&mut stream)?;
+    let (size, mode) = parse_get_reply(&reply)?;
+
+    // write to a temporary file first so that the destination is not left half written
+    let tmp = dest.with_file_name(format!(
+        ".{}.kuasar-cp",
+        file_name(&dest.to_string_lossy())?
+    ));
+    let res = (|| -> Result<()> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(mode)
+            .open(&tmp)
+            .with_context(|| format!("Failed to create {}", tmp.display()))?;
+        copy_exact(&mut stream, &mut file, size, &mut progress)?;
+        file.sync_all()?;
+        fs::set_permissions(&tmp, fs::Permissions::from_mode(mode))?;
+        fs::rename(&tmp, &dest)
+            .with_context(|| format!("Failed to rename to {}", dest.display()))?;
+        Ok(())
+    })();
+    if res.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    res?;
+    Ok(size)
+}
+
+/// Copy the local file `local_path` to `guest_path`, the file name is appended if the
+/// guest path ends with "/". `progress` is called with the bytes copied and the total bytes,
+/// returns the bytes copied.
+pub fn upload<S, F>(
+    mut stream: S,
+    local_path: &str,
+    guest_path: &str,
+    mut progress: F,
+) -> Result<u64>
+where
+    S: Read + Write,
+    F: FnMut(u64, u64),
+{
+    let metadata =
+        fs::metadata(local_path).with_context(|| format!("Failed to stat {}", local_path))?;
+    if !metadata.is_file() {
+        return Err(anyhow::anyhow!("{} is not a regular file", local_path));
+    }
+    let mut dest = guest_path.to_string();
+    if dest.ends_with('/') {
+        dest.push_str(file_name(local_path)?);
+    }
+    let size = metadata.len();
+    let mode = metadata.permissions().mode() & 0o7777;
+    let mut file =
+        File::open(local_path).with_context(|| format!("Failed to open {}", local_path))?;
+
+    writeln!(stream, "PUT {} {:o} {}", size, mode, dest).context("Failed to send PUT request")?;
+    expect_reply(&read_line(&mut stream)?, "OK")?;
+    copy_exact(&mut file, &mut stream, size, &mut progress)?;
+    stream.flush()?;
+    expect_reply(&read_line(&mut stream)?, "DONE")?;
+    Ok(size)
+}
+
+/// Parse the reply of GET, which is "OK <size> <mode in octal>"
+pub fn parse_get_reply(reply: &str) -> Result<(u64, u32)> {
+    expect_reply(reply, "OK")?;
+    let mut parts = reply.split_whitespace().skip(1);
+    let size = parts.next().and_then(|s| s.parse().ok());
+    let mode = parts.next().and_then(|m| u32::from_str_radix(m, 8).ok());
+    match (size, mode) {
+        (Some(size), Some(mode)) => Ok((size, mode & 0o7777)),
+        _ => Err(anyhow::anyhow!("Invalid reply from guest: {}", reply)),
+    }
+}
+
+fn expect_reply(reply: &str, expected: &str) -> Result<()> {
+    if let Some(msg) = reply.strip_prefix("ERR ") {
+        return Err(anyhow::anyhow!("Guest error: {}", msg));
+    }
+    if reply.split_whitespace().next() != Some(expected) {
+        return Err(anyhow::anyhow!("Unexpected reply from guest: {}", reply));
+    }
+    Ok(())
+}
+
+// Read a line byte by byte, so that none of the content following it is consumed.
+fn read_line<R: Read>(reader: &mut R) -> Result<String> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        if reader.read(&mut byte).context("Failed to read reply")? == 0 {
+            return Err(anyhow::anyhow!("Connection closed by guest"));
+        }
+        if byte[0] == b'\n' {
+            break;
+        }
+        line.push(byte[0]);
+        if line.len() > MAX_LINE_LENGTH {
+            return Err(anyhow::anyhow!("Reply from guest is too long"));
+        }
+    }
+    Ok(String::from_utf8_lossy(&line).to_string())
+}
+
+fn copy_exact<R, W, F>(reader: &mut R, writer: &mut W, size: u64, progress: &mut F) -> Result<()>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(u64, u64),
+{
+    let mut buf = vec![0u8; BUFFER_SIZE];
+    let mut copied = 0;
+    progress(copied, size);
+    while copied < size {
+        let want = (size - copied).min(buf.len() as u64) as usize;
+        let n = reader
+            .read(&mut buf[..want])
+            .context("Failed to read content")?;
+        if n == 0 {
+            return Err(anyhow::anyhow!(
+                "Unexpected end of content, copied {} of {} bytes",
+                copied,
+                size
+            ));
+        }
+        writer
+            .write_all(&buf[..n])
+            .context("Failed to write content")?;
+        copied += n as u64;
+        progress(copied, size);
+    }
+    Ok(())
+}
+
+fn file_name(path: &str) -> Result<&str> {
+    Path::new(path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| anyhow::anyhow!("No file name in path {}", path))
+}
@@ -0,0 +1,93 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Unit tests for the requests to the control socket of the vmm sandboxer
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixListener;
+use std::thread;
+use tempfile::TempDir;
+
+use kuasarctl::control::{absolute_path, checkpoint_request, restore_request, send_request};
+
+// Serve one request in place of the sandboxer
+fn fake_sandboxer(listener: UnixListener, reply: &'static str) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request = String::new();
+        reader.read_line(&mut request).unwrap();
+        let mut stream = stream;
+        stream.write_all(reply.as_bytes()).unwrap();
+        request
+    })
+}
+
+#[test]
+fn test_control_requests() {
+    assert_eq!(
+        checkpoint_request("pod-abc", "/tmp/cp", true).to_string(),
+        r#"{"checkpoint":{"exit":true,"id":"pod-abc","path":"/tmp/cp"}}"#
+    );
+    assert_eq!(
+        restore_request("/tmp/cp").to_string(),
+        r#"{"restore":{"path":"/tmp/cp"}}"#
+    );
+}
+
+#[test]
+fn test_send_request() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let address = temp_dir.path().join("control.sock");
+    let listener = UnixListener::bind(&address).unwrap();
+    let server = fake_sandboxer(listener, "{\"id\":\"pod-abc\"}\n");
+
+    let id = send_request(address.to_str().unwrap(), &restore_request("/tmp/cp")).unwrap();
+    assert_eq!(id, "pod-abc");
+    assert_eq!(
+        server.join().unwrap(),
+        "{\"restore\":{\"path\":\"/tmp/cp\"}}\n"
+    );
+}
+
+#[test]
+fn test_send_request_error() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let address = temp_dir.path().join("control.sock");
+    let listener = UnixListener::bind(&address).unwrap();
+    let server = fake_sandboxer(listener, "{\"error\":\"sandbox pod-abc not found\"}\n");
+
+    let err = send_request(
+        address.to_str().unwrap(),
+        &checkpoint_request("pod-abc", "/tmp/cp", false),
+    )
+    .unwrap_err();
+    assert_eq!(err.to_string(), "sandbox pod-abc not found");
+    server.join().unwrap();
+
+    // the sandboxer does not serve control requests
+    assert!(send_request(address.to_str().unwrap(), &restore_request("/tmp/cp")).is_err());
+}
+
+#[test]
+fn test_absolute_path() {
+    assert_eq!(absolute_path("/tmp/cp").unwrap(), "/tmp/cp");
+    let cwd = std::env::current_dir().unwrap();
+    assert_eq!(
+        absolute_path("cp").unwrap(),
+        cwd.join("cp").display().to_string()
+    );
+}
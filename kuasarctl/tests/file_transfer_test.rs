@@ -0,0 +1,168 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Unit tests for copying files between the host and the guest
+
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::thread;
+use tempfile::TempDir;
+
+use kuasarctl::transfer::{download, parse_cp_target, parse_get_reply, upload, CpTarget};
+
+// Serve one GET request in place of the guest agent
+fn fake_guest_get(stream: UnixStream, reply: &'static [u8]) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request = String::new();
+        reader.read_line(&mut request).unwrap();
+        let mut stream = stream;
+        stream.write_all(reply).unwrap();
+        request
+    })
+}
+
+#[test]
+fn test_parse_cp_target() {
+    assert_eq!(
+        parse_cp_target("pod-abc:/var/log/messages"),
+        CpTarget::Guest {
+            pod_id: "pod-abc".to_string(),
+            path: "/var/log/messages".to_string(),
+        }
+    );
+    assert_eq!(
+        parse_cp_target("./messages"),
+        CpTarget::Local("./messages".to_string())
+    );
+    assert_eq!(
+        parse_cp_target("./a:/b"),
+        CpTarget::Local("./a:/b".to_string())
+    );
+    assert_eq!(
+        parse_cp_target("pod-abc:relative"),
+        CpTarget::Local("pod-abc:relative".to_string())
+    );
+}
+
+#[test]
+fn test_parse_get_reply() {
+    assert_eq!(parse_get_reply("OK 1024 755").unwrap(), (1024, 0o755));
+    assert!(parse_get_reply("OK 1024").is_err());
+    assert!(parse_get_reply("OK abc 644").is_err());
+    let err = parse_get_reply("ERR /tmp/a is not a regular file")
+        .unwrap_err()
+        .to_string();
+    assert!(err.contains("is not a regular file"));
+}
+
+#[test]
+fn test_download_into_directory() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let (client, server) = UnixStream::pair().unwrap();
+    let guest = fake_guest_get(server, b"OK 11 640\nhello world");
+
+    let mut progress = Vec::new();
+    let size = download(
+        client,
+        "/var/log/app.log",
+        temp_dir.path().to_str().unwrap(),
+        |copied, total| progress.push((copied, total)),
+    )
+    .unwrap();
+
+    assert_eq!(guest.join().unwrap(), "GET /var/log/app.log\n");
+    assert_eq!(size, 11);
+    assert_eq!(progress.first(), Some(&(0, 11)));
+    assert_eq!(progress.last(), Some(&(11, 11)));
+    let path = temp_dir.path().join("app.log");
+    assert_eq!(fs::read_to_string(&path).unwrap(), "hello world");
+    let mode = fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o7777, 0o640);
+}
+
+#[test]
+fn test_download_guest_error() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let dest = temp_dir.path().join("missing");
+    let (client, server) = UnixStream::pair().unwrap();
+    let guest = fake_guest_get(server, b"ERR failed to stat /missing\n");
+
+    let err = download(client, "/missing", dest.to_str().unwrap(), |_, _| {})
+        .unwrap_err()
+        .to_string();
+    guest.join().unwrap();
+    assert!(err.contains("failed to stat /missing"));
+    assert!(!dest.exists());
+}
+
+#[test]
+fn test_download_truncated_content() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let dest = temp_dir.path().join("file");
+    let (client, server) = UnixStream::pair().unwrap();
+    let guest = fake_guest_get(server, b"OK 100 644\nshort");
+
+    let res = download(client, "/file", dest.to_str().unwrap(), |_, _| {});
+    guest.join().unwrap();
+    assert!(res.is_err());
+    // neither the destination nor the temporary file is left
+    assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_upload_to_guest_directory() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let src = temp_dir.path().join("tool");
+    fs::write(&src, "#!/bin/sh\n").unwrap();
+    fs::set_permissions(&src, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let (client, server) = UnixStream::pair().unwrap();
+    let guest = thread::spawn(move || {
+        let mut reader = BufReader::new(server.try_clone().unwrap());
+        let mut request = String::new();
+        reader.read_line(&mut request).unwrap();
+        let mut server = server;
+        server.write_all(b"OK\n").unwrap();
+        let mut content = vec![0u8; 10];
+        reader.read_exact(&mut content).unwrap();
+        server.write_all(b"DONE\n").unwrap();
+        (request, content)
+    });
+
+    let size = upload(client, src.to_str().unwrap(), "/usr/local/bin/", |_, _| {}).unwrap();
+    let (request, content) = guest.join().unwrap();
+    assert_eq!(size, 10);
+    assert_eq!(request, "PUT 10 755 /usr/local/bin/tool\n");
+    assert_eq!(content, b"#!/bin/sh\n");
+}
+
+#[test]
+fn test_upload_rejects_directory() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let (client, _server) = UnixStream::pair().unwrap();
+    let err = upload(
+        client,
+        temp_dir.path().to_str().unwrap(),
+        "/tmp/",
+        |_, _| {},
+    )
+    .unwrap_err()
+    .to_string();
+    assert!(err.contains("is not a regular file"));
+}
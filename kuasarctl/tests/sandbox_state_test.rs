@@ -0,0 +1,220 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Unit tests for reading the sandbox state dumped by the vmm sandboxer
+
+use std::fs;
+use std::time::{Duration, UNIX_EPOCH};
+use tempfile::TempDir;
+
+use kuasarctl::sandbox::{
+    affiliated_pids_of, format_age, format_bytes, list_sandbox_summaries, list_sandboxes,
+    load_sandbox, parse_proc_stat, parse_vm_rss, resolve_sandbox_id, sandbox_cgroup_path,
+    summarize,
+};
+
+const CLH_SANDBOX: &str = r#"{
+    "vm": {
+        "id": "pod-abc-123",
+        "netns": "/var/run/netns/cni-1",
+        "base_dir": "/run/kuasar-vmm/pod-abc-123",
+        "agent_socket": "hvsock:///run/kuasar-vmm/pod-abc-123/task.vsock:1024",
+        "virtiofsd_config": {},
+        "pids": {"vmm_pid": 1234, "affiliated_pids": [1233]}
+    },
+    "id": "pod-abc-123",
+    "status": {"Running": 1234},
+    "base_dir": "/run/kuasar-vmm/pod-abc-123",
+    "data": {"created_at": {"secs_since_epoch": 1700000000, "nanos_since_epoch": 0}},
+    "containers": {"c1": {}, "c2": {}},
+    "storages": [],
+    "network": {"interfaces": [], "routes": []},
+    "hypervisor": "cloud-hypervisor"
+}"#;
+
+fn write_sandbox(dir: &TempDir, id: &str, content: &str) {
+    let path = dir.path().join(id);
+    fs::create_dir(&path).expect("Failed to create sandbox dir");
+    fs::write(path.join("sandbox.json"), content).expect("Failed to write sandbox.json");
+}
+
+#[test]
+fn test_list_sandboxes_ignores_dirs_without_state() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    write_sandbox(&temp_dir, "pod-002", CLH_SANDBOX);
+    write_sandbox(&temp_dir, "pod-001", CLH_SANDBOX);
+    fs::create_dir(temp_dir.path().join("pod-003")).expect("Failed to create dir");
+
+    let sandboxes = list_sandboxes(temp_dir.path().to_str().unwrap()).unwrap();
+    assert_eq!(
+        sandboxes,
+        vec!["pod-001".to_string(), "pod-002".to_string()]
+    );
+}
+
+#[test]
+fn test_list_sandboxes_nonexistent_directory() {
+    let sandboxes = list_sandboxes("/nonexistent/kuasar-vmm").unwrap();
+    assert!(sandboxes.is_empty());
+}
+
+#[test]
+fn test_resolve_sandbox_id_prefix() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let dir = temp_dir.path().to_str().unwrap();
+    write_sandbox(&temp_dir, "pod-abc-123", CLH_SANDBOX);
+    write_sandbox(&temp_dir, "pod-abd-456", CLH_SANDBOX);
+
+    assert_eq!(resolve_sandbox_id(dir, "pod-abc").unwrap(), "pod-abc-123");
+    assert_eq!(
+        resolve_sandbox_id(dir, "pod-abd-456").unwrap(),
+        "pod-abd-456"
+    );
+
+    let err = resolve_sandbox_id(dir, "pod-ab").unwrap_err().to_string();
+    assert!(err.contains("matches multiple pods"));
+    let err = resolve_sandbox_id(dir, "pod-x").unwrap_err().to_string();
+    assert!(err.contains("No pod found"));
+}
+
+#[test]
+fn test_summarize_running_sandbox() {
+    let sandbox: serde_json::Value = serde_json::from_str(CLH_SANDBOX).unwrap();
+    let summary = summarize("pod-abc-123", &sandbox, None);
+    assert_eq!(summary.hypervisor, "cloud-hypervisor");
+    assert_eq!(summary.status, "Running");
+    assert_eq!(summary.vmm_pid, Some(1234));
+    assert_eq!(summary.containers, 2);
+    assert_eq!(
+        summary.created_at,
+        Some(UNIX_EPOCH + Duration::from_secs(1700000000))
+    );
+    assert_eq!(affiliated_pids_of(&sandbox), vec![1233]);
+}
+
+#[test]
+fn test_summarize_hypervisors_and_status() {
+    let created_qemu = r#"{"vm": {"console_socket": "", "virtiofsd_config": null,
+        "pids": {"vmm_pid": null, "affiliated_pids": []}}, "status": "Created",
+        "hypervisor": "qemu"}"#;
+    let sandbox: serde_json::Value = serde_json::from_str(created_qemu).unwrap();
+    let summary = summarize("pod-qemu", &sandbox, None);
+    assert_eq!(summary.hypervisor, "qemu");
+    assert_eq!(summary.status, "Created");
+    assert_eq!(summary.vmm_pid, None);
+    assert_eq!(summary.containers, 0);
+    assert_eq!(summary.created_at, None);
+
+    let stopped_stratovirt = r#"{"vm": {"console_socket": "", "virtiofs_daemon": {},
+        "pids": {"vmm_pid": 42}}, "status": {"Stopped": [0, 1700000000000000000]},
+        "hypervisor": "stratovirt"}"#;
+    let sandbox: serde_json::Value = serde_json::from_str(stopped_stratovirt).unwrap();
+    let summary = summarize("pod-stratovirt", &sandbox, None);
+    assert_eq!(summary.hypervisor, "stratovirt");
+    assert_eq!(summary.status, "Stopped");
+    assert_eq!(summary.vmm_pid, Some(42));
+
+    let firecracker = r#"{"vm": {"drives": [], "pids": {}}, "status": "Paused",
+        "hypervisor": "firecracker"}"#;
+    let sandbox: serde_json::Value = serde_json::from_str(firecracker).unwrap();
+    assert_eq!(
+        summarize("pod-fc", &sandbox, None).hypervisor,
+        "firecracker"
+    );
+
+    // the hypervisor is not guessed from the vm if it is not recorded
+    let unrecorded = r#"{"vm": {"drives": [], "pids": {}}, "status": "Paused"}"#;
+    let sandbox: serde_json::Value = serde_json::from_str(unrecorded).unwrap();
+    assert_eq!(summarize("pod-fc", &sandbox, None).hypervisor, "unknown");
+}
+
+#[test]
+fn test_list_and_load_sandbox() {
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let dir = temp_dir.path().to_str().unwrap();
+    write_sandbox(&temp_dir, "pod-abc-123", CLH_SANDBOX);
+
+    let summaries = list_sandbox_summaries(dir).unwrap();
+    assert_eq!(summaries.len(), 1);
+    assert_eq!(summaries[0].id, "pod-abc-123");
+
+    let sandbox = load_sandbox(dir, "pod-abc-123").unwrap();
+    assert!(sandbox["network"]["interfaces"].is_array());
+
+    write_sandbox(&temp_dir, "pod-broken", "{");
+    assert!(load_sandbox(dir, "pod-broken").is_err());
+
+    let summaries = list_sandbox_summaries(dir).unwrap();
+    assert_eq!(summaries.len(), 2);
+    assert_eq!(summaries[1].id, "pod-broken");
+    assert_eq!(summaries[1].status, "Unknown");
+    assert_eq!(summaries[1].hypervisor, "unknown");
+    assert!(summaries[1].created_at.is_some());
+}
+
+#[test]
+fn test_format_age() {
+    let now = UNIX_EPOCH + Duration::from_secs(1700000000);
+    let ago = |secs| Some(now - Duration::from_secs(secs));
+    assert_eq!(format_age(ago(1), now), "1 second ago");
+    assert_eq!(format_age(ago(59), now), "59 seconds ago");
+    assert_eq!(format_age(ago(120), now), "2 minutes ago");
+    assert_eq!(format_age(ago(3600), now), "1 hour ago");
+    assert_eq!(format_age(ago(3 * 86400), now), "3 days ago");
+    assert_eq!(format_age(None, now), "-");
+}
+
+#[test]
+fn test_parse_process_stats() {
+    let stat = "1234 (cloud-hyper visor) S 1 1234 1234 0 -1 4194560 2340 0 0 0 150 50 0 0 20 0 \
+                8 0 12345 1073741824 25600 18446744073709551615";
+    assert_eq!(
+        parse_proc_stat(stat),
+        Some(("cloud-hyper visor".to_string(), 200))
+    );
+    assert_eq!(parse_proc_stat("1234 (short) S"), None);
+
+    let status = "Name:\tvirtiofsd\nVmPeak:\t  20480 kB\nVmRSS:\t    1024 kB\n";
+    assert_eq!(parse_vm_rss(status), Some(1024 * 1024));
+    assert_eq!(parse_vm_rss("Name:\tkthreadd\n"), None);
+}
+
+#[test]
+fn test_format_bytes() {
+    assert_eq!(format_bytes(512), "512 B");
+    assert_eq!(format_bytes(1536), "1.5 KiB");
+    assert_eq!(format_bytes(256 << 20), "256.0 MiB");
+    assert_eq!(format_bytes(2 << 30), "2.0 GiB");
+}
+
+#[test]
+fn test_sandbox_cgroup_path() {
+    let systemd = r#"{"sandbox_cgroups": {"cgroup_parent_path": "kubepods-podxxx.slice",
+        "cgroup_driver": "systemd",
+        "sandbox_cgroup_path": "/kubepods.slice/kubepods-podxxx.slice/kubepods-podxxx-kuasar_abc.slice"}}"#;
+    let sandbox: serde_json::Value = serde_json::from_str(systemd).unwrap();
+    assert_eq!(
+        sandbox_cgroup_path(&sandbox, "abc"),
+        "kubepods.slice/kubepods-podxxx.slice/kubepods-podxxx-kuasar_abc.slice"
+    );
+
+    // dumped by the sandboxer which did not record the resolved path
+    let cgroupfs = r#"{"sandbox_cgroups": {"cgroup_parent_path": "/kubepods/podxxx"}}"#;
+    let sandbox: serde_json::Value = serde_json::from_str(cgroupfs).unwrap();
+    assert_eq!(sandbox_cgroup_path(&sandbox, "abc"), "kubepods/podxxx/abc");
+    let sandbox: serde_json::Value = serde_json::from_str("{}").unwrap();
+    assert_eq!(sandbox_cgroup_path(&sandbox, "abc"), "kuasar-vmm/abc");
+}
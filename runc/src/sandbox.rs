@@ -17,7 +17,10 @@ limitations under the License.
 use std::{
     collections::HashMap,
     io::Write,
-    os::fd::{AsRawFd, OwnedFd},
+    os::{
+        fd::{AsRawFd, OwnedFd},
+        unix::fs::MetadataExt,
+    },
     path::Path,
     sync::Arc,
 };
@@ -31,21 +34,29 @@ use containerd_sandbox::{
     signal::ExitSignal,
     Container, ContainerOption, Sandbox, SandboxOption, SandboxStatus, Sandboxer,
 };
-use log::warn;
+use log::{error, info, warn};
 use nix::{
+    libc,
     mount::{mount, umount, MsFlags},
     sys::signal::{kill, Signal},
     unistd::Pid,
 };
 use serde::{Deserialize, Serialize};
 use tokio::{
-    fs::{create_dir_all, remove_dir_all, File, OpenOptions},
+    fs::{create_dir_all, metadata, remove_dir_all, File, OpenOptions},
     io::{AsyncReadExt, AsyncWriteExt},
     sync::{Mutex, RwLock},
 };
 
+use vmm_common::process::{start_time, ProcessHandle};
+
 use crate::{read_count, write_all};
 
+// namespaces of the sandbox bound to the files in the sandbox dir if pid namespace is not shared
+const SANDBOX_NAMESPACES: [&str; 3] = ["uts", "ipc", "net"];
+// the sandbox process is reaped by the sandbox parent, so the exit code is unknown to us
+const UNKNOWN_EXIT_CODE: u32 = 255;
+
 pub struct RuncSandboxer {
     #[allow(clippy::type_complexity)]
     pub(crate) sandboxes: Arc<RwLock<HashMap<String, Arc<Mutex<RuncSandbox>>>>>,
@@ -62,6 +73,9 @@ pub struct RuncSandbox {
     #[serde(skip, default)]
     pub(crate) exit_signal: Arc<ExitSignal>,
     pub(crate) containers: HashMap<String, RuncContainerData>,
+    // start time of the sandbox process in clock ticks since boot, to tell if the pid is reused
+    #[serde(default)]
+    pub(crate) start_time: u64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -108,18 +122,17 @@ impl RuncSandboxer {
 
     pub async fn recover(&self, dir: &str) -> Result<()> {
         let mut subs = tokio::fs::read_dir(dir).await.map_err(Error::IO)?;
-        let mut pids = Vec::new();
         while let Some(entry) = subs.next_entry().await.unwrap() {
             if let Ok(t) = entry.file_type().await {
                 if t.is_dir() {
                     let path = Path::new(dir).join(entry.file_name());
                     match RuncSandbox::recover(&path).await {
-                        Ok(sb) => {
-                            if let SandboxStatus::Running(pid) = sb.status {
-                                // TODO need to check if the sandbox process is still running.
-                                pids.push(pid);
-                            }
+                        Ok(mut sb) => {
+                            let process = sb.check_recovered().await;
                             let sb_mutex = Arc::new(Mutex::new(sb));
+                            if let Some(process) = process {
+                                monitor(sb_mutex.clone(), process);
+                            }
                             self.sandboxes
                                 .write()
                                 .await
@@ -127,6 +140,12 @@ impl RuncSandboxer {
                         }
                         Err(e) => {
                             warn!("failed to recover sandbox {:?}, {:?}", entry.file_name(), e);
+                            // the leaked namespace mounts make the dir busy to be removed
+                            if let Some(dir) = path.to_str() {
+                                cleanup_ns_mounts(dir).await.unwrap_or_else(|e| {
+                                    warn!("failed to cleanup namespaces in {}, {:?}", dir, e)
+                                });
+                            }
                             remove_dir_all(&path).await.unwrap_or_default();
                         }
                     }
@@ -150,6 +169,7 @@ impl Sandboxer for RuncSandboxer {
             status: SandboxStatus::Created,
             exit_signal: Arc::new(Default::default()),
             containers: Default::default(),
+            start_time: 0,
         };
         create_dir_all(&sandbox.base_dir)
             .await
@@ -161,8 +181,8 @@ impl Sandboxer for RuncSandboxer {
     }
 
     async fn start(&self, id: &str) -> Result<()> {
-        let sandbox = self.sandbox(id).await?;
-        let mut sandbox = sandbox.lock().await;
+        let sandbox_mutex = self.sandbox(id).await?;
+        let mut sandbox = sandbox_mutex.lock().await;
         let mut sandbox_parent = self.sandbox_parent.lock().await;
         let sandbox_pid = sandbox_parent.fork_sandbox_process(id, &sandbox.data.netns)?;
         sandbox
@@ -179,6 +199,14 @@ impl Sandboxer for RuncSandboxer {
         sandbox.dump().await.inspect_err(|_| {
             kill(Pid::from_raw(sandbox_pid), Signal::SIGKILL).unwrap_or_default();
         })?;
+        if let SandboxStatus::Running(pid) = sandbox.status {
+            if pid != 0 {
+                match ProcessHandle::open(sandbox_pid as u32, sandbox.start_time) {
+                    Some(process) => monitor(sandbox_mutex.clone(), process),
+                    None => warn!("process {} of sandbox {} is not running", pid, id),
+                }
+            }
+        }
         Ok(())
     }
 
@@ -214,14 +242,12 @@ impl RuncSandbox {
     async fn stop(&mut self) -> Result<()> {
         if let SandboxStatus::Running(pid) = self.status {
             if pid != 0 {
-                kill(Pid::from_raw(pid as i32), Signal::SIGKILL).unwrap_or_default();
+                // the pid may be reused by another process after the sandbox process exited
+                if let Some(process) = ProcessHandle::open(pid, self.start_time) {
+                    process.signal(libc::SIGKILL).unwrap_or_default();
+                }
             } else {
-                let uts_path = format!("{}/uts", self.base_dir);
-                umount(&*uts_path)
-                    .map_err(|e| anyhow!("failed to umount sandbox uts ns, {}", e))?;
-                let ipc_path = format!("{}/ipc", self.base_dir);
-                umount(&*ipc_path)
-                    .map_err(|e| anyhow!("failed to umount sandbox ipc ns, {}", e))?;
+                cleanup_ns_mounts(&self.base_dir).await?;
             }
         }
         let ts = time::OffsetDateTime::now_utc().unix_timestamp_nanos();
@@ -230,6 +256,59 @@ impl RuncSandbox {
         Ok(())
     }
 
+    // Check if the recovered sandbox is still alive, the one whose process exited or whose
+    // namespaces are gone is stopped, and the running process is returned to be monitored.
+    async fn check_recovered(&mut self) -> Option<ProcessHandle> {
+        match self.status {
+            SandboxStatus::Running(0) => {
+                if !self.ns_mounted().await {
+                    warn!("namespaces of sandbox {} are gone", self.id);
+                    self.exited().await;
+                }
+                None
+            }
+            SandboxStatus::Running(pid) => match ProcessHandle::open(pid, self.start_time) {
+                Some(process) => Some(process),
+                None => {
+                    warn!("process {} of sandbox {} is not running", pid, self.id);
+                    self.exited().await;
+                    None
+                }
+            },
+            SandboxStatus::Stopped(_, _) => {
+                // the exit signal is not dumped, signal it again for the waiters
+                self.exit_signal.signal();
+                None
+            }
+            _ => None,
+        }
+    }
+
+    // Mark the sandbox stopped after its process exited unexpectedly.
+    async fn exited(&mut self) {
+        let ts = time::OffsetDateTime::now_utc().unix_timestamp_nanos();
+        self.status = SandboxStatus::Stopped(UNKNOWN_EXIT_CODE, ts);
+        self.exit_signal.signal();
+        if let Err(e) = cleanup_ns_mounts(&self.base_dir).await {
+            warn!(
+                "failed to cleanup namespaces of sandbox {}, {:?}",
+                self.id, e
+            );
+        }
+        self.dump()
+            .await
+            .unwrap_or_else(|e| error!("failed to dump sandbox {}, {:?}", self.id, e));
+    }
+
+    async fn ns_mounted(&self) -> bool {
+        for ns in SANDBOX_NAMESPACES {
+            if !is_mounted(&format!("{}/{}", self.base_dir, ns), &self.base_dir).await {
+                return false;
+            }
+        }
+        true
+    }
+
     async fn recover<P: AsRef<Path>>(base_dir: P) -> Result<Self> {
         let dump_path = base_dir.as_ref().join("sandbox.json");
         let mut dump_file = OpenOptions::new()
@@ -309,6 +388,7 @@ impl RuncSandbox {
             kill(Pid::from_raw(sandbox_pid), Signal::SIGKILL).unwrap_or_default();
             self.status = SandboxStatus::Running(0);
         } else {
+            self.start_time = start_time(sandbox_pid as u32)?;
             self.status = SandboxStatus::Running(sandbox_pid as u32);
         }
         Ok(())
@@ -382,3 +462,158 @@ impl Sandbox for RuncSandbox {
         Ok(self.data.clone())
     }
 }
+
+// Monitor the sandbox process until it exits, and mark the sandbox stopped if it is running.
+fn monitor(sandbox_mutex: Arc<Mutex<RuncSandbox>>, process: ProcessHandle) {
+    tokio::spawn(async move {
+        process.wait().await;
+        let mut sandbox = sandbox_mutex.lock().await;
+        if let SandboxStatus::Running(_) = sandbox.status {
+            info!("sandbox {} terminated", sandbox.id);
+            sandbox.exited().await;
+        }
+    });
+}
+
+// Unmount the namespaces bound to the sandbox dir, the ones not mounted are skipped.
+async fn cleanup_ns_mounts(base_dir: &str) -> Result<()> {
+    for ns in SANDBOX_NAMESPACES {
+        let path = format!("{}/{}", base_dir, ns);
+        if !is_mounted(&path, base_dir).await {
+            continue;
+        }
+        umount(&*path).map_err(|e| anyhow!("failed to umount sandbox {} ns, {}", ns, e))?;
+    }
+    Ok(())
+}
+
+// A file is mounted if it is on a different device from its parent dir.
+async fn is_mounted(path: &str, parent: &str) -> bool {
+    match (metadata(path).await, metadata(parent).await) {
+        (Ok(m), Ok(p)) => m.dev() != p.dev(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{process::Command, sync::Arc, time::Duration};
+
+    use containerd_sandbox::{data::SandboxData, SandboxStatus};
+    use tokio::{fs::remove_dir_all, sync::Mutex, time::timeout};
+
+    use vmm_common::process::start_time;
+
+    use crate::sandbox::{monitor, RuncSandbox, UNKNOWN_EXIT_CODE};
+
+    async fn new_sandbox(status: SandboxStatus, start_time: u64) -> RuncSandbox {
+        let base_dir = std::env::temp_dir().join(format!("runc-sandbox-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&base_dir).await.unwrap();
+        RuncSandbox {
+            id: "test".to_string(),
+            base_dir: base_dir.to_str().unwrap().to_string(),
+            data: SandboxData::default(),
+            status,
+            exit_signal: Arc::new(Default::default()),
+            containers: Default::default(),
+            start_time,
+        }
+    }
+
+    async fn assert_exited(sandbox: &RuncSandbox) {
+        assert!(matches!(
+            sandbox.status,
+            SandboxStatus::Stopped(UNKNOWN_EXIT_CODE, _)
+        ));
+        timeout(Duration::from_secs(1), sandbox.exit_signal.wait())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recover_running_process() {
+        let pid = std::process::id();
+        let start_time = start_time(pid).unwrap();
+        let mut sandbox = new_sandbox(SandboxStatus::Running(pid), start_time).await;
+        assert!(sandbox.check_recovered().await.is_some());
+        assert!(matches!(sandbox.status, SandboxStatus::Running(_)));
+        remove_dir_all(&sandbox.base_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recover_exited_process() {
+        let mut child = Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        let mut sandbox = new_sandbox(SandboxStatus::Running(child.id()), 0).await;
+        assert!(sandbox.check_recovered().await.is_none());
+        assert_exited(&sandbox).await;
+        remove_dir_all(&sandbox.base_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recover_reused_pid() {
+        let pid = std::process::id();
+        let start_time = start_time(pid).unwrap();
+        let mut sandbox = new_sandbox(SandboxStatus::Running(pid), start_time + 1).await;
+        assert!(sandbox.check_recovered().await.is_none());
+        assert_exited(&sandbox).await;
+        remove_dir_all(&sandbox.base_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recover_without_ns_mounts() {
+        let mut sandbox = new_sandbox(SandboxStatus::Running(0), 0).await;
+        assert!(sandbox.check_recovered().await.is_none());
+        assert_exited(&sandbox).await;
+        remove_dir_all(&sandbox.base_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_recover_stopped() {
+        let mut sandbox = new_sandbox(SandboxStatus::Stopped(0, 1), 0).await;
+        assert!(sandbox.check_recovered().await.is_none());
+        assert!(matches!(sandbox.status, SandboxStatus::Stopped(0, 1)));
+        timeout(Duration::from_secs(1), sandbox.exit_signal.wait())
+            .await
+            .unwrap();
+        remove_dir_all(&sandbox.base_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_monitor() {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let pid = child.id();
+        let start_time = start_time(pid).unwrap();
+        let mut sandbox = new_sandbox(SandboxStatus::Running(pid), start_time).await;
+        // the process is watched by pidfd, or polled if pidfd is not supported by the kernel
+        let process = sandbox.check_recovered().await.unwrap();
+        let exit_signal = sandbox.exit_signal.clone();
+        let base_dir = sandbox.base_dir.to_string();
+        let sandbox_mutex = Arc::new(Mutex::new(sandbox));
+        monitor(sandbox_mutex.clone(), process);
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+        timeout(Duration::from_secs(5), exit_signal.wait())
+            .await
+            .unwrap();
+        assert_exited(&*sandbox_mutex.lock().await).await;
+        remove_dir_all(&base_dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stop_reused_pid() {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let pid = child.id();
+        let start_time = start_time(pid).unwrap();
+        // the pid of the exited sandbox process is reused by another process
+        let mut sandbox = new_sandbox(SandboxStatus::Running(pid), start_time + 1).await;
+        sandbox.stop().await.unwrap();
+        assert!(matches!(sandbox.status, SandboxStatus::Stopped(0, _)));
+        assert!(child.try_wait().unwrap().is_none());
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+        remove_dir_all(&sandbox.base_dir).await.unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use containerd_sandbox::error::Result;
+use log::{debug, warn};
+use nix::{errno::Errno, libc};
+use time::OffsetDateTime;
+use tokio::{io::unix::AsyncFd, time::sleep};
+
+// interval to check the process if it is not watched by pidfd
+const PROCESS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Handle of a process opened by pidfd, which refers to the process itself instead of its pid,
+/// so another process reusing the pid after it exits is never signaled by mistake.
+/// It works for the processes not spawned by us, such as the ones recovered after restart.
+/// If pidfd is not available, e.g. on the kernels before 5.3, the process is told by its start
+/// time and signaled by kill(2).
+#[derive(Debug)]
+pub struct ProcessHandle {
+    pid: u32,
+    start_time: u64,
+    pidfd: Option<OwnedFd>,
+}
+
+impl ProcessHandle {
+    /// Open the handle of the process `pid` started at `start_time`, none if the process exited
+    /// or the pid is reused by another process, the start time is not checked if it is 0.
+    pub fn open(pid: u32, start_time: u64) -> Option<Self> {
+        let pidfd = match pidfd_open(pid) {
+            Ok(fd) => Some(fd),
+            Err(Errno::ESRCH) => return None,
+            Err(e) => {
+                debug!(
+                    "failed to open pidfd of process {}, check it by start time: {}",
+                    pid, e
+                );
+                None
+            }
+        };
+        // the start time is checked after the pidfd is opened,
+        // so the pidfd refers to the process of the start time if they match.
+        let start_time = match running_start_time(pid) {
+            Some(t) if start_time == 0 || t == start_time => t,
+            _ => return None,
+        };
+        Some(Self {
+            pid,
+            start_time,
+            pidfd,
+        })
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Send the signal to the process, it is fine if the process exited already.
+    pub fn signal(&self, signal: libc::c_int) -> Result<()> {
+        match self.send_signal(signal) {
+            Ok(_) | Err(Errno::ESRCH) => Ok(()),
+            Err(e) => Err(anyhow!(
+                "failed to send signal {} to process {}: {}",
+                signal,
+                self.pid,
+                e
+            )
+            .into()),
+        }
+    }
+
+    /// Wait for the process to exit, returns the exit code and the exit time, the exit code
+    /// is always 0 as the process may not be our child, which we can not reap.
+    pub async fn wait(&self) -> (u32, i128) {
+        let exited = match &self.pidfd {
+            Some(pidfd) => match wait_readable(pidfd).await {
+                Ok(_) => true,
+                Err(e) => {
+                    warn!(
+                        "failed to wait process {} by pidfd, poll it: {}",
+                        self.pid, e
+                    );
+                    false
+                }
+            },
+            None => false,
+        };
+        if !exited {
+            while self.send_signal(0).is_ok() {
+                sleep(PROCESS_POLL_INTERVAL).await;
+            }
+        }
+        (0, OffsetDateTime::now_utc().unix_timestamp_nanos())
+    }
+
+    fn send_signal(&self, signal: libc::c_int) -> std::result::Result<(), Errno> {
+        let ret = match &self.pidfd {
+            // SAFETY: the pidfd is owned by self and the siginfo is null as kill(2) does
+            Some(pidfd) => unsafe {
+                libc::syscall(
+                    libc::SYS_pidfd_send_signal,
+                    pidfd.as_raw_fd(),
+                    signal,
+                    std::ptr::null::<libc::siginfo_t>(),
+                    0,
+                )
+            },
+            None => {
+                // the pid may be reused by another process after the process exited
+                if running_start_time(self.pid) != Some(self.start_time) {
+                    return Err(Errno::ESRCH);
+                }
+                // SAFETY: kill takes no pointer
+                unsafe { libc::kill(self.pid as libc::pid_t, signal) as libc::c_long }
+            }
+        };
+        if ret < 0 {
+            return Err(Errno::last());
+        }
+        Ok(())
+    }
+}
+
+// The pidfd becomes readable when the process exits.
+async fn wait_readable(pidfd: &OwnedFd) -> std::io::Result<()> {
+    let fd = AsyncFd::new(pidfd.as_raw_fd())?;
+    let _guard = fd.readable().await?;
+    Ok(())
+}
+
+// Start time of the process if it is running, a zombie process has exited but not been reaped.
+fn running_start_time(pid: u32) -> Option<u64> {
+    let stat = procfs::process::Process::new(pid as i32)
+        .and_then(|p| p.stat())
+        .ok()?;
+    if stat.state == 'Z' {
+        return None;
+    }
+    Some(stat.starttime)
+}
+
+/// Start time of the process in clock ticks after boot, which tells the processes of the same pid.
+pub fn start_time(pid: u32) -> Result<u64> {
+    let stat = procfs::process::Process::new(pid as i32)
+        .and_then(|p| p.stat())
+        .map_err(|e| anyhow!("failed to get stat of process {}: {}", pid, e))?;
+    Ok(stat.starttime)
+}
+
+fn pidfd_open(pid: u32) -> std::result::Result<OwnedFd, Errno> {
+    // SAFETY: pidfd_open takes no pointer and returns a new fd on success
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        return Err(Errno::last());
+    }
+    // SAFETY: the fd is just opened and owned by nobody else
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{process::Command, time::Duration};
+
+    use nix::libc::SIGKILL;
+    use tokio::time::timeout;
+
+    use crate::process::{start_time, ProcessHandle};
+
+    // The handle opened on the kernels without pidfd
+    fn open_without_pidfd(pid: u32) -> ProcessHandle {
+        ProcessHandle {
+            pid,
+            start_time: start_time(pid).unwrap(),
+            pidfd: None,
+        }
+    }
+
+    #[test]
+    fn test_open_process() {
+        let pid = std::process::id();
+        let start = start_time(pid).unwrap();
+        let handle = ProcessHandle::open(pid, start).unwrap();
+        assert_eq!(handle.pid(), pid);
+        assert!(ProcessHandle::open(pid, 0).is_some());
+        // the pid is reused by another process if the start time differs
+        assert!(ProcessHandle::open(pid, start + 1).is_none());
+    }
+
+    #[test]
+    fn test_open_exited_process() {
+        let mut child = Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        // the zombie is taken as exited
+        while child.try_wait().unwrap().is_none() {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert!(ProcessHandle::open(pid, 0).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_signal_and_wait() {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let pid = child.id();
+        let handle = ProcessHandle::open(pid, start_time(pid).unwrap()).unwrap();
+        handle.signal(SIGKILL).unwrap();
+        let (code, ts) = timeout(Duration::from_secs(5), handle.wait())
+            .await
+            .unwrap();
+        assert_eq!(code, 0);
+        assert!(ts > 0);
+        child.wait().unwrap();
+        // signal the exited process is fine
+        handle.signal(SIGKILL).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_signal_and_wait_without_pidfd() {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let handle = open_without_pidfd(child.id());
+        handle.signal(SIGKILL).unwrap();
+        // the killed child is a zombie until it is reaped, which is taken as exited
+        let (code, ts) = timeout(Duration::from_secs(5), handle.wait())
+            .await
+            .unwrap();
+        assert_eq!(code, 0);
+        assert!(ts > 0);
+        child.wait().unwrap();
+        handle.signal(SIGKILL).unwrap();
+
+        // the pid reused by another process is not signaled
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let handle = ProcessHandle {
+            start_time: start_time(child.id()).unwrap() + 1,
+            ..open_without_pidfd(child.id())
+        };
+        handle.signal(SIGKILL).unwrap();
+        timeout(Duration::from_secs(1), handle.wait())
+            .await
+            .unwrap();
+        assert!(child.try_wait().unwrap().is_none());
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
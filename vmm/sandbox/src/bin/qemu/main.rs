@@ -17,6 +17,7 @@ limitations under the License.
 use std::path::Path;
 
 use clap::Parser;
+use log::error;
 use vmm_common::{signal, trace};
 use vmm_sandboxer::{
     args,
@@ -72,6 +73,21 @@ async fn main() {
         sandboxer.recover(&args.dir).await;
     }
 
+    // Boot vms into the pool if it is enabled, sandboxes boot their own vms without the pool
+    if let Err(e) = sandboxer.init_pool().await {
+        error!("failed to init vm pool: {}", e);
+    }
+
+    // Serve metrics of the sandboxes if it is enabled
+    if let Err(e) = sandboxer.serve_metrics().await {
+        error!("failed to serve metrics: {}", e);
+    }
+
+    // Serve checkpoint and restore requests of the sandboxes if it is enabled
+    if let Err(e) = sandboxer.serve_control().await {
+        error!("failed to serve control requests: {}", e);
+    }
+
     // Run the sandboxer
     containerd_sandbox::run(
         "kuasar-vmm-sandboxer-qemu",
@@ -14,76 +14,179 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::error::Error;
+use std::{error::Error, fs, path::Path};
 
 use anyhow::{anyhow, Ok, Result};
 use cgroups_rs::{
-    cgroup_builder::CgroupBuilder, cpu::CpuController, cpuset::CpuSetController,
-    hugetlb::HugeTlbController, memory::MemController, Cgroup,
+    blkio::BlkIoController, cgroup_builder::CgroupBuilder, cpu::CpuController,
+    cpuset::CpuSetController, hugetlb::HugeTlbController, memory::MemController, Cgroup,
 };
 use containerd_sandbox::{cri::api::v1::LinuxContainerResources, data::SandboxData};
+use log::warn;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    cgroup::systemd::SandboxUnits,
+    storage::ratelimit::DiskRateLimit,
     utils::{get_overhead_resources, get_resources, get_total_resources},
     vm::VcpuThreads,
 };
 
+mod systemd;
+
 pub const DEFAULT_CGROUP_PARENT_PATH: &str = "kuasar-vmm";
 pub const VCPU_CGROUP_NAME: &str = "vcpu";
 pub const POD_OVERHEAD_CGROUP_NAME: &str = "pod_overhead";
+const SYS_BLOCK_PATH: &str = "/sys/block";
+
+/// How the cgroups of sandboxes are managed, it should be the same as the one of kubelet.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CgroupDriver {
+    /// Create the cgroups in the cgroupfs directly.
+    #[default]
+    Cgroupfs,
+    /// Create the cgroups as transient units of systemd over D-Bus, the cgroup parent is a
+    /// slice like "kubepods-burstable-podxxx.slice" or in the "slice:prefix:name" form.
+    Systemd,
+}
 
 /// `SandboxCgroup` represents a set of cgroups for a sandbox.
 #[derive(Default, Debug, Serialize, Deserialize)]
 pub struct SandboxCgroup {
     pub cgroup_parent_path: String,
+    /// The driver the cgroups are created by, it is cgroupfs if systemd failed to create them.
+    #[serde(default)]
+    pub cgroup_driver: CgroupDriver,
+    /// Path of the sandbox cgroup in the hierarchies, resolved by the cgroup driver.
+    #[serde(default)]
+    pub sandbox_cgroup_path: String,
     #[serde(skip)]
     pub sandbox_cgroup: Cgroup,
     #[serde(skip)]
     pub vcpu_cgroup: Cgroup,
     #[serde(skip)]
     pub pod_overhead_cgroup: Cgroup,
+    #[serde(skip)]
+    systemd_units: Option<SandboxUnits>,
 }
 
 impl SandboxCgroup {
-    pub fn create_sandbox_cgroups(cgroup_parent_path: &str, sandbox_id: &str) -> Result<Self> {
+    pub async fn create_sandbox_cgroups(
+        cgroup_driver: CgroupDriver,
+        cgroup_parent_path: &str,
+        sandbox_id: &str,
+    ) -> Result<Self> {
+        if cgroup_driver == CgroupDriver::Systemd {
+            match Self::create_systemd_cgroups(cgroup_parent_path, sandbox_id).await {
+                Err(e) => warn!(
+                    "failed to create systemd units of sandbox {}, fall back to cgroupfs: {}",
+                    sandbox_id, e
+                ),
+                sandbox_cgroups => return sandbox_cgroups,
+            }
+        }
+
+        // The cgroup parent is a systemd slice if kubelet is using the systemd driver
+        let parent_path = if systemd::is_systemd_cgroup_parent(cgroup_parent_path) {
+            systemd::cgroupfs_parent(cgroup_parent_path)?
+        } else {
+            cgroup_parent_path.to_string()
+        };
         // Create sandbox cgroup in the all cgroup subsystem dir
-        let sandbox_cgroup_path = format!("{}/{}", cgroup_parent_path, sandbox_id);
-        // CgroupBuilder::new() func doesn't accept the cgroup name has "/" prefix,
-        // So need to remove the "/" prefix for sandbox_cgroup_path
-        let sandbox_cgroup_rela_path = sandbox_cgroup_path.trim_start_matches('/');
+        let sandbox_cgroup_path = format!("{}/{}", parent_path, sandbox_id);
+        Self::build_cgroups(
+            cgroup_parent_path,
+            &sandbox_cgroup_path,
+            &format!("{}/{}", sandbox_cgroup_path, VCPU_CGROUP_NAME),
+            &format!("{}/{}", sandbox_cgroup_path, POD_OVERHEAD_CGROUP_NAME),
+        )
+    }
 
-        let sandbox_cgroup =
-            CgroupBuilder::new(sandbox_cgroup_rela_path).build(cgroups_rs::hierarchies::auto())?;
+    async fn create_systemd_cgroups(cgroup_parent_path: &str, sandbox_id: &str) -> Result<Self> {
+        let units = SandboxUnits::new(cgroup_parent_path, sandbox_id)?;
+        units.start().await?;
+        let build_cgroups = || -> Result<Self> {
+            let sandbox_cgroup_path = systemd::expand_slice(&units.sandbox_slice)?;
+            let sandbox_cgroup = CgroupBuilder::new(sandbox_cgroup_path.trim_start_matches('/'))
+                .build(cgroups_rs::hierarchies::auto())?;
+            // the vcpu and pod_overhead cgroups are created in the scope of the sandbox,
+            // which is started with the vmm process
+            let scope_path = units.scope_path()?;
+            Ok(SandboxCgroup {
+                cgroup_parent_path: cgroup_parent_path.to_string(),
+                cgroup_driver: CgroupDriver::Systemd,
+                sandbox_cgroup_path,
+                sandbox_cgroup,
+                vcpu_cgroup: load_cpu_cgroup(&format!("{}/{}", scope_path, VCPU_CGROUP_NAME)),
+                pod_overhead_cgroup: load_cpu_cgroup(&format!(
+                    "{}/{}",
+                    scope_path, POD_OVERHEAD_CGROUP_NAME
+                )),
+                systemd_units: None,
+            })
+        };
+        match build_cgroups() {
+            Err(e) => {
+                units.stop().await.unwrap_or_default();
+                Err(e)
+            }
+            sandbox_cgroups => Ok(SandboxCgroup {
+                systemd_units: Some(units),
+                ..sandbox_cgroups?
+            }),
+        }
+    }
 
-        // Only create the vcpu and pod_overhead cgroups in the cpu cgroup subsystem
-        let vcpu_cgroup_path = format!("{}/{}", sandbox_cgroup_rela_path, VCPU_CGROUP_NAME);
-        let vcpu_cgroup = CgroupBuilder::new(vcpu_cgroup_path.as_str())
-            .set_specified_controllers(vec!["cpu".to_string()])
+    fn build_cgroups(
+        cgroup_parent_path: &str,
+        sandbox_cgroup_path: &str,
+        vcpu_cgroup_path: &str,
+        pod_overhead_cgroup_path: &str,
+    ) -> Result<Self> {
+        // CgroupBuilder::new() func doesn't accept the cgroup name has "/" prefix,
+        // So need to remove the "/" prefix for the cgroup paths
+        let sandbox_cgroup = CgroupBuilder::new(sandbox_cgroup_path.trim_start_matches('/'))
             .build(cgroups_rs::hierarchies::auto())?;
 
-        let pod_overhead_cgroup_path =
-            format!("{}/{}", sandbox_cgroup_rela_path, POD_OVERHEAD_CGROUP_NAME);
-        let pod_overhead_cgroup = CgroupBuilder::new(pod_overhead_cgroup_path.as_str())
-            .set_specified_controllers(vec!["cpu".to_string()])
-            .build(cgroups_rs::hierarchies::auto())?;
+        let vcpu_cgroup = build_cpu_cgroup(vcpu_cgroup_path)?;
+        let pod_overhead_cgroup = build_cpu_cgroup(pod_overhead_cgroup_path)?;
 
         Ok(SandboxCgroup {
             cgroup_parent_path: cgroup_parent_path.to_string(),
+            cgroup_driver: CgroupDriver::Cgroupfs,
+            sandbox_cgroup_path: sandbox_cgroup_path.to_string(),
             sandbox_cgroup,
             vcpu_cgroup,
             pod_overhead_cgroup,
+            systemd_units: None,
         })
     }
 
-    pub fn update_res_for_sandbox_cgroups(&self, sandbox_data: &SandboxData) -> Result<()> {
+    pub async fn update_res_for_sandbox_cgroups(
+        &self,
+        sandbox_data: &SandboxData,
+        disk_rate_limit: &DiskRateLimit,
+    ) -> Result<()> {
+        let total_resources = get_total_resources(sandbox_data);
+        if let (Some(units), Some(total_resources)) = (&self.systemd_units, &total_resources) {
+            units.set_resources(total_resources).await?;
+        }
+
         // apply the total resources = sum(sum(containers_resources) + pod_overhead)) in the sandbox cgroup dir
-        if let Some(total_resources) = get_total_resources(sandbox_data) {
+        if let Some(total_resources) = total_resources {
             apply_cpu_resource(&self.sandbox_cgroup, &total_resources)?;
             apply_memory_resource(&self.sandbox_cgroup, &total_resources)?;
             apply_cpuset_resources(&self.sandbox_cgroup, &total_resources)?;
             apply_hugetlb_resources(&self.sandbox_cgroup, &total_resources)?;
         }
+        apply_blkio_resources(&self.sandbox_cgroup, disk_rate_limit)?;
+
+        // the vcpu and pod_overhead cgroups of the systemd driver do not exist until the vmm
+        // process is started, the resources are applied again after it is added to the cgroups
+        if !self.vcpu_cgroup.exists() {
+            return Ok(());
+        }
 
         // apply the cpu resource of containers in the vcpu cpu subsystem cgroup
         if let Some(containers_resources) = get_resources(sandbox_data) {
@@ -98,13 +201,27 @@ impl SandboxCgroup {
         Ok(())
     }
 
-    pub fn add_process_into_sandbox_cgroups(
+    pub async fn add_process_into_sandbox_cgroups(
         &self,
         pid: u32,
         vcpu_threads: Option<VcpuThreads>,
     ) -> Result<()> {
-        // Add vmm process into the sandbox_cgroup
-        self.sandbox_cgroup.add_task_by_tgid((pid as u64).into())?;
+        if let Some(units) = &self.systemd_units {
+            // systemd moves the first process into the scope, and the scope is delegated,
+            // so the other processes are moved into it and its sub cgroups by cgroupfs
+            units.start_scope(pid).await?;
+            let scope_path = units.scope_path()?;
+            Cgroup::load(
+                cgroups_rs::hierarchies::auto(),
+                scope_path.trim_start_matches('/'),
+            )
+            .add_task_by_tgid((pid as u64).into())?;
+            build_cpu_cgroup(&format!("{}/{}", scope_path, VCPU_CGROUP_NAME))?;
+            build_cpu_cgroup(&format!("{}/{}", scope_path, POD_OVERHEAD_CGROUP_NAME))?;
+        } else {
+            // Add vmm process into the sandbox_cgroup
+            self.sandbox_cgroup.add_task_by_tgid((pid as u64).into())?;
+        }
         self.pod_overhead_cgroup
             .add_task_by_tgid((pid as u64).into())?;
 
@@ -119,7 +236,11 @@ impl SandboxCgroup {
         Ok(())
     }
 
-    pub fn remove_sandbox_cgroups(&self) -> Result<()> {
+    pub async fn remove_sandbox_cgroups(&self) -> Result<()> {
+        // the cgroups in the hierarchies not managed by systemd are left to be removed below
+        if let Some(units) = &self.systemd_units {
+            units.stop().await?;
+        }
         remove_sandbox_cgroup(&self.vcpu_cgroup)?;
         remove_sandbox_cgroup(&self.pod_overhead_cgroup)?;
         remove_sandbox_cgroup(&self.sandbox_cgroup)?;
@@ -127,6 +248,22 @@ impl SandboxCgroup {
     }
 }
 
+// Only create the vcpu and pod_overhead cgroups in the cpu cgroup subsystem
+fn build_cpu_cgroup(path: &str) -> Result<Cgroup> {
+    Ok(CgroupBuilder::new(path.trim_start_matches('/'))
+        .set_specified_controllers(vec!["cpu".to_string()])
+        .build(cgroups_rs::hierarchies::auto())?)
+}
+
+// Load the cgroup in the cpu cgroup subsystem without creating it
+fn load_cpu_cgroup(path: &str) -> Cgroup {
+    Cgroup::load_with_specified_controllers(
+        cgroups_rs::hierarchies::auto(),
+        path.trim_start_matches('/'),
+        vec!["cpu".to_string()],
+    )
+}
+
 fn apply_cpu_resource(cgroup: &Cgroup, res: &LinuxContainerResources) -> Result<()> {
     let cpu_controller: &CpuController = cgroup
         .controller_of()
@@ -185,6 +322,55 @@ fn apply_hugetlb_resources(cgroup: &Cgroup, res: &LinuxContainerResources) -> Re
     Ok(())
 }
 
+// The io of the vmm and virtiofsd processes in the sandbox cgroup is throttled on every disk of
+// the host, so the block volumes and the dirs shared by virtiofs are limited as a whole. The io to
+// a partition is throttled on the disk it belongs to, as partitions can not be throttled alone.
+fn apply_blkio_resources(cgroup: &Cgroup, limit: &DiskRateLimit) -> Result<()> {
+    if limit.is_empty() {
+        return Ok(());
+    }
+    let blkio_controller: &BlkIoController = cgroup
+        .controller_of()
+        .ok_or_else(|| anyhow!("No blkio controller attached!"))?;
+
+    for (major, minor) in host_disks(Path::new(SYS_BLOCK_PATH))? {
+        let throttle = || -> Result<()> {
+            if let Some(bps) = limit.bandwidth {
+                blkio_controller.throttle_read_bps_for_device(major, minor, bps)?;
+                blkio_controller.throttle_write_bps_for_device(major, minor, bps)?;
+            }
+            if let Some(iops) = limit.iops {
+                blkio_controller.throttle_read_iops_for_device(major, minor, iops)?;
+                blkio_controller.throttle_write_iops_for_device(major, minor, iops)?;
+            }
+            Ok(())
+        };
+        // some virtual disks, such as the ones of zram, are not throttleable
+        if let Err(e) = throttle() {
+            warn!("failed to throttle io on disk {}:{}: {}", major, minor, e);
+        }
+    }
+    Ok(())
+}
+
+// Get the device numbers of the disks in use, the unused loop devices have a size of 0.
+fn host_disks(sys_block: &Path) -> Result<Vec<(u64, u64)>> {
+    let mut disks = vec![];
+    for entry in fs::read_dir(sys_block)? {
+        let path = entry?.path();
+        let size = fs::read_to_string(path.join("size")).unwrap_or_default();
+        if size.trim().is_empty() || size.trim() == "0" {
+            continue;
+        }
+        let dev = fs::read_to_string(path.join("dev"))?;
+        if let Some((major, minor)) = dev.trim().split_once(':') {
+            disks.push((major.parse()?, minor.parse()?));
+        }
+    }
+    disks.sort();
+    Ok(disks)
+}
+
 fn remove_sandbox_cgroup(cgroup: &Cgroup) -> Result<()> {
     // get the tids in the current cgroup and then move the tids to parent cgroup
     let tids = cgroup.tasks();
@@ -219,6 +405,8 @@ mod tests {
         PodSandboxConfig,
     };
 
+    use temp_dir::TempDir;
+
     use super::*;
     use crate::utils::get_sandbox_cgroup_parent_path;
 
@@ -260,7 +448,23 @@ mod tests {
     }
 
     #[test]
-    fn test_create_sandbox_cgroups() {
+    fn test_host_disks() {
+        let dir = TempDir::new().unwrap();
+        for (name, dev, size) in [
+            ("sda", "8:0", "1953525168"),
+            ("loop0", "7:0", "0"),
+            ("nvme0n1", "259:0", "500118192"),
+        ] {
+            let disk = dir.path().join(name);
+            fs::create_dir(&disk).unwrap();
+            fs::write(disk.join("dev"), format!("{}\n", dev)).unwrap();
+            fs::write(disk.join("size"), format!("{}\n", size)).unwrap();
+        }
+        assert_eq!(host_disks(dir.path()).unwrap(), vec![(8, 0), (259, 0)]);
+    }
+
+    #[tokio::test]
+    async fn test_create_sandbox_cgroups() {
         // Currently only support cgroup V1, cgroup V2 is not supported now
         if cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
             return;
@@ -279,7 +483,12 @@ mod tests {
         let pod_sandbox_config = create_mock_pod_sandbox_config();
         sandbox_data.config = Some(pod_sandbox_config);
         let sandbox_cgroup_path = get_sandbox_cgroup_parent_path(&sandbox_data).unwrap();
-        let result = SandboxCgroup::create_sandbox_cgroups(&sandbox_cgroup_path, &sandbox_data.id);
+        let result = SandboxCgroup::create_sandbox_cgroups(
+            CgroupDriver::Cgroupfs,
+            &sandbox_cgroup_path,
+            &sandbox_data.id,
+        )
+        .await;
         match result {
             Ok(sandbox_cgoups) => {
                 // Get the test environment cpu subsystem cgroup mountpoint path
@@ -315,7 +524,12 @@ mod tests {
 
         // Case 3: If sandbox cgroups already exist in the system, then call create_sandbox_cgroups
         //         function again will not fail
-        let result = SandboxCgroup::create_sandbox_cgroups(&sandbox_cgroup_path, &sandbox_data.id);
+        let result = SandboxCgroup::create_sandbox_cgroups(
+            CgroupDriver::Cgroupfs,
+            &sandbox_cgroup_path,
+            &sandbox_data.id,
+        )
+        .await;
         match result {
             Ok(sandbox_cgoups) => {
                 let sandbox_cgroup_mem_controller: &MemController =
@@ -327,14 +541,14 @@ mod tests {
                 );
 
                 // Clean the test sandbox cgroups
-                assert_eq!(sandbox_cgoups.remove_sandbox_cgroups().is_ok(), true);
+                assert_eq!(sandbox_cgoups.remove_sandbox_cgroups().await.is_ok(), true);
             }
             Err(e) => panic!("Expected an Ok, but got error: {}", e.to_string()),
         }
     }
 
-    #[test]
-    fn test_update_res_for_sandbox_cgroups_success() {
+    #[tokio::test]
+    async fn test_update_res_for_sandbox_cgroups_success() {
         // Currently only support cgroup V1, cgroup V2 is not supported now
         if cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
             return;
@@ -348,9 +562,16 @@ mod tests {
 
         // Create a SandboxData instance
         let sandbox_cgroup_path = get_sandbox_cgroup_parent_path(&sandbox_data).unwrap();
-        let sandbox_cgroups =
-            SandboxCgroup::create_sandbox_cgroups(&sandbox_cgroup_path, &sandbox_data.id).unwrap();
-        let result = sandbox_cgroups.update_res_for_sandbox_cgroups(&sandbox_data);
+        let sandbox_cgroups = SandboxCgroup::create_sandbox_cgroups(
+            CgroupDriver::Cgroupfs,
+            &sandbox_cgroup_path,
+            &sandbox_data.id,
+        )
+        .await
+        .unwrap();
+        let result = sandbox_cgroups
+            .update_res_for_sandbox_cgroups(&sandbox_data, &DiskRateLimit::default())
+            .await;
 
         match result {
             Ok(_) => {
@@ -416,6 +637,6 @@ mod tests {
             Err(e) => panic!("Expected an Ok, but got error: {}", e.to_string()),
         }
 
-        assert_eq!(sandbox_cgroups.remove_sandbox_cgroups().is_ok(), true);
+        assert_eq!(sandbox_cgroups.remove_sandbox_cgroups().await.is_ok(), true);
     }
 }
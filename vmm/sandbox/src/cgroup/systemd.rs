@@ -0,0 +1,414 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use containerd_sandbox::cri::api::v1::LinuxContainerResources;
+use lazy_static::lazy_static;
+use log::debug;
+use tokio::sync::OnceCell;
+use zbus::{
+    zvariant::{OwnedObjectPath, OwnedValue, Value},
+    CacheProperties, Connection, Proxy, ProxyBuilder,
+};
+
+const SYSTEMD_DESTINATION: &str = "org.freedesktop.systemd1";
+const SYSTEMD_PATH: &str = "/org/freedesktop/systemd1";
+const SYSTEMD_MANAGER_INTERFACE: &str = "org.freedesktop.systemd1.Manager";
+const SYSTEMD_JOB_INTERFACE: &str = "org.freedesktop.systemd1.Job";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+const ERROR_UNIT_EXISTS: &str = "org.freedesktop.systemd1.UnitExists";
+const ERROR_NO_SUCH_UNIT: &str = "org.freedesktop.systemd1.NoSuchUnit";
+
+const DEFAULT_SLICE: &str = "system.slice";
+const DEFAULT_UNIT_PREFIX: &str = "kuasar";
+const SLICE_SUFFIX: &str = ".slice";
+
+const JOB_TIMEOUT: Duration = Duration::from_secs(10);
+const JOB_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// The period systemd uses when CPUQuotaPeriodUSec is not set
+const DEFAULT_CPU_PERIOD: u64 = 100_000;
+
+lazy_static! {
+    // one connection to the system bus is shared by all the sandboxes
+    static ref SYSTEMD_MANAGER: OnceCell<SystemdManager> = OnceCell::new();
+}
+
+/// Whether the cgroup parent is a systemd slice, either a slice name like
+/// "kubepods-burstable-podxxx.slice", or in the "slice:prefix:name" form.
+pub fn is_systemd_cgroup_parent(cgroup_parent: &str) -> bool {
+    cgroup_parent.ends_with(SLICE_SUFFIX) || cgroup_parent.contains(':')
+}
+
+/// Convert a slice name to its path in the cgroupfs, the dashes in the name denote the parents,
+/// e.g. "kubepods-burstable-podxxx.slice" is
+/// "/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-podxxx.slice".
+pub fn expand_slice(slice: &str) -> Result<String> {
+    let name = slice
+        .strip_suffix(SLICE_SUFFIX)
+        .filter(|n| !n.contains('/'))
+        .ok_or_else(|| anyhow!("invalid slice name {}", slice))?;
+    // "-.slice" is the root slice
+    if name == "-" {
+        return Ok("/".to_string());
+    }
+
+    let mut path = String::new();
+    let mut prefix = String::new();
+    for component in name.split('-') {
+        if component.is_empty() {
+            return Err(anyhow!("invalid slice name {}", slice));
+        }
+        prefix.push_str(component);
+        path.push_str(&format!("/{}{}", prefix, SLICE_SUFFIX));
+        prefix.push('-');
+    }
+    Ok(path)
+}
+
+/// The cgroupfs path of the cgroup parent in the systemd form, for the cgroupfs driver.
+pub fn cgroupfs_parent(cgroup_parent: &str) -> Result<String> {
+    let (slice, _, _) = parse_cgroup_parent(cgroup_parent)?;
+    expand_slice(slice)
+}
+
+// Split the cgroup parent into slice, unit prefix and unit name, the empty ones are defaulted.
+fn parse_cgroup_parent(cgroup_parent: &str) -> Result<(&str, &str, &str)> {
+    let parts = cgroup_parent.split(':').collect::<Vec<_>>();
+    let (slice, prefix, name) = match parts[..] {
+        [slice] => (slice, "", ""),
+        [slice, prefix, name] => (slice, prefix, name),
+        _ => return Err(anyhow!("invalid systemd cgroup parent {}", cgroup_parent)),
+    };
+    let slice = if slice.is_empty() {
+        DEFAULT_SLICE
+    } else {
+        slice
+    };
+    let prefix = if prefix.is_empty() {
+        DEFAULT_UNIT_PREFIX
+    } else {
+        prefix
+    };
+    Ok((slice, prefix, name))
+}
+
+/// The transient units of a sandbox. The sandbox slice is nested in the parent slice and holds
+/// a delegated scope, in which the vmm processes run. The vcpu and pod_overhead cgroups are
+/// created in the scope by cgroupfs, as the subtree of a delegated scope is left to kuasar.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SandboxUnits {
+    pub sandbox_slice: String,
+    pub scope: String,
+}
+
+impl SandboxUnits {
+    pub fn new(cgroup_parent: &str, sandbox_id: &str) -> Result<Self> {
+        let (parent_slice, prefix, name) = parse_cgroup_parent(cgroup_parent)?;
+        // make sure the parent slice is valid
+        expand_slice(parent_slice)?;
+        let name = if name.is_empty() { sandbox_id } else { name };
+
+        // a dash in a slice name means a level of the hierarchy
+        let unit = format!("{}_{}", prefix, name).replace('-', "_");
+        let sandbox_slice = match parent_slice.trim_end_matches(SLICE_SUFFIX) {
+            "-" => format!("{}{}", unit, SLICE_SUFFIX),
+            parent => format!("{}-{}{}", parent, unit, SLICE_SUFFIX),
+        };
+        Ok(Self {
+            sandbox_slice,
+            scope: format!("{}-{}.scope", prefix, name),
+        })
+    }
+
+    /// The cgroupfs path of the scope, it exists after the scope is started.
+    pub fn scope_path(&self) -> Result<String> {
+        Ok(format!(
+            "{}/{}",
+            expand_slice(&self.sandbox_slice)?,
+            self.scope
+        ))
+    }
+
+    /// Start the slice of the sandbox, the slice already started is kept as it is, so that
+    /// it can be called again when the sandbox is recovered.
+    pub async fn start(&self) -> Result<()> {
+        let properties = vec![(
+            "Description",
+            Value::from(format!("kuasar sandbox slice {}", self.sandbox_slice)),
+        )];
+        manager()
+            .await?
+            .start_transient_unit(&self.sandbox_slice, properties)
+            .await
+    }
+
+    /// Start the scope of the sandbox with the process `pid`, all the controllers are delegated
+    /// to the scope so that the subtree of it can be managed by cgroupfs. The scope is kept if it
+    /// was already started by another process of the sandbox.
+    pub async fn start_scope(&self, pid: u32) -> Result<()> {
+        let properties = vec![
+            (
+                "Description",
+                Value::from(format!("kuasar sandbox scope {}", self.scope)),
+            ),
+            ("Slice", Value::from(self.sandbox_slice.as_str())),
+            ("Delegate", Value::from(true)),
+            ("PIDs", Value::from(vec![pid])),
+        ];
+        manager()
+            .await?
+            .start_transient_unit(&self.scope, properties)
+            .await
+    }
+
+    /// Keep systemd in line with the resources written to the cgroupfs, otherwise they are reset
+    /// by systemd when it realizes the cgroups again, e.g. on daemon-reload. The cgroups in the
+    /// delegated scope are not touched by systemd.
+    pub async fn set_resources(&self, total: &LinuxContainerResources) -> Result<()> {
+        let mut properties = cpu_properties(total);
+        properties.extend(memory_properties(total));
+        manager()
+            .await?
+            .set_unit_properties(&self.sandbox_slice, properties)
+            .await
+    }
+
+    /// Stop the sandbox slice, with the scope in it, the cgroups of them are removed by systemd.
+    pub async fn stop(&self) -> Result<()> {
+        manager().await?.stop_unit(&self.sandbox_slice).await
+    }
+}
+
+async fn manager() -> Result<&'static SystemdManager> {
+    SYSTEMD_MANAGER
+        .get_or_try_init(SystemdManager::connect)
+        .await
+}
+
+fn cpu_properties(res: &LinuxContainerResources) -> Vec<(&'static str, Value<'static>)> {
+    let mut properties = vec![];
+    if res.cpu_shares > 0 {
+        properties.push(("CPUShares", Value::from(res.cpu_shares as u64)));
+    }
+    if res.cpu_quota > 0 {
+        let period = if res.cpu_period > 0 {
+            properties.push(("CPUQuotaPeriodUSec", Value::from(res.cpu_period as u64)));
+            res.cpu_period as u64
+        } else {
+            DEFAULT_CPU_PERIOD
+        };
+        // systemd keeps the quota as a percentage of a cpu, round it up to 1% so that
+        // the quota of the cgroup is no less than the one written to the cgroupfs
+        let quota_per_sec = (res.cpu_quota as u64 * 1_000_000 / period).div_ceil(10_000) * 10_000;
+        properties.push(("CPUQuotaPerSecUSec", Value::from(quota_per_sec)));
+    }
+    properties
+}
+
+fn memory_properties(res: &LinuxContainerResources) -> Vec<(&'static str, Value<'static>)> {
+    let mut properties = vec![];
+    if res.memory_limit_in_bytes > 0 {
+        properties.push(("MemoryLimit", Value::from(res.memory_limit_in_bytes as u64)));
+    }
+    properties
+}
+
+fn is_method_error(e: &zbus::Error, name: &str) -> bool {
+    matches!(e, zbus::Error::MethodError(n, _, _) if n.as_str() == name)
+}
+
+struct SystemdManager {
+    conn: Connection,
+    proxy: Proxy<'static>,
+}
+
+impl SystemdManager {
+    async fn connect() -> Result<Self> {
+        let conn = Connection::system()
+            .await
+            .map_err(|e| anyhow!("failed to connect to system bus: {}", e))?;
+        let proxy = Proxy::new(
+            &conn,
+            SYSTEMD_DESTINATION,
+            SYSTEMD_PATH,
+            SYSTEMD_MANAGER_INTERFACE,
+        )
+        .await?;
+        Ok(Self { conn, proxy })
+    }
+
+    async fn start_transient_unit(
+        &self,
+        name: &str,
+        properties: Vec<(&str, Value<'_>)>,
+    ) -> Result<()> {
+        let aux: Vec<(&str, Vec<(&str, Value)>)> = vec![];
+        let res = self
+            .proxy
+            .call::<_, _, OwnedObjectPath>(
+                "StartTransientUnit",
+                &(name, "replace", properties, aux),
+            )
+            .await;
+        match res {
+            Ok(job) => {
+                debug!("start transient unit {}", name);
+                self.wait_job(&job).await
+            }
+            Err(e) if is_method_error(&e, ERROR_UNIT_EXISTS) => {
+                debug!("transient unit {} already exists", name);
+                Ok(())
+            }
+            Err(e) => Err(anyhow!("failed to start transient unit {}: {}", name, e)),
+        }
+    }
+
+    async fn set_unit_properties(
+        &self,
+        name: &str,
+        properties: Vec<(&str, Value<'_>)>,
+    ) -> Result<()> {
+        if properties.is_empty() {
+            return Ok(());
+        }
+        self.proxy
+            .call::<_, _, ()>("SetUnitProperties", &(name, true, properties))
+            .await
+            .map_err(|e| anyhow!("failed to set properties of unit {}: {}", name, e))
+    }
+
+    async fn stop_unit(&self, name: &str) -> Result<()> {
+        match self
+            .proxy
+            .call::<_, _, OwnedObjectPath>("StopUnit", &(name, "replace"))
+            .await
+        {
+            Ok(job) => self.wait_job(&job).await,
+            Err(e) if is_method_error(&e, ERROR_NO_SUCH_UNIT) => Ok(()),
+            Err(e) => Err(anyhow!("failed to stop unit {}: {}", name, e)),
+        }
+    }
+
+    // The job object is removed once the job finished, the unit is started or stopped by then.
+    async fn wait_job(&self, job: &OwnedObjectPath) -> Result<()> {
+        let proxy: Proxy = ProxyBuilder::new_bare(&self.conn)
+            .destination(SYSTEMD_DESTINATION)?
+            .path(job.as_str())?
+            .interface(PROPERTIES_INTERFACE)?
+            .cache_properties(CacheProperties::No)
+            .build()
+            .await?;
+        let start = Instant::now();
+        while proxy
+            .call::<_, _, OwnedValue>("Get", &(SYSTEMD_JOB_INTERFACE, "State"))
+            .await
+            .is_ok()
+        {
+            if start.elapsed() > JOB_TIMEOUT {
+                return Err(anyhow!("timeout waiting for systemd job {}", job.as_str()));
+            }
+            tokio::time::sleep(JOB_POLL_INTERVAL).await;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use containerd_sandbox::cri::api::v1::LinuxContainerResources;
+    use zbus::zvariant::Value;
+
+    use super::{
+        cgroupfs_parent, cpu_properties, expand_slice, is_systemd_cgroup_parent, SandboxUnits,
+    };
+
+    #[test]
+    fn test_expand_slice() {
+        assert_eq!(expand_slice("-.slice").unwrap(), "/");
+        assert_eq!(expand_slice("system.slice").unwrap(), "/system.slice");
+        assert_eq!(
+            expand_slice("kubepods-burstable-podxxx.slice").unwrap(),
+            "/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-podxxx.slice"
+        );
+        assert!(expand_slice("kubepods-burstable").is_err());
+        assert!(expand_slice("kubepods--burstable.slice").is_err());
+        assert!(expand_slice("-kubepods.slice").is_err());
+        assert!(expand_slice("kubepods/burstable.slice").is_err());
+    }
+
+    #[test]
+    fn test_cgroup_parent() {
+        assert!(is_systemd_cgroup_parent("kubepods-besteffort-podxxx.slice"));
+        assert!(is_systemd_cgroup_parent("system.slice:kuasar:sandbox"));
+        assert!(!is_systemd_cgroup_parent("/kubepods/burstable/podxxx"));
+        assert_eq!(
+            cgroupfs_parent("kubepods-besteffort-podxxx.slice:cri-containerd:sandbox").unwrap(),
+            "/kubepods.slice/kubepods-besteffort.slice/kubepods-besteffort-podxxx.slice"
+        );
+        assert_eq!(cgroupfs_parent(":kuasar:sandbox").unwrap(), "/system.slice");
+        assert!(cgroupfs_parent("system.slice:kuasar").is_err());
+    }
+
+    #[test]
+    fn test_sandbox_units() {
+        let units = SandboxUnits::new("kubepods-burstable-podxxx.slice", "abc").unwrap();
+        assert_eq!(
+            units,
+            SandboxUnits {
+                sandbox_slice: "kubepods-burstable-podxxx-kuasar_abc.slice".to_string(),
+                scope: "kuasar-abc.scope".to_string(),
+            }
+        );
+        assert_eq!(
+            units.scope_path().unwrap(),
+            "/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-podxxx.slice/\
+             kubepods-burstable-podxxx-kuasar_abc.slice/kuasar-abc.scope"
+        );
+
+        let units = SandboxUnits::new("-.slice:cri-containerd:pod-1", "abc").unwrap();
+        assert_eq!(units.sandbox_slice, "cri_containerd_pod_1.slice");
+        assert_eq!(units.scope, "cri-containerd-pod-1.scope");
+        assert_eq!(
+            units.scope_path().unwrap(),
+            "/cri_containerd_pod_1.slice/cri-containerd-pod-1.scope"
+        );
+
+        assert!(SandboxUnits::new("kuasar-vmm", "abc").is_err());
+    }
+
+    #[test]
+    fn test_cpu_properties() {
+        let res = LinuxContainerResources {
+            cpu_period: 100000,
+            cpu_quota: 150500,
+            cpu_shares: 1024,
+            ..Default::default()
+        };
+        assert_eq!(
+            cpu_properties(&res),
+            vec![
+                ("CPUShares", Value::from(1024u64)),
+                ("CPUQuotaPeriodUSec", Value::from(100000u64)),
+                ("CPUQuotaPerSecUSec", Value::from(1510000u64)),
+            ]
+        );
+        assert!(cpu_properties(&LinuxContainerResources::default()).is_empty());
+    }
+}
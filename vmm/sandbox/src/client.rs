@@ -48,7 +48,10 @@ use ttrpc::{
     r#async::{Client, TtrpcContext},
 };
 use vmm_common::api::{
-    sandbox::{CheckRequest, SetupSandboxRequest, SyncClockPacket},
+    sandbox::{
+        CheckRequest, Interface, OnlineCPUMemRequest, Route, SetupSandboxRequest, SyncClockPacket,
+        UpdateInterfacesRequest, UpdateRoutesRequest,
+    },
     sandbox_ttrpc::SandboxServiceClient,
 };
 
@@ -253,6 +256,56 @@ pub(crate) async fn client_setup_sandbox(
     Ok(())
 }
 
+pub(crate) async fn client_update_interfaces(
+    client: &SandboxServiceClient,
+    interfaces: Vec<Interface>,
+) -> Result<()> {
+    let mut req = UpdateInterfacesRequest::new();
+    req.interfaces = interfaces;
+    client
+        .update_interfaces(
+            with_timeout(Duration::from_secs(10).as_nanos() as i64),
+            &req,
+        )
+        .await
+        .map_err(|e| anyhow!("failed to update interfaces: {}", e))?;
+    Ok(())
+}
+
+pub(crate) async fn client_update_routes(
+    client: &SandboxServiceClient,
+    routes: Vec<Route>,
+) -> Result<()> {
+    let mut req = UpdateRoutesRequest::new();
+    req.routes = routes;
+    client
+        .update_routes(
+            with_timeout(Duration::from_secs(10).as_nanos() as i64),
+            &req,
+        )
+        .await
+        .map_err(|e| anyhow!("failed to update routes: {}", e))?;
+    Ok(())
+}
+
+pub(crate) async fn client_online_cpu_mem(
+    client: &SandboxServiceClient,
+    nb_cpus: u32,
+    cpu_only: bool,
+) -> Result<()> {
+    let mut req = OnlineCPUMemRequest::new();
+    req.nb_cpus = nb_cpus;
+    req.cpu_only = cpu_only;
+    client
+        .online_cpu_mem(
+            with_timeout(Duration::from_secs(10).as_nanos() as i64),
+            &req,
+        )
+        .await
+        .map_err(|e| anyhow!("failed to online cpu and memory: {}", e))?;
+    Ok(())
+}
+
 pub(crate) fn client_sync_clock(
     client: &SandboxServiceClient,
     id: &str,
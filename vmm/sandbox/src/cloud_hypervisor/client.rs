@@ -15,7 +15,7 @@ limitations under the License.
 */
 
 use std::{
-    os::unix::net::UnixStream,
+    os::unix::{io::AsRawFd, net::UnixStream},
     thread::sleep,
     time::{Duration, SystemTime},
 };
@@ -24,15 +24,49 @@ use anyhow::anyhow;
 use api_client::{simple_api_command, simple_api_full_command_with_fds_and_response};
 use containerd_sandbox::error::Result;
 use log::{debug, error, trace};
+use serde_derive::Serialize;
 use tokio::task::spawn_blocking;
 
 use crate::{
-    cloud_hypervisor::devices::{block::DiskConfig, AddDeviceResponse, RemoveDeviceRequest},
+    cloud_hypervisor::devices::{
+        block::{DiskConfig, RateLimiterConfig},
+        vfio::{DeviceConfig, VfioDevice},
+        virtio_net::NetConfig,
+        AddDeviceResponse, RemoveDeviceRequest,
+    },
     device::DeviceInfo,
 };
 
 pub(crate) const CLOUD_HYPERVISOR_START_TIMEOUT_IN_SEC: u64 = 10;
 
+#[derive(Serialize, Debug)]
+pub struct VmSnapshotConfig {
+    pub destination_url: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RestoreConfig {
+    pub source_url: String,
+    pub prefault: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub net_fds: Vec<RestoredNetConfig>,
+}
+
+/// Net device whose tap fds are passed again when restoring, as the fds in the snapshot are invalid.
+#[derive(Serialize, Debug, Clone)]
+pub struct RestoredNetConfig {
+    pub id: String,
+    pub num_fds: usize,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct VmResize {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desired_vcpus: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub desired_ram: Option<u64>,
+}
+
 pub struct ChClient {
     socket: UnixStream,
 }
@@ -76,35 +110,59 @@ impl ChClient {
                     vhost_user: false,
                     vhost_socket: None,
                     id: blk.id,
+                    rate_limiter_config: RateLimiterConfig::new(&blk.rate_limit),
                 };
                 let request_body = serde_json::to_string(&disk_config)
                     .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", disk_config, e))?;
-                let response_opt = simple_api_full_command_with_fds_and_response(
-                    &mut self.socket,
-                    "PUT",
-                    "vm.add-disk",
-                    Some(&request_body),
-                    vec![],
-                )
-                .map_err(|e| anyhow!("failed to hotplug disk {}, {}", request_body, e))?;
-                if let Some(response_body) = response_opt {
-                    let response = serde_json::from_str::<AddDeviceResponse>(&response_body)
-                        .map_err(|e| {
-                            anyhow!("failed to unmarshal response {}, {}", response_body, e)
-                        })?;
-                    Ok(response.bdf)
-                } else {
-                    Err(anyhow!("no response body from server").into())
-                }
+                self.add_device("vm.add-disk", &request_body, vec![])
             }
-            DeviceInfo::Tap(_) => {
-                todo!()
+            DeviceInfo::Tap(tap) => {
+                let num_queues = if tap.fds.is_empty() {
+                    None
+                } else {
+                    Some((tap.fds.len() * 2) as u32)
+                };
+                let net_config = NetConfig {
+                    // cloud hypervisor takes the fds instead of opening the tap by name if fds are given
+                    tap: if tap.fds.is_empty() {
+                        Some(tap.name)
+                    } else {
+                        None
+                    },
+                    mac: tap.mac_address,
+                    num_queues,
+                    id: tap.id,
+                    ..Default::default()
+                };
+                let request_body = serde_json::to_string(&net_config)
+                    .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", net_config, e))?;
+                // the fds are duplicated by cloud hypervisor when received,
+                // so it is ok to close them after the request returns
+                let fds = tap.fds.iter().map(|fd| fd.as_raw_fd()).collect();
+                self.add_device("vm.add-net", &request_body, fds)
             }
-            DeviceInfo::Physical(_) => {
-                todo!()
+            DeviceInfo::Physical(physical) => {
+                let device_config: DeviceConfig =
+                    VfioDevice::new(&physical.id, &physical.bdf).into();
+                let request_body = serde_json::to_string(&device_config)
+                    .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", device_config, e))?;
+                self.add_device("vm.add-device", &request_body, vec![])
             }
-            DeviceInfo::VhostUser(_) => {
-                todo!()
+            DeviceInfo::VhostUser(vhost_user) => {
+                // vhost-user net devices are added by vm.add-net in cloud hypervisor,
+                // vm.add-user-device is only for vfio-user devices.
+                let net_config = NetConfig {
+                    mac: vhost_user.mac_address,
+                    vhost_user: true,
+                    vhost_socket: Some(vhost_user.socket_path),
+                    // cloud hypervisor deserializes VhostMode by its variant name
+                    vhost_mode: Some("Client".to_string()),
+                    id: vhost_user.id,
+                    ..Default::default()
+                };
+                let request_body = serde_json::to_string(&net_config)
+                    .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", net_config, e))?;
+                self.add_device("vm.add-net", &request_body, vec![])
             }
             DeviceInfo::Char(_) => {
                 unimplemented!()
@@ -112,6 +170,76 @@ impl ChClient {
         }
     }
 
+    fn add_device(&mut self, command: &str, request_body: &str, fds: Vec<i32>) -> Result<String> {
+        let response_opt = simple_api_full_command_with_fds_and_response(
+            &mut self.socket,
+            "PUT",
+            command,
+            Some(request_body),
+            fds,
+        )
+        .map_err(|e| {
+            anyhow!(
+                "failed to hotplug device by {} {}, {}",
+                command,
+                request_body,
+                e
+            )
+        })?;
+        if let Some(response_body) = response_opt {
+            let response = serde_json::from_str::<AddDeviceResponse>(&response_body)
+                .map_err(|e| anyhow!("failed to unmarshal response {}, {}", response_body, e))?;
+            Ok(response.bdf)
+        } else {
+            Err(anyhow!("no response body from server").into())
+        }
+    }
+
+    pub fn pause(&mut self) -> Result<()> {
+        simple_api_command(&mut self.socket, "PUT", "pause", None)
+            .map_err(|e| anyhow!("failed to pause vm, {}", e))?;
+        Ok(())
+    }
+
+    pub fn resume(&mut self) -> Result<()> {
+        simple_api_command(&mut self.socket, "PUT", "resume", None)
+            .map_err(|e| anyhow!("failed to resume vm, {}", e))?;
+        Ok(())
+    }
+
+    pub fn snapshot(&mut self, destination_url: &str) -> Result<()> {
+        let request = VmSnapshotConfig {
+            destination_url: destination_url.to_string(),
+        };
+        let request_body = serde_json::to_string(&request)
+            .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", request, e))?;
+        simple_api_command(&mut self.socket, "PUT", "snapshot", Some(&request_body))
+            .map_err(|e| anyhow!("failed to snapshot vm {}, {}", request_body, e))?;
+        Ok(())
+    }
+
+    pub fn restore(&mut self, request: &RestoreConfig, fds: Vec<i32>) -> Result<()> {
+        let request_body = serde_json::to_string(request)
+            .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", request, e))?;
+        simple_api_full_command_with_fds_and_response(
+            &mut self.socket,
+            "PUT",
+            "vm.restore",
+            Some(&request_body),
+            fds,
+        )
+        .map_err(|e| anyhow!("failed to restore vm {}, {}", request_body, e))?;
+        Ok(())
+    }
+
+    pub fn resize(&mut self, request: &VmResize) -> Result<()> {
+        let request_body = serde_json::to_string(request)
+            .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", request, e))?;
+        simple_api_command(&mut self.socket, "PUT", "resize", Some(&request_body))
+            .map_err(|e| anyhow!("failed to resize vm {}, {}", request_body, e))?;
+        Ok(())
+    }
+
     pub fn hot_detach(&mut self, device_id: &str) -> Result<()> {
         let request = RemoveDeviceRequest {
             id: device_id.to_string(),
@@ -128,3 +256,107 @@ impl ChClient {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        os::unix::net::UnixStream,
+        thread,
+    };
+
+    use crate::{
+        cloud_hypervisor::client::{ChClient, VmResize},
+        device::{DeviceInfo, VhostUserDeviceInfo},
+    };
+
+    #[test]
+    fn test_vm_resize() {
+        let request = VmResize {
+            desired_vcpus: Some(2),
+            desired_ram: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"desired_vcpus":2}"#
+        );
+        let request = VmResize {
+            desired_vcpus: None,
+            desired_ram: Some(2147483648),
+        };
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"desired_ram":2147483648}"#
+        );
+    }
+
+    #[test]
+    fn test_resize_request() {
+        let (socket, mut server) = UnixStream::pair().unwrap();
+        let handle = thread::spawn(move || {
+            // the header and body of the request may be sent separately
+            let mut request = vec![];
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"}") {
+                let n = server.read(&mut buf).unwrap();
+                assert!(n > 0);
+                request.extend_from_slice(&buf[..n]);
+            }
+            server
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        });
+        let mut client = ChClient { socket };
+        client
+            .resize(&VmResize {
+                desired_vcpus: Some(4),
+                desired_ram: None,
+            })
+            .unwrap();
+        let request = handle.join().unwrap();
+        assert!(request.starts_with("PUT /api/v1/vm.resize HTTP/1.1\r\n"));
+        assert!(request.ends_with(r#"{"desired_vcpus":4}"#));
+    }
+
+    #[test]
+    fn test_hot_attach_vhost_user_net() {
+        let (socket, mut server) = UnixStream::pair().unwrap();
+        let handle = thread::spawn(move || {
+            let mut request = vec![];
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"}") {
+                let n = server.read(&mut buf).unwrap();
+                assert!(n > 0);
+                request.extend_from_slice(&buf[..n]);
+            }
+            let body = r#"{"id":"intf-1","bdf":"0000:00:05.0"}"#;
+            server
+                .write_all(
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                    .as_bytes(),
+                )
+                .unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        });
+        let mut client = ChClient { socket };
+        let bdf = client
+            .hot_attach(DeviceInfo::VhostUser(VhostUserDeviceInfo {
+                id: "intf-1".to_string(),
+                socket_path: "/run/vhost-user/net.sock".to_string(),
+                mac_address: "02:00:00:00:00:01".to_string(),
+                r#type: "virtio-net-pci".to_string(),
+            }))
+            .unwrap();
+        assert_eq!(bdf, "0000:00:05.0");
+        let request = handle.join().unwrap();
+        assert!(request.starts_with("PUT /api/v1/vm.add-net HTTP/1.1\r\n"));
+        assert!(request.ends_with(
+            r#"{"mac":"02:00:00:00:00:01","vhost_user":true,"vhost_socket":"/run/vhost-user/net.sock","vhost_mode":"Client","id":"intf-1"}"#
+        ));
+    }
+}
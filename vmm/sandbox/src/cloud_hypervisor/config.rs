@@ -34,6 +34,12 @@ pub struct CloudHypervisorVMConfig {
     pub entropy_source: String,
     pub task: TaskConfig,
     pub virtiofsd: VirtiofsdConfig,
+    /// Max vcpus the vm can be resized to, no vcpus hotplug if it is not larger than vcpus.
+    #[serde(default)]
+    pub max_vcpus: u32,
+    /// Size of the memory that can be hot plugged to the vm, no memory hotplug if it is 0.
+    #[serde(default)]
+    pub hotplug_memory_in_mb: u32,
 }
 
 impl Default for CloudHypervisorVMConfig {
@@ -45,6 +51,8 @@ impl Default for CloudHypervisorVMConfig {
             entropy_source: "/dev/urandom".to_string(),
             task: TaskConfig::default(),
             virtiofsd: VirtiofsdConfig::default(),
+            max_vcpus: 0,
+            hotplug_memory_in_mb: 0,
         }
     }
 }
@@ -135,6 +143,8 @@ pub struct Memory {
     pub(crate) prefault: Option<bool>,
     #[property(generator = "crate::utils::bool_to_on_off")]
     pub(crate) thp: Option<bool>,
+    #[property(key = "hotplug_size")]
+    pub(crate) hotplug_size: Option<u64>,
 }
 
 impl Memory {
@@ -146,18 +156,25 @@ impl Memory {
             hugepage_size: None,
             prefault: None,
             thp: None,
+            hotplug_size: None,
         }
     }
 }
 
 impl CloudHypervisorConfig {
     pub fn from(vm_config: &CloudHypervisorVMConfig) -> Self {
-        let cpus = Cpus::new(vm_config.common.vcpus);
-        let memory = Memory::new(
+        let mut cpus = Cpus::new(vm_config.common.vcpus);
+        if vm_config.max_vcpus > vm_config.common.vcpus {
+            cpus.max = Some(vm_config.max_vcpus);
+        }
+        let mut memory = Memory::new(
             (vm_config.common.memory_in_mb as u64) * 1024 * 1024,
             true,
             vm_config.hugepages,
         );
+        if vm_config.hotplug_memory_in_mb > 0 {
+            memory.hotplug_size = Some((vm_config.hotplug_memory_in_mb as u64) * 1024 * 1024);
+        }
         let mut cmdline = format!(
             "{} {}",
             DEFAULT_KERNEL_PARAMS, vm_config.common.kernel_params
@@ -213,6 +230,7 @@ mod tests {
                 hugepage_size: Some("2M".to_string()),
                 prefault: None,
                 thp: None,
+                hotplug_size: Some(1024 * 1024 * 1024),
             },
             kernel: "/path/to/kernel".to_string(),
             cmdline: "task.sharefs_type=virtiofs".to_string(),
@@ -231,7 +249,7 @@ mod tests {
         assert_eq!(params[4], "--memory");
         assert_eq!(
             params[5],
-            "size=4294967296,shared=off,hugepages=on,hugepage_size=2M"
+            "size=4294967296,shared=off,hugepages=on,hugepage_size=2M,hotplug_size=1073741824"
         );
         assert_eq!(params[6], "--kernel");
         assert_eq!(params[7], "/path/to/kernel");
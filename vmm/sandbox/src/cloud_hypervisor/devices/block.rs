@@ -17,6 +17,11 @@ limitations under the License.
 use sandbox_derive::CmdLineParams;
 use serde_derive::Serialize;
 
+use crate::storage::ratelimit::DiskRateLimit;
+
+// the token buckets are refilled every second, so the sizes of them are the limits per second
+const RATE_LIMIT_REFILL_TIME_MS: u64 = 1000;
+
 #[derive(CmdLineParams, Debug, Clone)]
 pub struct Disk {
     path: String,
@@ -25,10 +30,14 @@ pub struct Disk {
     direct: Option<bool>,
     iommu: Option<bool>,
     num_queues: Option<u32>,
-    bw_refill_time: Option<u32>,
-    ops_size: Option<u32>,
-    ope_one_time_burst: Option<u32>,
-    ops_refill_time: Option<u32>,
+    #[property(key = "bw_size")]
+    bw_size: Option<u64>,
+    #[property(key = "bw_refill_time")]
+    bw_refill_time: Option<u64>,
+    #[property(key = "ops_size")]
+    ops_size: Option<u64>,
+    #[property(key = "ops_refill_time")]
+    ops_refill_time: Option<u64>,
     pci_segment: Option<String>,
 }
 
@@ -43,13 +52,24 @@ impl Disk {
             direct: Some(direct),
             iommu: None,
             num_queues: None,
+            bw_size: None,
             bw_refill_time: None,
             ops_size: None,
-            ope_one_time_burst: None,
             ops_refill_time: None,
             pci_segment: None,
         }
     }
+
+    pub fn set_rate_limit(&mut self, limit: &DiskRateLimit) {
+        if let Some(bandwidth) = limit.bandwidth {
+            self.bw_size = Some(bandwidth);
+            self.bw_refill_time = Some(RATE_LIMIT_REFILL_TIME_MS);
+        }
+        if let Some(iops) = limit.iops {
+            self.ops_size = Some(iops);
+            self.ops_refill_time = Some(RATE_LIMIT_REFILL_TIME_MS);
+        }
+    }
 }
 
 #[derive(Serialize, Debug)]
@@ -60,4 +80,67 @@ pub struct DiskConfig {
     pub vhost_user: bool,
     pub vhost_socket: Option<String>,
     pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limiter_config: Option<RateLimiterConfig>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct RateLimiterConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<TokenBucketConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ops: Option<TokenBucketConfig>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TokenBucketConfig {
+    pub size: u64,
+    pub refill_time: u64,
+}
+
+impl RateLimiterConfig {
+    pub fn new(limit: &DiskRateLimit) -> Option<Self> {
+        if limit.is_empty() {
+            return None;
+        }
+        let bucket = |size: u64| TokenBucketConfig {
+            size,
+            refill_time: RATE_LIMIT_REFILL_TIME_MS,
+        };
+        Some(Self {
+            bandwidth: limit.bandwidth.map(bucket),
+            ops: limit.iops.map(bucket),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cloud_hypervisor::devices::block::{Disk, RateLimiterConfig},
+        param::ToParams,
+        storage::ratelimit::DiskRateLimit,
+    };
+
+    #[test]
+    fn test_disk_rate_limit() {
+        let limit = DiskRateLimit {
+            bandwidth: Some(10485760),
+            iops: None,
+        };
+        let mut disk = Disk::new("blk0", "/dev/sdb", false, true);
+        disk.set_rate_limit(&limit);
+        let params = disk.to_params();
+        let property = params.get(0).unwrap();
+        assert_eq!(property.get("bw_size").unwrap(), "10485760");
+        assert_eq!(property.get("bw_refill_time").unwrap(), "1000");
+        assert!(property.get("ops_size").is_none());
+
+        assert!(RateLimiterConfig::new(&DiskRateLimit::default()).is_none());
+        let config = RateLimiterConfig::new(&limit).unwrap();
+        assert_eq!(
+            serde_json::to_string(&config).unwrap(),
+            r#"{"bandwidth":{"size":10485760,"refill_time":1000}}"#
+        );
+    }
 }
@@ -15,6 +15,7 @@ limitations under the License.
 */
 
 use sandbox_derive::CmdLineParams;
+use serde_derive::Serialize;
 
 const VFIO_DEVICE_SYSFS_PATH: &str = "/sys/bus/pci/devices";
 
@@ -37,6 +38,22 @@ impl VfioDevice {
     }
 }
 
+/// Body of the `vm.add-device` request
+#[derive(Serialize, Debug)]
+pub struct DeviceConfig {
+    pub path: String,
+    pub id: String,
+}
+
+impl From<VfioDevice> for DeviceConfig {
+    fn from(d: VfioDevice) -> Self {
+        Self {
+            path: d.path,
+            id: d.id,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{cloud_hypervisor::devices::vfio::VfioDevice, param::ToParams};
@@ -17,6 +17,7 @@ limitations under the License.
 use std::os::unix::io::RawFd;
 
 use sandbox_derive::CmdLineParams;
+use serde_derive::Serialize;
 
 #[derive(CmdLineParams, Debug, Clone)]
 #[params("net")]
@@ -53,6 +54,22 @@ impl VirtioNetDevice {
     }
 }
 
+/// Body of the `vm.add-net` request, tap fds are passed alongside it over SCM_RIGHTS
+#[derive(Serialize, Debug, Default)]
+pub struct NetConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tap: Option<String>,
+    pub mac: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_queues: Option<u32>,
+    pub vhost_user: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vhost_socket: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vhost_mode: Option<String>,
+    pub id: String,
+}
+
 pub fn vec_to_string<T: ToString>(v: &[T]) -> String {
     format!(
         "[{}]",
@@ -39,6 +39,14 @@ impl VMFactory for CloudHypervisorVMFactory {
         Self { vm_config: config }
     }
 
+    fn hypervisor(&self) -> &'static str {
+        "cloud-hypervisor"
+    }
+
+    fn pool_supported(&self) -> bool {
+        true
+    }
+
     async fn create_vm(
         &self,
         id: &str,
@@ -49,7 +49,9 @@ async fn process_config(sandbox: &mut KuasarSandbox<CloudHypervisorVM>) -> Resul
             // get ceil of cpus if it is not integer
             let base = (resources.cpu_quota as f64 / resources.cpu_period as f64).ceil();
             sandbox.vm.config.cpus.boot = base as u32;
-            sandbox.vm.config.cpus.max = Some(base as u32);
+            // keep the configured max vcpus so that vcpus can be hot plugged later
+            let max = sandbox.vm.config.cpus.max.unwrap_or_default();
+            sandbox.vm.config.cpus.max = Some(max.max(base as u32));
         }
         if resources.memory_limit_in_bytes > 0 {
             sandbox.vm.config.memory.size = resources.memory_limit_in_bytes as u64;
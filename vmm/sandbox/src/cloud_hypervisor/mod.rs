@@ -14,35 +14,37 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{os::fd::OwnedFd, process::Stdio, time::Duration};
+use std::{
+    os::fd::{AsRawFd, OwnedFd},
+    process::Stdio,
+    time::Duration,
+};
 
 use anyhow::anyhow;
 use async_trait::async_trait;
 use containerd_sandbox::error::{Error, Result};
-use log::{debug, error, info, warn};
-use nix::{errno::Errno::ESRCH, sys::signal, unistd::Pid};
+use log::{debug, info, warn};
+use nix::sys::signal;
 use serde::{Deserialize, Serialize};
-use time::OffsetDateTime;
 use tokio::{
     fs::create_dir_all,
-    process::Child,
-    sync::watch::{channel, Receiver, Sender},
-    task::JoinHandle,
+    sync::watch::{channel, Receiver},
 };
 use tracing::instrument;
 use vmm_common::SHARED_DIR_SUFFIX;
 
 use crate::{
     cloud_hypervisor::{
-        client::ChClient,
+        client::{ChClient, RestoreConfig, RestoredNetConfig, VmResize},
         config::{CloudHypervisorConfig, CloudHypervisorVMConfig, VirtiofsdConfig},
         devices::{
             block::Disk, vfio::VfioDevice, virtio_net::VirtioNetDevice, CloudHypervisorDevice,
         },
     },
     device::{BusType, DeviceInfo},
+    network::net_queues,
     param::ToCmdLineParams,
-    utils::{read_std, set_cmd_fd, set_cmd_netns, wait_channel, wait_pid, write_file_atomic},
+    utils::{set_cmd_fd, set_cmd_netns, spawn_wait, wait_channel},
     vm::{Pids, VcpuThreads, VM},
 };
 
@@ -70,7 +72,11 @@ pub struct CloudHypervisorVM {
     client: Option<ChClient>,
     #[serde(skip)]
     fds: Vec<OwnedFd>,
+    #[serde(skip)]
+    net_fds: Vec<RestoredNetConfig>,
     pids: Pids,
+    #[serde(default)]
+    max_net_queues: u32,
 }
 
 impl CloudHypervisorVM {
@@ -95,7 +101,9 @@ impl CloudHypervisorVM {
             wait_chan: None,
             client: None,
             fds: vec![],
+            net_fds: vec![],
             pids: Pids::default(),
+            max_net_queues: vm_config.common.max_net_queues,
         }
     }
 
@@ -145,41 +153,13 @@ impl CloudHypervisorVM {
         self.fds.len() - 1 + 3
     }
 
-    async fn wait_stop(&mut self, t: Duration) -> Result<()> {
-        if let Some(rx) = self.wait_channel().await {
-            let (_, ts) = *rx.borrow();
-            if ts == 0 {
-                wait_channel(t, rx).await?;
-            }
-        }
-        Ok(())
-    }
-}
-
-#[async_trait]
-impl VM for CloudHypervisorVM {
-    #[instrument(skip_all)]
-    async fn start(&mut self) -> Result<u32> {
-        create_dir_all(&self.base_dir).await?;
-        let virtiofsd_pid = self.start_virtiofsd().await?;
-        // TODO: add child virtiofsd process
-        self.pids.affiliated_pids.push(virtiofsd_pid);
-        let mut params = self.config.to_cmdline_params("--");
-        for d in self.devices.iter() {
-            params.extend(d.to_cmdline_params("--"));
-        }
-
-        // the log level is single hyphen parameter, has to handle separately
-        if self.config.debug {
-            params.push("-vv".to_string());
-        }
-
+    async fn launch(&mut self, params: Vec<String>, fds: Vec<OwnedFd>) -> Result<u32> {
         // Drop cmd immediately to let the fds in pre_exec be closed.
         let child = {
             let mut cmd = tokio::process::Command::new(&self.config.path);
             cmd.args(params.as_slice());
 
-            set_cmd_fd(&mut cmd, self.fds.drain(..).collect())?;
+            set_cmd_fd(&mut cmd, fds)?;
             set_cmd_netns(&mut cmd, self.netns.to_string())?;
             cmd.stdout(Stdio::piped());
             cmd.stderr(Stdio::piped());
@@ -193,7 +173,10 @@ impl VM for CloudHypervisorVM {
             self.id,
             pid.unwrap_or_default()
         );
-        self.pids.vmm_pid = pid;
+        match pid {
+            Some(pid) => self.pids.set_vmm_pid(pid),
+            None => self.pids.vmm_pid = None,
+        }
         let pid_file = format!("{}/pid", self.base_dir);
         let (tx, rx) = channel((0u32, 0i128));
         self.wait_chan = Some(rx);
@@ -217,6 +200,39 @@ impl VM for CloudHypervisorVM {
         Ok(pid.unwrap_or_default())
     }
 
+    async fn wait_stop(&mut self, t: Duration) -> Result<()> {
+        if let Some(rx) = self.wait_channel().await {
+            let (_, ts) = *rx.borrow();
+            if ts == 0 {
+                wait_channel(t, rx).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl VM for CloudHypervisorVM {
+    #[instrument(skip_all)]
+    async fn start(&mut self) -> Result<u32> {
+        create_dir_all(&self.base_dir).await?;
+        let virtiofsd_pid = self.start_virtiofsd().await?;
+        // TODO: add child virtiofsd process
+        self.pids.add_affiliated_pid(virtiofsd_pid);
+        let mut params = self.config.to_cmdline_params("--");
+        for d in self.devices.iter() {
+            params.extend(d.to_cmdline_params("--"));
+        }
+
+        // the log level is single hyphen parameter, has to handle separately
+        if self.config.debug {
+            params.push("-vv".to_string());
+        }
+
+        let fds = self.fds.drain(..).collect();
+        self.launch(params, fds).await
+    }
+
     #[instrument(skip_all)]
     async fn stop(&mut self, force: bool) -> Result<()> {
         let signal = if force {
@@ -227,22 +243,14 @@ impl VM for CloudHypervisorVM {
 
         let pids = self.pids();
         if let Some(vmm_pid) = pids.vmm_pid {
-            if vmm_pid > 0 {
-                // TODO: Consider pid reused
-                match signal::kill(Pid::from_raw(vmm_pid as i32), signal) {
-                    Err(e) => {
-                        if e != ESRCH {
-                            return Err(anyhow!("kill vmm process {}: {}", vmm_pid, e).into());
-                        }
-                    }
-                    Ok(_) => self.wait_stop(Duration::from_secs(10)).await?,
-                }
+            if vmm_pid > 0 && pids.kill(vmm_pid, signal)? {
+                self.wait_stop(Duration::from_secs(10)).await?;
             }
         }
-        for affiliated_pid in pids.affiliated_pids {
-            if affiliated_pid > 0 {
+        for affiliated_pid in pids.affiliated_pids.iter() {
+            if *affiliated_pid > 0 {
                 // affiliated process may exits automatically, so it's ok not handle error
-                signal::kill(Pid::from_raw(affiliated_pid as i32), signal).unwrap_or_default();
+                pids.kill(*affiliated_pid, signal).unwrap_or_default();
             }
         }
 
@@ -253,10 +261,17 @@ impl VM for CloudHypervisorVM {
     async fn attach(&mut self, device_info: DeviceInfo) -> Result<()> {
         match device_info {
             DeviceInfo::Block(blk_info) => {
-                let device = Disk::new(&blk_info.id, &blk_info.path, blk_info.read_only, true);
+                let mut device = Disk::new(&blk_info.id, &blk_info.path, blk_info.read_only, true);
+                device.set_rate_limit(&blk_info.rate_limit);
                 self.add_device(device);
             }
             DeviceInfo::Tap(tap_info) => {
+                if !tap_info.fds.is_empty() {
+                    self.net_fds.push(RestoredNetConfig {
+                        id: tap_info.id.to_string(),
+                        num_fds: tap_info.fds.len(),
+                    });
+                }
                 let mut fd_ints = vec![];
                 for fd in tap_info.fds {
                     let index = self.append_fd(fd);
@@ -340,75 +355,136 @@ impl VM for CloudHypervisorVM {
     fn pids(&self) -> Pids {
         self.pids.clone()
     }
+
+    #[instrument(skip_all)]
+    async fn pause(&mut self) -> Result<()> {
+        self.get_client()?.pause()
+    }
+
+    #[instrument(skip_all)]
+    async fn resume(&mut self) -> Result<()> {
+        self.get_client()?.resume()
+    }
+
+    #[instrument(skip_all)]
+    async fn snapshot(&mut self, path: &str) -> Result<()> {
+        create_dir_all(path).await?;
+        let destination_url = format!("file://{}", path);
+        let client = self.get_client()?;
+        client.pause()?;
+        let res = client.snapshot(&destination_url);
+        // resume the vm even if snapshot failed
+        client.resume()?;
+        res
+    }
+
+    #[instrument(skip_all)]
+    async fn restore(&mut self, path: &str) -> Result<u32> {
+        create_dir_all(&self.base_dir).await?;
+        let virtiofsd_pid = self.start_virtiofsd().await?;
+        self.pids.add_affiliated_pid(virtiofsd_pid);
+        // devices are restored from the config in snapshot, only the api socket is needed
+        let mut params = vec![
+            "--api-socket".to_string(),
+            self.config.api_socket.to_string(),
+        ];
+        if self.config.debug {
+            params.push("-vv".to_string());
+        }
+        let pid = self.launch(params, vec![]).await?;
+
+        // the fds are duplicated by cloud hypervisor, they can be closed after the request
+        let fds: Vec<OwnedFd> = self.fds.drain(..).collect();
+        let request = RestoreConfig {
+            source_url: format!("file://{}", path),
+            prefault: false,
+            net_fds: self.net_fds.clone(),
+        };
+        let client = self.get_client()?;
+        let res = client
+            .restore(&request, fds.iter().map(|fd| fd.as_raw_fd()).collect())
+            .and_then(|_| client.resume());
+        if let Err(e) = res {
+            if let Err(re) = self.stop(true).await {
+                warn!("roll back in restore cloud hypervisor: {}", re);
+            }
+            return Err(e);
+        }
+        Ok(pid)
+    }
+
+    #[instrument(skip_all)]
+    async fn resize_vcpus(&mut self, vcpus: u32) -> Result<()> {
+        let max = self.config.cpus.max.unwrap_or(self.config.cpus.boot);
+        if vcpus > max {
+            return Err(Error::ResourceExhausted(format!(
+                "vcpus of cloud hypervisor {}, {} vcpus at most",
+                self.id, max
+            )));
+        }
+        let desired_vcpus = u8::try_from(vcpus).map_err(|_| {
+            Error::InvalidArgument(format!(
+                "vcpus of cloud hypervisor {}, {} is out of range",
+                self.id, vcpus
+            ))
+        })?;
+        self.get_client()?.resize(&VmResize {
+            desired_vcpus: Some(desired_vcpus),
+            desired_ram: None,
+        })?;
+        self.config.cpus.boot = vcpus;
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn resize_memory(&mut self, size: u64) -> Result<()> {
+        // memory hot plugged by acpi can not be unplugged,
+        // the memory usage is limited by the cgroups in guest instead.
+        if size <= self.config.memory.size {
+            return Ok(());
+        }
+        let hotplug_size = self.config.memory.hotplug_size.unwrap_or_default();
+        if size > self.config.memory.size + hotplug_size {
+            return Err(Error::ResourceExhausted(format!(
+                "memory of cloud hypervisor {}, {} bytes can be hot plugged at most",
+                self.id, hotplug_size
+            )));
+        }
+        self.get_client()?.resize(&VmResize {
+            desired_vcpus: None,
+            desired_ram: Some(size),
+        })?;
+        // the hotplug area is shrunk by the hot plugged size
+        self.config.memory.hotplug_size = Some(self.config.memory.size + hotplug_size - size);
+        self.config.memory.size = size;
+        Ok(())
+    }
+
+    fn net_queues(&self) -> u32 {
+        net_queues(self.config.cpus.boot, self.max_net_queues)
+    }
+
+    fn shared_fs_supported(&self) -> bool {
+        true
+    }
 }
 
 #[async_trait]
 impl crate::vm::Recoverable for CloudHypervisorVM {
     #[instrument(skip_all)]
     async fn recover(&mut self) -> Result<()> {
-        self.client = Some(self.create_client().await?);
         let pid = self.pid()?;
+        let process = self
+            .pids
+            .open(pid)
+            .ok_or_else(|| anyhow!("vmm process {} is not running any more", pid))?;
+        self.client = Some(self.create_client().await?);
         let (tx, rx) = channel((0u32, 0i128));
         tokio::spawn(async move {
-            let wait_result = wait_pid(pid as i32).await;
+            let wait_result = process.wait().await;
             tx.send(wait_result).unwrap_or_default();
         });
         self.wait_chan = Some(rx);
         Ok(())
     }
 }
-
-macro_rules! read_stdio {
-    ($stdio:expr, $cmd_name:ident) => {
-        if let Some(std) = $stdio {
-            let cmd_name_clone = $cmd_name.clone();
-            tokio::spawn(async move {
-                read_std(std, &cmd_name_clone).await.unwrap_or_default();
-            });
-        }
-    };
-}
-
-fn spawn_wait(
-    child: Child,
-    cmd_name: String,
-    pid_file_path: Option<String>,
-    exit_chan: Option<Sender<(u32, i128)>>,
-) -> JoinHandle<()> {
-    let mut child = child;
-    tokio::spawn(async move {
-        if let Some(pid_file) = pid_file_path {
-            if let Some(pid) = child.id() {
-                write_file_atomic(&pid_file, &pid.to_string())
-                    .await
-                    .unwrap_or_default();
-            }
-        }
-
-        read_stdio!(child.stdout.take(), cmd_name);
-        read_stdio!(child.stderr.take(), cmd_name);
-
-        match child.wait().await {
-            Ok(status) => {
-                if !status.success() {
-                    error!("{} exit {}", cmd_name, status);
-                }
-                let now = OffsetDateTime::now_utc();
-                if let Some(tx) = exit_chan {
-                    tx.send((
-                        status.code().unwrap_or_default() as u32,
-                        now.unix_timestamp_nanos(),
-                    ))
-                    .unwrap_or_default();
-                }
-            }
-            Err(e) => {
-                error!("{} wait error {}", cmd_name, e);
-                let now = OffsetDateTime::now_utc();
-                if let Some(tx) = exit_chan {
-                    tx.send((0, now.unix_timestamp_nanos())).unwrap_or_default();
-                }
-            }
-        }
-    })
-}
@@ -59,7 +59,7 @@ pub mod tests {
     use serde_derive::Deserialize;
     use temp_dir::TempDir;
 
-    use crate::{config::Config, sandbox::SandboxConfig};
+    use crate::{cgroup::CgroupDriver, config::Config, sandbox::SandboxConfig};
 
     #[derive(Deserialize)]
     struct MockHypervisor {
@@ -97,6 +97,8 @@ pub mod tests {
 [sandbox]
 log_level = \"debug\"
 enable_tracing = false
+cgroup_driver = \"systemd\"
+disk_rate_limit = { bandwidth = 104857600, iops = 1000 }
 [hypervisor]
 path = \"/usr/local/bin/mock-hypervisor\"
 ";
@@ -110,6 +112,9 @@ path = \"/usr/local/bin/mock-hypervisor\"
 
         assert_eq!(config.sandbox.log_level, "debug");
         assert_eq!(config.sandbox.enable_tracing, false);
+        assert_eq!(config.sandbox.cgroup_driver, CgroupDriver::Systemd);
+        assert_eq!(config.sandbox.disk_rate_limit.bandwidth, Some(104857600));
+        assert_eq!(config.sandbox.disk_rate_limit.iops, Some(1000));
         assert_eq!(config.hypervisor.path, "/usr/local/bin/mock-hypervisor");
     }
 
@@ -72,7 +72,7 @@ where
         let container = sandbox.container(&self.container_id).await?;
         let bundle = container.data.bundle.to_string();
         for device_id in container.io_devices.clone() {
-            sandbox.vm.hot_detach(&device_id).await?;
+            sandbox.hot_detach(&device_id).await?;
         }
         let io_file_path = format!("{}/{}-{}", bundle, IO_FILE_PREFIX, self.container_id);
         tokio::fs::remove_file(&io_file_path)
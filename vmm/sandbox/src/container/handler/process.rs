@@ -161,7 +161,7 @@ async fn remove_io_devices_for_process<T: VM + Sync + Send>(
         }
     }
     for device_id in io_devices {
-        sandbox.vm.hot_detach(&device_id).await.unwrap_or_default();
+        sandbox.hot_detach(&device_id).await.unwrap_or_default();
     }
     let io_file_path = format!(
         "{}/{}-{}-{}",
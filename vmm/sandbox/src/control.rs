@@ -0,0 +1,223 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{fs::Permissions, os::unix::fs::PermissionsExt, path::Path, sync::Arc};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use containerd_sandbox::error::Result;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::UnixListener,
+};
+
+/// Request on the control socket, each request is a json object in a line, such as
+/// `{"checkpoint":{"id":"<sandbox id>","path":"/path/to/dir","exit":false}}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ControlRequest {
+    Checkpoint {
+        id: String,
+        path: String,
+        #[serde(default)]
+        exit: bool,
+    },
+    Restore {
+        path: String,
+    },
+}
+
+/// Response of a control request in a line, the error is empty if the request succeeds.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ControlResponse {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub id: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub error: String,
+}
+
+/// Operations on the sandboxes which are not in the sandbox api of containerd.
+#[async_trait]
+pub trait Controller {
+    async fn checkpoint(&self, id: &str, path: &str, exit: bool) -> Result<()>;
+    async fn restore(&self, path: &str) -> Result<String>;
+}
+
+/// Serve the control requests on the unix socket `path`, which is only accessible by root.
+pub async fn serve(path: &str, controller: Arc<dyn Controller + Sync + Send>) -> Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    // remove the socket left by the last sandboxer process
+    if let Err(e) = tokio::fs::remove_file(path).await {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            return Err(e.into());
+        }
+    }
+    let listener =
+        UnixListener::bind(path).map_err(|e| anyhow!("failed to listen on {}: {}", path, e))?;
+    tokio::fs::set_permissions(path, Permissions::from_mode(0o600)).await?;
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_connection(stream, controller.clone()));
+                }
+                Err(e) => warn!("failed to accept control connection: {}", e),
+            }
+        }
+    });
+    info!("control requests are served on {}", path);
+    Ok(())
+}
+
+async fn handle_connection<S>(stream: S, controller: Arc<dyn Controller + Sync + Send>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut stream = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match stream.read_line(&mut line).await {
+            Ok(0) => return,
+            Ok(_) => {}
+            Err(e) => {
+                debug!("failed to read control request: {}", e);
+                return;
+            }
+        }
+        let response = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => handle_request(request, controller.as_ref()).await,
+            Err(e) => ControlResponse {
+                error: format!("invalid request: {}", e),
+                ..Default::default()
+            },
+        };
+        let mut buf = serde_json::to_vec(&response).unwrap_or_default();
+        buf.push(b'\n');
+        if let Err(e) = stream.get_mut().write_all(&buf).await {
+            debug!("failed to write control response: {}", e);
+            return;
+        }
+    }
+}
+
+async fn handle_request(
+    request: ControlRequest,
+    controller: &(dyn Controller + Sync + Send),
+) -> ControlResponse {
+    info!("handle control request {:?}", request);
+    let res = match request {
+        ControlRequest::Checkpoint { id, path, exit } => {
+            controller.checkpoint(&id, &path, exit).await.map(|_| id)
+        }
+        ControlRequest::Restore { path } => controller.restore(&path).await,
+    };
+    match res {
+        Ok(id) => ControlResponse {
+            id,
+            ..Default::default()
+        },
+        Err(e) => ControlResponse {
+            error: e.to_string(),
+            ..Default::default()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use containerd_sandbox::error::{Error, Result};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream};
+
+    use crate::control::{handle_connection, ControlRequest, ControlResponse, Controller};
+
+    struct MockController;
+
+    #[async_trait]
+    impl Controller for MockController {
+        async fn checkpoint(&self, id: &str, _path: &str, _exit: bool) -> Result<()> {
+            if id == "unknown" {
+                return Err(Error::NotFound(id.to_string()));
+            }
+            Ok(())
+        }
+
+        async fn restore(&self, _path: &str) -> Result<String> {
+            Ok("restored".to_string())
+        }
+    }
+
+    #[test]
+    fn test_control_request() {
+        let request: ControlRequest =
+            serde_json::from_str(r#"{"checkpoint":{"id":"sb","path":"/tmp/cp"}}"#).unwrap();
+        assert_eq!(
+            request,
+            ControlRequest::Checkpoint {
+                id: "sb".to_string(),
+                path: "/tmp/cp".to_string(),
+                exit: false,
+            }
+        );
+        let request = ControlRequest::Restore {
+            path: "/tmp/cp".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"restore":{"path":"/tmp/cp"}}"#
+        );
+    }
+
+    async fn roundtrip(client: &mut BufReader<DuplexStream>, request: &str) -> ControlResponse {
+        client
+            .get_mut()
+            .write_all(request.as_bytes())
+            .await
+            .unwrap();
+        let mut line = String::new();
+        client.read_line(&mut line).await.unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_handle_connection() {
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(handle_connection(server, Arc::new(MockController)));
+        let mut client = BufReader::new(client);
+
+        let response = roundtrip(&mut client, "{\"restore\":{\"path\":\"/tmp/cp\"}}\n").await;
+        assert_eq!(response.id, "restored");
+        assert!(response.error.is_empty());
+
+        let response = roundtrip(
+            &mut client,
+            "{\"checkpoint\":{\"id\":\"unknown\",\"path\":\"/tmp/cp\"}}\n",
+        )
+        .await;
+        assert!(response.id.is_empty());
+        assert!(response.error.contains("unknown"));
+
+        let response = roundtrip(&mut client, "{\"stop\":{}}\n").await;
+        assert!(response.error.starts_with("invalid request"));
+    }
+}
@@ -18,6 +18,8 @@ use std::os::fd::OwnedFd;
 
 use containerd_sandbox::error::{Error, Result};
 
+use crate::storage::ratelimit::DiskRateLimit;
+
 macro_rules! impl_device_no_bus {
     ($ty:ty) => {
         impl crate::device::Device for $ty {
@@ -190,6 +192,7 @@ pub struct BlockDeviceInfo {
     pub id: String,
     pub path: String,
     pub read_only: bool,
+    pub rate_limit: DiskRateLimit,
 }
 
 #[derive(Debug)]
@@ -0,0 +1,239 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    os::unix::net::UnixStream,
+    thread::sleep,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::anyhow;
+use containerd_sandbox::error::Result;
+use log::{debug, error, trace};
+use serde::Serialize;
+use tokio::task::spawn_blocking;
+
+use crate::firecracker::config::{
+    BootSource, Drive, InstanceActionInfo, MachineConfig, NetworkInterface, PartialDrive,
+    SnapshotCreateParams, SnapshotLoadParams, VmState, Vsock,
+};
+
+pub(crate) const FIRECRACKER_START_TIMEOUT_IN_SEC: u64 = 10;
+// requests are sent in the async methods of the vm, a hung api server should not block forever,
+// the timeout is long enough for the snapshot of a vm with large memory
+const FIRECRACKER_API_TIMEOUT_IN_SEC: u64 = 60;
+
+/// A minimal HTTP/1.1 client of the firecracker REST API, which is served on a unix socket.
+pub struct FcClient {
+    socket: UnixStream,
+}
+
+impl FcClient {
+    pub async fn new(socket_path: String) -> Result<Self> {
+        let s = socket_path.to_string();
+        let start_time = SystemTime::now();
+        let socket = spawn_blocking(move || -> Result<UnixStream> {
+            loop {
+                match UnixStream::connect(&socket_path) {
+                    Ok(socket) => {
+                        return Ok(socket);
+                    }
+                    Err(e) => {
+                        trace!("failed to create client: {:?}", e);
+                        if start_time.elapsed().unwrap().as_secs()
+                            > FIRECRACKER_START_TIMEOUT_IN_SEC
+                        {
+                            error!("failed to create client: {:?}", e);
+                            return Err(anyhow!("timeout connect client, {}", e).into());
+                        }
+                        sleep(Duration::from_millis(10));
+                    }
+                }
+            }
+        })
+        .await
+        .map_err(|e| anyhow!("failed to join thread {}", e))??;
+        let timeout = Some(Duration::from_secs(FIRECRACKER_API_TIMEOUT_IN_SEC));
+        socket
+            .set_read_timeout(timeout)
+            .and_then(|_| socket.set_write_timeout(timeout))
+            .map_err(|e| anyhow!("failed to set timeout of api socket {}: {}", s, e))?;
+        debug!("connected to api server {}", s);
+        Ok(Self { socket })
+    }
+
+    pub fn put_boot_source(&mut self, boot_source: &BootSource) -> Result<()> {
+        self.request("PUT", "/boot-source", Some(boot_source))
+    }
+
+    pub fn put_machine_config(&mut self, machine_config: &MachineConfig) -> Result<()> {
+        self.request("PUT", "/machine-config", Some(machine_config))
+    }
+
+    pub fn put_drive(&mut self, drive: &Drive) -> Result<()> {
+        self.request("PUT", &format!("/drives/{}", drive.drive_id), Some(drive))
+    }
+
+    pub fn patch_drive(&mut self, drive: &PartialDrive) -> Result<()> {
+        self.request("PATCH", &format!("/drives/{}", drive.drive_id), Some(drive))
+    }
+
+    pub fn put_network_interface(&mut self, intf: &NetworkInterface) -> Result<()> {
+        self.request(
+            "PUT",
+            &format!("/network-interfaces/{}", intf.iface_id),
+            Some(intf),
+        )
+    }
+
+    pub fn put_vsock(&mut self, vsock: &Vsock) -> Result<()> {
+        self.request("PUT", "/vsock", Some(vsock))
+    }
+
+    pub fn instance_start(&mut self) -> Result<()> {
+        self.request(
+            "PUT",
+            "/actions",
+            Some(&InstanceActionInfo::instance_start()),
+        )
+    }
+
+    pub fn patch_vm(&mut self, state: &VmState) -> Result<()> {
+        self.request("PATCH", "/vm", Some(state))
+    }
+
+    pub fn create_snapshot(&mut self, params: &SnapshotCreateParams) -> Result<()> {
+        self.request("PUT", "/snapshot/create", Some(params))
+    }
+
+    pub fn load_snapshot(&mut self, params: &SnapshotLoadParams) -> Result<()> {
+        self.request("PUT", "/snapshot/load", Some(params))
+    }
+
+    /// Get the information of the instance, to check whether the api server is alive.
+    pub fn describe_instance(&self) -> Result<()> {
+        self.request::<()>("GET", "/", None)
+    }
+
+    fn request<T: Serialize + std::fmt::Debug>(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&T>,
+    ) -> Result<()> {
+        let body = match body {
+            None => "".to_string(),
+            Some(b) => serde_json::to_string(b)
+                .map_err(|e| anyhow!("failed to marshal {:?} to json, {}", b, e))?,
+        };
+        let request = format_request(method, path, &body);
+        debug!("firecracker api request: {} {} {}", method, path, body);
+        (&self.socket)
+            .write_all(request.as_bytes())
+            .map_err(|e| anyhow!("failed to send request {} {}: {}", method, path, e))?;
+        let (status, response) = read_response(&self.socket)
+            .map_err(|e| anyhow!("failed to read response of {} {}: {}", method, path, e))?;
+        if !(200..300).contains(&status) {
+            return Err(anyhow!(
+                "firecracker api {} {} failed with status {}: {}",
+                method,
+                path,
+                status,
+                response
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+fn format_request(method: &str, path: &str, body: &str) -> String {
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nAccept: application/json\r\n",
+        method, path
+    );
+    if !body.is_empty() {
+        request.push_str("Content-Type: application/json\r\n");
+    }
+    request.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+    request
+}
+
+fn read_response<R: Read>(reader: R) -> std::io::Result<(u16, String)> {
+    let mut reader = BufReader::new(reader);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid status line {:?}", status_line),
+            )
+        })?;
+
+    let mut content_length = 0usize;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((k, v)) = line.split_once(':') {
+            if k.trim().eq_ignore_ascii_case("content-length") {
+                content_length = v.trim().parse().unwrap_or_default();
+            }
+        }
+    }
+
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body)?;
+    Ok((status, String::from_utf8_lossy(&body).to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::firecracker::client::{format_request, read_response};
+
+    #[test]
+    fn test_format_request() {
+        let request = format_request("PUT", "/actions", "{\"action_type\":\"InstanceStart\"}");
+        assert_eq!(
+            request,
+            "PUT /actions HTTP/1.1\r\nHost: localhost\r\nAccept: application/json\r\n\
+Content-Type: application/json\r\nContent-Length: 31\r\n\r\n{\"action_type\":\"InstanceStart\"}"
+        );
+    }
+
+    #[test]
+    fn test_read_response() {
+        let response = "HTTP/1.1 204 \r\nServer: Firecracker API\r\nConnection: keep-alive\r\n\r\n";
+        let (status, body) = read_response(response.as_bytes()).unwrap();
+        assert_eq!(status, 204);
+        assert!(body.is_empty());
+
+        let response = "HTTP/1.1 400 \r\nContent-Type: application/json\r\nContent-Length: 29\r\n\r\n{\"fault_message\":\"bad drive\"}";
+        let (status, body) = read_response(response.as_bytes()).unwrap();
+        assert_eq!(status, 400);
+        assert_eq!(body, "{\"fault_message\":\"bad drive\"}");
+    }
+}
@@ -0,0 +1,315 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use serde::{Deserialize, Serialize};
+
+use crate::{storage::ratelimit::DiskRateLimit, vm::HypervisorCommonConfig};
+
+// Firecracker has neither virtio-fs nor virtio-9p, so the guest should not try to mount a share fs.
+const DEFAULT_KERNEL_PARAMS: &str = "console=ttyS0 \
+reboot=k panic=1 pci=off \
+root=/dev/vda \
+rootflags=data=ordered,errors=remount-ro \
+ro rootfstype=ext4 \
+task.sharefs_type=none";
+
+const DEFAULT_BLOCK_DEVICE_SLOTS: u32 = 8;
+// the token buckets are refilled every second, so the sizes of them are the limits per second
+const RATE_LIMIT_REFILL_TIME_MS: u64 = 1000;
+
+#[derive(Deserialize)]
+pub struct FirecrackerVMConfig {
+    pub path: String,
+    #[serde(flatten)]
+    pub common: HypervisorCommonConfig,
+    #[serde(default = "default_block_device_slots")]
+    pub block_device_slots: u32,
+    pub task: TaskConfig,
+}
+
+impl Default for FirecrackerVMConfig {
+    fn default() -> Self {
+        Self {
+            path: "/usr/local/bin/firecracker".to_string(),
+            common: HypervisorCommonConfig::default(),
+            block_device_slots: DEFAULT_BLOCK_DEVICE_SLOTS,
+            task: TaskConfig::default(),
+        }
+    }
+}
+
+fn default_block_device_slots() -> u32 {
+    DEFAULT_BLOCK_DEVICE_SLOTS
+}
+
+#[derive(Deserialize, Default)]
+pub struct TaskConfig {
+    pub debug: bool,
+    pub enable_tracing: bool,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct FirecrackerConfig {
+    pub path: String,
+    pub api_socket: String,
+    pub boot_source: BootSource,
+    pub machine_config: MachineConfig,
+    pub debug: bool,
+}
+
+impl FirecrackerConfig {
+    pub fn from(vm_config: &FirecrackerVMConfig) -> Self {
+        let mut boot_args = format!(
+            "{} {}",
+            DEFAULT_KERNEL_PARAMS, vm_config.common.kernel_params
+        );
+
+        if vm_config.task.debug {
+            boot_args.push_str(" task.log_level=debug");
+        }
+
+        boot_args.push_str(&format!(
+            " task.enable_tracing={}",
+            vm_config.task.enable_tracing
+        ));
+
+        let initrd_path = if vm_config.common.initrd_path.is_empty() {
+            None
+        } else {
+            Some(vm_config.common.initrd_path.to_string())
+        };
+
+        Self {
+            path: vm_config.path.to_string(),
+            api_socket: "".to_string(),
+            boot_source: BootSource {
+                kernel_image_path: vm_config.common.kernel_path.to_string(),
+                boot_args,
+                initrd_path,
+            },
+            machine_config: MachineConfig {
+                vcpu_count: vm_config.common.vcpus,
+                mem_size_mib: vm_config.common.memory_in_mb as u64,
+                smt: false,
+            },
+            debug: vm_config.common.debug,
+        }
+    }
+}
+
+/// Body of `PUT /boot-source`
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct BootSource {
+    pub kernel_image_path: String,
+    pub boot_args: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initrd_path: Option<String>,
+}
+
+/// Body of `PUT /machine-config`
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct MachineConfig {
+    pub vcpu_count: u32,
+    pub mem_size_mib: u64,
+    pub smt: bool,
+}
+
+/// Body of `PUT /drives/{drive_id}`
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct Drive {
+    pub drive_id: String,
+    pub path_on_host: String,
+    pub is_root_device: bool,
+    pub is_read_only: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limiter: Option<RateLimiter>,
+}
+
+/// Body of `PATCH /drives/{drive_id}`, used to swap the backing file of a placeholder drive
+#[derive(Clone, Debug, Serialize)]
+pub struct PartialDrive {
+    pub drive_id: String,
+    pub path_on_host: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limiter: Option<RateLimiter>,
+}
+
+/// Rate limiter of a drive, a token bucket of size 0 means no limit
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct RateLimiter {
+    pub bandwidth: TokenBucket,
+    pub ops: TokenBucket,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct TokenBucket {
+    pub size: u64,
+    pub refill_time: u64,
+}
+
+impl From<&DiskRateLimit> for RateLimiter {
+    fn from(limit: &DiskRateLimit) -> Self {
+        let bucket = |size: Option<u64>| match size {
+            Some(size) => TokenBucket {
+                size,
+                refill_time: RATE_LIMIT_REFILL_TIME_MS,
+            },
+            None => TokenBucket::default(),
+        };
+        Self {
+            bandwidth: bucket(limit.bandwidth),
+            ops: bucket(limit.iops),
+        }
+    }
+}
+
+/// Body of `PUT /network-interfaces/{iface_id}`
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct NetworkInterface {
+    pub iface_id: String,
+    pub host_dev_name: String,
+    pub guest_mac: String,
+}
+
+/// Body of `PUT /vsock`
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct Vsock {
+    pub guest_cid: u32,
+    pub uds_path: String,
+}
+
+/// Body of `PUT /actions`
+#[derive(Clone, Debug, Serialize)]
+pub struct InstanceActionInfo {
+    pub action_type: String,
+}
+
+impl InstanceActionInfo {
+    pub fn instance_start() -> Self {
+        Self {
+            action_type: "InstanceStart".to_string(),
+        }
+    }
+}
+
+/// Body of `PATCH /vm`
+#[derive(Clone, Debug, Serialize)]
+pub struct VmState {
+    pub state: String,
+}
+
+impl VmState {
+    pub fn paused() -> Self {
+        Self {
+            state: "Paused".to_string(),
+        }
+    }
+
+    pub fn resumed() -> Self {
+        Self {
+            state: "Resumed".to_string(),
+        }
+    }
+}
+
+/// Body of `PUT /snapshot/create`
+#[derive(Clone, Debug, Serialize)]
+pub struct SnapshotCreateParams {
+    pub snapshot_type: String,
+    pub snapshot_path: String,
+    pub mem_file_path: String,
+}
+
+/// Body of `PUT /snapshot/load`
+#[derive(Clone, Debug, Serialize)]
+pub struct SnapshotLoadParams {
+    pub snapshot_path: String,
+    pub mem_backend: MemoryBackend,
+    pub resume_vm: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MemoryBackend {
+    pub backend_type: String,
+    pub backend_path: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::Config,
+        firecracker::config::{FirecrackerConfig, FirecrackerVMConfig, RateLimiter},
+        storage::ratelimit::DiskRateLimit,
+    };
+
+    const TOML_STR: &str = "
+[sandbox]
+enable_tracing = false
+[hypervisor]
+path = \"/usr/local/bin/firecracker\"
+vcpus = 1
+memory_in_mb = 1024
+kernel_path = \"/var/lib/kuasar/vmlinux.bin\"
+image_path = \"/var/lib/kuasar/kuasar.img\"
+initrd_path = \"\"
+kernel_params = \"\"
+[hypervisor.task]
+debug = true
+enable_tracing = false
+";
+
+    #[test]
+    fn test_toml() {
+        let config: Config<FirecrackerVMConfig> = toml::from_str(TOML_STR).unwrap();
+        assert_eq!(config.hypervisor.path, "/usr/local/bin/firecracker");
+        assert_eq!(
+            config.hypervisor.common.image_path,
+            "/var/lib/kuasar/kuasar.img"
+        );
+        assert_eq!(config.hypervisor.block_device_slots, 8);
+        assert!(config.hypervisor.task.debug);
+    }
+
+    #[test]
+    fn test_boot_source() {
+        let config: Config<FirecrackerVMConfig> = toml::from_str(TOML_STR).unwrap();
+        let fcc = FirecrackerConfig::from(&config.hypervisor);
+
+        assert_eq!(
+            fcc.boot_source.kernel_image_path,
+            "/var/lib/kuasar/vmlinux.bin"
+        );
+        assert!(fcc.boot_source.initrd_path.is_none());
+        assert_eq!(fcc.boot_source.boot_args, "console=ttyS0 reboot=k panic=1 pci=off root=/dev/vda rootflags=data=ordered,errors=remount-ro ro rootfstype=ext4 task.sharefs_type=none  task.log_level=debug task.enable_tracing=false");
+        assert_eq!(fcc.machine_config.vcpu_count, 1);
+        assert_eq!(fcc.machine_config.mem_size_mib, 1024);
+
+        let body = serde_json::to_string(&fcc.boot_source).unwrap();
+        assert!(!body.contains("initrd_path"));
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::from(&DiskRateLimit {
+            bandwidth: None,
+            iops: Some(1000),
+        });
+        assert_eq!(
+            serde_json::to_string(&limiter).unwrap(),
+            r#"{"bandwidth":{"size":0,"refill_time":0},"ops":{"size":1000,"refill_time":1000}}"#
+        );
+    }
+}
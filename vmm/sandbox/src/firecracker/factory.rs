@@ -0,0 +1,79 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use containerd_sandbox::SandboxOption;
+
+use crate::{
+    firecracker::{
+        config::{Drive, FirecrackerVMConfig, Vsock},
+        FirecrackerVM,
+    },
+    utils::get_netns,
+    vm::VMFactory,
+};
+
+pub struct FirecrackerVMFactory {
+    vm_config: FirecrackerVMConfig,
+}
+
+#[async_trait::async_trait]
+impl VMFactory for FirecrackerVMFactory {
+    type VM = FirecrackerVM;
+    type Config = FirecrackerVMConfig;
+
+    fn new(config: Self::Config) -> Self {
+        Self { vm_config: config }
+    }
+
+    fn hypervisor(&self) -> &'static str {
+        "firecracker"
+    }
+
+    fn pool_supported(&self) -> bool {
+        // network interfaces can only be configured before the firecracker vm boots
+        false
+    }
+
+    async fn create_vm(
+        &self,
+        id: &str,
+        s: &SandboxOption,
+    ) -> containerd_sandbox::error::Result<Self::VM> {
+        let netns = get_netns(&s.sandbox);
+        let mut vm = FirecrackerVM::new(id, &netns, &s.base_dir, &self.vm_config);
+        // add image as the root device
+        if !self.vm_config.common.image_path.is_empty() {
+            vm.add_drive(Drive {
+                drive_id: "rootfs".to_string(),
+                path_on_host: self.vm_config.common.image_path.to_string(),
+                is_root_device: true,
+                is_read_only: true,
+                rate_limiter: None,
+            });
+        }
+
+        // add vsock device, firecracker exposes the guest vsock as a hybrid vsock
+        // on the host, which is the same as cloud hypervisor does.
+        let guest_socket_path = format!("{}/task.vsock", s.base_dir);
+        vm.vsock = Some(Vsock {
+            guest_cid: 3,
+            uds_path: guest_socket_path.to_string(),
+        });
+        vm.agent_socket = format!("hvsock://{}:1024", guest_socket_path);
+
+        Ok(vm)
+    }
+}
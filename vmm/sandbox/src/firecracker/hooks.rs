@@ -0,0 +1,52 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use containerd_sandbox::error::Result;
+
+use crate::{firecracker::FirecrackerVM, sandbox::KuasarSandbox, utils::get_resources, vm::Hooks};
+
+#[derive(Default)]
+pub struct FirecrackerHooks {}
+
+#[async_trait::async_trait]
+impl Hooks<FirecrackerVM> for FirecrackerHooks {
+    async fn pre_start(&self, sandbox: &mut KuasarSandbox<FirecrackerVM>) -> Result<()> {
+        process_config(sandbox).await?;
+        Ok(())
+    }
+
+    async fn post_start(&self, sandbox: &mut KuasarSandbox<FirecrackerVM>) -> Result<()> {
+        sandbox.data.task_address = format!("ttrpc+{}", sandbox.vm.agent_socket);
+        // sync clock
+        sandbox.sync_clock().await;
+        Ok(())
+    }
+}
+
+async fn process_config(sandbox: &mut KuasarSandbox<FirecrackerVM>) -> Result<()> {
+    if let Some(resources) = get_resources(&sandbox.data) {
+        if resources.cpu_period > 0 && resources.cpu_quota > 0 {
+            // get ceil of cpus if it is not integer
+            let base = (resources.cpu_quota as f64 / resources.cpu_period as f64).ceil();
+            sandbox.vm.config.machine_config.vcpu_count = base as u32;
+        }
+        if resources.memory_limit_in_bytes > 0 {
+            sandbox.vm.config.machine_config.mem_size_mib =
+                resources.memory_limit_in_bytes as u64 / bytefmt::MIB;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,566 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{process::Stdio, time::Duration};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use containerd_sandbox::error::{Error, Result};
+use log::{info, warn};
+use nix::sys::signal;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{create_dir_all, remove_file, File},
+    sync::watch::{channel, Receiver},
+};
+use tracing::instrument;
+
+use crate::{
+    device::{BusType, DeviceInfo},
+    firecracker::{
+        client::FcClient,
+        config::{
+            Drive, FirecrackerConfig, FirecrackerVMConfig, MemoryBackend, NetworkInterface,
+            PartialDrive, RateLimiter, SnapshotCreateParams, SnapshotLoadParams, VmState, Vsock,
+        },
+    },
+    impl_recoverable,
+    utils::{read_file, set_cmd_netns, spawn_wait, wait_channel, write_file_atomic},
+    vm::{Pids, VcpuThreads, VM},
+};
+
+mod client;
+pub mod config;
+pub mod factory;
+pub mod hooks;
+
+// Firecracker names its vcpu threads as "fc_vcpu <index>"
+const VCPU_PREFIX: &str = "fc_vcpu ";
+const PLACEHOLDER_DRIVE_PREFIX: &str = "drive_";
+const SNAPSHOT_STATE_FILE: &str = "vmstate";
+const SNAPSHOT_MEMORY_FILE: &str = "memory";
+const SNAPSHOT_DRIVE_SLOTS_FILE: &str = "drive_slots.json";
+
+/// A block device slot of firecracker.
+///
+/// Firecracker can not hot plug devices into a running VM, so a fixed number of drives
+/// backed by empty placeholder files are configured before boot, and "hot attach" of a
+/// block device swaps the backing file of a free slot with `PATCH /drives/{drive_id}`.
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct DriveSlot {
+    drive: Drive,
+    placeholder: String,
+    // id of the device attached to this slot, empty if the slot is free
+    device_id: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct FirecrackerVM {
+    id: String,
+    config: FirecrackerConfig,
+    netns: String,
+    base_dir: String,
+    agent_socket: String,
+    drives: Vec<Drive>,
+    drive_slots: Vec<DriveSlot>,
+    network_interfaces: Vec<NetworkInterface>,
+    vsock: Option<Vsock>,
+    #[serde(skip)]
+    wait_chan: Option<Receiver<(u32, i128)>>,
+    #[serde(skip)]
+    client: Option<FcClient>,
+    pids: Pids,
+}
+
+impl FirecrackerVM {
+    pub fn new(id: &str, netns: &str, base_dir: &str, vm_config: &FirecrackerVMConfig) -> Self {
+        let mut config = FirecrackerConfig::from(vm_config);
+        config.api_socket = format!("{}/fc-api.sock", base_dir);
+        let drive_slots = (0..vm_config.block_device_slots)
+            .map(|i| {
+                let drive_id = format!("{}{}", PLACEHOLDER_DRIVE_PREFIX, i);
+                let placeholder = format!("{}/{}", base_dir, drive_id);
+                DriveSlot {
+                    drive: Drive {
+                        drive_id,
+                        path_on_host: placeholder.to_string(),
+                        is_root_device: false,
+                        is_read_only: false,
+                        rate_limiter: None,
+                    },
+                    placeholder,
+                    device_id: "".to_string(),
+                }
+            })
+            .collect();
+        Self {
+            id: id.to_string(),
+            config,
+            netns: netns.to_string(),
+            base_dir: base_dir.to_string(),
+            agent_socket: "".to_string(),
+            drives: vec![],
+            drive_slots,
+            network_interfaces: vec![],
+            vsock: None,
+            wait_chan: None,
+            client: None,
+            pids: Pids::default(),
+        }
+    }
+
+    pub fn add_drive(&mut self, drive: Drive) {
+        // firecracker always puts the root device in the first place
+        if drive.is_root_device {
+            self.drives.insert(0, drive);
+        } else {
+            self.drives.push(drive);
+        }
+    }
+
+    fn pid(&self) -> Result<u32> {
+        match self.pids.vmm_pid {
+            None => Err(anyhow!("empty pid from vmm_pid").into()),
+            Some(pid) => Ok(pid),
+        }
+    }
+
+    async fn create_client(&self) -> Result<FcClient> {
+        FcClient::new(self.config.api_socket.to_string()).await
+    }
+
+    fn get_client(&mut self) -> Result<&mut FcClient> {
+        self.client
+            .as_mut()
+            .ok_or(Error::NotFound("firecracker client not inited".to_string()))
+    }
+
+    async fn wait_stop(&mut self, t: Duration) -> Result<()> {
+        if let Some(rx) = self.wait_channel().await {
+            let (_, ts) = *rx.borrow();
+            if ts == 0 {
+                wait_channel(t, rx).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn create_placeholders(&self) -> Result<()> {
+        for slot in self.drive_slots.iter() {
+            File::create(&slot.placeholder).await.map_err(|e| {
+                anyhow!(
+                    "failed to create placeholder drive {}: {}",
+                    slot.placeholder,
+                    e
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    // Guest names the virtio-mmio block devices by the order they are configured,
+    // and the drive slots are always configured after the other drives.
+    fn guest_device_path(&self, slot_index: usize) -> String {
+        format!(
+            "/dev/vd{}",
+            disk_name_suffix(self.drives.len() + slot_index)
+        )
+    }
+
+    // Spawn the firecracker process and connect to its api server, nothing is configured yet.
+    async fn launch(&mut self) -> Result<u32> {
+        create_dir_all(&self.base_dir).await?;
+        self.create_placeholders().await?;
+        // firecracker refuses to start if the api socket already exists
+        remove_file(&self.config.api_socket)
+            .await
+            .unwrap_or_default();
+
+        let mut params = vec![
+            "--api-sock".to_string(),
+            self.config.api_socket.to_string(),
+            "--id".to_string(),
+            self.id.to_string(),
+        ];
+        if self.config.debug {
+            params.push("--level".to_string());
+            params.push("Debug".to_string());
+        }
+
+        let child = {
+            let mut cmd = tokio::process::Command::new(&self.config.path);
+            cmd.args(params.as_slice());
+            set_cmd_netns(&mut cmd, self.netns.to_string())?;
+            cmd.stdin(Stdio::null());
+            cmd.stdout(Stdio::piped());
+            cmd.stderr(Stdio::piped());
+            info!("start firecracker with cmdline: {:?}", cmd);
+            cmd.spawn()
+                .map_err(|e| anyhow!("failed to spawn firecracker command: {}", e))?
+        };
+        let pid = child.id();
+        info!(
+            "firecracker for {} is running with pid {}",
+            self.id,
+            pid.unwrap_or_default()
+        );
+        match pid {
+            Some(pid) => self.pids.set_vmm_pid(pid),
+            None => self.pids.vmm_pid = None,
+        }
+        let pid_file = format!("{}/pid", self.base_dir);
+        let (tx, rx) = channel((0u32, 0i128));
+        self.wait_chan = Some(rx);
+        spawn_wait(
+            child,
+            format!("firecracker {}", self.id),
+            Some(pid_file),
+            Some(tx),
+        );
+
+        match self.create_client().await {
+            Ok(client) => self.client = Some(client),
+            Err(e) => {
+                if let Err(re) = self.stop(true).await {
+                    warn!("roll back in create firecracker api client: {}", re);
+                    return Err(e);
+                }
+                return Err(e);
+            }
+        };
+        Ok(pid.unwrap_or_default())
+    }
+
+    // Configure all the devices and boot the vm through the api server.
+    fn boot(&mut self) -> Result<()> {
+        let machine_config = self.config.machine_config.clone();
+        let boot_source = self.config.boot_source.clone();
+        let mut drives = self.drives.clone();
+        drives.extend(self.drive_slots.iter().map(|s| s.drive.clone()));
+        let network_interfaces = self.network_interfaces.clone();
+        let vsock = self.vsock.clone();
+
+        let client = self.get_client()?;
+        client.put_machine_config(&machine_config)?;
+        client.put_boot_source(&boot_source)?;
+        for d in drives.iter() {
+            client.put_drive(d)?;
+        }
+        for intf in network_interfaces.iter() {
+            client.put_network_interface(intf)?;
+        }
+        if let Some(v) = vsock {
+            client.put_vsock(&v)?;
+        }
+        client.instance_start()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl VM for FirecrackerVM {
+    #[instrument(skip_all)]
+    async fn start(&mut self) -> Result<u32> {
+        let pid = self.launch().await?;
+        if let Err(e) = self.boot() {
+            if let Err(re) = self.stop(true).await {
+                warn!("roll back in boot firecracker: {}", re);
+                return Err(e);
+            }
+            return Err(e);
+        }
+        Ok(pid)
+    }
+
+    #[instrument(skip_all)]
+    async fn stop(&mut self, force: bool) -> Result<()> {
+        let signal = if force {
+            signal::SIGKILL
+        } else {
+            signal::SIGTERM
+        };
+
+        if let Some(vmm_pid) = self.pids.vmm_pid {
+            if vmm_pid > 0 && self.pids.kill(vmm_pid, signal)? {
+                self.wait_stop(Duration::from_secs(10)).await?;
+            }
+        }
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn attach(&mut self, device_info: DeviceInfo) -> Result<()> {
+        match device_info {
+            DeviceInfo::Block(blk_info) => {
+                self.add_drive(Drive {
+                    drive_id: blk_info.id.to_string(),
+                    path_on_host: blk_info.path.to_string(),
+                    is_root_device: false,
+                    is_read_only: blk_info.read_only,
+                    rate_limiter: if blk_info.rate_limit.is_empty() {
+                        None
+                    } else {
+                        Some(RateLimiter::from(&blk_info.rate_limit))
+                    },
+                });
+            }
+            DeviceInfo::Tap(tap_info) => {
+                // firecracker opens the tap device by its name,
+                // the fds are dropped here and the persistent tap device is kept.
+                self.network_interfaces.push(NetworkInterface {
+                    iface_id: tap_info.id.to_string(),
+                    host_dev_name: tap_info.name.to_string(),
+                    guest_mac: tap_info.mac_address.to_string(),
+                });
+            }
+            DeviceInfo::Physical(_) => {
+                return Err(Error::Unimplemented(
+                    "firecracker does not support vfio device".to_string(),
+                ));
+            }
+            DeviceInfo::VhostUser(_) => {
+                return Err(Error::Unimplemented(
+                    "firecracker does not support vhost_user device".to_string(),
+                ));
+            }
+            DeviceInfo::Char(_) => {
+                return Err(Error::Unimplemented(
+                    "firecracker does not support char device".to_string(),
+                ));
+            }
+        };
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn hot_attach(&mut self, device_info: DeviceInfo) -> Result<(BusType, String)> {
+        match device_info {
+            DeviceInfo::Block(blk_info) => {
+                let index = self
+                    .drive_slots
+                    .iter()
+                    .position(|s| s.device_id.is_empty())
+                    .ok_or_else(|| {
+                        Error::ResourceExhausted("firecracker drive slot".to_string())
+                    })?;
+                // firecracker can not change the read only property after boot,
+                // so the read only is left to the mount options in the guest.
+                // the rate limiter is always patched to clear the limits of the previous drive.
+                let drive = PartialDrive {
+                    drive_id: self.drive_slots[index].drive.drive_id.to_string(),
+                    path_on_host: blk_info.path.to_string(),
+                    rate_limiter: Some(RateLimiter::from(&blk_info.rate_limit)),
+                };
+                self.get_client()?.patch_drive(&drive)?;
+                let slot = &mut self.drive_slots[index];
+                slot.device_id = blk_info.id.to_string();
+                slot.drive.path_on_host = blk_info.path.to_string();
+                slot.drive.rate_limiter = drive.rate_limiter;
+                Ok((BusType::MMIO, self.guest_device_path(index)))
+            }
+            // network interfaces can only be configured before the vm boots
+            DeviceInfo::Tap(_) => Err(Error::Unimplemented(
+                "firecracker does not support hot attach for tap device".to_string(),
+            )),
+            DeviceInfo::Physical(_) => Err(Error::Unimplemented(
+                "firecracker does not support vfio device".to_string(),
+            )),
+            DeviceInfo::VhostUser(_) => Err(Error::Unimplemented(
+                "firecracker does not support vhost_user device".to_string(),
+            )),
+            DeviceInfo::Char(_) => Err(Error::Unimplemented(
+                "firecracker does not support char device".to_string(),
+            )),
+        }
+    }
+
+    #[instrument(skip_all)]
+    async fn hot_detach(&mut self, id: &str) -> Result<()> {
+        let index = match self.drive_slots.iter().position(|s| s.device_id == id) {
+            None => return Ok(()),
+            Some(index) => index,
+        };
+        let drive = PartialDrive {
+            drive_id: self.drive_slots[index].drive.drive_id.to_string(),
+            path_on_host: self.drive_slots[index].placeholder.to_string(),
+            rate_limiter: Some(RateLimiter::default()),
+        };
+        self.get_client()?.patch_drive(&drive)?;
+        let slot = &mut self.drive_slots[index];
+        slot.device_id = "".to_string();
+        slot.drive.path_on_host = slot.placeholder.to_string();
+        slot.drive.rate_limiter = None;
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn ping(&self) -> Result<()> {
+        self.client
+            .as_ref()
+            .ok_or(Error::NotFound("firecracker client not inited".to_string()))?
+            .describe_instance()
+    }
+
+    #[instrument(skip_all)]
+    fn socket_address(&self) -> String {
+        self.agent_socket.to_string()
+    }
+
+    #[instrument(skip_all)]
+    async fn wait_channel(&self) -> Option<Receiver<(u32, i128)>> {
+        self.wait_chan.clone()
+    }
+
+    #[instrument(skip_all)]
+    async fn vcpus(&self) -> Result<VcpuThreads> {
+        Ok(VcpuThreads {
+            vcpus: procfs::process::Process::new(self.pid()? as i32)
+                .map_err(|e| anyhow!("failed to get process {}", e))?
+                .tasks()
+                .map_err(|e| anyhow!("failed to get tasks {}", e))?
+                .flatten()
+                .filter_map(|t| {
+                    t.stat()
+                        .map_err(|e| anyhow!("failed to get stat {}", e))
+                        .ok()?
+                        .comm
+                        .strip_prefix(VCPU_PREFIX)
+                        .and_then(|comm| comm.parse().ok())
+                        .map(|index| (index, t.tid as i64))
+                })
+                .collect(),
+        })
+    }
+
+    #[instrument(skip_all)]
+    fn pids(&self) -> Pids {
+        self.pids.clone()
+    }
+
+    #[instrument(skip_all)]
+    async fn pause(&mut self) -> Result<()> {
+        self.get_client()?.patch_vm(&VmState::paused())
+    }
+
+    #[instrument(skip_all)]
+    async fn resume(&mut self) -> Result<()> {
+        self.get_client()?.patch_vm(&VmState::resumed())
+    }
+
+    #[instrument(skip_all)]
+    async fn snapshot(&mut self, path: &str) -> Result<()> {
+        create_dir_all(path).await?;
+        let params = SnapshotCreateParams {
+            snapshot_type: "Full".to_string(),
+            snapshot_path: format!("{}/{}", path, SNAPSHOT_STATE_FILE),
+            mem_file_path: format!("{}/{}", path, SNAPSHOT_MEMORY_FILE),
+        };
+        let client = self.get_client()?;
+        client.patch_vm(&VmState::paused())?;
+        let res = client.create_snapshot(&params);
+        // resume the vm even if snapshot failed
+        client.patch_vm(&VmState::resumed())?;
+        res?;
+
+        // the slots are assigned on the host side, save them for the restored vm
+        let slots = serde_json::to_string(&self.drive_slots)
+            .map_err(|e| anyhow!("failed to marshal drive slots, {}", e))?;
+        write_file_atomic(format!("{}/{}", path, SNAPSHOT_DRIVE_SLOTS_FILE), &slots).await?;
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn restore(&mut self, path: &str) -> Result<u32> {
+        let slots = read_file(format!("{}/{}", path, SNAPSHOT_DRIVE_SLOTS_FILE)).await?;
+        self.drive_slots = serde_json::from_str(&slots)
+            .map_err(|e| anyhow!("failed to unmarshal drive slots {}, {}", slots, e))?;
+
+        let pid = self.launch().await?;
+        // devices are restored from the snapshot, taps are opened again by their names
+        let params = SnapshotLoadParams {
+            snapshot_path: format!("{}/{}", path, SNAPSHOT_STATE_FILE),
+            mem_backend: MemoryBackend {
+                backend_type: "File".to_string(),
+                backend_path: format!("{}/{}", path, SNAPSHOT_MEMORY_FILE),
+            },
+            resume_vm: true,
+        };
+        if let Err(e) = self.get_client().and_then(|c| c.load_snapshot(&params)) {
+            if let Err(re) = self.stop(true).await {
+                warn!("roll back in restore firecracker: {}", re);
+                return Err(e);
+            }
+            return Err(e);
+        }
+        Ok(pid)
+    }
+
+    #[instrument(skip_all)]
+    async fn resize_vcpus(&mut self, _vcpus: u32) -> Result<()> {
+        Err(Error::Unimplemented(
+            "vcpus hotplug of firecracker".to_string(),
+        ))
+    }
+
+    #[instrument(skip_all)]
+    async fn resize_memory(&mut self, _size: u64) -> Result<()> {
+        Err(Error::Unimplemented(
+            "memory hotplug of firecracker".to_string(),
+        ))
+    }
+
+    fn net_queues(&self) -> u32 {
+        // the virtio-net device of firecracker has only one queue pair
+        1
+    }
+
+    fn shared_fs_supported(&self) -> bool {
+        // firecracker has neither virtio-fs nor virtio-9p
+        false
+    }
+}
+
+impl_recoverable!(FirecrackerVM);
+
+// disk_name_suffix returns the suffix of the virtio block device name in guest,
+// 0 -> "a", 25 -> "z", 26 -> "aa"
+fn disk_name_suffix(mut index: usize) -> String {
+    let mut suffix = vec![];
+    loop {
+        suffix.push((b'a' + (index % 26) as u8) as char);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    suffix.iter().rev().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::firecracker::disk_name_suffix;
+
+    #[test]
+    fn test_disk_name_suffix() {
+        assert_eq!(disk_name_suffix(0), "a");
+        assert_eq!(disk_name_suffix(1), "b");
+        assert_eq!(disk_name_suffix(25), "z");
+        assert_eq!(disk_name_suffix(26), "aa");
+        assert_eq!(disk_name_suffix(27), "ab");
+        assert_eq!(disk_name_suffix(701), "zz");
+        assert_eq!(disk_name_suffix(702), "aaa");
+    }
+}
@@ -36,7 +36,7 @@ where
             name: chardev_id.to_string(),
             backend: CharBackendType::Pipe(path.to_string()),
         };
-        self.vm.hot_attach(DeviceInfo::Char(char_dev)).await?;
+        self.hot_attach(DeviceInfo::Char(char_dev)).await?;
         Ok((device_id, chardev_id))
     }
 }
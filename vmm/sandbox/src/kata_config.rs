@@ -209,7 +209,10 @@ impl KataConfig {
             .hypervisor
             .get(h)
             .ok_or_else(|| Error::NotFound(format!("no hypervisor config of {} in kata", h)))?;
-        Ok(SandboxConfig::default())
+        Ok(SandboxConfig {
+            enable_cpu_memory_hotplug: config.runtime.enable_cpu_memory_hotplug,
+            ..Default::default()
+        })
     }
 }
 
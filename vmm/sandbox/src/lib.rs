@@ -21,15 +21,19 @@ mod device;
 mod cgroup;
 mod client;
 mod container;
+mod control;
 mod io;
+mod metrics;
 mod network;
 mod param;
+mod pool;
 mod storage;
 mod vm;
 
 pub mod args;
 pub mod cloud_hypervisor;
 pub mod config;
+pub mod firecracker;
 pub mod kata_config;
 pub mod qemu;
 pub mod sandbox;
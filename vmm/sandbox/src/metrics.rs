@@ -0,0 +1,378 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use containerd_sandbox::{error::Result, SandboxStatus};
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
+use prometheus::{
+    register_counter_vec, register_gauge, register_gauge_vec, register_histogram_vec,
+    register_int_gauge_vec, CounterVec, Encoder, Gauge, GaugeVec, HistogramVec, IntGaugeVec,
+    TextEncoder,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, UnixListener},
+    sync::{Mutex, RwLock},
+    time::timeout,
+};
+
+use crate::{sandbox::KuasarSandbox, vm::VM};
+
+const UNIX_ADDRESS_PREFIX: &str = "unix://";
+const METRICS_PATH: &str = "/metrics";
+const MAX_REQUEST_SIZE: usize = 8192;
+const REQUEST_READ_TIMEOUT_IN_SEC: u64 = 5;
+
+pub(crate) const PHASE_CREATE_VM: &str = "create_vm";
+pub(crate) const PHASE_VM_START: &str = "vm_start";
+pub(crate) const PHASE_CLIENT_CHECK: &str = "client_check";
+pub(crate) const PHASE_SETUP_SANDBOX: &str = "setup_sandbox";
+
+pub(crate) const OPERATION_ATTACH: &str = "attach";
+pub(crate) const OPERATION_DETACH: &str = "detach";
+const RESULTS: [&str; 2] = ["success", "failure"];
+
+const STATUS_CREATED: &str = "created";
+const STATUS_RUNNING: &str = "running";
+const STATUS_STOPPED: &str = "stopped";
+const STATUS_UNKNOWN: &str = "unknown";
+
+lazy_static! {
+    static ref BOOT_DURATION: HistogramVec = register_histogram_vec!(
+        "kuasar_vmm_sandbox_boot_duration_seconds",
+        "Duration of each phase to boot the sandbox",
+        &["phase"]
+    )
+    .unwrap();
+    static ref PROCESS_RSS: GaugeVec = register_gauge_vec!(
+        "kuasar_vmm_sandbox_process_rss_bytes",
+        "Resident memory of the vmm and its affiliated processes of the sandbox",
+        &["sandbox_id", "process", "pid"]
+    )
+    .unwrap();
+    static ref HOT_PLUG_TOTAL: CounterVec = register_counter_vec!(
+        "kuasar_vmm_sandbox_hot_plug_total",
+        "Number of devices hot attached to or detached from the sandboxes",
+        &["operation", "result"]
+    )
+    .unwrap();
+    static ref HOT_PLUG_DURATION: HistogramVec = register_histogram_vec!(
+        "kuasar_vmm_hot_plug_duration_seconds",
+        "Latency to hot attach or detach a device",
+        &["operation"]
+    )
+    .unwrap();
+    static ref RECOVERY_DURATION: Gauge = register_gauge!(
+        "kuasar_vmm_recovery_duration_seconds",
+        "Duration to recover the sandboxes when the sandboxer is restarted"
+    )
+    .unwrap();
+    static ref RECOVERED_SANDBOXES: IntGaugeVec = register_int_gauge_vec!(
+        "kuasar_vmm_recovered_sandboxes",
+        "Number of sandboxes recovered when the sandboxer is restarted",
+        &["result"]
+    )
+    .unwrap();
+    static ref SANDBOXES: IntGaugeVec = register_int_gauge_vec!(
+        "kuasar_vmm_sandboxes",
+        "Number of sandboxes by status",
+        &["status"]
+    )
+    .unwrap();
+}
+
+/// Collect the metrics which are only meaningful at the time they are scraped.
+#[async_trait]
+pub trait Collector: Send + Sync {
+    async fn collect(&self);
+}
+
+pub(crate) struct SandboxCollector<V: VM> {
+    #[allow(clippy::type_complexity)]
+    sandboxes: Arc<RwLock<HashMap<String, Arc<Mutex<KuasarSandbox<V>>>>>>,
+}
+
+impl<V: VM> SandboxCollector<V> {
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn new(
+        sandboxes: Arc<RwLock<HashMap<String, Arc<Mutex<KuasarSandbox<V>>>>>>,
+    ) -> Self {
+        Self { sandboxes }
+    }
+}
+
+#[async_trait]
+impl<V> Collector for SandboxCollector<V>
+where
+    V: VM + Sync + Send,
+{
+    async fn collect(&self) {
+        let sandboxes: Vec<_> = self.sandboxes.read().await.values().cloned().collect();
+        let mut statuses: HashMap<&str, i64> = [STATUS_CREATED, STATUS_RUNNING, STATUS_STOPPED]
+            .into_iter()
+            .map(|s| (s, 0))
+            .collect();
+        PROCESS_RSS.reset();
+        for sandbox_mutex in sandboxes {
+            let sandbox = sandbox_mutex.lock().await;
+            *statuses.entry(status_name(&sandbox.status)).or_default() += 1;
+            if !matches!(sandbox.status, SandboxStatus::Running(_)) {
+                continue;
+            }
+            let pids = sandbox.vm.pids();
+            if let Some(pid) = pids.vmm_pid {
+                set_process_rss(&sandbox.id, "vmm", pid);
+            }
+            for pid in pids.affiliated_pids {
+                let name = procfs::process::Process::new(pid as i32)
+                    .and_then(|p| p.stat())
+                    .map(|s| s.comm)
+                    .unwrap_or_else(|_| "affiliated".to_string());
+                set_process_rss(&sandbox.id, &name, pid);
+            }
+        }
+        for (status, count) in statuses {
+            SANDBOXES.with_label_values(&[status]).set(count);
+        }
+    }
+}
+
+fn set_process_rss(sandbox_id: &str, process: &str, pid: u32) {
+    let rss = procfs::process::Process::new(pid as i32)
+        .and_then(|p| p.status())
+        .map(|s| s.vmrss.unwrap_or_default() * 1024);
+    match rss {
+        Ok(rss) => PROCESS_RSS
+            .with_label_values(&[sandbox_id, process, &pid.to_string()])
+            .set(rss as f64),
+        Err(e) => debug!("failed to get rss of process {}: {}", pid, e),
+    }
+}
+
+fn status_name(status: &SandboxStatus) -> &'static str {
+    match status {
+        SandboxStatus::Created => STATUS_CREATED,
+        SandboxStatus::Running(_) => STATUS_RUNNING,
+        SandboxStatus::Stopped(_, _) => STATUS_STOPPED,
+        _ => STATUS_UNKNOWN,
+    }
+}
+
+/// Record the duration of a boot phase of a sandbox, which starts at `start`.
+pub(crate) fn observe_boot_phase(phase: &str, start: Instant) {
+    BOOT_DURATION
+        .with_label_values(&[phase])
+        .observe(start.elapsed().as_secs_f64());
+}
+
+/// Record a hot attach or detach of the device, which starts at `start`.
+pub(crate) fn observe_hot_plug(operation: &str, start: Instant, success: bool) {
+    HOT_PLUG_DURATION
+        .with_label_values(&[operation])
+        .observe(start.elapsed().as_secs_f64());
+    let result = if success { RESULTS[0] } else { RESULTS[1] };
+    HOT_PLUG_TOTAL.with_label_values(&[operation, result]).inc();
+}
+
+pub(crate) fn observe_recovery(duration: Duration, succeeded: i64, failed: i64) {
+    RECOVERY_DURATION.set(duration.as_secs_f64());
+    RECOVERED_SANDBOXES
+        .with_label_values(&[RESULTS[0]])
+        .set(succeeded);
+    RECOVERED_SANDBOXES
+        .with_label_values(&[RESULTS[1]])
+        .set(failed);
+}
+
+/// Serve the metrics in prometheus text format on `address`, which is either a tcp address
+/// like "127.0.0.1:9100" or a unix socket like "unix:///run/kuasar-vmm-metrics.sock".
+pub async fn serve(address: &str, collector: Arc<dyn Collector>) -> Result<()> {
+    match unix_socket_path(address) {
+        Some(path) => {
+            if let Some(parent) = Path::new(path).parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            // remove the socket left by the last sandboxer process
+            if let Err(e) = tokio::fs::remove_file(path).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    return Err(e.into());
+                }
+            }
+            let listener = UnixListener::bind(path)
+                .map_err(|e| anyhow!("failed to listen metrics on {}: {}", path, e))?;
+            tokio::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, _)) => {
+                            tokio::spawn(handle_connection(stream, collector.clone()));
+                        }
+                        Err(e) => warn!("failed to accept metrics connection: {}", e),
+                    }
+                }
+            });
+        }
+        None => {
+            let listener = TcpListener::bind(address)
+                .await
+                .map_err(|e| anyhow!("failed to listen metrics on {}: {}", address, e))?;
+            tokio::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, _)) => {
+                            tokio::spawn(handle_connection(stream, collector.clone()));
+                        }
+                        Err(e) => warn!("failed to accept metrics connection: {}", e),
+                    }
+                }
+            });
+        }
+    }
+    info!("metrics are served on {}", address);
+    Ok(())
+}
+
+fn unix_socket_path(address: &str) -> Option<&str> {
+    if let Some(path) = address.strip_prefix(UNIX_ADDRESS_PREFIX) {
+        return Some(path);
+    }
+    if address.starts_with('/') {
+        return Some(address);
+    }
+    None
+}
+
+async fn handle_connection<S>(mut stream: S, collector: Arc<dyn Collector>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if let Err(e) = respond(&mut stream, collector).await {
+        debug!("failed to respond metrics request: {}", e);
+    }
+}
+
+async fn respond<S>(stream: &mut S, collector: Arc<dyn Collector>) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; MAX_REQUEST_SIZE];
+    // a client which never finishes its request should not hold the connection forever
+    let len = timeout(
+        Duration::from_secs(REQUEST_READ_TIMEOUT_IN_SEC),
+        read_request(stream, &mut buf),
+    )
+    .await
+    .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "timeout reading request"))??;
+    let request = String::from_utf8_lossy(&buf[..len]);
+    let encoder = TextEncoder::new();
+    let (status, content_type, body) = match request_path(&request) {
+        Some(METRICS_PATH) => {
+            collector.collect().await;
+            let mut body = vec![];
+            match encoder.encode(&prometheus::gather(), &mut body) {
+                Ok(_) => ("200 OK", encoder.format_type().to_string(), body),
+                Err(e) => (
+                    "500 Internal Server Error",
+                    "text/plain".to_string(),
+                    format!("{}\n", e).into_bytes(),
+                ),
+            }
+        }
+        _ => (
+            "404 Not Found",
+            "text/plain".to_string(),
+            b"404 page not found\n".to_vec(),
+        ),
+    };
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.shutdown().await
+}
+
+// Read the request until the end of headers, only the request line is needed,
+// headers and body are ignored.
+async fn read_request<S>(stream: &mut S, buf: &mut [u8]) -> std::io::Result<usize>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut len = 0;
+    while len < buf.len() {
+        let n = stream.read(&mut buf[len..]).await?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+        if buf[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+            break;
+        }
+    }
+    Ok(len)
+}
+
+// Get the path of a GET request, without the query string.
+fn request_path(request: &str) -> Option<&str> {
+    let mut parts = request.lines().next()?.split_whitespace();
+    if parts.next()? != "GET" {
+        return None;
+    }
+    parts.next()?.split('?').next()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::metrics::{request_path, unix_socket_path};
+
+    #[test]
+    fn test_request_path() {
+        assert_eq!(
+            request_path("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+            Some("/metrics")
+        );
+        assert_eq!(
+            request_path("GET /metrics?name[]=x HTTP/1.1\r\n\r\n"),
+            Some("/metrics")
+        );
+        assert_eq!(request_path("POST /metrics HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(request_path(""), None);
+    }
+
+    #[test]
+    fn test_unix_socket_path() {
+        assert_eq!(
+            unix_socket_path("unix:///run/kuasar-vmm-metrics.sock"),
+            Some("/run/kuasar-vmm-metrics.sock")
+        );
+        assert_eq!(
+            unix_socket_path("/run/kuasar-vmm-metrics.sock"),
+            Some("/run/kuasar-vmm-metrics.sock")
+        );
+        assert_eq!(unix_socket_path("127.0.0.1:9100"), None);
+    }
+}
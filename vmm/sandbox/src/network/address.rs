@@ -51,23 +51,32 @@ pub struct IpNet {
 
 impl From<String> for IpNet {
     fn from(s: String) -> Self {
-        let sp: Vec<&str> = s.split('/').collect();
-        let mut ip = IpAddr::from_str("0.0.0.0").unwrap();
-        let mut prefix_len = 32u8;
-        if !sp.is_empty() {
-            if let Ok(i) = IpAddr::from_str(sp[0]) {
-                ip = i;
-            }
-        }
-        if sp.len() == 2 {
-            if let Ok(s) = sp[1].parse() {
-                prefix_len = s;
-            }
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.as_str(), None),
         };
+        let ip = IpAddr::from_str(addr).unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let max_prefix_len = if ip.is_ipv6() { 128 } else { 32 };
+        // the prefix of cni ip addresses may be a netmask, like "255.255.255.0" or "ffff:ffff::"
+        let prefix_len = prefix
+            .and_then(|p| {
+                p.parse::<u8>()
+                    .ok()
+                    .or_else(|| IpAddr::from_str(p).ok().map(netmask_to_prefix_len))
+            })
+            .filter(|l| *l <= max_prefix_len)
+            .unwrap_or(max_prefix_len);
         Self { ip, prefix_len }
     }
 }
 
+fn netmask_to_prefix_len(mask: IpAddr) -> u8 {
+    match mask {
+        IpAddr::V4(m) => u32::from(m).leading_ones() as u8,
+        IpAddr::V6(m) => u128::from(m).leading_ones() as u8,
+    }
+}
+
 impl From<IpNet> for String {
     fn from(ip_net: IpNet) -> String {
         format!("{}/{}", ip_net.addr_string(), ip_net.prefix_len)
@@ -87,6 +96,14 @@ impl IpNet {
         self.ip.to_string()
     }
 
+    /// Whether it is in 169.254.0.0/16 or fe80::/10
+    pub fn is_link_local(&self) -> bool {
+        match self.ip {
+            IpAddr::V4(v4) => v4.is_link_local(),
+            IpAddr::V6(v6) => (v6.segments()[0] & 0xffc0) == 0xfe80,
+        }
+    }
+
     pub fn netmask(&self) -> IpAddr {
         if self.ip.is_ipv6() {
             let mask = Ipv6Addr::from(self.netmask_u128());
@@ -148,3 +165,56 @@ pub fn convert_to_ip_address(address: RouteAddress) -> Result<IpAddr> {
         _ => Err(anyhow!("unsupported ip address {:?}", address).into()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use crate::network::address::{CniIPAddress, IpNet};
+
+    #[test]
+    fn test_ipnet_from_string() {
+        let ip = IpNet::from("10.0.0.2/24".to_string());
+        assert_eq!(ip.addr_string(), "10.0.0.2");
+        assert_eq!(ip.prefix_len, 24);
+        assert_eq!(ip.netmask().to_string(), "255.255.255.0");
+
+        let ip = IpNet::from("fd00::2/64".to_string());
+        assert_eq!(ip.addr_string(), "fd00::2");
+        assert_eq!(ip.prefix_len, 64);
+        assert_eq!(ip.netmask().to_string(), "ffff:ffff:ffff:ffff::");
+
+        // the prefix length is of a single address if not specified
+        assert_eq!(IpNet::from("10.0.0.2".to_string()).prefix_len, 32);
+        assert_eq!(IpNet::from("fd00::2".to_string()).prefix_len, 128);
+        assert_eq!(IpNet::from("10.0.0.2/33".to_string()).prefix_len, 32);
+    }
+
+    #[test]
+    fn test_ipnet_from_cni_ip_address() {
+        let mut cni_ip = CniIPAddress {
+            family: 0,
+            address: "10.0.0.2".to_string(),
+            mask: "255.255.252.0".to_string(),
+        };
+        let ip = IpNet::from(String::from(&mut cni_ip));
+        assert_eq!(ip.prefix_len, 22);
+
+        let mut cni_ip = CniIPAddress {
+            family: 1,
+            address: "2001:db8::2".to_string(),
+            mask: "ffff:ffff:ffff:ffff:ffff:ffff::".to_string(),
+        };
+        let ip = IpNet::from(String::from(&mut cni_ip));
+        assert_eq!(ip.addr(), &"2001:db8::2".parse::<IpAddr>().unwrap());
+        assert_eq!(ip.prefix_len, 96);
+    }
+
+    #[test]
+    fn test_ipnet_is_link_local() {
+        assert!(IpNet::from("169.254.1.1/16".to_string()).is_link_local());
+        assert!(IpNet::from("fe80::1/64".to_string()).is_link_local());
+        assert!(!IpNet::from("10.0.0.2/24".to_string()).is_link_local());
+        assert!(!IpNet::from("fd00::2/64".to_string()).is_link_local());
+    }
+}
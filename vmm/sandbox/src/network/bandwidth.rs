@@ -0,0 +1,152 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use containerd_sandbox::error::Result;
+use serde_derive::{Deserialize, Serialize};
+
+pub const INGRESS_BANDWIDTH_ANNOTATION: &str = "kubernetes.io/ingress-bandwidth";
+pub const EGRESS_BANDWIDTH_ANNOTATION: &str = "kubernetes.io/egress-bandwidth";
+
+// the same range as kubelet accepts
+const MIN_BANDWIDTH: u64 = 1000;
+const MAX_BANDWIDTH: u64 = 1_000_000_000_000_000;
+
+/// Bandwidth limits of the pod in bits per second, ingress is the traffic into the pod.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BandwidthLimit {
+    #[serde(default)]
+    pub ingress: Option<u64>,
+    #[serde(default)]
+    pub egress: Option<u64>,
+}
+
+impl BandwidthLimit {
+    pub fn from_annotations(annotations: &HashMap<String, String>) -> Result<Self> {
+        let parse = |key: &str| -> Result<Option<u64>> {
+            annotations
+                .get(key)
+                .map(|v| parse_bandwidth(v).map_err(|e| anyhow!("invalid {}: {}", key, e)))
+                .transpose()
+                .map_err(Into::into)
+        };
+        Ok(Self {
+            ingress: parse(INGRESS_BANDWIDTH_ANNOTATION)?,
+            egress: parse(EGRESS_BANDWIDTH_ANNOTATION)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ingress.is_none() && self.egress.is_none()
+    }
+}
+
+fn parse_bandwidth(value: &str) -> anyhow::Result<u64> {
+    let bandwidth = parse_quantity(value)?;
+    if !(MIN_BANDWIDTH..=MAX_BANDWIDTH).contains(&bandwidth) {
+        return Err(anyhow!(
+            "{} is out of range [{}, {}]",
+            value,
+            MIN_BANDWIDTH,
+            MAX_BANDWIDTH
+        ));
+    }
+    Ok(bandwidth)
+}
+
+// Parse the kubernetes resource quantity like "10M", "1.5Gi" or "1e6", rounded up to an integer.
+pub(crate) fn parse_quantity(value: &str) -> anyhow::Result<u64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '+'))
+        .unwrap_or(value.len());
+    let (number, suffix) = value.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| anyhow!("invalid quantity {}", value))?;
+    let multiplier = match suffix {
+        "" => 1f64,
+        "m" => 1e-3,
+        "k" => 1e3,
+        "M" => 1e6,
+        "G" => 1e9,
+        "T" => 1e12,
+        "P" => 1e15,
+        "E" => 1e18,
+        "Ki" => 1024f64,
+        "Mi" => 1024f64.powi(2),
+        "Gi" => 1024f64.powi(3),
+        "Ti" => 1024f64.powi(4),
+        "Pi" => 1024f64.powi(5),
+        "Ei" => 1024f64.powi(6),
+        s if s.starts_with(['e', 'E']) => {
+            let exp: i32 = s[1..]
+                .parse()
+                .map_err(|_| anyhow!("invalid quantity {}", value))?;
+            10f64.powi(exp)
+        }
+        _ => return Err(anyhow!("invalid quantity suffix of {}", value)),
+    };
+    let quantity = (number * multiplier).ceil();
+    if !quantity.is_finite() || quantity > u64::MAX as f64 {
+        return Err(anyhow!("quantity {} is too large", value));
+    }
+    Ok(quantity as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::network::bandwidth::{
+        parse_quantity, BandwidthLimit, EGRESS_BANDWIDTH_ANNOTATION, INGRESS_BANDWIDTH_ANNOTATION,
+    };
+
+    #[test]
+    fn test_parse_quantity() {
+        assert_eq!(parse_quantity("1000").unwrap(), 1000);
+        assert_eq!(parse_quantity("10M").unwrap(), 10_000_000);
+        assert_eq!(parse_quantity("1.5k").unwrap(), 1500);
+        assert_eq!(parse_quantity("1Gi").unwrap(), 1 << 30);
+        assert_eq!(parse_quantity("100Ki").unwrap(), 102400);
+        assert_eq!(parse_quantity("1e6").unwrap(), 1_000_000);
+        assert_eq!(parse_quantity("1500m").unwrap(), 2);
+        assert!(parse_quantity("10Mbps").is_err());
+        assert!(parse_quantity("M").is_err());
+        assert!(parse_quantity("-1M").is_err());
+    }
+
+    #[test]
+    fn test_bandwidth_from_annotations() {
+        let mut annotations = HashMap::new();
+        let limit = BandwidthLimit::from_annotations(&annotations).unwrap();
+        assert!(limit.is_empty());
+
+        annotations.insert(INGRESS_BANDWIDTH_ANNOTATION.to_string(), "10M".to_string());
+        annotations.insert(EGRESS_BANDWIDTH_ANNOTATION.to_string(), "1G".to_string());
+        let limit = BandwidthLimit::from_annotations(&annotations).unwrap();
+        assert_eq!(limit.ingress, Some(10_000_000));
+        assert_eq!(limit.egress, Some(1_000_000_000));
+
+        // kubelet rejects bandwidth less than 1k or more than 1P
+        annotations.insert(EGRESS_BANDWIDTH_ANNOTATION.to_string(), "10".to_string());
+        assert!(BandwidthLimit::from_annotations(&annotations).is_err());
+        annotations.insert(EGRESS_BANDWIDTH_ANNOTATION.to_string(), "2P".to_string());
+        assert!(BandwidthLimit::from_annotations(&annotations).is_err());
+    }
+}
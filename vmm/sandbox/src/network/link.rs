@@ -28,6 +28,7 @@ use anyhow::anyhow;
 use containerd_sandbox::error::Result;
 use futures_util::TryStreamExt;
 use libc::{IFF_MULTI_QUEUE, IFF_NO_PI, IFF_TAP, IFF_VNET_HDR};
+use log::warn;
 use netlink_packet_route::link::{
     InfoData, InfoIpVlan, InfoKind, InfoMacVlan, InfoMacVtap, InfoVlan, InfoVxlan, LinkFlag,
     LinkInfo, LinkMessage,
@@ -44,6 +45,7 @@ use crate::{
     device::{DeviceInfo, PhysicalDeviceInfo, TapDeviceInfo, VhostUserDeviceInfo},
     network::{
         address::{CniIPAddress, IpNet, MacAddress},
+        bandwidth::BandwidthLimit,
         create_netlink_handle, execute_in_netns, run_in_new_netns,
     },
     sandbox::KuasarSandbox,
@@ -56,6 +58,11 @@ const DEVICE_DRIVER_VFIO: &str = "vfio-pci";
 const SIOCETHTOOL: u64 = 0x8946;
 const ETHTOOL_GDRVINFO: u32 = 0x00000003;
 
+// the latency of packets queued in tbf, the same as the bandwidth cni plugin
+const TBF_LATENCY: &str = "25ms";
+// the burst should hold at least one gso packet, as the tap is created with IFF_VNET_HDR
+const TBF_MIN_BURST: u64 = 65536;
+
 const TUNSETIFF: u64 = 0x400454ca;
 const TUNSETPERSIST: u64 = 0x400454cb;
 
@@ -132,6 +139,23 @@ impl Display for LinkType {
     }
 }
 
+impl LinkType {
+    // Interfaces of these types can not be passed to the vm directly, a tap device is created
+    // for each of them and the traffic is redirected between them by tc filters.
+    pub fn is_redirected_by_tap(&self) -> bool {
+        matches!(
+            self,
+            LinkType::Veth
+                | LinkType::Macvlan(_)
+                | LinkType::Ipvlan(_)
+                | LinkType::Vlan(_)
+                | LinkType::Bond
+                | LinkType::Vxlan(_)
+                | LinkType::Bridge
+        )
+    }
+}
+
 impl From<InfoData> for LinkType {
     fn from(d: InfoData) -> Self {
         match d {
@@ -174,9 +198,9 @@ impl From<InfoData> for LinkType {
 pub struct NetworkInterface {
     #[serde(default)]
     pub device: String,
-    #[serde(skip)]
+    #[serde(default)]
     pub r#type: LinkType,
-    #[serde(skip)]
+    #[serde(default)]
     pub index: u32,
     #[serde(default)]
     pub name: String,
@@ -204,6 +228,9 @@ pub struct NetworkInterface {
     pub fds: Vec<OwnedFd>,
     #[serde(skip)]
     pub queue: u32,
+    // index of the bridge or bond the link is enslaved to
+    #[serde(default)]
+    pub controller: Option<u32>,
 }
 
 // netlink-packet-route-0.19.0/src/link/link_flag.rs:26
@@ -241,7 +268,6 @@ impl NetworkInterface {
                         } else if let LinkInfo::Kind(InfoKind::Veth) = info {
                             // for veth, there is no Info::Data, but SlaveKind and SlaveData,
                             // so we have to get the type from Info::Kind
-                            intf.queue = queue;
                             intf.r#type = LinkType::Veth;
                         }
                     }
@@ -252,9 +278,13 @@ impl NetworkInterface {
                 LinkAttribute::IfAlias(a) => intf.alias = a,
                 LinkAttribute::Mtu(m) => intf.mtu = m,
                 LinkAttribute::Address(u) => intf.mac_address = MacAddress(u),
+                LinkAttribute::Controller(c) => intf.controller = Some(c),
                 _ => {}
             }
         }
+        if intf.r#type.is_redirected_by_tap() {
+            intf.queue = queue;
+        }
         let mut addresses = handle
             .address()
             .get()
@@ -310,10 +340,13 @@ impl NetworkInterface {
     }
 
     pub async fn prepare_attaching(&mut self, netns: &str) -> Result<()> {
+        if self.is_enslaved() {
+            return Ok(());
+        }
         match &self.r#type {
-            LinkType::Veth => {
+            t if t.is_redirected_by_tap() => {
                 let handle = create_netlink_handle(netns).await?;
-                let tap_name = format!("tap_kua_{}", self.index);
+                let tap_name = self.tap_name();
                 let tap_intf =
                     create_tap_in_netns(netns, &tap_name, self.queue, self.mtu, &handle).await?;
                 tap_intf.add_qdisc_ingress(netns, &handle).await?;
@@ -331,69 +364,134 @@ impl NetworkInterface {
     }
 
     pub async fn attach_to<V: VM>(&mut self, sandbox: &mut KuasarSandbox<V>) -> Result<()> {
-        let id = format!("intf-{}", self.index);
-        match &self.r#type {
-            LinkType::Veth => {
+        if let Some(device_info) = self.device_info()? {
+            sandbox.attach_device(device_info).await?;
+        }
+        Ok(())
+    }
+
+    /// Hot attach the interface to the running vm of the sandbox
+    pub async fn hot_attach_to<V: VM>(&mut self, sandbox: &mut KuasarSandbox<V>) -> Result<()> {
+        if let Some(device_info) = self.device_info()? {
+            sandbox.hot_attach(device_info).await?;
+        }
+        Ok(())
+    }
+
+    // The traffic of a link enslaved to a bridge or bond goes through its controller, which is
+    // attached instead, attaching the link too would duplicate the traffic into the vm.
+    fn is_enslaved(&self) -> bool {
+        self.controller.is_some()
+    }
+
+    pub(crate) fn device_id(&self) -> String {
+        format!("intf-{}", self.index)
+    }
+
+    fn tap_name(&self) -> String {
+        format!("tap_kua_{}", self.index)
+    }
+
+    // Get the device to attach to the vm, None if the interface needs no device.
+    fn device_info(&mut self) -> Result<Option<DeviceInfo>> {
+        if self.is_enslaved() {
+            return Ok(None);
+        }
+        let id = self.device_id();
+        let device_info = match &self.r#type {
+            t if t.is_redirected_by_tap() => {
                 if let Some(intf) = self.twin.as_mut() {
-                    sandbox
-                        .vm
-                        .attach(DeviceInfo::Tap(TapDeviceInfo {
-                            id,
-                            index: self.index,
-                            name: intf.name.to_string(),
-                            mac_address: self.mac_address.to_string(),
-                            fds: intf.fds.drain(..).collect(),
-                        }))
-                        .await?;
-                } else {
-                    return Err(anyhow!("no tap interface created for veth {}", self.name).into());
-                }
-            }
-            LinkType::VhostUser(sock) => {
-                sandbox
-                    .vm
-                    .attach(DeviceInfo::VhostUser(VhostUserDeviceInfo {
-                        id,
-                        socket_path: sock.to_string(),
-                        mac_address: self.mac_address.to_string(),
-                        r#type: "virtio-net-pci".to_string(),
-                    }))
-                    .await?;
-            }
-            LinkType::Physical(bdf, _driver) => {
-                sandbox
-                    .vm
-                    .attach(DeviceInfo::Physical(PhysicalDeviceInfo {
-                        id,
-                        bdf: bdf.to_string(),
-                    }))
-                    .await?;
-            }
-            LinkType::Tap => {
-                sandbox
-                    .vm
-                    .attach(DeviceInfo::Tap(TapDeviceInfo {
+                    DeviceInfo::Tap(TapDeviceInfo {
                         id,
                         index: self.index,
-                        name: self.name.to_string(),
+                        name: intf.name.to_string(),
                         mac_address: self.mac_address.to_string(),
-                        fds: vec![],
-                    }))
-                    .await?;
+                        fds: intf.fds.drain(..).collect(),
+                    })
+                } else {
+                    return Err(anyhow!(
+                        "no tap interface created for {} {}",
+                        self.r#type,
+                        self.name
+                    )
+                    .into());
+                }
             }
-            LinkType::Loopback => {}
-            _ => {}
+            LinkType::VhostUser(sock) => DeviceInfo::VhostUser(VhostUserDeviceInfo {
+                id,
+                socket_path: sock.to_string(),
+                mac_address: self.mac_address.to_string(),
+                r#type: "virtio-net-pci".to_string(),
+            }),
+            LinkType::Physical(bdf, _driver) => DeviceInfo::Physical(PhysicalDeviceInfo {
+                id,
+                bdf: bdf.to_string(),
+            }),
+            LinkType::Tap => DeviceInfo::Tap(TapDeviceInfo {
+                id,
+                index: self.index,
+                name: self.name.to_string(),
+                mac_address: self.mac_address.to_string(),
+                fds: vec![],
+            }),
+            _ => return Ok(None),
+        };
+        Ok(Some(device_info))
+    }
+
+    /// Remove the tap device created for the interface, after the interface is gone from netns
+    pub async fn remove_tap(&mut self, netns: &str) -> Result<()> {
+        if self.is_enslaved() || !self.r#type.is_redirected_by_tap() {
+            return Ok(());
+        }
+        self.twin = None;
+        let handle = create_netlink_handle(netns).await?;
+        let mut links = handle.link().get().match_name(self.tap_name()).execute();
+        if let Ok(Some(msg)) = links.try_next().await {
+            handle
+                .link()
+                .del(msg.header.index)
+                .execute()
+                .await
+                .map_err(|e| anyhow!("failed to delete {}: {}", self.tap_name(), e))?;
         }
         Ok(())
     }
 
     pub async fn after_detach(&mut self, _netns: &str) -> Result<()> {
+        if self.is_enslaved() {
+            return Ok(());
+        }
         if let LinkType::Physical(bdf, driver) = &self.r#type {
             bind_device_to_driver(driver, bdf).await?
         }
         Ok(())
     }
 
+    /// Shape the traffic with tbf qdiscs, as the limits set by the bandwidth cni plugin on host
+    /// are bypassed once the traffic is redirected to the tap. The traffic into the pod is shaped
+    /// on the egress of the tap, and the traffic out of the pod is shaped on the egress of the
+    /// interface, which the traffic from the tap is redirected to.
+    pub async fn limit_bandwidth(&self, netns: &str, limit: &BandwidthLimit) -> Result<()> {
+        if limit.is_empty() || self.is_enslaved() {
+            return Ok(());
+        }
+        if !self.r#type.is_redirected_by_tap() {
+            warn!(
+                "bandwidth limit is not supported for {} interface {}",
+                self.r#type, self.name
+            );
+            return Ok(());
+        }
+        if let Some(rate) = limit.ingress {
+            add_tbf_qdisc(netns, &self.tap_name(), rate).await?;
+        }
+        if let Some(rate) = limit.egress {
+            add_tbf_qdisc(netns, &self.name, rate).await?;
+        }
+        Ok(())
+    }
+
     async fn add_qdisc_ingress(&self, netns: &str, _handle: &Handle) -> Result<()> {
         // TODO use netlink to add ingress
         let mut cmd = std::process::Command::new("tc");
@@ -431,6 +529,34 @@ impl NetworkInterface {
     }
 }
 
+async fn add_tbf_qdisc(netns: &str, dev: &str, rate: u64) -> Result<()> {
+    // TODO do this with netlink library
+    let burst = tbf_burst(rate).to_string();
+    let rate = format!("{}bit", rate);
+    let mut cmd = std::process::Command::new("tc");
+    cmd.args([
+        "qdisc",
+        "replace",
+        "dev",
+        dev,
+        "root",
+        "tbf",
+        "rate",
+        &*rate,
+        "burst",
+        &*burst,
+        "latency",
+        TBF_LATENCY,
+    ]);
+    execute_in_netns(netns, cmd).await?;
+    Ok(())
+}
+
+// the burst in bytes allows 100ms of traffic at the rate
+fn tbf_burst(rate: u64) -> u64 {
+    (rate / 8 / 10).max(TBF_MIN_BURST)
+}
+
 fn get_bdf_for_eth(if_name: &str) -> Result<String> {
     if if_name.len() > 16 {
         return Err(anyhow!("the interface name length is larger than 16").into());
@@ -618,7 +744,43 @@ async fn bind_device_to_driver(driver: &str, bdf: &str) -> Result<()> {
 mod tests {
     use std::process::Command;
 
-    use crate::network::link::create_tap_device;
+    use crate::network::{
+        address::MacAddress,
+        link::{create_tap_device, tbf_burst, LinkType, NetworkInterface, TBF_MIN_BURST},
+    };
+
+    #[test]
+    fn test_tbf_burst() {
+        assert_eq!(tbf_burst(1_000_000), TBF_MIN_BURST);
+        assert_eq!(tbf_burst(1_000_000_000), 12_500_000);
+    }
+
+    #[tokio::test]
+    async fn test_enslaved_link_is_not_attached() {
+        // a veth enslaved to a bridge, whose traffic goes through the bridge
+        let mut intf = NetworkInterface {
+            r#type: LinkType::Veth,
+            index: 3,
+            name: "veth0".to_string(),
+            mac_address: MacAddress(vec![0x02, 0, 0, 0, 0, 0x03]),
+            controller: Some(2),
+            ..NetworkInterface::default()
+        };
+        // no tap is created in the netns for it
+        intf.prepare_attaching("/nonexistent/netns").await.unwrap();
+        assert!(intf.twin.is_none());
+        assert!(intf.device_info().unwrap().is_none());
+        intf.remove_tap("/nonexistent/netns").await.unwrap();
+
+        // the bridge is redirected by a tap, which fails without the tap created
+        let mut bridge = NetworkInterface {
+            r#type: LinkType::Bridge,
+            index: 2,
+            name: "br0".to_string(),
+            ..NetworkInterface::default()
+        };
+        assert!(bridge.device_info().is_err());
+    }
 
     #[test]
     fn add_tap_device_with_long_name() {
@@ -22,26 +22,43 @@ use std::{
 
 use anyhow::anyhow;
 use containerd_sandbox::error::Result;
-use futures_util::TryStreamExt;
+use futures_util::{Stream, StreamExt, TryStreamExt};
 use log::{debug, error, info, warn};
 use nix::{
     fcntl::OFlag,
     sched::{setns, CloneFlags},
     sys::stat::Mode,
 };
-use rtnetlink::{new_connection, Handle, IpVersion};
+use rtnetlink::{
+    constants::{
+        RTMGRP_IPV4_IFADDR, RTMGRP_IPV4_ROUTE, RTMGRP_IPV6_IFADDR, RTMGRP_IPV6_ROUTE, RTMGRP_LINK,
+    },
+    new_connection,
+    sys::{AsyncSocket, SocketAddr},
+    Handle, IpVersion,
+};
 use serde_derive::{Deserialize, Serialize};
 use tokio::task::spawn_blocking;
 
-pub use crate::network::{address::IpNet, link::NetworkInterface, route::Route};
+pub use crate::network::{
+    address::IpNet, bandwidth::BandwidthLimit, link::NetworkInterface, route::Route,
+};
 use crate::{network::link::LinkType, sandbox::KuasarSandbox, utils::safe_open_file, vm::VM};
 
 pub mod address;
+pub mod bandwidth;
 mod convert;
 pub mod link;
 mod netlink;
 pub mod route;
 
+// the max queues of a tap device allowed by the kernel
+const MAX_TAP_QUEUES: u32 = 256;
+
+// netlink multicast groups of the link, address and route changes
+const NETLINK_CHANGE_GROUPS: u32 =
+    RTMGRP_LINK | RTMGRP_IPV4_IFADDR | RTMGRP_IPV6_IFADDR | RTMGRP_IPV4_ROUTE | RTMGRP_IPV6_ROUTE;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Network {
     pub(crate) config: NetworkConfig,
@@ -130,6 +147,7 @@ impl Network {
         let mut me = self;
         for intf in &mut me.intfs {
             intf.prepare_attaching(&netns).await?;
+            intf.limit_bandwidth(&netns, &me.config.bandwidth).await?;
             intf.attach_to(sandbox).await?;
         }
         sandbox.network = Some(me);
@@ -147,6 +165,15 @@ impl Network {
         }
     }
 
+    /// Scan the netns again and merge the interfaces into the network, the routes are replaced.
+    /// Returns the interfaces newly found and those gone, which are not attached or detached yet.
+    pub async fn rescan(&mut self) -> Result<(Vec<NetworkInterface>, Vec<NetworkInterface>)> {
+        let scanned = Self::new_in_netns(self.config.clone()).await?;
+        let (added, removed) = merge_intfs(&mut self.intfs, scanned.intfs);
+        self.routes = scanned.routes;
+        Ok((added, removed))
+    }
+
     pub fn interfaces(&self) -> &Vec<NetworkInterface> {
         self.intfs.as_ref()
     }
@@ -159,7 +186,7 @@ impl Network {
         intfs
             .into_iter()
             .filter(|intf| match intf.r#type {
-                LinkType::Veth => true,
+                ref t if t.is_redirected_by_tap() => true,
                 LinkType::VhostUser(_) => true,
                 LinkType::Physical(_, _) => true,
                 LinkType::Tap => true,
@@ -173,11 +200,70 @@ impl Network {
     }
 }
 
+// Interfaces are matched by index, name and mac address, so that a recreated interface is
+// attached as a new one. The matched ones are updated in place. The index is 0 if the interface
+// is recovered from a sandbox.json dumped before the index was persisted, it is matched by the
+// name and mac address only then.
+fn merge_intfs(
+    current: &mut Vec<NetworkInterface>,
+    scanned: Vec<NetworkInterface>,
+) -> (Vec<NetworkInterface>, Vec<NetworkInterface>) {
+    let mut kept = vec![];
+    let mut added = vec![];
+    for intf in scanned {
+        let matched = current.iter().position(|c| {
+            (c.index == 0 || c.index == intf.index)
+                && c.name == intf.name
+                && c.mac_address.to_string() == intf.mac_address.to_string()
+        });
+        match matched {
+            Some(i) => {
+                let mut c = current.remove(i);
+                c.index = intf.index;
+                c.r#type = intf.r#type;
+                c.ip_addresses = intf.ip_addresses;
+                c.mtu = intf.mtu;
+                c.flags = intf.flags;
+                kept.push(c);
+            }
+            None => added.push(intf),
+        }
+    }
+    let removed = std::mem::replace(current, kept);
+    (added, removed)
+}
+
+/// Subscribe the link, address and route changes in the netns, an item is yielded for each
+/// change, and the subscription is cancelled once the stream is dropped.
+pub(crate) async fn subscribe_changes(netns: &str) -> Result<impl Stream<Item = ()> + Unpin> {
+    let (mut connection, _, messages) = run_in_new_netns(netns, new_connection).await??;
+    connection
+        .socket_mut()
+        .socket_mut()
+        .bind(&SocketAddr::new(0, NETLINK_CHANGE_GROUPS))
+        .map_err(|e| anyhow!("failed to subscribe netlink changes in {}: {}", netns, e))?;
+    tokio::spawn(connection);
+    Ok(messages.map(|_| ()))
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkConfig {
     pub(crate) netns: String,
     pub(crate) sandbox_id: String,
     pub(crate) queue: u32,
+    #[serde(default)]
+    pub(crate) bandwidth: BandwidthLimit,
+}
+
+/// Queues of the tap devices of a vm with `vcpus` vcpus, each vcpu handles the packets of its own
+/// queue pair, but there are no more than `max_queues` queues if it is not 0.
+pub fn net_queues(vcpus: u32, max_queues: u32) -> u32 {
+    let queues = vcpus.clamp(1, MAX_TAP_QUEUES);
+    if max_queues > 0 {
+        queues.min(max_queues)
+    } else {
+        queues
+    }
 }
 
 async fn run_in_new_netns<P: AsRef<Path>, F, T>(netns: P, f: F) -> Result<T>
@@ -256,7 +342,119 @@ impl Display for NetType {
 
 #[cfg(test)]
 mod tests {
-    use crate::network::{Network, NetworkConfig};
+    use crate::network::{
+        address::{IpNet, MacAddress},
+        link::LinkType,
+        merge_intfs, net_queues, Network, NetworkConfig, NetworkInterface,
+    };
+
+    fn interface(name: &str, r#type: LinkType) -> NetworkInterface {
+        NetworkInterface {
+            name: name.to_string(),
+            r#type,
+            ..NetworkInterface::default()
+        }
+    }
+
+    fn interface_with_mac(name: &str, index: u32, mac: &[u8], ip: &str) -> NetworkInterface {
+        NetworkInterface {
+            name: name.to_string(),
+            index,
+            r#type: LinkType::Veth,
+            mac_address: MacAddress(mac.to_vec()),
+            ip_addresses: vec![IpNet::from(ip.to_string())],
+            ..NetworkInterface::default()
+        }
+    }
+
+    #[test]
+    fn test_net_queues() {
+        assert_eq!(net_queues(0, 0), 1);
+        assert_eq!(net_queues(4, 0), 4);
+        assert_eq!(net_queues(16, 8), 8);
+        assert_eq!(net_queues(2, 8), 2);
+        assert_eq!(net_queues(1024, 0), 256);
+    }
+
+    #[test]
+    fn test_merge_intfs() {
+        // eth0 is recovered from a sandbox.json without the index and type
+        let mut eth0 = interface_with_mac("eth0", 0, &[2, 0, 0, 0, 0, 1], "10.0.0.2/24");
+        eth0.r#type = LinkType::Unkonwn;
+        let mut current = vec![
+            eth0,
+            interface_with_mac("eth1", 3, &[2, 0, 0, 0, 0, 2], "10.1.0.2/24"),
+            interface_with_mac("eth2", 4, &[2, 0, 0, 0, 0, 3], "10.2.0.2/24"),
+            interface_with_mac("eth4", 7, &[2, 0, 0, 0, 0, 6], "10.4.0.2/24"),
+        ];
+        let scanned = vec![
+            // the ip address of eth0 is changed
+            interface_with_mac("eth0", 2, &[2, 0, 0, 0, 0, 1], "10.0.0.3/24"),
+            // eth1 is recreated with another mac address
+            interface_with_mac("eth1", 5, &[2, 0, 0, 0, 0, 4], "10.1.0.2/24"),
+            // eth3 is added, and eth2 is gone
+            interface_with_mac("eth3", 6, &[2, 0, 0, 0, 0, 5], "10.3.0.2/24"),
+            // eth4 is recreated with the same mac address
+            interface_with_mac("eth4", 8, &[2, 0, 0, 0, 0, 6], "10.4.0.2/24"),
+        ];
+
+        let (added, removed) = merge_intfs(&mut current, scanned);
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].name, "eth0");
+        assert_eq!(current[0].index, 2);
+        assert!(matches!(current[0].r#type, LinkType::Veth));
+        assert_eq!(
+            String::from(current[0].ip_addresses[0].clone()),
+            "10.0.0.3/24"
+        );
+
+        let added = added.iter().map(|i| i.index).collect::<Vec<u32>>();
+        assert_eq!(added, vec![5, 6, 8]);
+        let removed = removed.iter().map(|i| i.index).collect::<Vec<u32>>();
+        assert_eq!(removed, vec![3, 4, 7]);
+    }
+
+    #[test]
+    fn test_recover_interface() {
+        let mut eth0 = interface_with_mac("eth0", 2, &[2, 0, 0, 0, 0, 1], "10.0.0.2/24");
+        eth0.r#type = LinkType::Vlan(100);
+        let json = serde_json::to_string(&eth0).unwrap();
+        let recovered: NetworkInterface = serde_json::from_str(&json).unwrap();
+        assert_eq!(recovered.index, 2);
+        assert!(matches!(recovered.r#type, LinkType::Vlan(100)));
+
+        // dumped before the index and type were persisted
+        let json = r#"{"name": "eth0", "IPAddresses": [], "hwAddr": "02:00:00:00:00:01",
+            "vhostUserSocket": ""}"#;
+        let recovered: NetworkInterface = serde_json::from_str(json).unwrap();
+        assert_eq!(recovered.index, 0);
+        assert!(matches!(recovered.r#type, LinkType::Unkonwn));
+    }
+
+    #[test]
+    fn test_filter_intfs() {
+        let intfs = vec![
+            interface("eth0", LinkType::Veth),
+            interface("eth1", LinkType::Macvlan(4)),
+            interface("eth2", LinkType::Ipvlan(1)),
+            interface("eth3", LinkType::Vlan(100)),
+            interface("br0", LinkType::Bridge),
+            interface("bond0", LinkType::Bond),
+            interface("vxlan0", LinkType::Vxlan(42)),
+            interface("tap0", LinkType::Tap),
+            interface("tun0", LinkType::Tun),
+            interface("macvtap0", LinkType::Macvtap(4)),
+            interface("unknown0", LinkType::Unkonwn),
+        ];
+        let names = Network::filter_intfs(intfs)
+            .into_iter()
+            .map(|i| i.name)
+            .collect::<Vec<String>>();
+        assert_eq!(
+            names,
+            vec!["eth0", "eth1", "eth2", "eth3", "br0", "bond0", "vxlan0", "tap0"]
+        );
+    }
 
     #[tokio::test]
     async fn test_new() {
@@ -264,6 +462,7 @@ mod tests {
             netns: "".to_string(),
             sandbox_id: "".to_string(),
             queue: 1,
+            bandwidth: Default::default(),
         })
         .await
         .unwrap();
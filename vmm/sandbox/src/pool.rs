@@ -0,0 +1,261 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{
+    collections::{HashSet, VecDeque},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use containerd_sandbox::{data::SandboxData, error::Result, SandboxOption};
+use log::{debug, error, info, warn};
+use nix::sys::signal;
+use serde::de::DeserializeOwned;
+use tokio::{
+    fs::{create_dir_all, read_dir, remove_dir_all},
+    sync::{Mutex, Notify},
+    time::sleep,
+};
+use uuid::Uuid;
+use vmm_common::SHARED_DIR_SUFFIX;
+
+use crate::{
+    client::{client_check, new_sandbox_client},
+    utils::{read_file, write_file_atomic},
+    vm::{VMFactory, VM},
+};
+
+const POOL_VM_FILE: &str = "vm.json";
+const POOL_RETRY_INTERVAL_IN_SEC: u64 = 5;
+
+/// A pre-booted vm waiting in the pool, the guest task agent is ready and the vm is paused.
+pub struct PooledVM<V> {
+    pub(crate) vm: V,
+    // the vm was booted with this base dir, sockets and the shared dir of the vm are in it.
+    pub(crate) base_dir: String,
+}
+
+impl<V> PooledVM<V> {
+    pub fn shared_dir(&self) -> String {
+        format!("{}/{}", self.base_dir, SHARED_DIR_SUFFIX)
+    }
+}
+
+/// VMPool keeps `size` vms booted ahead of the sandbox creations, so that the kernel boot
+/// and the task agent startup are not on the critical path of pod startup.
+pub struct VMPool<F: VMFactory> {
+    factory: Arc<F>,
+    dir: String,
+    size: usize,
+    vms: Mutex<VecDeque<PooledVM<F::VM>>>,
+    notify: Notify,
+}
+
+impl<F> VMPool<F>
+where
+    F: VMFactory,
+    F::VM: VM + Sync + Send,
+{
+    pub fn new(factory: Arc<F>, dir: &str, size: usize) -> Self {
+        Self {
+            factory,
+            dir: dir.to_string(),
+            size,
+            vms: Mutex::new(VecDeque::new()),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Take a running vm from the pool, the vm is still paused.
+    pub async fn take(&self) -> Option<PooledVM<F::VM>> {
+        let mut vms = self.vms.lock().await;
+        let mut taken = None;
+        while let Some(mut pooled) = vms.pop_front() {
+            if is_running(&pooled.vm).await {
+                taken = Some(pooled);
+                break;
+            }
+            warn!("pooled vm in {} exited, discard it", pooled.base_dir);
+            destroy(&mut pooled).await;
+        }
+        drop(vms);
+        self.notify.notify_one();
+        taken
+    }
+}
+
+impl<F> VMPool<F>
+where
+    F: VMFactory + Sync + Send + 'static,
+    F::VM: VM + DeserializeOwned + Sync + Send + 'static,
+{
+    /// Clean up the vms left by the last sandboxer process, and start filling the pool.
+    /// The vms in `taken_dirs` were taken by the recovered sandboxes and are kept running.
+    pub async fn run(self: &Arc<Self>, taken_dirs: &[String]) {
+        cleanup::<F::VM>(&self.dir, taken_dirs).await;
+        let pool = self.clone();
+        tokio::spawn(async move {
+            loop {
+                pool.fill().await;
+                pool.notify.notified().await;
+            }
+        });
+    }
+
+    async fn fill(&self) {
+        loop {
+            if self.vms.lock().await.len() >= self.size {
+                return;
+            }
+            match self.boot().await {
+                Ok(pooled) => {
+                    debug!("vm in {} is added to the pool", pooled.base_dir);
+                    self.vms.lock().await.push_back(pooled);
+                }
+                Err(e) => {
+                    error!("failed to boot vm for the pool: {}", e);
+                    sleep(Duration::from_secs(POOL_RETRY_INTERVAL_IN_SEC)).await;
+                }
+            }
+        }
+    }
+
+    async fn boot(&self) -> Result<PooledVM<F::VM>> {
+        let id = Uuid::new_v4().to_string();
+        let base_dir = format!("{}/{}", self.dir, id);
+        create_dir_all(format!("{}/{}", base_dir, SHARED_DIR_SUFFIX)).await?;
+        let option = SandboxOption::new(base_dir.to_string(), SandboxData::default());
+        let vm = match self.factory.create_vm(&id, &option).await {
+            Ok(vm) => vm,
+            Err(e) => {
+                remove_dir_all(&base_dir).await.unwrap_or_default();
+                return Err(e);
+            }
+        };
+        let mut pooled = PooledVM { vm, base_dir };
+        if let Err(e) = prepare(&mut pooled).await {
+            destroy(&mut pooled).await;
+            return Err(e);
+        }
+        Ok(pooled)
+    }
+}
+
+// Kill the vms in the pool dir and remove their dirs, except the ones taken by sandboxes,
+// as a taken vm keeps running in its base dir under the pool dir.
+async fn cleanup<V: VM + DeserializeOwned>(dir: &str, taken_dirs: &[String]) {
+    let taken: HashSet<PathBuf> = taken_dirs.iter().map(PathBuf::from).collect();
+    let mut entries = match read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) => {
+            if e.kind() != ErrorKind::NotFound {
+                warn!("failed to read pool dir {}: {}", dir, e);
+            }
+            return;
+        }
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if taken.contains(&path) {
+            debug!("pooled vm in {} is taken by a sandbox", path.display());
+            continue;
+        }
+        match read_file(path.join(POOL_VM_FILE)).await.and_then(|s| {
+            serde_json::from_str::<V>(&s)
+                .map_err(|e| anyhow!("failed to deserialize vm, {}", e).into())
+        }) {
+            Ok(vm) => kill(&vm),
+            Err(e) => warn!("failed to load pooled vm in {}: {}", path.display(), e),
+        }
+        if let Err(e) = remove_dir_all(&path).await {
+            warn!("failed to remove pooled vm dir {}: {}", path.display(), e);
+        }
+    }
+}
+
+// Start the vm, wait for the task agent and then pause the vm.
+async fn prepare<V: VM>(pooled: &mut PooledVM<V>) -> Result<()> {
+    pooled.vm.start().await?;
+    let client = new_sandbox_client(&pooled.vm.socket_address()).await?;
+    client_check(&client).await?;
+    pooled.vm.pause().await?;
+    let vm_data =
+        serde_json::to_string(&pooled.vm).map_err(|e| anyhow!("failed to serialize vm, {}", e))?;
+    write_file_atomic(Path::new(&pooled.base_dir).join(POOL_VM_FILE), &vm_data).await?;
+    info!("vm in {} is booted for the pool", pooled.base_dir);
+    Ok(())
+}
+
+/// Stop the vm and remove its base dir, used when a pooled vm can not be used.
+pub async fn destroy<V: VM>(pooled: &mut PooledVM<V>) {
+    if let Err(e) = pooled.vm.stop(true).await {
+        warn!("failed to stop pooled vm in {}: {}", pooled.base_dir, e);
+    }
+    remove_dir_all(&pooled.base_dir).await.unwrap_or_default();
+}
+
+async fn is_running<V: VM>(vm: &V) -> bool {
+    match vm.wait_channel().await {
+        Some(rx) => {
+            let (_, ts) = *rx.borrow();
+            ts == 0
+        }
+        None => false,
+    }
+}
+
+fn kill<V: VM>(vm: &V) {
+    let pids = vm.pids();
+    for pid in pids.vmm_pid.iter().chain(pids.affiliated_pids.iter()) {
+        if *pid > 0 {
+            pids.kill(*pid, signal::SIGKILL).unwrap_or_default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use temp_dir::TempDir;
+
+    use crate::{cloud_hypervisor::CloudHypervisorVM, pool::cleanup};
+
+    #[tokio::test]
+    async fn test_cleanup_keeps_taken_vms() {
+        let tmp_dir = TempDir::new().unwrap();
+        let pool_dir = tmp_dir.path().to_str().unwrap().to_string();
+        let taken = format!("{}/taken", pool_dir);
+        let idle = format!("{}/idle", pool_dir);
+        for dir in [&taken, &idle] {
+            tokio::fs::create_dir_all(format!("{}/shared", dir))
+                .await
+                .unwrap();
+        }
+
+        // the sandboxer restarts with a recovered sandbox running in the taken vm
+        cleanup::<CloudHypervisorVM>(&pool_dir, &[taken.to_string()]).await;
+        assert!(Path::new(&taken).join("shared").exists());
+        assert!(!Path::new(&idle).exists());
+
+        // the taken vm is removed once no sandbox refers to it
+        cleanup::<CloudHypervisorVM>(&pool_dir, &[]).await;
+        assert!(!Path::new(&taken).exists());
+    }
+}
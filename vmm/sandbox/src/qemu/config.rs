@@ -27,6 +27,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     param::ToCmdLineParams,
+    qemu::QEMU_MEMORY_HOTPLUG_ALIGN,
     utils::{bool_to_on_off, get_host_memory_in_mb},
     vm::{BlockDriver, HypervisorCommonConfig, ShareFsType},
 };
@@ -115,7 +116,7 @@ impl Default for QemuVMConfig {
             default_bridges: 1,
             default_max_vcpus: 0,
             entropy_source: "/dev/urandom".to_string(),
-            mem_slots: 1,
+            mem_slots: 0,
             mem_offset: 0,
             memory_path: "".to_string(),
             file_backend_mem_path: "".to_string(),
@@ -206,10 +207,15 @@ impl QemuVMConfig {
                 "Vhost-user-blk/scsi is enabled without hugepages enabled".to_string(),
             ));
         }
+        let max_mem_in_mb = get_host_memory_in_mb().await?;
         result.memory = Memory {
             size: format!("{}M", self.common.memory_in_mb),
-            slots: self.mem_slots,
-            max_mem: format!("{}M", get_host_memory_in_mb().await?),
+            slots: if self.mem_slots > 0 {
+                self.mem_slots
+            } else {
+                hotplug_mem_slots(self.common.memory_in_mb, max_mem_in_mb)
+            },
+            max_mem: format!("{}M", max_mem_in_mb),
             backend_type: MemoryBackend::Ram,
             pre_alloc: self.mem_prealloc,
             shared: self.enable_vhost_user_store,
@@ -512,6 +518,13 @@ pub struct IOThread {
     object: Object,
 }
 
+// Every memory hotplug takes a slot for a dimm of at least QEMU_MEMORY_HOTPLUG_ALIGN bytes,
+// so the slots are derived from the memory that can be hotplugged up to the max memory.
+fn hotplug_mem_slots(memory_in_mb: u32, max_mem_in_mb: u64) -> u8 {
+    let hotpluggable = max_mem_in_mb.saturating_sub(memory_in_mb as u64) << 20;
+    (hotpluggable / QEMU_MEMORY_HOTPLUG_ALIGN).clamp(1, u8::MAX as u64) as u8
+}
+
 #[derive(CmdLineParams, Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Object {
     #[property(ignore_key)]
@@ -525,7 +538,9 @@ mod tests {
 
     use crate::{
         param::ToCmdLineParams,
-        qemu::config::{IOThread, Incoming, MigrationType, Object, QemuVMConfig, QmpSocket},
+        qemu::config::{
+            hotplug_mem_slots, IOThread, Incoming, MigrationType, Object, QemuVMConfig, QmpSocket,
+        },
     };
 
     #[tokio::test]
@@ -557,4 +572,13 @@ mod tests {
         eprintln!("params: {:?}", params);
         // TODO asserts
     }
+
+    #[test]
+    fn test_hotplug_mem_slots() {
+        // 128M each slot
+        assert_eq!(hotplug_mem_slots(1024, 2048), 8);
+        assert_eq!(hotplug_mem_slots(1024, 1024 + 100), 1);
+        assert_eq!(hotplug_mem_slots(2048, 1024), 1);
+        assert_eq!(hotplug_mem_slots(1024, 1024 * 1024), u8::MAX);
+    }
 }
@@ -20,16 +20,21 @@ use log::{debug, error};
 use qapi::{
     qmp::{
         blockdev_add, blockdev_del, device_add, BlockdevOptions, BlockdevOptionsBase,
-        BlockdevOptionsFile,
+        BlockdevOptionsFile, BlockdevOptionsThrottle, BlockdevRef,
     },
     Dictionary,
 };
 use sandbox_derive::CmdLineParams;
-use serde_json::Value;
+use serde_json::{json, Value};
 
 use crate::{
     device::{BusType, Device, Transport},
-    qemu::{devices::HotAttachable, qmp_client::QmpClient},
+    qemu::{
+        devices::HotAttachable,
+        qmp::{ObjectAdd, ObjectDel},
+        qmp_client::QmpClient,
+    },
+    storage::ratelimit::DiskRateLimit,
 };
 
 pub const VIRTIO_BLK_DRIVER: &str = "virtio-blk";
@@ -84,6 +89,10 @@ pub struct VirtioBlockDevice {
     pub share_rw: bool,
     #[property(param = "drive", generator = "crate::utils::bool_to_on_off")]
     pub readonly: bool,
+    #[property(param = "drive", key = "throttling.bps-total")]
+    pub bps_total: Option<u64>,
+    #[property(param = "drive", key = "throttling.iops-total")]
+    pub iops_total: Option<u64>,
 }
 
 impl_device_no_bus!(VirtioBlockDevice);
@@ -110,8 +119,23 @@ impl VirtioBlockDevice {
             disable_legacy: true,
             #[cfg(feature = "virtcca")]
             iommu_platform: true,
+            bps_total: None,
+            iops_total: None,
         }
     }
+
+    pub fn set_rate_limit(&mut self, limit: &DiskRateLimit) {
+        self.bps_total = limit.bandwidth;
+        self.iops_total = limit.iops;
+    }
+
+    fn is_throttled(&self) -> bool {
+        self.bps_total.is_some() || self.iops_total.is_some()
+    }
+
+    fn throttle_group_id(&self) -> String {
+        format!("throttle-{}", self.id)
+    }
 }
 
 #[async_trait]
@@ -124,7 +148,13 @@ impl HotAttachable for VirtioBlockDevice {
         slot_index: usize,
     ) -> Result<()> {
         debug!("hot attach block device {}", self.id);
-        client.execute(self.to_blockdev_add()).await?;
+        if let Some(group) = self.to_throttle_group_add() {
+            client.execute(group).await?;
+        }
+        if let Err(e) = client.execute(self.to_blockdev_add()).await {
+            self.delete_throttle_group(client).await;
+            return Err(e);
+        }
         match client
             .execute(self.to_device_add(bus_type, bus_id, slot_index))
             .await
@@ -138,6 +168,7 @@ impl HotAttachable for VirtioBlockDevice {
                         error!("failed to delete blockdev after device_add failed, {:?}", e);
                         qapi::Empty {}
                     });
+                self.delete_throttle_group(client).await;
                 Err(e)
             }
         }
@@ -148,13 +179,19 @@ impl HotAttachable for VirtioBlockDevice {
         let device_id = format!("virtio-{}", self.id());
         client.delete_device(&device_id).await?;
         client.execute(self.to_blockdev_del()).await?;
+        self.delete_throttle_group(client).await;
         Ok(())
     }
 }
 
 impl VirtioBlockDevice {
+    // The throttled device is a throttle node of the throttle group with the host device
+    // as its child, the device is attached to the throttle node which has the id as node name.
     fn to_blockdev_add(&self) -> blockdev_add {
-        blockdev_add(BlockdevOptions::host_device {
+        if !self.is_throttled() {
+            return blockdev_add(self.to_host_device(Some(self.id.to_string())));
+        }
+        blockdev_add(BlockdevOptions::throttle {
             base: BlockdevOptionsBase {
                 node_name: Some(self.id.to_string()),
                 read_only: Some(self.readonly),
@@ -164,6 +201,24 @@ impl VirtioBlockDevice {
                 discard: None,
                 detect_zeroes: None,
             },
+            throttle: BlockdevOptionsThrottle {
+                throttle_group: self.throttle_group_id(),
+                file: BlockdevRef::definition(Box::new(self.to_host_device(None))),
+            },
+        })
+    }
+
+    fn to_host_device(&self, node_name: Option<String>) -> BlockdevOptions {
+        BlockdevOptions::host_device {
+            base: BlockdevOptionsBase {
+                node_name,
+                read_only: Some(self.readonly),
+                auto_read_only: None,
+                cache: None,
+                force_share: None,
+                discard: None,
+                detect_zeroes: None,
+            },
             host_device: BlockdevOptionsFile {
                 drop_cache: None,
                 locking: None,
@@ -172,9 +227,39 @@ impl VirtioBlockDevice {
                 aio: None,
                 pr_manager: None,
             },
+        }
+    }
+
+    fn to_throttle_group_add(&self) -> Option<ObjectAdd> {
+        if !self.is_throttled() {
+            return None;
+        }
+        let mut limits = Dictionary::new();
+        if let Some(bps) = self.bps_total {
+            limits.insert("bps-total".to_string(), json!(bps));
+        }
+        if let Some(iops) = self.iops_total {
+            limits.insert("iops-total".to_string(), json!(iops));
+        }
+        let mut props = Dictionary::new();
+        props.insert("limits".to_string(), Value::Object(limits));
+        Some(ObjectAdd {
+            qom_type: "throttle-group".to_string(),
+            id: self.throttle_group_id(),
+            props,
         })
     }
 
+    async fn delete_throttle_group(&self, client: &QmpClient) {
+        if !self.is_throttled() {
+            return;
+        }
+        let id = self.throttle_group_id();
+        if let Err(e) = client.execute(ObjectDel { id: id.to_string() }).await {
+            error!("failed to delete throttle group {}, {:?}", id, e);
+        }
+    }
+
     fn to_device_add(&self, bus_type: &BusType, bus_id: &str, index: usize) -> device_add {
         let mut args = Dictionary::new();
         if self.share_rw {
@@ -226,3 +311,39 @@ impl VirtioBlockDevice {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        param::ToParams,
+        qemu::devices::block::{VirtioBlockDevice, VIRTIO_BLK_DRIVER},
+        storage::ratelimit::DiskRateLimit,
+    };
+
+    #[test]
+    fn test_rate_limit() {
+        let mut device = VirtioBlockDevice::new(
+            VIRTIO_BLK_DRIVER,
+            "blk0",
+            Some("/dev/sdb".to_string()),
+            false,
+        );
+        assert!(device.to_throttle_group_add().is_none());
+
+        device.set_rate_limit(&DiskRateLimit {
+            bandwidth: None,
+            iops: Some(1000),
+        });
+        let params = device.to_params();
+        let drive = params.iter().find(|p| p.name == "drive").unwrap();
+        assert_eq!(drive.get("throttling.iops-total").unwrap(), "1000");
+        assert!(drive.get("throttling.bps-total").is_none());
+
+        let group = device.to_throttle_group_add().unwrap();
+        assert_eq!(group.id, "throttle-blk0");
+        assert_eq!(
+            serde_json::to_string(&group).unwrap(),
+            r#"{"qom-type":"throttle-group","id":"throttle-blk0","limits":{"iops-total":1000}}"#
+        );
+    }
+}
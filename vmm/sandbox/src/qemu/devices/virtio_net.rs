@@ -116,7 +116,8 @@ impl VirtioNetDevice {
         vhostfds: Vec<RawFd>,
     ) -> Self {
         let driver = transport.to_driver(VIRTIO_NET_DRIVER);
-        let multi_queue = !fds.is_empty();
+        // each fd is a queue pair of the tap device
+        let multi_queue = fds.len() > 1;
         Self {
             r#type: NetType::Tap,
             transport,
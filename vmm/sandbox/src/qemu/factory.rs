@@ -56,6 +56,15 @@ impl VMFactory for QemuVMFactory {
         }
     }
 
+    fn hypervisor(&self) -> &'static str {
+        "qemu"
+    }
+
+    fn pool_supported(&self) -> bool {
+        // tap devices can not be hot attached to qemu vms yet
+        false
+    }
+
     async fn create_vm(
         &self,
         id: &str,
@@ -68,6 +77,7 @@ impl VMFactory for QemuVMFactory {
         vm.config.name = format!("sandbox-{}", id);
         vm.config.pid_file = format!("{}/sandbox-{}.pid", s.base_dir, id);
         vm.block_driver = self.default_config.block_device_driver.clone();
+        vm.max_net_queues = self.default_config.common.max_net_queues;
 
         // set qmp socket
         vm.config.qmp_socket = Some(QmpSocket {
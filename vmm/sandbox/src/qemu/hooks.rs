@@ -25,7 +25,6 @@ use crate::{
 };
 
 pub struct QemuHooks {
-    #[allow(dead_code)]
     config: QemuVMConfig,
 }
 
@@ -39,7 +38,7 @@ impl QemuHooks {
 impl Hooks<QemuVM> for QemuHooks {
     async fn pre_start(&self, sandbox: &mut KuasarSandbox<QemuVM>) -> Result<()> {
         process_annotation(sandbox).await?;
-        process_config(sandbox).await?;
+        process_config(sandbox, &self.config).await?;
         Ok(())
     }
 
@@ -55,13 +54,18 @@ async fn process_annotation(_sandbox: &KuasarSandbox<QemuVM>) -> Result<()> {
     Ok(())
 }
 
-async fn process_config(sandbox: &mut KuasarSandbox<QemuVM>) -> Result<()> {
+async fn process_config(sandbox: &mut KuasarSandbox<QemuVM>, config: &QemuVMConfig) -> Result<()> {
     if let Some(resources) = get_resources(&sandbox.data) {
         if resources.cpu_period > 0 && resources.cpu_quota > 0 {
             // get ceil of cpus if it is not integer
             let base = (resources.cpu_quota as f64 / resources.cpu_period as f64).ceil();
             sandbox.vm.config.smp.cpus = base as u32;
-            sandbox.vm.config.smp.max_cpus = base as u32;
+            // keep the configured max vcpus so that vcpus can be hot plugged later
+            let max_cpus = config.default_max_vcpus.max(base as u32);
+            sandbox.vm.config.smp.max_cpus = max_cpus;
+            if sandbox.vm.config.smp.sockets > 0 {
+                sandbox.vm.config.smp.sockets = max_cpus;
+            }
         }
         if resources.memory_limit_in_bytes > 0 {
             sandbox.vm.config.memory.size = format!(
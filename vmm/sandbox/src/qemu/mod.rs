@@ -15,7 +15,6 @@ limitations under the License.
 */
 
 use std::{
-    collections::HashMap,
     os::{
         fd::OwnedFd,
         unix::io::{AsRawFd, FromRawFd, RawFd},
@@ -29,25 +28,34 @@ use async_trait::async_trait;
 use containerd_sandbox::error::{Error, Result};
 use futures_util::TryFutureExt;
 use log::{debug, error, trace, warn};
-use nix::{fcntl::OFlag, libc::kill, sys::stat::Mode};
-use qapi::qmp::quit;
+use nix::{fcntl::OFlag, sys::signal::Signal, sys::stat::Mode};
+use qapi::{
+    qmp::{
+        cont, device_add, migrate, query_cpus_fast, query_migrate, query_status, quit, stop,
+        MigrationStatus, RunState,
+    },
+    Dictionary,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use time::OffsetDateTime;
 use tokio::{
+    fs::create_dir_all,
     net::UnixStream,
-    process::Child,
-    sync::watch::{channel, Receiver, Sender},
-    task::{spawn_blocking, JoinHandle},
+    sync::watch::{channel, Receiver},
+    task::spawn_blocking,
     time::sleep,
 };
 use unshare::Fd;
+use vmm_common::process::ProcessHandle;
 
 use crate::{
     device::{BusType, DeviceInfo, SlotStatus, Transport},
     impl_recoverable,
+    network::net_queues,
     param::ToCmdLineParams,
     qemu::{
-        config::{QemuConfig, VirtiofsdConfig},
+        config::{Incoming, MemoryBackend, MigrationType, QemuConfig, VirtiofsdConfig},
         devices::{
             block::{VirtioBlockDevice, VIRTIO_BLK_DRIVER},
             char::{CharDevice, VIRT_SERIAL_PORT_DRIVER},
@@ -57,9 +65,9 @@ use crate::{
             QemuDevice, QemuHotAttachable,
         },
         qmp_client::QmpClient,
-        utils::detect_pid,
+        utils::{detect_pid, parse_memory_size, shell_quote, vcpu_threads},
     },
-    utils::{read_std, set_cmd_netns, wait_channel, wait_pid, write_file_atomic},
+    utils::{read_std, set_cmd_netns, spawn_wait, wait_channel},
     vm::{BlockDriver, Pids, VcpuThreads, VM},
 };
 
@@ -67,10 +75,15 @@ pub mod config;
 mod devices;
 pub mod factory;
 pub mod hooks;
+mod qmp;
 mod qmp_client;
 mod utils;
 
 pub(crate) const QEMU_START_TIMEOUT_IN_SEC: u64 = 10;
+pub(crate) const QEMU_MIGRATE_TIMEOUT_IN_SEC: u64 = 300;
+const SNAPSHOT_STATE_FILE: &str = "vmstate";
+// linux hot adds memory in blocks of 128MiB
+const QEMU_MEMORY_HOTPLUG_ALIGN: u64 = 128 << 20;
 
 // restart recovery is not supported yet,
 // so we annotate the QemuVM with Serialize and Deserlize,
@@ -96,15 +109,28 @@ pub struct QemuVM {
     #[serde(skip)]
     client: Option<QmpClient>,
     virtiofsd_config: Option<VirtiofsdConfig>,
+    #[serde(default)]
+    hotplugged_vcpus: Vec<String>,
+    // ids of the hotplugged vcpus are not reused, as device_del completes asynchronously
+    #[serde(default)]
+    next_vcpu_id: u32,
+    #[serde(default)]
+    hotplugged_dimms: Vec<String>,
+    #[serde(default)]
+    hotplugged_memory_size: u64,
+    #[serde(default)]
+    max_net_queues: u32,
 }
 
 #[async_trait]
 impl VM for QemuVM {
     async fn start(&mut self) -> Result<u32> {
         debug!("start vm {}", self.id);
+        // pids of the last run are stale if the vm is started again by restore
+        self.pids = Pids::default();
         if self.virtiofsd_config.is_some() {
             let virtiofsd_pid = self.start_virtiofsd().await?;
-            self.pids.affiliated_pids.push(virtiofsd_pid);
+            self.pids.add_affiliated_pid(virtiofsd_pid);
         }
         let wait_chan = self.launch().await?;
         self.wait_chan = Some(wait_chan);
@@ -138,8 +164,8 @@ impl VM for QemuVM {
         });
         // update vmm related pids
         let vmm_pid = detect_pid(self.config.pid_file.as_str(), self.config.path.as_str()).await?;
-        self.pids.vmm_pid = Some(vmm_pid);
-        Ok(0)
+        self.pids.set_vmm_pid(vmm_pid);
+        Ok(vmm_pid)
     }
 
     async fn stop(&mut self, force: bool) -> Result<()> {
@@ -156,7 +182,7 @@ impl VM for QemuVM {
         if let Err(e) = self.wait_stop(Duration::from_secs(10)).await {
             if force {
                 if let Ok(pid) = self.pid() {
-                    unsafe { kill(pid as i32, 9) };
+                    self.pids.kill(pid, Signal::SIGKILL).unwrap_or_default();
                 }
             } else {
                 return Err(e);
@@ -169,12 +195,13 @@ impl VM for QemuVM {
     async fn attach(&mut self, device_info: DeviceInfo) -> Result<()> {
         match device_info {
             DeviceInfo::Block(blk_info) => {
-                let device = VirtioBlockDevice::new(
+                let mut device = VirtioBlockDevice::new(
                     &Transport::Pci.to_driver(VIRTIO_BLK_DRIVER),
                     &blk_info.id,
                     Some(blk_info.path),
                     blk_info.read_only,
                 );
+                device.set_rate_limit(&blk_info.rate_limit);
                 self.attach_device(device);
             }
             DeviceInfo::Tap(tap_info) => {
@@ -223,12 +250,13 @@ impl VM for QemuVM {
     async fn hot_attach(&mut self, device_info: DeviceInfo) -> Result<(BusType, String)> {
         match device_info {
             DeviceInfo::Block(blk_info) => {
-                let device = VirtioBlockDevice::new(
+                let mut device = VirtioBlockDevice::new(
                     "",
                     &blk_info.id,
                     Some(blk_info.path),
                     blk_info.read_only,
                 );
+                device.set_rate_limit(&blk_info.rate_limit);
                 let (bus_addr, index) = self
                     .hot_attach_device(device, self.block_driver.to_bus_type())
                     .await?;
@@ -300,7 +328,7 @@ impl VM for QemuVM {
 
     async fn ping(&self) -> Result<()> {
         let client = self.get_client()?;
-        let _res = client.execute(qapi::qmp::query_status {}).await?;
+        let _res = client.execute(query_status {}).await?;
         Ok(())
     }
 
@@ -313,15 +341,208 @@ impl VM for QemuVM {
     }
 
     async fn vcpus(&self) -> Result<VcpuThreads> {
-        // TODO: support get vcpu threads id
-        Ok(VcpuThreads {
-            vcpus: HashMap::new(),
-        })
+        let cpus = self.get_client()?.execute(query_cpus_fast {}).await?;
+        vcpu_threads(&cpus)
     }
 
     fn pids(&self) -> Pids {
-        // TODO: support get all vmm related pids
-        Pids::default()
+        self.pids.clone()
+    }
+
+    async fn pause(&mut self) -> Result<()> {
+        self.get_client()?.execute(stop {}).await?;
+        Ok(())
+    }
+
+    async fn resume(&mut self) -> Result<()> {
+        self.get_client()?.execute(cont {}).await?;
+        Ok(())
+    }
+
+    async fn snapshot(&mut self, path: &str) -> Result<()> {
+        // the restored vm is started with the devices in the command line only, so the state of
+        // the hot attached devices would have no device to be loaded into.
+        if !self.hot_attached_devices.is_empty() {
+            let ids = self
+                .hot_attached_devices
+                .iter()
+                .map(|d| d.id())
+                .collect::<Vec<String>>();
+            return Err(Error::Unimplemented(format!(
+                "snapshot of qemu vm with hot attached devices {}, \
+                 checkpoint it before the containers or interfaces are added",
+                ids.join(",")
+            )));
+        }
+        create_dir_all(path).await?;
+        let client = self.get_client()?;
+        client.execute(stop {}).await?;
+        let state_file = format!("{}/{}", path, SNAPSHOT_STATE_FILE);
+        let res = self
+            .migrate(format!("exec:cat > {}", shell_quote(&state_file)))
+            .await;
+        // resume the vm even if snapshot failed
+        self.get_client()?.execute(cont {}).await?;
+        res
+    }
+
+    async fn restore(&mut self, path: &str) -> Result<u32> {
+        self.config.incoming = Some(Incoming {
+            migration_type: MigrationType::Exec(format!(
+                "cat {}",
+                shell_quote(&format!("{}/{}", path, SNAPSHOT_STATE_FILE))
+            )),
+        });
+        let res = self.start().await;
+        self.config.incoming = None;
+        let pid = res?;
+        if let Err(e) = self.wait_incoming().await {
+            if let Err(re) = self.stop(true).await {
+                warn!("roll back in restore qemu: {}", re);
+                return Err(e);
+            }
+            return Err(e);
+        }
+        Ok(pid)
+    }
+
+    async fn resize_vcpus(&mut self, vcpus: u32) -> Result<()> {
+        let client = self
+            .client
+            .as_ref()
+            .ok_or_else(|| anyhow!("qmp client is not init"))?;
+        let cpus = client.execute(qmp::QueryHotpluggableCpus {}).await?;
+        let mut online: u32 = cpus
+            .iter()
+            .filter(|c| c.qom_path.is_some())
+            .map(|c| c.vcpus_count)
+            .sum();
+        if vcpus >= online {
+            for cpu in cpus.iter().filter(|c| c.qom_path.is_none()) {
+                if online >= vcpus {
+                    break;
+                }
+                let id = loop {
+                    let id = format!("vcpu-{}", self.next_vcpu_id);
+                    self.next_vcpu_id += 1;
+                    // ids may be taken by vcpus hotplugged before next_vcpu_id is recorded
+                    let qom_path = vcpu_qom_path(&id);
+                    if !cpus.iter().any(|c| c.qom_path.as_ref() == Some(&qom_path)) {
+                        break id;
+                    }
+                };
+                client
+                    .execute(device_add {
+                        driver: cpu.driver.to_string(),
+                        bus: None,
+                        id: Some(id.to_string()),
+                        arguments: cpu.props.clone(),
+                    })
+                    .await?;
+                self.hotplugged_vcpus.push(id);
+                online += cpu.vcpus_count;
+            }
+            if online < vcpus {
+                return Err(Error::ResourceExhausted(format!(
+                    "vcpus of qemu {}, {} vcpus at most",
+                    self.id, online
+                )));
+            }
+            return Ok(());
+        }
+        while online > vcpus {
+            // vcpus on the command line can not be unplugged
+            let id = match self.hotplugged_vcpus.pop() {
+                Some(id) => id,
+                None => {
+                    warn!("qemu {} keeps {} boot vcpus", self.id, online);
+                    break;
+                }
+            };
+            // a vcpu device has vcpus_count threads, usually 1 unless threads > 1
+            let qom_path = vcpu_qom_path(&id);
+            let count = cpus
+                .iter()
+                .find(|c| c.qom_path.as_ref() == Some(&qom_path))
+                .map(|c| c.vcpus_count)
+                .unwrap_or(1);
+            if let Err(e) = client.delete_device(&id).await {
+                self.hotplugged_vcpus.push(id);
+                return Err(e);
+            }
+            online = online.saturating_sub(count);
+        }
+        Ok(())
+    }
+
+    async fn resize_memory(&mut self, size: u64) -> Result<()> {
+        let current = parse_memory_size(&self.config.memory.size)? + self.hotplugged_memory_size;
+        if size <= current {
+            // pc-dimm can only be unplugged after the guest offlined it,
+            // the memory usage is limited by the cgroups in guest instead.
+            debug!(
+                "memory of qemu {} is {} bytes, skip shrinking",
+                self.id, current
+            );
+            return Ok(());
+        }
+        let delta = (size - current + QEMU_MEMORY_HOTPLUG_ALIGN - 1) / QEMU_MEMORY_HOTPLUG_ALIGN
+            * QEMU_MEMORY_HOTPLUG_ALIGN;
+        let index = self.hotplugged_dimms.len();
+        if index >= self.config.memory.slots as usize {
+            return Err(Error::ResourceExhausted(format!(
+                "memory slots of qemu {}, {} slots at most",
+                self.id, self.config.memory.slots
+            )));
+        }
+        let mem_id = format!("hotplug-mem{}", index);
+        let dimm_id = format!("hotplug-dimm{}", index);
+
+        let mut props = Dictionary::new();
+        props.insert("size".to_string(), Value::from(delta));
+        props.insert("share".to_string(), Value::from(self.config.memory.shared));
+        let qom_type = match &self.config.memory.backend_type {
+            MemoryBackend::Ram => "memory-backend-ram",
+            MemoryBackend::File(f) => {
+                props.insert("mem-path".to_string(), Value::from(f.to_string()));
+                "memory-backend-file"
+            }
+        };
+        let client = self.get_client()?;
+        client
+            .execute(qmp::ObjectAdd {
+                qom_type: qom_type.to_string(),
+                id: mem_id.to_string(),
+                props,
+            })
+            .await?;
+        let mut args = Dictionary::new();
+        args.insert("memdev".to_string(), Value::from(mem_id.to_string()));
+        if let Err(e) = client
+            .execute(device_add {
+                driver: "pc-dimm".to_string(),
+                bus: None,
+                id: Some(dimm_id.to_string()),
+                arguments: args,
+            })
+            .await
+        {
+            if let Err(re) = client.execute(qmp::ObjectDel { id: mem_id }).await {
+                warn!("roll back in hot plug memory of qemu: {}", re);
+            }
+            return Err(e);
+        }
+        self.hotplugged_dimms.push(dimm_id);
+        self.hotplugged_memory_size += delta;
+        Ok(())
+    }
+
+    fn net_queues(&self) -> u32 {
+        net_queues(self.config.smp.cpus, self.max_net_queues)
+    }
+
+    fn shared_fs_supported(&self) -> bool {
+        true
     }
 }
 
@@ -341,6 +562,11 @@ impl QemuVM {
             wait_chan: None,
             client: None,
             virtiofsd_config: None,
+            hotplugged_vcpus: vec![],
+            next_vcpu_id: 0,
+            hotplugged_dimms: vec![],
+            hotplugged_memory_size: 0,
+            max_net_queues: 0,
         }
     }
 
@@ -411,16 +637,19 @@ impl QemuVM {
         let (tx, rx) = channel((0u32, 0i128));
         let _wait_handle = tokio::spawn(async move {
             // because the direct child process is not the actual running qemu process,
-            // so we have to read pid from the qemu.pid file,
-            // NOTE: it is hard to eliminate the race condition when pid reused.
-            if let Ok(pid) = detect_pid(&pid_file, &path).await {
-                let wait_result = wait_pid(pid as i32).await;
-                tx.send(wait_result).unwrap_or_default();
-            } else {
-                warn!("failed to get qemu pid from {}", pid_file);
-                tx.send((0, OffsetDateTime::now_utc().unix_timestamp_nanos()))
-                    .unwrap_or_default();
-            }
+            // so we have to read pid from the qemu.pid file, and wait it by pidfd.
+            let process = match detect_pid(&pid_file, &path).await {
+                Ok(pid) => ProcessHandle::open(pid, 0),
+                Err(_) => {
+                    warn!("failed to get qemu pid from {}", pid_file);
+                    None
+                }
+            };
+            let wait_result = match process {
+                Some(p) => p.wait().await,
+                None => (0, OffsetDateTime::now_utc().unix_timestamp_nanos()),
+            };
+            tx.send(wait_result).unwrap_or_default();
         });
         Ok(rx)
     }
@@ -443,6 +672,61 @@ impl QemuVM {
         Ok(client)
     }
 
+    async fn migrate(&self, uri: String) -> Result<()> {
+        let client = self.get_client()?;
+        client
+            .execute(migrate {
+                uri,
+                blk: None,
+                inc: None,
+                detach: None,
+                resume: None,
+            })
+            .await?;
+        let start_time = SystemTime::now();
+        loop {
+            let info = client.execute(query_migrate {}).await?;
+            match info.status {
+                Some(MigrationStatus::completed) => return Ok(()),
+                Some(MigrationStatus::failed) | Some(MigrationStatus::cancelled) => {
+                    return Err(anyhow!(
+                        "migration of qemu {} failed: {}",
+                        self.id,
+                        info.error_desc.unwrap_or_default()
+                    )
+                    .into());
+                }
+                _ => {}
+            }
+            if start_time.elapsed().unwrap().as_secs() > QEMU_MIGRATE_TIMEOUT_IN_SEC {
+                return Err(anyhow!("timeout waiting for migration of qemu {}", self.id).into());
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    // Wait for the incoming migration finished, and resume the vm if it stays paused.
+    async fn wait_incoming(&self) -> Result<()> {
+        let client = self.get_client()?;
+        let start_time = SystemTime::now();
+        loop {
+            let status = client.execute(query_status {}).await?;
+            if status.running {
+                return Ok(());
+            }
+            if status.status != RunState::inmigrate {
+                client.execute(cont {}).await?;
+                return Ok(());
+            }
+            if start_time.elapsed().unwrap().as_secs() > QEMU_MIGRATE_TIMEOUT_IN_SEC {
+                return Err(
+                    anyhow!("timeout waiting for incoming migration of qemu {}", self.id).into(),
+                );
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
     async fn wait_stop(&mut self, t: Duration) -> Result<()> {
         if let Some(rx) = self.wait_chan.clone() {
             let (_, ts) = *rx.borrow();
@@ -556,59 +840,9 @@ impl QemuVM {
     }
 }
 
-macro_rules! read_stdio {
-    ($stdio:expr, $cmd_name:ident) => {
-        if let Some(std) = $stdio {
-            let cmd_name_clone = $cmd_name.clone();
-            tokio::spawn(async move {
-                read_std(std, &cmd_name_clone).await.unwrap_or_default();
-            });
-        }
-    };
-}
-
-fn spawn_wait(
-    child: Child,
-    cmd_name: String,
-    pid_file_path: Option<String>,
-    exit_chan: Option<Sender<(u32, i128)>>,
-) -> JoinHandle<()> {
-    let mut child = child;
-    tokio::spawn(async move {
-        if let Some(pid_file) = pid_file_path {
-            if let Some(pid) = child.id() {
-                write_file_atomic(&pid_file, &pid.to_string())
-                    .await
-                    .unwrap_or_default();
-            }
-        }
-
-        read_stdio!(child.stdout.take(), cmd_name);
-        read_stdio!(child.stderr.take(), cmd_name);
-
-        match child.wait().await {
-            Ok(status) => {
-                if !status.success() {
-                    error!("{} exit {}", cmd_name, status);
-                }
-                let now = OffsetDateTime::now_utc();
-                if let Some(tx) = exit_chan {
-                    tx.send((
-                        status.code().unwrap_or_default() as u32,
-                        now.unix_timestamp_nanos(),
-                    ))
-                    .unwrap_or_default();
-                }
-            }
-            Err(e) => {
-                error!("{} wait error {}", cmd_name, e);
-                let now = OffsetDateTime::now_utc();
-                if let Some(tx) = exit_chan {
-                    tx.send((0, now.unix_timestamp_nanos())).unwrap_or_default();
-                }
-            }
-        }
-    })
+// vcpus added by device_add are placed in the qom tree by their ids
+fn vcpu_qom_path(id: &str) -> String {
+    format!("/machine/peripheral/{}", id)
 }
 
 impl_recoverable!(QemuVM);
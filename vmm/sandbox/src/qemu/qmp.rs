@@ -0,0 +1,149 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use qapi::{qmp::QmpCommand, Dictionary};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueryHotpluggableCpus {}
+
+impl QmpCommand for QueryHotpluggableCpus {}
+impl ::qapi_spec::Command for QueryHotpluggableCpus {
+    const NAME: &'static str = "query-hotpluggable-cpus";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = Vec<HotpluggableCpu>;
+}
+
+// The props differ between machine types and qemu versions,
+// they are kept as a dictionary and passed to device_add as they are.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HotpluggableCpu {
+    #[serde(rename = "type")]
+    pub driver: String,
+    #[serde(rename = "vcpus-count")]
+    pub vcpus_count: u32,
+    pub props: Dictionary,
+    #[serde(rename = "qom-path", default)]
+    pub qom_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectAdd {
+    #[serde(rename = "qom-type")]
+    pub qom_type: String,
+    pub id: String,
+    #[serde(flatten)]
+    pub props: Dictionary,
+}
+
+impl QmpCommand for ObjectAdd {}
+impl ::qapi_spec::Command for ObjectAdd {
+    const NAME: &'static str = "object-add";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = ::qapi_spec::Empty;
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ObjectDel {
+    pub id: String,
+}
+
+impl QmpCommand for ObjectDel {}
+impl ::qapi_spec::Command for ObjectDel {
+    const NAME: &'static str = "object-del";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = ::qapi_spec::Empty;
+}
+
+#[cfg(test)]
+mod tests {
+    use qapi::{qmp::device_add, Dictionary};
+    use serde_json::{json, Value};
+
+    use crate::qemu::qmp::{HotpluggableCpu, ObjectAdd, ObjectDel};
+
+    #[test]
+    fn test_hotpluggable_cpus() {
+        let reply = r#"[
+            {"props": {"core-id": 0, "thread-id": 0, "socket-id": 1},
+             "vcpus-count": 1, "type": "host-x86_64-cpu"},
+            {"props": {"core-id": 0, "thread-id": 0, "socket-id": 0},
+             "vcpus-count": 1, "qom-path": "/machine/unattached/device[0]",
+             "type": "host-x86_64-cpu"}
+        ]"#;
+        let cpus: Vec<HotpluggableCpu> = serde_json::from_str(reply).unwrap();
+        assert_eq!(cpus.len(), 2);
+        assert!(cpus[0].qom_path.is_none());
+        assert_eq!(
+            cpus[1].qom_path.as_deref(),
+            Some("/machine/unattached/device[0]")
+        );
+
+        // the props of the cpu are passed to device_add as they are
+        let cmd = device_add {
+            driver: cpus[0].driver.to_string(),
+            bus: None,
+            id: Some("vcpu-1".to_string()),
+            arguments: cpus[0].props.clone(),
+        };
+        assert_eq!(
+            serde_json::to_value(&cmd).unwrap(),
+            json!({"driver": "host-x86_64-cpu", "id": "vcpu-1",
+                   "core-id": 0, "thread-id": 0, "socket-id": 1})
+        );
+    }
+
+    #[test]
+    fn test_memory_hotplug_commands() {
+        let mut props = Dictionary::new();
+        props.insert("size".to_string(), Value::from(134217728u64));
+        props.insert("share".to_string(), Value::from(true));
+        let cmd = ObjectAdd {
+            qom_type: "memory-backend-ram".to_string(),
+            id: "hotplug-mem0".to_string(),
+            props,
+        };
+        assert_eq!(
+            serde_json::to_value(&cmd).unwrap(),
+            json!({"qom-type": "memory-backend-ram", "id": "hotplug-mem0",
+                   "size": 134217728u64, "share": true})
+        );
+
+        let mut args = Dictionary::new();
+        args.insert("memdev".to_string(), Value::from("hotplug-mem0"));
+        let cmd = device_add {
+            driver: "pc-dimm".to_string(),
+            bus: None,
+            id: Some("hotplug-dimm0".to_string()),
+            arguments: args,
+        };
+        assert_eq!(
+            serde_json::to_value(&cmd).unwrap(),
+            json!({"driver": "pc-dimm", "id": "hotplug-dimm0", "memdev": "hotplug-mem0"})
+        );
+
+        let cmd = ObjectDel {
+            id: "hotplug-mem0".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&cmd).unwrap(),
+            r#"{"id":"hotplug-mem0"}"#
+        );
+    }
+}
@@ -14,12 +14,13 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
 
 use anyhow::anyhow;
 use containerd_sandbox::error::{Error, Result};
+use qapi::qmp::CpuInfoFast;
 
-use crate::utils::read_file;
+use crate::{utils::read_file, vm::VcpuThreads};
 
 pub(crate) async fn detect_pid(path: &str, bin_path: &str) -> Result<u32> {
     let mut err = None;
@@ -60,3 +61,90 @@ pub(crate) async fn detect_pid(path: &str, bin_path: &str) -> Result<u32> {
 
     Err(anyhow!("timeout waiting for the pid file, err: {:?}", err).into())
 }
+
+// Parse the memory size of qemu command line like "2048M" into bytes,
+// a size without suffix is in MiB as qemu does.
+pub(crate) fn parse_memory_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let (num, unit) = match size.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&size[..i], c.to_ascii_uppercase()),
+        _ => (size, 'M'),
+    };
+    let num = num
+        .parse::<u64>()
+        .map_err(|e| anyhow!("failed to parse memory size {}, {}", size, e))?;
+    let multiplier = match unit {
+        'K' => 1 << 10,
+        'M' => 1 << 20,
+        'G' => 1 << 30,
+        'T' => 1 << 40,
+        _ => return Err(Error::InvalidArgument(format!("memory size {}", size))),
+    };
+    Ok(num * multiplier)
+}
+
+// Quote the argument of the command run by qemu with "/bin/sh -c", such as the exec migration,
+// so that the spaces and shell metacharacters in it are taken literally.
+pub(crate) fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+// Map the index of each vcpu to its thread id, the info of vcpus is a union of target specific
+// variants, and the common fields are flattened into each of them.
+pub(crate) fn vcpu_threads(cpus: &[CpuInfoFast]) -> Result<VcpuThreads> {
+    let mut vcpus = HashMap::new();
+    for cpu in cpus {
+        let cpu = serde_json::to_value(cpu)
+            .map_err(|e| anyhow!("failed to serialize cpu info, {}", e))?;
+        match (cpu["cpu-index"].as_i64(), cpu["thread-id"].as_i64()) {
+            (Some(index), Some(thread_id)) => {
+                vcpus.insert(index, thread_id);
+            }
+            _ => return Err(anyhow!("no cpu index or thread id in cpu info {}", cpu).into()),
+        }
+    }
+    Ok(VcpuThreads { vcpus })
+}
+
+#[cfg(test)]
+mod tests {
+    use qapi::qmp::CpuInfoFast;
+
+    use crate::qemu::utils::{parse_memory_size, shell_quote, vcpu_threads};
+
+    #[test]
+    fn test_parse_memory_size() {
+        assert_eq!(parse_memory_size("2048M").unwrap(), 2048 << 20);
+        assert_eq!(parse_memory_size("2g").unwrap(), 2 << 30);
+        assert_eq!(parse_memory_size("512").unwrap(), 512 << 20);
+        assert!(parse_memory_size("2X").is_err());
+        assert!(parse_memory_size("M").is_err());
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(
+            shell_quote("/run/snapshot/vmstate"),
+            "'/run/snapshot/vmstate'"
+        );
+        assert_eq!(shell_quote("/run/a b;rm -rf /"), "'/run/a b;rm -rf /'");
+        assert_eq!(shell_quote("/run/it's$(id)"), r"'/run/it'\''s$(id)'");
+    }
+
+    #[test]
+    fn test_vcpu_threads() {
+        let reply = r#"[
+            {"thread-id": 25627, "props": {"core-id": 0, "thread-id": 0, "socket-id": 0},
+             "qom-path": "/machine/unattached/device[0]", "cpu-index": 0,
+             "arch": "x86", "target": "x86_64"},
+            {"thread-id": 25628, "props": {"core-id": 0, "thread-id": 0, "socket-id": 1},
+             "qom-path": "/machine/peripheral/vcpu-1", "cpu-index": 1,
+             "arch": "x86", "target": "x86_64"}
+        ]"#;
+        let cpus: Vec<CpuInfoFast> = serde_json::from_str(reply).unwrap();
+        let threads = vcpu_threads(&cpus).unwrap();
+        assert_eq!(threads.vcpus.len(), 2);
+        assert_eq!(threads.vcpus[&0], 25627);
+        assert_eq!(threads.vcpus[&1], 25628);
+    }
+}
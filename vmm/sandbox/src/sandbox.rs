@@ -18,9 +18,10 @@ use std::{
     collections::HashMap,
     env,
     io::ErrorKind,
+    net::IpAddr,
     path::Path,
     sync::Arc,
-    time::Instant,
+    time::{Duration, Instant},
 };
 
 use anyhow::anyhow;
@@ -34,6 +35,7 @@ use containerd_sandbox::{
     ContainerOption, Sandbox, SandboxOption, SandboxStatus, Sandboxer,
 };
 use containerd_shim::{protos::api::Envelope, util::write_str_to_file};
+use futures_util::StreamExt;
 use log::{debug, error, info, warn};
 use protobuf::{well_known_types::any::Any, MessageField};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
@@ -41,31 +43,55 @@ use tokio::{
     fs::{copy, create_dir_all, remove_dir_all, OpenOptions},
     io::{AsyncReadExt, AsyncWriteExt},
     sync::{Mutex, RwLock, Semaphore},
+    time::timeout,
 };
 use tracing::instrument;
 use ttrpc::context::with_timeout;
 use vmm_common::{
-    api::{empty::Empty, sandbox::SetupSandboxRequest, sandbox_ttrpc::SandboxServiceClient},
+    api::{
+        empty::Empty,
+        sandbox::{Interface, Route, SetupSandboxRequest},
+        sandbox_ttrpc::SandboxServiceClient,
+    },
+    mount::bind_mount,
     storage::Storage,
     ETC_HOSTS, ETC_RESOLV, HOSTNAME_FILENAME, HOSTS_FILENAME, RESOLV_FILENAME, SHARED_DIR_SUFFIX,
 };
 
 use crate::{
-    cgroup::{SandboxCgroup, DEFAULT_CGROUP_PARENT_PATH},
-    client::{client_check, client_setup_sandbox, client_sync_clock, new_sandbox_client},
+    cgroup::{CgroupDriver, SandboxCgroup, DEFAULT_CGROUP_PARENT_PATH},
+    client::{
+        client_check, client_online_cpu_mem, client_setup_sandbox, client_sync_clock,
+        client_update_interfaces, client_update_routes, new_sandbox_client,
+    },
     container::KuasarContainer,
-    network::{Network, NetworkConfig},
-    utils::{get_dns_config, get_hostname, get_resources, get_sandbox_cgroup_parent_path},
+    control::{self, Controller},
+    device::{BusType, DeviceInfo},
+    metrics::{
+        observe_boot_phase, observe_hot_plug, observe_recovery, serve, SandboxCollector,
+        OPERATION_ATTACH, OPERATION_DETACH, PHASE_CLIENT_CHECK, PHASE_CREATE_VM,
+        PHASE_SETUP_SANDBOX, PHASE_VM_START,
+    },
+    network::{subscribe_changes, BandwidthLimit, Network, NetworkConfig},
+    pool::{destroy, PooledVM, VMPool},
+    storage::ratelimit::DiskRateLimit,
+    utils::{
+        get_dns_config, get_hostname, get_resources, get_sandbox_cgroup_parent_path,
+        get_total_resources,
+    },
     vm::{Hooks, Recoverable, VMFactory, VM},
 };
 
 pub const KUASAR_GUEST_SHARE_DIR: &str = "/run/kuasar/storage/containers/";
+const DEFAULT_POOL_DIR: &str = "/run/kuasar-vmm-pool";
+// a CNI run changes the netns many times in a row, apply them after it is quiet for a while
+const NETWORK_CHANGE_QUIET_PERIOD_MS: u64 = 500;
 
 pub struct KuasarSandboxer<F: VMFactory, H: Hooks<F::VM>> {
-    factory: F,
-    hooks: H,
-    #[allow(dead_code)]
+    factory: Arc<F>,
+    hooks: Arc<H>,
     config: SandboxConfig,
+    pool: Option<Arc<VMPool<F>>>,
     #[allow(clippy::type_complexity)]
     sandboxes: Arc<RwLock<HashMap<String, Arc<Mutex<KuasarSandbox<F::VM>>>>>>,
 }
@@ -78,12 +104,38 @@ where
 {
     pub fn new(config: SandboxConfig, vmm_config: F::Config, hooks: H) -> Self {
         Self {
-            factory: F::new(vmm_config),
-            hooks,
+            factory: Arc::new(F::new(vmm_config)),
+            hooks: Arc::new(hooks),
             config,
+            pool: None,
             sandboxes: Arc::new(Default::default()),
         }
     }
+
+    // Take a vm from the pool if the sandbox can use it, the shared dir of the sandbox is
+    // bind mounted to the shared dir of the pooled vm, which is shared into the guest.
+    async fn take_pooled_vm(&self, s: &SandboxOption) -> Option<PooledVM<F::VM>> {
+        let pool = self.pool.as_ref()?;
+        // vms in the pool are booted with the default cpu and memory of the config
+        if let Some(resources) = get_resources(&s.sandbox) {
+            if resources.cpu_quota > 0 || resources.memory_limit_in_bytes > 0 {
+                return None;
+            }
+        }
+        let mut pooled = pool.take().await?;
+        let shared_dir = format!("{}/{}", s.base_dir, SHARED_DIR_SUFFIX);
+        let res = match create_dir_all(&shared_dir).await {
+            Ok(_) => bind_mount(pooled.shared_dir(), &shared_dir, &[]),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = res {
+            warn!("failed to share {} to pooled vm: {}", shared_dir, e);
+            destroy(&mut pooled).await;
+            return None;
+        }
+        debug!("sandbox {} takes vm in {}", s.sandbox.id, pooled.base_dir);
+        Some(pooled)
+    }
 }
 
 impl<F, H> KuasarSandboxer<F, H>
@@ -183,6 +235,7 @@ where
             let dir_path_clone = dir.to_string();
             let sandboxes_clone = self.sandboxes.clone();
             let entry_name_for_handle = entry_name.clone();
+            let hypervisor = self.factory.hypervisor();
 
             let handle = tokio::spawn(async move {
                 let _permit = permit; // Released when _permit goes out of scope
@@ -190,7 +243,14 @@ where
 
                 let path = Path::new(&dir_path_clone).join(&entry_name);
                 match KuasarSandbox::recover(&path).await {
-                    Ok(sb) => {
+                    Ok(mut sb) => {
+                        // sandboxes dumped by the older sandboxer did not record the hypervisor
+                        if sb.hypervisor.is_empty() {
+                            sb.hypervisor = hypervisor.to_string();
+                            if let Err(e) = sb.dump().await {
+                                warn!("failed to dump sandbox {}: {}", entry_name, e);
+                            }
+                        }
                         // Optimized: Avoid cloning status by using reference
                         let is_running = matches!(&sb.status, SandboxStatus::Running(_));
                         let sb_mutex = Arc::new(Mutex::new(sb));
@@ -199,6 +259,7 @@ where
                         if is_running {
                             let sb_clone = sb_mutex.clone();
                             monitor(sb_clone);
+                            watch_network(sb_mutex.clone());
                         }
 
                         sandboxes_clone.write().await.insert(entry_name.clone(), sb_mutex);
@@ -244,6 +305,182 @@ where
             failed,
             duration.as_secs_f64()
         );
+        observe_recovery(duration, succeeded, failed);
+    }
+}
+
+impl<F, H> KuasarSandboxer<F, H>
+where
+    F: VMFactory + Sync + Send + 'static,
+    H: Hooks<F::VM> + Sync + Send + 'static,
+    F::VM: VM + DeserializeOwned + Sync + Send + 'static,
+{
+    /// Start booting vms into the pool if `pool_size` is configured, should be called after
+    /// the recovery so that the pool dir is not mistaken for a sandbox dir.
+    pub async fn init_pool(&mut self) -> Result<()> {
+        if self.config.pool_size == 0 {
+            return Ok(());
+        }
+        if !self.factory.pool_supported() {
+            return Err(Error::InvalidArgument(
+                "pool_size should be 0 as the vm pool is not supported by the hypervisor"
+                    .to_string(),
+            ));
+        }
+        let pool = Arc::new(VMPool::new(
+            self.factory.clone(),
+            &self.config.pool_dir(),
+            self.config.pool_size,
+        ));
+        // vms taken by the recovered sandboxes are still in the pool dir
+        let mut taken_dirs = vec![];
+        for sandbox_mutex in self.sandboxes.read().await.values() {
+            if let Some(dir) = &sandbox_mutex.lock().await.pooled_vm_dir {
+                taken_dirs.push(dir.to_string());
+            }
+        }
+        pool.run(&taken_dirs).await;
+        info!(
+            "vm pool of size {} is started in {}",
+            self.config.pool_size,
+            self.config.pool_dir()
+        );
+        self.pool = Some(pool);
+        Ok(())
+    }
+
+    /// Serve the metrics of the sandboxes if `metrics_address` is configured.
+    pub async fn serve_metrics(&self) -> Result<()> {
+        if self.config.metrics_address.is_empty() {
+            return Ok(());
+        }
+        let collector = Arc::new(SandboxCollector::new(self.sandboxes.clone()));
+        serve(&self.config.metrics_address, collector).await
+    }
+
+    /// Serve the checkpoint and restore requests if `control_address` is configured.
+    pub async fn serve_control(&self) -> Result<()> {
+        if self.config.control_address.is_empty() {
+            return Ok(());
+        }
+        let controller = Arc::new(Self {
+            factory: self.factory.clone(),
+            hooks: self.hooks.clone(),
+            config: self.config.clone(),
+            pool: self.pool.clone(),
+            sandboxes: self.sandboxes.clone(),
+        });
+        control::serve(&self.config.control_address, controller).await
+    }
+
+    // Boot the vm of the restored sandbox from the snapshot in `path` with its network.
+    async fn start_restored(&self, sandbox: &mut KuasarSandbox<F::VM>, path: &str) -> Result<()> {
+        self.hooks.pre_start(sandbox).await?;
+
+        if !sandbox.data.netns.is_empty() {
+            sandbox.prepare_network().await?;
+        }
+
+        if let Err(e) = sandbox.restore(path).await {
+            sandbox.destroy_network().await;
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn setup_restored(&self, sandbox: &mut KuasarSandbox<F::VM>) -> Result<()> {
+        sandbox.add_to_cgroup().await?;
+        self.hooks.post_start(sandbox).await?;
+        sandbox.dump().await
+    }
+}
+
+#[async_trait]
+impl<F, H> Controller for KuasarSandboxer<F, H>
+where
+    F: VMFactory + Sync + Send + 'static,
+    H: Hooks<F::VM> + Sync + Send + 'static,
+    F::VM: VM + DeserializeOwned + Sync + Send + 'static,
+{
+    /// Save the vm state of a running sandbox and its `sandbox.json` into the directory `path`.
+    ///
+    /// The storages and network of the sandbox are recorded in `sandbox.json`, and are expected
+    /// to be still on the host when restoring, so a sandbox to be restored later should not be
+    /// stopped by `Sandboxer::stop`, which removes them. Set `exit` to kill the vm after the
+    /// checkpoint while keeping the storages.
+    #[instrument(skip_all)]
+    async fn checkpoint(&self, id: &str, path: &str, exit: bool) -> Result<()> {
+        let sandbox_mutex = self.sandbox(id).await?;
+        let mut sandbox = sandbox_mutex.lock().await;
+        if !matches!(sandbox.status, SandboxStatus::Running(_)) {
+            return Err(
+                anyhow!("sandbox {} is in {:?} while checkpoint", id, sandbox.status).into(),
+            );
+        }
+        create_dir_all(path).await?;
+        sandbox.vm.snapshot(path).await?;
+        sandbox.dump_to(path).await?;
+        info!("sandbox {} is checkpointed to {}", id, path);
+        if exit {
+            // the monitor of the sandbox will update its status after vm exits
+            sandbox.vm.stop(true).await?;
+        }
+        Ok(())
+    }
+
+    /// Restore a sandbox from the directory `path` saved by `checkpoint`, returns the sandbox id.
+    #[instrument(skip_all)]
+    async fn restore(&self, path: &str) -> Result<String> {
+        let mut sandbox = KuasarSandbox::<F::VM>::load(path).await?;
+        let id = sandbox.id.to_string();
+        if let Some(sb_mutex) = self.sandboxes.read().await.get(&id) {
+            if let SandboxStatus::Running(_) = sb_mutex.lock().await.status {
+                return Err(Error::AlreadyExist(format!("running sandbox {}", id)));
+            }
+        }
+
+        // create the vm again for the cold attached devices, the rest is in the snapshot
+        let option = SandboxOption::new(sandbox.base_dir.to_string(), sandbox.data.clone());
+        sandbox.vm = self.factory.create_vm(&id, &option).await?;
+        sandbox.hypervisor = self.factory.hypervisor().to_string();
+        sandbox.pooled_vm_dir = None;
+        sandbox.status = SandboxStatus::Created;
+        sandbox.network = None;
+        // Currently only support cgroup V1, cgroup V2 is not supported now
+        if !cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
+            sandbox.sandbox_cgroups = SandboxCgroup::create_sandbox_cgroups(
+                sandbox.sandbox_cgroups.cgroup_driver,
+                &sandbox.sandbox_cgroups.cgroup_parent_path,
+                &id,
+            )
+            .await?;
+        }
+
+        if let Err(e) = self.start_restored(&mut sandbox, path).await {
+            remove_cgroups(&sandbox).await;
+            return Err(e);
+        }
+
+        let sandbox_mutex = Arc::new(Mutex::new(sandbox));
+        monitor(sandbox_mutex.clone());
+        {
+            let mut sandbox = sandbox_mutex.lock().await;
+            if let Err(e) = self.setup_restored(&mut sandbox).await {
+                if let Err(re) = sandbox.stop(true).await {
+                    warn!("roll back in set up restored sandbox {}", re);
+                    return Err(e);
+                }
+                remove_cgroups(&sandbox).await;
+                return Err(e);
+            }
+        }
+        watch_network(sandbox_mutex.clone());
+        self.sandboxes
+            .write()
+            .await
+            .insert(id.to_string(), sandbox_mutex);
+        info!("sandbox {} is restored from {}", id, path);
+        Ok(id)
     }
 }
 
@@ -264,6 +501,16 @@ pub struct KuasarSandbox<V: VM> {
     pub(crate) exit_signal: Arc<ExitSignal>,
     #[serde(default)]
     pub(crate) sandbox_cgroups: SandboxCgroup,
+    // base dir of the pooled vm if the vm is taken from the pool, it was booted before the
+    // sandbox is created and devices should be hot attached to it.
+    #[serde(default)]
+    pub(crate) pooled_vm_dir: Option<String>,
+    // rate limits of the io of the whole sandbox on each disk of the host
+    #[serde(default)]
+    pub(crate) disk_rate_limit: DiskRateLimit,
+    // name of the hypervisor running the vm, read by kuasarctl
+    #[serde(default)]
+    pub(crate) hypervisor: String,
 }
 
 #[async_trait]
@@ -281,25 +528,46 @@ where
             return Err(Error::AlreadyExist("sandbox".to_string()));
         }
 
+        let disk_rate_limit = match &s.sandbox.config {
+            Some(config) => DiskRateLimit::from_annotations(&config.annotations)?,
+            None => DiskRateLimit::default(),
+        }
+        .or(self.config.disk_rate_limit);
+
         let mut sandbox_cgroups = SandboxCgroup::default();
         let cgroup_parent_path = get_sandbox_cgroup_parent_path(&s.sandbox)
             .unwrap_or(DEFAULT_CGROUP_PARENT_PATH.to_string());
         // Currently only support cgroup V1, cgroup V2 is not supported now
         if !cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
             // Create sandbox's cgroup and apply sandbox's resources limit
-            let create_and_update_sandbox_cgroup = (|| {
-                sandbox_cgroups =
-                    SandboxCgroup::create_sandbox_cgroups(&cgroup_parent_path, &s.sandbox.id)?;
-                sandbox_cgroups.update_res_for_sandbox_cgroups(&s.sandbox)?;
-                Ok(())
-            })();
+            let create_and_update_sandbox_cgroup = async {
+                sandbox_cgroups = SandboxCgroup::create_sandbox_cgroups(
+                    self.config.cgroup_driver,
+                    &cgroup_parent_path,
+                    &s.sandbox.id,
+                )
+                .await?;
+                sandbox_cgroups
+                    .update_res_for_sandbox_cgroups(&s.sandbox, &disk_rate_limit)
+                    .await?;
+                Ok::<(), Error>(())
+            }
+            .await;
             // If create and update sandbox cgroup failed, do rollback operation
             if let Err(e) = create_and_update_sandbox_cgroup {
-                let _ = sandbox_cgroups.remove_sandbox_cgroups();
+                let _ = sandbox_cgroups.remove_sandbox_cgroups().await;
                 return Err(e);
             }
         }
-        let vm = self.factory.create_vm(id, &s).await?;
+        let (vm, pooled_vm_dir) = match self.take_pooled_vm(&s).await {
+            Some(pooled) => (pooled.vm, Some(pooled.base_dir)),
+            None => {
+                let start = Instant::now();
+                let vm = self.factory.create_vm(id, &s).await?;
+                observe_boot_phase(PHASE_CREATE_VM, start);
+                (vm, None)
+            }
+        };
         let mut sandbox = KuasarSandbox {
             vm,
             id: id.to_string(),
@@ -313,12 +581,24 @@ where
             client: Arc::new(Mutex::new(None)),
             exit_signal: Arc::new(ExitSignal::default()),
             sandbox_cgroups,
+            pooled_vm_dir,
+            disk_rate_limit,
+            hypervisor: self.factory.hypervisor().to_string(),
         };
 
-        // setup sandbox files: hosts, hostname and resolv.conf for guest
-        sandbox.setup_sandbox_files().await?;
-        self.hooks.post_create(&mut sandbox).await?;
-        sandbox.dump().await?;
+        let setup_sandbox = async {
+            // setup sandbox files: hosts, hostname and resolv.conf for guest
+            sandbox.setup_sandbox_files().await?;
+            self.hooks.post_create(&mut sandbox).await?;
+            sandbox.dump().await?;
+            Ok::<(), Error>(())
+        }
+        .await;
+        // The vm taken from the pool is not released by anything else if the creation fails
+        if let Err(e) = setup_sandbox {
+            sandbox.release_pooled_vm().await;
+            return Err(e);
+        }
         self.sandboxes
             .write()
             .await
@@ -332,9 +612,18 @@ where
         let mut sandbox = sandbox_mutex.lock().await;
         self.hooks.pre_start(&mut sandbox).await?;
 
+        // The pooled vm has to be running to accept the hot attached network devices
+        if sandbox.pooled_vm_dir.is_some() {
+            sandbox.vm.resume().await?;
+        }
+
         // Prepare pod network if it has a private network namespace
         if !sandbox.data.netns.is_empty() {
             sandbox.prepare_network().await?;
+            if let Err(e) = sandbox.setup_hosts_file().await {
+                sandbox.destroy_network().await;
+                return Err(e);
+            }
         }
 
         if let Err(e) = sandbox.start().await {
@@ -372,6 +661,7 @@ where
             return Err(e);
         }
 
+        watch_network(sandbox_mutex.clone());
         Ok(())
     }
 
@@ -379,9 +669,26 @@ where
     async fn update(&self, id: &str, data: SandboxData) -> Result<()> {
         let sandbox_mutex = self.sandbox(id).await?;
         let mut sandbox = sandbox_mutex.lock().await;
+        let resources_changed = get_total_resources(&sandbox.data) != get_total_resources(&data);
         sandbox.data = data;
+        let mut res = Ok(());
+        if resources_changed && self.config.enable_cpu_memory_hotplug {
+            if let SandboxStatus::Running(_) = sandbox.status {
+                // Currently only support cgroup V1, cgroup V2 is not supported now
+                if !cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
+                    res = sandbox
+                        .sandbox_cgroups
+                        .update_res_for_sandbox_cgroups(&sandbox.data, &sandbox.disk_rate_limit)
+                        .await;
+                }
+                if res.is_ok() {
+                    res = sandbox.update_resources().await;
+                }
+            }
+        }
+        // dump the sandbox even if resize failed, as some of the resources may be hot plugged
         sandbox.dump().await?;
-        Ok(())
+        res
     }
 
     #[instrument(skip_all)]
@@ -416,7 +723,7 @@ where
             // Currently only support cgroup V1, cgroup V2 is not supported now
             if !cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
                 // remove the sandbox cgroups
-                sb.sandbox_cgroups.remove_sandbox_cgroups()?;
+                sb.sandbox_cgroups.remove_sandbox_cgroups().await?;
             }
 
             cleanup_mounts(&sb.base_dir).await?;
@@ -426,6 +733,13 @@ where
                     return Err(e.into());
                 }
             }
+            if let Some(dir) = &sb.pooled_vm_dir {
+                if let Err(e) = remove_dir_all(dir).await {
+                    if e.kind() != ErrorKind::NotFound {
+                        return Err(e.into());
+                    }
+                }
+            }
         }
         self.sandboxes.write().await.remove(id);
         Ok(())
@@ -490,7 +804,7 @@ where
             None => {}
             Some(c) => {
                 for device_id in c.io_devices {
-                    self.vm.hot_detach(&device_id).await?;
+                    self.hot_detach(&device_id).await?;
                 }
             }
         }
@@ -515,9 +829,14 @@ where
 {
     #[instrument(skip_all)]
     async fn dump(&self) -> Result<()> {
+        self.dump_to(&self.base_dir).await
+    }
+
+    #[instrument(skip_all)]
+    async fn dump_to(&self, dir: &str) -> Result<()> {
         let dump_data =
             serde_json::to_vec(&self).map_err(|e| anyhow!("failed to serialize sandbox, {}", e))?;
-        let dump_path = format!("{}/sandbox.json", self.base_dir);
+        let dump_path = format!("{}/sandbox.json", dir);
         let mut dump_file = OpenOptions::new()
             .write(true)
             .create(true)
@@ -535,11 +854,11 @@ where
 
 impl<V> KuasarSandbox<V>
 where
-    V: VM + DeserializeOwned + Recoverable + Sync + Send,
+    V: VM + DeserializeOwned + Sync + Send,
 {
     #[instrument(skip_all)]
-    async fn recover<P: AsRef<Path>>(base_dir: P) -> Result<Self> {
-        let dump_path = base_dir.as_ref().join("sandbox.json");
+    async fn load<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dump_path = dir.as_ref().join("sandbox.json");
         let mut dump_file = OpenOptions::new()
             .read(true)
             .open(&dump_path)
@@ -550,8 +869,19 @@ where
             .read_to_end(&mut content)
             .await
             .map_err(Error::IO)?;
-        let mut sb = serde_json::from_slice::<KuasarSandbox<V>>(content.as_slice())
+        let sb = serde_json::from_slice::<KuasarSandbox<V>>(content.as_slice())
             .map_err(|e| anyhow!("failed to deserialize sandbox, {}", e))?;
+        Ok(sb)
+    }
+}
+
+impl<V> KuasarSandbox<V>
+where
+    V: VM + DeserializeOwned + Recoverable + Sync + Send,
+{
+    #[instrument(skip_all)]
+    async fn recover<P: AsRef<Path>>(base_dir: P) -> Result<Self> {
+        let mut sb = Self::load(base_dir).await?;
         if let SandboxStatus::Running(_) = sb.status {
             if let Err(e) = sb.vm.recover().await {
                 warn!("failed to recover vm {}: {}, then force kill it!", sb.id, e);
@@ -572,8 +902,12 @@ where
             sb.forward_events().await;
         }
         // recover the sandbox_cgroups in the sandbox object
-        sb.sandbox_cgroups =
-            SandboxCgroup::create_sandbox_cgroups(&sb.sandbox_cgroups.cgroup_parent_path, &sb.id)?;
+        sb.sandbox_cgroups = SandboxCgroup::create_sandbox_cgroups(
+            sb.sandbox_cgroups.cgroup_driver,
+            &sb.sandbox_cgroups.cgroup_parent_path,
+            &sb.id,
+        )
+        .await?;
 
         Ok(sb)
     }
@@ -585,8 +919,17 @@ where
 {
     #[instrument(skip_all)]
     async fn start(&mut self) -> Result<()> {
-        let pid = self.vm.start().await?;
+        let pid = if self.pooled_vm_dir.is_some() {
+            // the pooled vm is booted and resumed already
+            self.vm.pids().vmm_pid.unwrap_or_default()
+        } else {
+            let start = Instant::now();
+            let pid = self.vm.start().await?;
+            observe_boot_phase(PHASE_VM_START, start);
+            pid
+        };
 
+        let start = Instant::now();
         if let Err(e) = self.init_client().await {
             if let Err(re) = self.vm.stop(true).await {
                 warn!("roll back in init task client: {}", re);
@@ -594,7 +937,9 @@ where
             }
             return Err(e);
         }
+        observe_boot_phase(PHASE_CLIENT_CHECK, start);
 
+        let start = Instant::now();
         if let Err(e) = self.setup_sandbox().await {
             if let Err(re) = self.vm.stop(true).await {
                 error!("roll back in setup sandbox client: {}", re);
@@ -602,6 +947,26 @@ where
             }
             return Err(e);
         }
+        observe_boot_phase(PHASE_SETUP_SANDBOX, start);
+
+        self.forward_events().await;
+
+        self.status = SandboxStatus::Running(pid);
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    async fn restore(&mut self, path: &str) -> Result<()> {
+        let pid = self.vm.restore(path).await?;
+
+        // the guest has been set up before snapshot, only the task client is needed
+        if let Err(e) = self.init_client().await {
+            if let Err(re) = self.vm.stop(true).await {
+                warn!("roll back in init task client of restored vm: {}", re);
+                return Err(e);
+            }
+            return Err(e);
+        }
 
         self.forward_events().await;
 
@@ -716,6 +1081,33 @@ where
         }
     }
 
+    /// Resize the vcpus and memory of the running vm to the resources of the sandbox,
+    /// and online the hot plugged ones in guest.
+    #[instrument(skip_all)]
+    pub(crate) async fn update_resources(&mut self) -> Result<()> {
+        let resources = match get_resources(&self.data) {
+            Some(r) => r.clone(),
+            None => return Ok(()),
+        };
+        let mut vcpus = 0;
+        if resources.cpu_period > 0 && resources.cpu_quota > 0 {
+            // get ceil of cpus if it is not integer, the same as the vm is booted with
+            vcpus = (resources.cpu_quota as f64 / resources.cpu_period as f64).ceil() as u32;
+            self.vm.resize_vcpus(vcpus).await?;
+            // the hot plugged vcpu threads should be moved into the vcpu cgroup as well
+            self.add_to_cgroup().await?;
+        }
+        if resources.memory_limit_in_bytes > 0 {
+            self.vm
+                .resize_memory(resources.memory_limit_in_bytes as u64)
+                .await?;
+        }
+        if let Some(client) = &*self.client.lock().await {
+            client_online_cpu_mem(client, vcpus, resources.memory_limit_in_bytes <= 0).await?;
+        }
+        Ok(())
+    }
+
     #[instrument(skip_all)]
     async fn setup_sandbox_files(&self) -> Result<()> {
         let shared_path = self.get_sandbox_shared_path();
@@ -724,12 +1116,7 @@ where
             .map_err(|e| anyhow!("create host sandbox path({}): {}", shared_path, e))?;
 
         // Handle hostname
-        let mut hostname = get_hostname(&self.data);
-        if hostname.is_empty() {
-            hostname = hostname::get()
-                .map(|s| s.to_string_lossy().to_string())
-                .unwrap_or_default();
-        }
+        let mut hostname = self.hostname();
         hostname.push('\n');
         let hostname_path = Path::new(&shared_path).join(HOSTNAME_FILENAME);
         write_str_to_file(hostname_path, &hostname)
@@ -766,33 +1153,160 @@ where
         Ok(())
     }
 
+    fn hostname(&self) -> String {
+        let hostname = get_hostname(&self.data);
+        if !hostname.is_empty() {
+            return hostname;
+        }
+        hostname::get()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    // The hosts file copied from host is replaced by the one with the ip addresses of the pod,
+    // if the pod has its own network.
+    async fn setup_hosts_file(&self) -> Result<()> {
+        let network = match &self.network {
+            Some(network) => network,
+            None => return Ok(()),
+        };
+        let ips = network
+            .interfaces()
+            .iter()
+            .flat_map(|intf| intf.ip_addresses.iter())
+            .filter(|ip| !ip.is_link_local() && !ip.addr().is_loopback())
+            .map(|ip| *ip.addr())
+            .collect::<Vec<IpAddr>>();
+        let hosts_path = Path::new(&self.get_sandbox_shared_path()).join(HOSTS_FILENAME);
+        write_str_to_file(hosts_path, &generate_hosts(&self.hostname(), &ips))
+            .await
+            .map_err(|e| anyhow!("write hosts: {:?}", e))?;
+        Ok(())
+    }
+
     #[instrument(skip_all)]
     pub fn get_sandbox_shared_path(&self) -> String {
         format!("{}/{}", self.base_dir, SHARED_DIR_SUFFIX)
     }
 
-    #[instrument(skip_all)]
-    pub async fn prepare_network(&mut self) -> Result<()> {
-        // get vcpu for interface queue, at least one vcpu
-        let mut vcpu = 1;
-        if let Some(resources) = get_resources(&self.data) {
-            if resources.cpu_period > 0 && resources.cpu_quota > 0 {
-                // get ceil of cpus if it is not integer
-                let base = (resources.cpu_quota as f64 / resources.cpu_period as f64).ceil();
-                vcpu = base as u32;
+    /// Attach a device to the vm, it is hot attached if the vm is taken from the pool.
+    pub(crate) async fn attach_device(&mut self, device_info: DeviceInfo) -> Result<()> {
+        if self.pooled_vm_dir.is_some() {
+            self.hot_attach(device_info).await?;
+            return Ok(());
+        }
+        self.vm.attach(device_info).await
+    }
+
+    /// Hot attach a device to the running vm, the latency is recorded in metrics.
+    pub(crate) async fn hot_attach(
+        &mut self,
+        device_info: DeviceInfo,
+    ) -> Result<(BusType, String)> {
+        let start = Instant::now();
+        let res = self.vm.hot_attach(device_info).await;
+        observe_hot_plug(OPERATION_ATTACH, start, res.is_ok());
+        res
+    }
+
+    /// Hot detach a device from the running vm, the latency is recorded in metrics.
+    pub(crate) async fn hot_detach(&mut self, id: &str) -> Result<()> {
+        let start = Instant::now();
+        let res = self.vm.hot_detach(id).await;
+        observe_hot_plug(OPERATION_DETACH, start, res.is_ok());
+        res
+    }
+
+    // Stop the pooled vm taken by a sandbox failed to be created.
+    async fn release_pooled_vm(&mut self) {
+        if let Some(dir) = self.pooled_vm_dir.take() {
+            if let Err(e) = self.vm.stop(true).await {
+                warn!("failed to stop pooled vm in {}: {}", dir, e);
             }
+            cleanup_mounts(&self.base_dir).await.unwrap_or_default();
+            remove_dir_all(&dir).await.unwrap_or_default();
         }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn prepare_network(&mut self) -> Result<()> {
+        let bandwidth = match &self.data.config {
+            Some(config) => BandwidthLimit::from_annotations(&config.annotations)?,
+            None => BandwidthLimit::default(),
+        };
 
         let network_config = NetworkConfig {
             netns: self.data.netns.to_string(),
             sandbox_id: self.id.to_string(),
-            queue: vcpu,
+            // the vcpus have been resized by the hooks according to the cpu limits
+            queue: self.vm.net_queues(),
+            bandwidth,
         };
         let network = Network::new(network_config).await?;
         network.attach_to(self).await?;
         Ok(())
     }
 
+    /// Apply the changes of the pod netns to the running vm, the new interfaces are hot attached
+    /// and the gone ones are hot detached, then the interfaces and routes in guest are updated.
+    #[instrument(skip_all)]
+    pub(crate) async fn update_network(&mut self) -> Result<()> {
+        // take the network out as the interfaces are attached to self
+        let mut network = match self.network.take() {
+            Some(network) => network,
+            None => return Ok(()),
+        };
+        let res = self.apply_network_changes(&mut network).await;
+        self.network = Some(network);
+        res?;
+        self.setup_hosts_file().await
+    }
+
+    async fn apply_network_changes(&mut self, network: &mut Network) -> Result<()> {
+        let (old_interfaces, old_routes) = guest_network(network);
+        let (added, removed) = network.rescan().await?;
+        let netns = self.data.netns.to_string();
+
+        for mut intf in removed {
+            info!("interface {} is removed from sandbox {}", intf.name, self.id);
+            if let Err(e) = self.hot_detach(&intf.device_id()).await {
+                warn!("failed to hot detach interface {}: {}", intf.name, e);
+            }
+            if let Err(e) = intf.remove_tap(&netns).await {
+                warn!("failed to remove tap of interface {}: {}", intf.name, e);
+            }
+            if let Err(e) = intf.after_detach(&netns).await {
+                warn!("failed to recycle interface {}: {}", intf.name, e);
+            }
+        }
+
+        for mut intf in added {
+            info!("interface {} is added to sandbox {}", intf.name, self.id);
+            intf.prepare_attaching(&netns).await?;
+            intf.limit_bandwidth(&netns, &network.config.bandwidth)
+                .await?;
+            if let Err(e) = intf.hot_attach_to(self).await {
+                // the interface is taken as a new one again in the next change
+                intf.remove_tap(&netns).await.unwrap_or_default();
+                return Err(e);
+            }
+            network.intfs.push(intf);
+        }
+
+        let (interfaces, routes) = guest_network(network);
+        if interfaces == old_interfaces && routes == old_routes {
+            return Ok(());
+        }
+        if let Some(client) = &*self.client.lock().await {
+            // routes are flushed when the interfaces are updated, so update them anyway
+            if interfaces != old_interfaces {
+                client_update_interfaces(client, interfaces).await?;
+            }
+            client_update_routes(client, routes).await?;
+        }
+        Ok(())
+    }
+
     //  If a sandbox is still running, destroy network may hang with its running
     #[instrument(skip_all)]
     pub async fn destroy_network(&mut self) {
@@ -814,12 +1328,19 @@ where
                     vmm_pid, vcpu_threads
                 );
                 self.sandbox_cgroups
-                    .add_process_into_sandbox_cgroups(vmm_pid, Some(vcpu_threads))?;
+                    .add_process_into_sandbox_cgroups(vmm_pid, Some(vcpu_threads))
+                    .await?;
                 // move all vmm-related process into sandbox cgroup
                 for pid in self.vm.pids().affiliated_pids {
                     self.sandbox_cgroups
-                        .add_process_into_sandbox_cgroups(pid, None)?;
+                        .add_process_into_sandbox_cgroups(pid, None)
+                        .await?;
                 }
+                // some cgroups are created along with the vmm process, such as the ones in the
+                // scope of the systemd driver, so the resources are applied to them now
+                self.sandbox_cgroups
+                    .update_res_for_sandbox_cgroups(&self.data, &self.disk_rate_limit)
+                    .await?;
             } else {
                 return Err(Error::Other(anyhow!(
                     "sandbox status is not Running after started!"
@@ -868,6 +1389,33 @@ where
     }
 }
 
+// The interfaces and routes of the network set up in guest
+fn guest_network(network: &Network) -> (Vec<Interface>, Vec<Route>) {
+    (
+        network.interfaces().iter().map(|x| x.into()).collect(),
+        network.routes().iter().map(|x| x.into()).collect(),
+    )
+}
+
+// generate_hosts generates the hosts file content with the localhost entries of both ipv4 and
+// ipv6, and the hostname resolved to each ip address of the pod.
+fn generate_hosts(hostname: &str, ips: &[IpAddr]) -> String {
+    let mut hosts_content = String::from(
+        "127.0.0.1\tlocalhost\n\
+         ::1\tlocalhost ip6-localhost ip6-loopback\n\
+         fe00::0\tip6-localnet\n\
+         fe00::0\tip6-mcastprefix\n\
+         fe00::1\tip6-allnodes\n\
+         fe00::2\tip6-allrouters\n",
+    );
+    if !hostname.is_empty() {
+        for ip in ips {
+            hosts_content.push_str(&format!("{}\t{}\n", ip, hostname));
+        }
+    }
+    hosts_content
+}
+
 // parse_dnsoptions parse DNS options into resolv.conf format content,
 // if none option is specified, will return empty with no error.
 fn parse_dnsoptions(servers: &[String], searches: &[String], options: &[String]) -> String {
@@ -913,12 +1461,45 @@ fn convert_envelope(envelope: vmm_common::api::events::Envelope) -> Envelope {
     }
 }
 
-#[derive(Default, Debug, Deserialize)]
+#[derive(Default, Debug, Clone, Deserialize)]
 pub struct SandboxConfig {
     #[serde(default)]
     pub log_level: String,
     #[serde(default)]
     pub enable_tracing: bool,
+    /// Hot plug vcpus and memory to the running vm when the resources of the sandbox are updated,
+    /// the max vcpus and hotplug memory of the hypervisor should be configured for it.
+    /// Memory hotplug of stratovirt and both vcpus and memory hotplug of firecracker are not
+    /// implemented, updating the resources of sandboxes on them fails with Unimplemented.
+    #[serde(default)]
+    pub enable_cpu_memory_hotplug: bool,
+    /// Number of vms booted ahead in the pool, 0 disables the pool.
+    /// Only sandboxes without cpu and memory limits take vms from the pool, and network
+    /// devices are hot attached to them, so it is only supported by cloud hypervisor.
+    #[serde(default)]
+    pub pool_size: usize,
+    /// Directory of the pooled vms, default is "/run/kuasar-vmm-pool".
+    #[serde(default)]
+    pub pool_dir: String,
+    /// Address to serve the prometheus metrics of sandboxes, either a tcp address like
+    /// "127.0.0.1:9100" or a unix socket like "unix:///run/kuasar-vmm-metrics.sock".
+    /// Metrics are not served if it is empty.
+    #[serde(default)]
+    pub metrics_address: String,
+    /// Unix socket to serve the checkpoint and restore requests of sandboxes from kuasarctl,
+    /// like "/run/kuasar-vmm-control.sock". They are not served if it is empty.
+    #[serde(default)]
+    pub control_address: String,
+    /// Driver of the sandbox cgroups, "cgroupfs" or "systemd", it should be the same as the
+    /// cgroup driver of kubelet. Falls back to cgroupfs if systemd fails to create the cgroups.
+    #[serde(default)]
+    pub cgroup_driver: CgroupDriver,
+    /// Default rate limits of the io of each sandbox on each disk of the host, which are
+    /// overridden by the limits in the pod annotations. They are enforced by the blkio cgroup of
+    /// the sandbox, which holds the vmm and virtiofsd, so the block volumes and the volumes
+    /// shared by virtiofs are limited as a whole.
+    #[serde(default)]
+    pub disk_rate_limit: DiskRateLimit,
 }
 
 impl SandboxConfig {
@@ -929,6 +1510,13 @@ impl SandboxConfig {
     pub fn enable_tracing(&self) -> bool {
         self.enable_tracing
     }
+
+    pub fn pool_dir(&self) -> String {
+        if self.pool_dir.is_empty() {
+            return DEFAULT_POOL_DIR.to_string();
+        }
+        self.pool_dir.to_string()
+    }
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -940,6 +1528,17 @@ pub struct StaticDeviceSpec {
     pub(crate) _bdf: Vec<String>,
 }
 
+// Remove the cgroups of a sandbox whose vm failed to start.
+async fn remove_cgroups<V: VM>(sandbox: &KuasarSandbox<V>) {
+    // Currently only support cgroup V1, cgroup V2 is not supported now
+    if cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
+        return;
+    }
+    if let Err(e) = sandbox.sandbox_cgroups.remove_sandbox_cgroups().await {
+        warn!("failed to remove cgroups of sandbox {}: {}", sandbox.id, e);
+    }
+}
+
 fn monitor<V: VM + 'static>(sandbox_mutex: Arc<Mutex<KuasarSandbox<V>>>) {
     tokio::spawn(async move {
         let mut rx = {
@@ -991,6 +1590,57 @@ fn monitor<V: VM + 'static>(sandbox_mutex: Arc<Mutex<KuasarSandbox<V>>>) {
     });
 }
 
+// Watch the pod netns of the running sandbox, and apply the link, address and route changes,
+// such as a CNI re-run or an added secondary interface, to the vm.
+fn watch_network<V: VM + 'static>(sandbox_mutex: Arc<Mutex<KuasarSandbox<V>>>) {
+    tokio::spawn(async move {
+        let (id, netns, exit_signal) = {
+            let sandbox = sandbox_mutex.lock().await;
+            (
+                sandbox.id.to_string(),
+                sandbox.data.netns.to_string(),
+                sandbox.exit_signal.clone(),
+            )
+        };
+        if netns.is_empty() {
+            return;
+        }
+        let mut changes = match subscribe_changes(&netns).await {
+            Ok(changes) => changes,
+            Err(e) => {
+                warn!("failed to watch network of sandbox {}: {}", id, e);
+                return;
+            }
+        };
+
+        let fut = async {
+            while changes.next().await.is_some() {
+                let quiet_period = Duration::from_millis(NETWORK_CHANGE_QUIET_PERIOD_MS);
+                while let Ok(Some(_)) = timeout(quiet_period, changes.next()).await {}
+
+                let mut sandbox = sandbox_mutex.lock().await;
+                if !matches!(sandbox.status, SandboxStatus::Running(_)) {
+                    return;
+                }
+                debug!("network of sandbox {} changed", id);
+                if let Err(e) = sandbox.update_network().await {
+                    error!("failed to update network of sandbox {}: {}", id, e);
+                }
+                sandbox
+                    .dump()
+                    .await
+                    .map_err(|e| error!("dump sandbox {} in watch network: {}", id, e))
+                    .unwrap_or_default();
+            }
+        };
+
+        tokio::select! {
+            _ = fut => {},
+            _ = exit_signal.wait() => {},
+        }
+    });
+}
+
 #[cfg(test)]
 mod tests {
     mod dns {
@@ -1043,4 +1693,29 @@ options timeout:1
             assert_eq!(resolv_content, expected_content)
         }
     }
+
+    mod hosts {
+        use std::net::IpAddr;
+
+        use crate::sandbox::generate_hosts;
+
+        #[test]
+        fn test_generate_dual_stack_hosts() {
+            let ips: Vec<IpAddr> = vec!["10.0.0.2".parse().unwrap(), "fd00::2".parse().unwrap()];
+            let hosts = generate_hosts("pod-a", &ips);
+            let lines = hosts.lines().collect::<Vec<&str>>();
+            assert_eq!(lines[0], "127.0.0.1\tlocalhost");
+            assert_eq!(lines[1], "::1\tlocalhost ip6-localhost ip6-loopback");
+            assert!(lines.contains(&"10.0.0.2\tpod-a"));
+            assert!(lines.contains(&"fd00::2\tpod-a"));
+        }
+
+        #[test]
+        fn test_generate_hosts_without_hostname() {
+            let ips: Vec<IpAddr> = vec!["fd00::2".parse().unwrap()];
+            let hosts = generate_hosts("", &ips);
+            assert!(!hosts.contains("fd00::2"));
+            assert!(hosts.contains("ip6-allrouters"));
+        }
+    }
 }
@@ -34,11 +34,15 @@ use vmm_common::{
 use crate::{
     device::{BlockDeviceInfo, DeviceInfo},
     sandbox::{KuasarSandbox, KUASAR_GUEST_SHARE_DIR},
-    storage::mount::{get_mount_info, is_bind, is_bind_shm, is_overlay},
+    storage::{
+        mount::{get_mount_info, is_bind, is_bind_shm, is_overlay},
+        ratelimit::DiskRateLimit,
+    },
     vm::{BlockDriver, VM},
 };
 
 pub mod mount;
+pub mod ratelimit;
 pub mod utils;
 
 impl<V> KuasarSandbox<V>
@@ -74,6 +78,13 @@ where
             return Ok(());
         }
 
+        if !self.vm.shared_fs_supported() && (is_bind(m) || is_overlay(m)) {
+            return Err(Error::Unimplemented(format!(
+                "only block devices can be mounted into the vm without shared fs, but got {:?}",
+                m
+            )));
+        }
+
         if is_bind(m) {
             self.handle_bind_mount(&id, container_id, m).await?;
             return Ok(());
@@ -107,13 +118,20 @@ where
         } else {
             m.source.clone()
         };
+        // the io of the whole sandbox is limited by the sandbox cgroup, and the volume is
+        // limited further by the hypervisor if it has limits of its own
+        let rate_limit = match &self.data.config {
+            Some(config) => DiskRateLimit::for_volume(&config.annotations, &m.destination)?,
+            None => None,
+        }
+        .unwrap_or_default();
         let device_id = format!("blk{}", self.increment_and_get_id());
         let (bus_type, addr) = self
-            .vm
             .hot_attach(DeviceInfo::Block(BlockDeviceInfo {
                 id: device_id.to_string(),
                 path: source.clone(),
                 read_only,
+                rate_limit,
             }))
             .await?;
         // only pass options "ro" to agent, as other mount options may belongs to bind mount only.
@@ -309,7 +327,7 @@ where
         fs_type: &str,
     ) -> Result<()> {
         if device_id.is_some() {
-            self.vm.hot_detach(&device_id.unwrap()).await?;
+            self.hot_detach(&device_id.unwrap()).await?;
         } else if fs_type == "bind" {
             let mount_point = format!("{}/{}", self.get_sandbox_shared_path(), &id);
             unmount(&mount_point, MNT_DETACH | MNT_NOFOLLOW)?;
@@ -0,0 +1,197 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use containerd_sandbox::error::Result;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::network::bandwidth::parse_quantity;
+
+/// Bandwidth limit of the pod on each disk of the host in bytes per second, like "100Mi",
+/// applied to reads and writes separately. It is enforced by the blkio cgroup of the sandbox,
+/// so the block volumes and the volumes shared by virtiofs are limited as a whole. Like the other
+/// resources of the sandbox, it is only enforced on cgroup v1 hosts, where the blkio cgroup does
+/// not throttle the buffered writes, which are written back by the kernel threads.
+pub const DISK_BANDWIDTH_ANNOTATION: &str = "io.kuasar.disk/bandwidth";
+/// IO operations limit of the pod on each disk of the host per second, like "1k".
+pub const DISK_IOPS_ANNOTATION: &str = "io.kuasar.disk/iops";
+/// Limits of the block volumes enforced by the hypervisor, within the limits of the pod, in
+/// json keyed by the mount destination in container, like
+/// `{"/data": {"bandwidth": "10Mi", "iops": "500"}}`.
+pub const VOLUME_RATE_LIMITS_ANNOTATION: &str = "io.kuasar.disk/volume-rate-limits";
+
+/// Rate limits of a block device attached to the vm, the bandwidth is in bytes per second
+/// and iops is the io operations per second, none means unlimited.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DiskRateLimit {
+    #[serde(default)]
+    pub bandwidth: Option<u64>,
+    #[serde(default)]
+    pub iops: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VolumeRateLimit {
+    #[serde(default)]
+    bandwidth: Option<String>,
+    #[serde(default)]
+    iops: Option<String>,
+}
+
+impl DiskRateLimit {
+    /// Limits of the io of the pod.
+    pub fn from_annotations(annotations: &HashMap<String, String>) -> Result<Self> {
+        let parse = |key: &str| -> Result<Option<u64>> {
+            annotations
+                .get(key)
+                .map(|v| parse_rate(v).map_err(|e| anyhow!("invalid {}: {}", key, e)))
+                .transpose()
+                .map_err(Into::into)
+        };
+        Ok(Self {
+            bandwidth: parse(DISK_BANDWIDTH_ANNOTATION)?,
+            iops: parse(DISK_IOPS_ANNOTATION)?,
+        })
+    }
+
+    /// Limits of the volume mounted to `destination`, none if it is not specified.
+    pub fn for_volume(
+        annotations: &HashMap<String, String>,
+        destination: &str,
+    ) -> Result<Option<Self>> {
+        let value = match annotations.get(VOLUME_RATE_LIMITS_ANNOTATION) {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let mut limits: HashMap<String, VolumeRateLimit> = serde_json::from_str(value)
+            .map_err(|e| anyhow!("invalid {}: {}", VOLUME_RATE_LIMITS_ANNOTATION, e))?;
+        let limit = match limits.remove(destination) {
+            Some(l) => l,
+            None => return Ok(None),
+        };
+        let parse = |value: Option<String>| -> Result<Option<u64>> {
+            value
+                .map(|v| {
+                    parse_rate(&v).map_err(|e| {
+                        anyhow!(
+                            "invalid {} of {}: {}",
+                            VOLUME_RATE_LIMITS_ANNOTATION,
+                            destination,
+                            e
+                        )
+                    })
+                })
+                .transpose()
+                .map_err(Into::into)
+        };
+        Ok(Some(Self {
+            bandwidth: parse(limit.bandwidth)?,
+            iops: parse(limit.iops)?,
+        }))
+    }
+
+    /// Take the limits not specified in self from `other`.
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            bandwidth: self.bandwidth.or(other.bandwidth),
+            iops: self.iops.or(other.iops),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bandwidth.is_none() && self.iops.is_none()
+    }
+}
+
+fn parse_rate(value: &str) -> anyhow::Result<u64> {
+    let rate = parse_quantity(value)?;
+    if rate == 0 {
+        return Err(anyhow!("{} should be greater than 0", value));
+    }
+    Ok(rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::storage::ratelimit::{
+        DiskRateLimit, DISK_BANDWIDTH_ANNOTATION, DISK_IOPS_ANNOTATION,
+        VOLUME_RATE_LIMITS_ANNOTATION,
+    };
+
+    #[test]
+    fn test_disk_rate_limit_from_annotations() {
+        let mut annotations = HashMap::new();
+        let limit = DiskRateLimit::from_annotations(&annotations).unwrap();
+        assert!(limit.is_empty());
+
+        annotations.insert(DISK_BANDWIDTH_ANNOTATION.to_string(), "10Mi".to_string());
+        annotations.insert(DISK_IOPS_ANNOTATION.to_string(), "1k".to_string());
+        let limit = DiskRateLimit::from_annotations(&annotations).unwrap();
+        assert_eq!(limit.bandwidth, Some(10 << 20));
+        assert_eq!(limit.iops, Some(1000));
+
+        annotations.insert(DISK_IOPS_ANNOTATION.to_string(), "0".to_string());
+        assert!(DiskRateLimit::from_annotations(&annotations).is_err());
+    }
+
+    #[test]
+    fn test_volume_rate_limit() {
+        let mut annotations = HashMap::new();
+        assert_eq!(
+            DiskRateLimit::for_volume(&annotations, "/data").unwrap(),
+            None
+        );
+
+        annotations.insert(
+            VOLUME_RATE_LIMITS_ANNOTATION.to_string(),
+            r#"{"/data": {"iops": "500"}, "/log": {"bandwidth": "1M"}}"#.to_string(),
+        );
+        let pod_limit = DiskRateLimit {
+            bandwidth: Some(100 << 20),
+            iops: Some(1000),
+        };
+        let limit = DiskRateLimit::for_volume(&annotations, "/data")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            limit.or(pod_limit),
+            DiskRateLimit {
+                bandwidth: Some(100 << 20),
+                iops: Some(500),
+            }
+        );
+        let limit = DiskRateLimit::for_volume(&annotations, "/log")
+            .unwrap()
+            .unwrap();
+        assert_eq!(limit.bandwidth, Some(1_000_000));
+        assert_eq!(limit.iops, None);
+        assert_eq!(
+            DiskRateLimit::for_volume(&annotations, "/other").unwrap(),
+            None
+        );
+
+        annotations.insert(
+            VOLUME_RATE_LIMITS_ANNOTATION.to_string(),
+            r#"{"/data": {"ops": "500"}}"#.to_string(),
+        );
+        assert!(DiskRateLimit::for_volume(&annotations, "/data").is_err());
+    }
+}
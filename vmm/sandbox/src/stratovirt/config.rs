@@ -39,6 +39,9 @@ pub struct StratoVirtVMConfig {
     #[serde(flatten)]
     pub common: HypervisorCommonConfig,
     pub virtiofsd_conf: VirtiofsdConfig,
+    /// Max vcpus the vm can be resized to, no vcpus hotplug if it is not larger than vcpus.
+    #[serde(default)]
+    pub max_vcpus: u32,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
@@ -56,6 +59,7 @@ impl Default for StratoVirtVMConfig {
                 path: DEFAULT_VHOST_USER_FS_BIN_PATH.to_string(),
             },
             block_device_driver: "virtio-blk".to_string(),
+            max_vcpus: 0,
         }
     }
 }
@@ -76,6 +80,11 @@ impl StratoVirtVMConfig {
 
         result.smp = SMP {
             cpus: self.common.vcpus,
+            max_cpus: if self.max_vcpus > self.common.vcpus {
+                self.max_vcpus
+            } else {
+                0
+            },
         };
 
         result.memory = Memory {
@@ -203,6 +212,9 @@ pub struct Global {
 #[derive(CmdLineParams, Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SMP {
     pub cpus: u32,
+    #[property(key = "maxcpus", predicate = "self.max_cpus > 0")]
+    #[serde(default)]
+    pub max_cpus: u32,
 }
 
 #[derive(CmdLineParams, Debug, Default, Clone, Serialize, Deserialize)]
@@ -255,6 +267,7 @@ pub struct StratoVirtConfig {
     pub global_params: Vec<Global>,
     pub knobs: Knobs,
     pub firmware: Option<Firmware>,
+    pub incoming: Option<String>,
 }
 
 #[cfg(test)]
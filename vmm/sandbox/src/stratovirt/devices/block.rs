@@ -15,7 +15,7 @@ limitations under the License.
 */
 
 use async_trait::async_trait;
-use containerd_sandbox::error::Result;
+use containerd_sandbox::error::{Error, Result};
 use log::{debug, error};
 use qapi::{
     qmp::{
@@ -30,7 +30,8 @@ use serde_json::Value;
 
 use crate::{
     device::Device,
-    stratovirt::{devices::HotAttachable, qmp_client::QmpClient},
+    storage::ratelimit::DiskRateLimit,
+    stratovirt::{devices::HotAttachable, qmp::BlockdevAddWithIops, qmp_client::QmpClient},
 };
 
 pub const VIRTIO_BLK_DRIVER: &str = "virtio-blk";
@@ -57,6 +58,8 @@ pub struct VirtioBlockDevice {
     pub bus: Option<String>,
     #[property(param = "device", predicate = "self.addr.len()>0")]
     pub addr: String,
+    #[property(param = "drive")]
+    pub iops: Option<u64>,
 }
 
 impl_device_no_bus!(VirtioBlockDevice);
@@ -80,7 +83,20 @@ impl VirtioBlockDevice {
             direct: None,
             bus: None,
             addr: "".to_string(),
+            iops: None,
+        }
+    }
+
+    // stratovirt limits only the io operations of the disks
+    pub fn set_rate_limit(&mut self, limit: &DiskRateLimit) -> Result<()> {
+        if limit.bandwidth.is_some() {
+            return Err(Error::InvalidArgument(format!(
+                "stratovirt can not limit the bandwidth of disk {}",
+                self.id
+            )));
         }
+        self.iops = limit.iops;
+        Ok(())
     }
 }
 
@@ -88,7 +104,17 @@ impl VirtioBlockDevice {
 impl HotAttachable for VirtioBlockDevice {
     async fn execute_hot_attach(&self, client: &QmpClient, rp_id: &str) -> Result<()> {
         debug!("hot attach block device {}", self.id);
-        client.execute(self.to_blockdev_add()).await?;
+        match self.iops {
+            Some(iops) => {
+                client
+                    .execute(BlockdevAddWithIops {
+                        options: self.to_blockdev_add().0,
+                        iops,
+                    })
+                    .await?
+            }
+            None => client.execute(self.to_blockdev_add()).await?,
+        };
         match client.execute(self.to_device_add(rp_id)).await {
             Ok(_) => Ok(()),
             Err(e) => {
@@ -185,6 +211,7 @@ mod tests {
     use serde_json::Value;
 
     use super::{VirtioBlockDevice, VIRTIO_BLK_DRIVER};
+    use crate::{storage::ratelimit::DiskRateLimit, stratovirt::qmp::BlockdevAddWithIops};
 
     fn compare_json_strings(json_str1: &str, json_str2: &str) -> bool {
         let value1: Value = serde_json::from_str(json_str1).unwrap();
@@ -262,4 +289,39 @@ mod tests {
         let expected_params_str = r#"{"node-name":"drive-0"}"#;
         assert_eq!(expected_params_str, blockdev_del_qmp_json_str);
     }
+
+    #[test]
+    fn test_block_device_add_with_iops_qmp_commands() {
+        let mut virtio_blk_device = VirtioBlockDevice::new(
+            VIRTIO_BLK_DRIVER,
+            "drive-0",
+            "",
+            Some("/dev/dm-8".to_string()),
+            Some(false),
+        );
+        virtio_blk_device
+            .set_rate_limit(&DiskRateLimit {
+                bandwidth: None,
+                iops: Some(1000),
+            })
+            .unwrap();
+        assert!(virtio_blk_device
+            .set_rate_limit(&DiskRateLimit {
+                bandwidth: Some(104857600),
+                iops: None,
+            })
+            .is_err());
+
+        let blockdev_add_qmp_cmd = BlockdevAddWithIops {
+            options: virtio_blk_device.to_blockdev_add().0,
+            iops: virtio_blk_device.iops.unwrap(),
+        };
+        let blockdev_add_qmp_json_str = serde_json::to_string(&blockdev_add_qmp_cmd).unwrap();
+
+        let expected_params_str = r#"{"driver":"raw","read-only":false,"node-name":"drive-0","cache":{"direct":true},"file":{"driver":"file","filename":"/dev/dm-8"},"iops":1000}"#;
+        assert!(compare_json_strings(
+            &blockdev_add_qmp_json_str,
+            expected_params_str,
+        ));
+    }
 }
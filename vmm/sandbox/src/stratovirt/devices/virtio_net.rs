@@ -143,7 +143,8 @@ impl VirtioNetDevice {
 
     pub fn fds(mut self, fds: Vec<RawFd>) -> Self {
         self.fds = fds;
-        self.multi_queue = !self.fds.is_empty();
+        // each fd is a queue pair of the tap device
+        self.multi_queue = self.fds.len() > 1;
         self
     }
 
@@ -248,4 +249,30 @@ mod tests {
                 == "virtio-net-device,netdev=intf-tap0,id=virtio-net-intf-tap0,mac=a1:b2:c3:d5:f4,mq=off")
             .is_some());
     }
+
+    #[test]
+    fn test_virtio_net_params_multi_queue() {
+        let device = VirtioNetDevice::new()
+            .id("intf-tap0")
+            .name("tap0")
+            .mac_address("a1:b2:c3:d5:f4")
+            .transport(Transport::Pci)
+            .fds(vec![4, 5])
+            .build();
+        let params = device.to_cmdline_params("-");
+        assert!(params.iter().any(|x| x == "tap,id=intf-tap0,fds=4:5"));
+        assert!(params.iter().any(|x| x
+            == "virtio-net-pci,netdev=intf-tap0,id=virtio-net-intf-tap0,vectors=6,mac=a1:b2:c3:d5:f4,mq=on"));
+
+        // a single queue pair needs no multi queue
+        let device = VirtioNetDevice::new()
+            .id("intf-tap0")
+            .mac_address("a1:b2:c3:d5:f4")
+            .transport(Transport::Pci)
+            .fds(vec![4])
+            .build();
+        let params = device.to_cmdline_params("-");
+        assert!(params.iter().any(|x| x
+            == "virtio-net-pci,netdev=intf-tap0,id=virtio-net-intf-tap0,vectors=4,mac=a1:b2:c3:d5:f4,mq=off"));
+    }
 }
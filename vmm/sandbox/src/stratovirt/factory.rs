@@ -56,6 +56,15 @@ impl VMFactory for StratoVirtVMFactory {
         }
     }
 
+    fn hypervisor(&self) -> &'static str {
+        "stratovirt"
+    }
+
+    fn pool_supported(&self) -> bool {
+        // tap devices can not be hot attached to stratovirt vms yet
+        false
+    }
+
     async fn create_vm(
         &self,
         id: &str,
@@ -68,6 +77,7 @@ impl VMFactory for StratoVirtVMFactory {
         vm.config.name = format!("sandbox-{}", id);
         vm.config.pid_file = format!("{}/sandbox-{}.pid", s.base_dir, id);
         vm.block_driver = BlockDriver::from(self.default_config.block_device_driver.as_str());
+        vm.max_net_queues = self.default_config.common.max_net_queues;
         if self.default_config.common.debug {
             vm.config.log_file = Some(format!("{}/sandbox-{}.log", s.base_dir, id));
         }
@@ -28,23 +28,33 @@ use async_trait::async_trait;
 use containerd_sandbox::error::{Error, Result};
 use futures_util::TryFutureExt;
 use log::{debug, error, trace, warn};
-use nix::{fcntl::OFlag, libc::kill, sys::stat::Mode};
-use qapi::qmp::quit;
+use nix::{fcntl::OFlag, sys::signal::Signal, sys::stat::Mode};
+use qapi::{
+    qmp::{
+        cont, device_add, migrate, query_migrate, query_status, quit, stop, MigrationStatus,
+        RunState,
+    },
+    Dictionary,
+};
 use qmp::CpuInfo;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use time::OffsetDateTime;
 use tokio::{
+    fs::create_dir_all,
     net::UnixStream,
     sync::watch::{channel, Receiver},
     task::spawn_blocking,
     time::sleep,
 };
 use unshare::Fd;
+use vmm_common::process::ProcessHandle;
 
 use self::devices::{pcie_rootbus::PcieRootBus, rootport::RootPort, PCIE_ROOTBUS_CAPACITY};
 use crate::{
     device::{Bus, BusType, DeviceInfo, Slot, SlotStatus},
     impl_recoverable,
+    network::net_queues,
     param::ToCmdLineParams,
     stratovirt::{
         config::StratoVirtConfig,
@@ -56,7 +66,7 @@ use crate::{
         utils::detect_pid,
         virtiofs::VirtiofsDaemon,
     },
-    utils::{read_std, wait_channel, wait_pid},
+    utils::{read_std, wait_channel},
     vm::{BlockDriver, Pids, VcpuThreads, VM},
 };
 
@@ -70,6 +80,9 @@ mod utils;
 mod virtiofs;
 
 pub(crate) const STRATOVIRT_START_TIMEOUT_IN_SEC: u64 = 10;
+pub(crate) const STRATOVIRT_MIGRATE_TIMEOUT_IN_SEC: u64 = 300;
+// cpu hotplug of stratovirt is only supported on x86_64
+const STRATOVIRT_HOTPLUG_VCPU_DRIVER: &str = "generic-x86-cpu";
 pub const CONFIG_STRATOVIRT_PATH: &str = "/var/lib/kuasar/config_stratovirt.toml";
 
 // restart recovery is not supported yet,
@@ -100,6 +113,10 @@ pub struct StratoVirtVM {
     pcie_root_bus: Option<PcieRootBus>,
     #[serde(skip)]
     pcie_root_ports_pool: Option<PCIERootPorts>,
+    #[serde(default)]
+    hotplugged_vcpus: Vec<String>,
+    #[serde(default)]
+    max_net_queues: u32,
 }
 
 #[async_trait]
@@ -143,10 +160,10 @@ impl VM for StratoVirtVM {
 
         // update vmm related pids
         let vmm_pid = detect_pid(self.config.pid_file.as_str(), self.config.path.as_str()).await?;
-        self.pids.vmm_pid = Some(vmm_pid);
+        self.pids.set_vmm_pid(vmm_pid);
         if let Some(virtiofsd) = &self.virtiofs_daemon {
             if let Some(pid) = virtiofsd.pid {
-                self.pids.affiliated_pids.push(pid);
+                self.pids.add_affiliated_pid(pid);
             }
         }
 
@@ -156,7 +173,7 @@ impl VM for StratoVirtVM {
     async fn stop(&mut self, force: bool) -> Result<()> {
         // before stop the vm process, stop the virtiofs daemon process firstly
         debug!("stop virtiofs daemon process");
-        self.virtiofs_daemon.as_mut().unwrap().stop()?;
+        self.virtiofs_daemon.as_mut().unwrap().stop(&self.pids)?;
 
         debug!("stop vm {}", self.id);
         if !force {
@@ -175,7 +192,7 @@ impl VM for StratoVirtVM {
                     if pid == 0 {
                         return Ok(());
                     }
-                    unsafe { kill(pid as i32, 9) };
+                    self.pids.kill(pid, Signal::SIGKILL).unwrap_or_default();
                 }
             } else {
                 return Err(e);
@@ -216,13 +233,14 @@ impl VM for StratoVirtVM {
     async fn hot_attach(&mut self, device_info: DeviceInfo) -> Result<(BusType, String)> {
         match device_info {
             DeviceInfo::Block(blk_info) => {
-                let device = VirtioBlockDevice::new(
+                let mut device = VirtioBlockDevice::new(
                     "",
                     &blk_info.id,
                     "",
                     Some(blk_info.path),
                     Some(blk_info.read_only),
                 );
+                device.set_rate_limit(&blk_info.rate_limit)?;
                 let index = self.hot_attach_device(device).await?;
                 let addr = format!("0000:00:{:02x}.0", index);
                 Ok((self.block_driver.to_bus_type(), addr))
@@ -271,7 +289,7 @@ impl VM for StratoVirtVM {
 
     async fn ping(&self) -> Result<()> {
         let client = self.get_client()?;
-        let _res = client.execute(qapi::qmp::query_status {}).await?;
+        let _res = client.execute(query_status {}).await?;
         Ok(())
     }
 
@@ -302,6 +320,120 @@ impl VM for StratoVirtVM {
     fn pids(&self) -> Pids {
         self.pids.clone()
     }
+
+    async fn pause(&mut self) -> Result<()> {
+        self.get_client()?.execute(stop {}).await?;
+        Ok(())
+    }
+
+    async fn resume(&mut self) -> Result<()> {
+        self.get_client()?.execute(cont {}).await?;
+        Ok(())
+    }
+
+    async fn snapshot(&mut self, path: &str) -> Result<()> {
+        // the restored vm is started with the devices in the command line only, so the state of
+        // the hot attached devices would have no device to be loaded into.
+        if !self.hot_attached_devices.is_empty() {
+            let ids = self
+                .hot_attached_devices
+                .iter()
+                .map(|d| d.id())
+                .collect::<Vec<String>>();
+            return Err(Error::Unimplemented(format!(
+                "snapshot of stratovirt vm with hot attached devices {}, \
+                 checkpoint it before the containers or interfaces are added",
+                ids.join(",")
+            )));
+        }
+        create_dir_all(path).await?;
+        let client = self.get_client()?;
+        client.execute(stop {}).await?;
+        let res = self.migrate(format!("file:{}", path)).await;
+        // resume the vm even if snapshot failed
+        self.get_client()?.execute(cont {}).await?;
+        res
+    }
+
+    async fn restore(&mut self, path: &str) -> Result<u32> {
+        self.config.incoming = Some(format!("file:{}", path));
+        let res = self.start().await;
+        self.config.incoming = None;
+        let pid = res?;
+        if let Err(e) = self.wait_incoming().await {
+            if let Err(re) = self.stop(true).await {
+                warn!("roll back in restore stratovirt: {}", re);
+                return Err(e);
+            }
+            return Err(e);
+        }
+        Ok(pid)
+    }
+
+    async fn resize_vcpus(&mut self, vcpus: u32) -> Result<()> {
+        if !cfg!(target_arch = "x86_64") {
+            return Err(Error::Unimplemented(
+                "vcpus hotplug of stratovirt on this arch".to_string(),
+            ));
+        }
+        let max = self.config.smp.max_cpus.max(self.config.smp.cpus);
+        if vcpus > max {
+            return Err(Error::ResourceExhausted(format!(
+                "vcpus of stratovirt {}, {} vcpus at most",
+                self.id, max
+            )));
+        }
+        let client = self
+            .client
+            .as_ref()
+            .ok_or_else(|| anyhow!("qmp client is not init"))?;
+        let mut online = client.execute(qmp::QueryCpus {}).await?.len() as u32;
+        while online < vcpus {
+            let id = format!("vcpu-{}", online);
+            let mut args = Dictionary::new();
+            args.insert("cpu-id".to_string(), Value::from(online));
+            client
+                .execute(device_add {
+                    driver: STRATOVIRT_HOTPLUG_VCPU_DRIVER.to_string(),
+                    bus: None,
+                    id: Some(id.to_string()),
+                    arguments: args,
+                })
+                .await?;
+            self.hotplugged_vcpus.push(id);
+            online += 1;
+        }
+        while online > vcpus {
+            // vcpus on the command line can not be unplugged
+            let id = match self.hotplugged_vcpus.pop() {
+                Some(id) => id,
+                None => {
+                    warn!("stratovirt {} keeps {} boot vcpus", self.id, online);
+                    break;
+                }
+            };
+            if let Err(e) = client.delete_device(&id).await {
+                self.hotplugged_vcpus.push(id);
+                return Err(e);
+            }
+            online -= 1;
+        }
+        Ok(())
+    }
+
+    async fn resize_memory(&mut self, _size: u64) -> Result<()> {
+        Err(Error::Unimplemented(
+            "memory hotplug of stratovirt".to_string(),
+        ))
+    }
+
+    fn net_queues(&self) -> u32 {
+        net_queues(self.config.smp.cpus, self.max_net_queues)
+    }
+
+    fn shared_fs_supported(&self) -> bool {
+        true
+    }
 }
 
 impl StratoVirtVM {
@@ -322,6 +454,8 @@ impl StratoVirtVM {
             pcie_root_ports_pool: None,
             pcie_root_bus: None,
             pids: Pids::default(),
+            hotplugged_vcpus: vec![],
+            max_net_queues: 0,
         }
     }
 
@@ -396,16 +530,19 @@ impl StratoVirtVM {
         let (tx, rx) = channel((0u32, 0i128));
         let _wait_handle = tokio::spawn(async move {
             // because the direct child process is not the actual running stratovirt process,
-            // so we have to read pid from the stratovirt.pid file,
-            // NOTE: it is hard to eliminate the race condition when pid reused.
-            if let Ok(pid) = detect_pid(&pid_file, &path).await {
-                let wait_result = wait_pid(pid as i32).await;
-                tx.send(wait_result).unwrap_or_default();
-            } else {
-                warn!("failed to get stratovirt pid from {}", pid_file);
-                tx.send((0, OffsetDateTime::now_utc().unix_timestamp_nanos()))
-                    .unwrap_or_default();
-            }
+            // so we have to read pid from the stratovirt.pid file, and wait it by pidfd.
+            let process = match detect_pid(&pid_file, &path).await {
+                Ok(pid) => ProcessHandle::open(pid, 0),
+                Err(_) => {
+                    warn!("failed to get stratovirt pid from {}", pid_file);
+                    None
+                }
+            };
+            let wait_result = match process {
+                Some(p) => p.wait().await,
+                None => (0, OffsetDateTime::now_utc().unix_timestamp_nanos()),
+            };
+            tx.send(wait_result).unwrap_or_default();
         });
         Ok(rx)
     }
@@ -428,6 +565,65 @@ impl StratoVirtVM {
         Ok(client)
     }
 
+    async fn migrate(&self, uri: String) -> Result<()> {
+        let client = self.get_client()?;
+        client
+            .execute(migrate {
+                uri,
+                blk: None,
+                inc: None,
+                detach: None,
+                resume: None,
+            })
+            .await?;
+        let start_time = SystemTime::now();
+        loop {
+            let info = client.execute(query_migrate {}).await?;
+            match info.status {
+                Some(MigrationStatus::completed) => return Ok(()),
+                Some(MigrationStatus::failed) | Some(MigrationStatus::cancelled) => {
+                    return Err(anyhow!(
+                        "migration of stratovirt {} failed: {}",
+                        self.id,
+                        info.error_desc.unwrap_or_default()
+                    )
+                    .into());
+                }
+                _ => {}
+            }
+            if start_time.elapsed().unwrap().as_secs() > STRATOVIRT_MIGRATE_TIMEOUT_IN_SEC {
+                return Err(
+                    anyhow!("timeout waiting for migration of stratovirt {}", self.id).into(),
+                );
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    // Wait for the incoming migration finished, and resume the vm if it stays paused.
+    async fn wait_incoming(&self) -> Result<()> {
+        let client = self.get_client()?;
+        let start_time = SystemTime::now();
+        loop {
+            let status = client.execute(query_status {}).await?;
+            if status.running {
+                return Ok(());
+            }
+            if status.status != RunState::inmigrate {
+                client.execute(cont {}).await?;
+                return Ok(());
+            }
+            if start_time.elapsed().unwrap().as_secs() > STRATOVIRT_MIGRATE_TIMEOUT_IN_SEC {
+                return Err(anyhow!(
+                    "timeout waiting for incoming migration of stratovirt {}",
+                    self.id
+                )
+                .into());
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+    }
+
     async fn wait_stop(&mut self, t: Duration) -> Result<()> {
         if let Some(rx) = self.wait_chan.clone() {
             let (_, ts) = *rx.borrow();
@@ -14,9 +14,25 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use qapi::qmp::QmpCommand;
+use qapi::qmp::{BlockdevOptions, QmpCommand};
 use serde::{Deserialize, Serialize};
 
+// stratovirt takes the iops limit of the disk as an argument of blockdev-add
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockdevAddWithIops {
+    #[serde(flatten)]
+    pub options: BlockdevOptions,
+    pub iops: u64,
+}
+
+impl QmpCommand for BlockdevAddWithIops {}
+impl ::qapi_spec::Command for BlockdevAddWithIops {
+    const NAME: &'static str = "blockdev-add";
+    const ALLOW_OOB: bool = false;
+
+    type Ok = ::qapi_spec::Empty;
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueryCpus {}
 
@@ -17,16 +17,11 @@ limitations under the License.
 use std::process::Stdio;
 
 use anyhow::{anyhow, Result};
-use log::{debug, error};
+use log::debug;
 use sandbox_derive::CmdLineParamSet;
 use serde::{Deserialize, Serialize};
-use time::OffsetDateTime;
-use tokio::{process::Child, sync::watch::Sender, task::JoinHandle};
 
-use crate::{
-    param::ToCmdLineParams,
-    utils::{read_std, write_file_atomic},
-};
+use crate::{param::ToCmdLineParams, utils::spawn_wait, vm::Pids};
 
 pub(crate) const DEFAULT_VHOST_USER_FS_BIN_PATH: &str = "/usr/bin/vhost_user_fs";
 
@@ -72,13 +67,12 @@ impl VirtiofsDaemon {
         Ok(())
     }
 
-    pub fn stop(&self) -> Result<()> {
+    /// Kill the daemon process, which is checked against the start time recorded in `pids`.
+    pub fn stop(&self, pids: &Pids) -> Result<()> {
         if let Some(pid) = self.pid {
             if pid > 1 {
-                nix::sys::signal::kill(
-                    nix::unistd::Pid::from_raw(pid as i32),
-                    nix::sys::signal::SIGKILL,
-                )?;
+                pids.kill(pid, nix::sys::signal::SIGKILL)
+                    .map_err(|e| anyhow!("{}", e))?;
             } else {
                 return Err(anyhow!("invalid virtiofs daemon process pid: {}", pid));
             }
@@ -87,58 +81,3 @@ impl VirtiofsDaemon {
         Ok(())
     }
 }
-
-macro_rules! read_stdio {
-    ($stdio:expr, $cmd_name:ident) => {
-        if let Some(std) = $stdio {
-            let cmd_name_clone = $cmd_name.clone();
-            tokio::spawn(async move {
-                read_std(std, &cmd_name_clone).await.unwrap_or_default();
-            });
-        }
-    };
-}
-
-fn spawn_wait(
-    child: Child,
-    cmd_name: String,
-    pid_file_path: Option<String>,
-    exit_chan: Option<Sender<(u32, i128)>>,
-) -> JoinHandle<()> {
-    let mut child = child;
-    tokio::spawn(async move {
-        if let Some(pid_file) = pid_file_path {
-            if let Some(pid) = child.id() {
-                write_file_atomic(&pid_file, &pid.to_string())
-                    .await
-                    .unwrap_or_default();
-            }
-        }
-
-        read_stdio!(child.stdout.take(), cmd_name);
-        read_stdio!(child.stderr.take(), cmd_name);
-
-        match child.wait().await {
-            Ok(status) => {
-                if !status.success() {
-                    error!("{} exit {}", cmd_name, status);
-                }
-                let now = OffsetDateTime::now_utc();
-                if let Some(tx) = exit_chan {
-                    tx.send((
-                        status.code().unwrap_or_default() as u32,
-                        now.unix_timestamp_nanos(),
-                    ))
-                    .unwrap_or_default();
-                }
-            }
-            Err(e) => {
-                error!("{} wait error {}", cmd_name, e);
-                let now = OffsetDateTime::now_utc();
-                if let Some(tx) = exit_chan {
-                    tx.send((0, now.unix_timestamp_nanos())).unwrap_or_default();
-                }
-            }
-        }
-    })
-}
@@ -36,7 +36,7 @@ use containerd_sandbox::{
 use log::{error, info};
 use nix::{
     fcntl::{fcntl, open, FdFlag, OFlag, F_GETFD, F_SETFD},
-    libc::{kill, setns, FD_CLOEXEC},
+    libc::{setns, FD_CLOEXEC},
     sched::CloneFlags,
     sys::stat::Mode,
     unistd::dup2,
@@ -45,9 +45,9 @@ use time::OffsetDateTime;
 use tokio::{
     fs::{File, OpenOptions},
     io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader},
-    process::Command,
-    sync::watch::Receiver,
-    time::sleep,
+    process::{Child, Command},
+    sync::watch::{Receiver, Sender},
+    task::JoinHandle,
 };
 use vmm_common::NET_NAMESPACE;
 
@@ -303,20 +303,6 @@ pub async fn get_host_memory_in_mb() -> Result<u64> {
     Err(anyhow!("can not get host memory info from /proc/meminfo").into())
 }
 
-// wait_pid waits for non-children process exit
-// we can only poll using kill(pid, 0) before kernel 5.3
-// we may open pidfd and epoll on it to get notification after kernel 5.3
-pub async fn wait_pid(pid: i32) -> (u32, i128) {
-    loop {
-        let kill_result = unsafe { kill(pid, 0) };
-        if kill_result != 0 {
-            let now = OffsetDateTime::now_utc();
-            return (0, now.unix_timestamp_nanos());
-        }
-        sleep(Duration::from_millis(5)).await;
-    }
-}
-
 pub async fn write_file_async<P: AsRef<Path>>(path: P, s: &str) -> Result<()> {
     let path = path.as_ref();
     let mut f = OpenOptions::new()
@@ -426,6 +412,63 @@ pub async fn read_std<T: AsyncRead + Unpin>(std: T, prefix: &str) -> Result<()>
     }
 }
 
+macro_rules! read_stdio {
+    ($stdio:expr, $cmd_name:ident) => {
+        if let Some(std) = $stdio {
+            let cmd_name_clone = $cmd_name.clone();
+            tokio::spawn(async move {
+                read_std(std, &cmd_name_clone).await.unwrap_or_default();
+            });
+        }
+    };
+}
+
+/// Log the stdout and stderr of the child process and wait for it to exit, the pid is written
+/// into `pid_file_path` and the exit code and time are sent to `exit_chan` if they are given.
+pub fn spawn_wait(
+    child: Child,
+    cmd_name: String,
+    pid_file_path: Option<String>,
+    exit_chan: Option<Sender<(u32, i128)>>,
+) -> JoinHandle<()> {
+    let mut child = child;
+    tokio::spawn(async move {
+        if let Some(pid_file) = pid_file_path {
+            if let Some(pid) = child.id() {
+                write_file_atomic(&pid_file, &pid.to_string())
+                    .await
+                    .unwrap_or_default();
+            }
+        }
+
+        read_stdio!(child.stdout.take(), cmd_name);
+        read_stdio!(child.stderr.take(), cmd_name);
+
+        match child.wait().await {
+            Ok(status) => {
+                if !status.success() {
+                    error!("{} exit {}", cmd_name, status);
+                }
+                let now = OffsetDateTime::now_utc();
+                if let Some(tx) = exit_chan {
+                    tx.send((
+                        status.code().unwrap_or_default() as u32,
+                        now.unix_timestamp_nanos(),
+                    ))
+                    .unwrap_or_default();
+                }
+            }
+            Err(e) => {
+                error!("{} wait error {}", cmd_name, e);
+                let now = OffsetDateTime::now_utc();
+                if let Some(tx) = exit_chan {
+                    tx.send((0, now.unix_timestamp_nanos())).unwrap_or_default();
+                }
+            }
+        }
+    })
+}
+
 pub fn safe_open_file<P: ?Sized + nix::NixPath>(
     path: &P,
     oflag: OFlag,
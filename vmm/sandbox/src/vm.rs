@@ -21,8 +21,11 @@ use containerd_sandbox::{
     error::{Error, Result},
     SandboxOption,
 };
+use log::warn;
+use nix::sys::signal::Signal;
 use serde::{Deserialize, Serialize};
 use tokio::sync::watch::Receiver;
+use vmm_common::process::{start_time, ProcessHandle};
 
 use crate::{
     device::{BusType, DeviceInfo},
@@ -37,6 +40,11 @@ pub trait VMFactory {
     type VM: VM + Sync + Send;
     type Config: Sync + Send;
     fn new(config: Self::Config) -> Self;
+    /// Name of the hypervisor, it is recorded in the dumped sandbox for kuasarctl.
+    fn hypervisor(&self) -> &'static str;
+    /// Whether the vms can be booted ahead in the pool, the network devices are hot attached
+    /// to a pooled vm after a sandbox takes it.
+    fn pool_supported(&self) -> bool;
     async fn create_vm(&self, id: &str, s: &SandboxOption) -> Result<Self::VM>;
 }
 
@@ -71,6 +79,22 @@ pub trait VM: Serialize + Sync + Send {
     async fn wait_channel(&self) -> Option<Receiver<(u32, i128)>>;
     async fn vcpus(&self) -> Result<VcpuThreads>;
     fn pids(&self) -> Pids;
+    async fn pause(&mut self) -> Result<()>;
+    async fn resume(&mut self) -> Result<()>;
+    /// Save the state of the running vm into the directory `path`, the vm keeps running after it.
+    async fn snapshot(&mut self, path: &str) -> Result<()>;
+    /// Launch a new vmm process from the snapshot saved in the directory `path`,
+    /// devices cold attached before are handed to the restored vm, returns the vmm pid.
+    async fn restore(&mut self, path: &str) -> Result<u32>;
+    /// Hot plug or unplug vcpus of the running vm so that it has `vcpus` vcpus.
+    async fn resize_vcpus(&mut self, vcpus: u32) -> Result<()>;
+    /// Hot plug memory to the running vm so that it has `size` bytes of memory.
+    async fn resize_memory(&mut self, size: u64) -> Result<()>;
+    /// Queues of each virtio-net device, decided by the vcpus the vm boots with.
+    fn net_queues(&self) -> u32;
+    /// Whether the sandbox shared dir is shared with guest by virtio-fs or virtio-9p,
+    /// storages other than block devices are handed to the guest through it.
+    fn shared_fs_supported(&self) -> bool;
 }
 
 #[macro_export]
@@ -79,11 +103,14 @@ macro_rules! impl_recoverable {
         #[async_trait]
         impl $crate::vm::Recoverable for $ty {
             async fn recover(&mut self) -> Result<()> {
-                self.client = Some(self.create_client().await?);
                 let pid = self.pid()?;
+                let process = self.pids.open(pid).ok_or_else(|| {
+                    ::anyhow::anyhow!("vmm process {} is not running any more", pid)
+                })?;
+                self.client = Some(self.create_client().await?);
                 let (tx, rx) = channel((0u32, 0i128));
                 tokio::spawn(async move {
-                    let wait_result = wait_pid(pid as i32).await;
+                    let wait_result = process.wait().await;
                     tx.send(wait_result).unwrap_or_default();
                 });
                 self.wait_chan = Some(rx);
@@ -116,6 +143,10 @@ pub struct HypervisorCommonConfig {
     pub firmware: String,
     #[serde(default)]
     pub enable_mem_prealloc: bool,
+    /// Max queues of each virtio-net device, the queues follow the vcpus the vm boots with,
+    /// 0 means no limit other than the vcpus.
+    #[serde(default)]
+    pub max_net_queues: u32,
 }
 
 impl Default for HypervisorCommonConfig {
@@ -130,6 +161,7 @@ impl Default for HypervisorCommonConfig {
             kernel_params: "".to_string(),
             firmware: "".to_string(),
             enable_mem_prealloc: false,
+            max_net_queues: 0,
         }
     }
 }
@@ -211,4 +243,45 @@ pub struct VcpuThreads {
 pub struct Pids {
     pub vmm_pid: Option<u32>,
     pub affiliated_pids: Vec<u32>,
+    /// Start time of the processes above, to tell whether the pid is reused after recovery.
+    #[serde(default)]
+    pub start_times: HashMap<u32, u64>,
+}
+
+impl Pids {
+    pub fn set_vmm_pid(&mut self, pid: u32) {
+        self.vmm_pid = Some(pid);
+        self.record_start_time(pid);
+    }
+
+    pub fn add_affiliated_pid(&mut self, pid: u32) {
+        self.affiliated_pids.push(pid);
+        self.record_start_time(pid);
+    }
+
+    /// Open the handle of the process `pid`, none if it exited or the pid is reused.
+    pub fn open(&self, pid: u32) -> Option<ProcessHandle> {
+        ProcessHandle::open(pid, self.start_times.get(&pid).copied().unwrap_or_default())
+    }
+
+    /// Send the signal to the process `pid`, returns false if it is not running any more.
+    pub fn kill(&self, pid: u32, signal: Signal) -> Result<bool> {
+        match self.open(pid) {
+            Some(process) => {
+                process.signal(signal as i32)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    // A process without the start time recorded is opened without checking it.
+    fn record_start_time(&mut self, pid: u32) {
+        match start_time(pid) {
+            Ok(t) => {
+                self.start_times.insert(pid, t);
+            }
+            Err(e) => warn!("failed to record start time of process {}: {}", pid, e),
+        }
+    }
 }
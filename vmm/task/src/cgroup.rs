@@ -0,0 +1,311 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::path::{Path, PathBuf};
+
+use containerd_shim::{io_error, other, Error, Result};
+use log::{debug, warn};
+use oci_spec::runtime::LinuxResources;
+
+use crate::mount::SYSFS_CGROUPPATH;
+
+pub const CGROUP_V1: u32 = 1;
+pub const CGROUP_V2: u32 = 2;
+
+const DEFAULT_CPU_PERIOD: u64 = 100_000;
+
+/// Whether the cgroup v2 unified hierarchy is mounted in guest.
+pub fn is_cgroup2_unified_mode() -> bool {
+    Path::new(SYSFS_CGROUPPATH)
+        .join("cgroup.controllers")
+        .exists()
+}
+
+/// Enable all the available controllers for the children of the root cgroup, so that the
+/// cgroups of containers can use them, a controller failed to be enabled is skipped.
+pub async fn enable_controllers() -> Result<()> {
+    let root = Path::new(SYSFS_CGROUPPATH);
+    let controllers_file = root.join("cgroup.controllers");
+    let file = controllers_file.display();
+    let controllers = tokio::fs::read_to_string(&controllers_file)
+        .await
+        .map_err(io_error!(e, "failed to read {}", file))?;
+    let subtree_control = root.join("cgroup.subtree_control");
+    for controller in controllers.split_whitespace() {
+        if let Err(e) = tokio::fs::write(&subtree_control, format!("+{}", controller)).await {
+            warn!("failed to enable cgroup controller {}: {}", controller, e);
+            continue;
+        }
+        debug!("cgroup controller {} enabled", controller);
+    }
+    Ok(())
+}
+
+/// Update the resources of the container which the process `pid` is in, the v1 resources in
+/// the request are converted to the v2 interface files in the unified mode.
+pub fn update_resources(pid: u32, resources: &LinuxResources) -> Result<()> {
+    if !is_cgroup2_unified_mode() {
+        return containerd_shim::cgroup::update_resources(pid, resources);
+    }
+    let dir = cgroup2_path(pid)?;
+    for (file, value) in convert_resources(resources)? {
+        let path = dir.join(&file);
+        debug!("write {} to {}", value, path.display());
+        std::fs::write(&path, &value).map_err(io_error!(
+            e,
+            "failed to write {} to {}",
+            value,
+            path.display()
+        ))?;
+    }
+    Ok(())
+}
+
+fn cgroup2_path(pid: u32) -> Result<PathBuf> {
+    let cgroup_file = format!("/proc/{}/cgroup", pid);
+    let content = std::fs::read_to_string(&cgroup_file)
+        .map_err(|e| other!("failed to read {}: {}", cgroup_file, e))?;
+    // the only line of cgroup v2 is like "0::/kubepods/podxxx/container"
+    let path = content
+        .lines()
+        .find_map(|l| l.strip_prefix("0::"))
+        .ok_or_else(|| other!("no cgroup v2 path of process {}", pid))?;
+    Ok(Path::new(SYSFS_CGROUPPATH).join(path.trim_start_matches('/')))
+}
+
+// Convert the resources to the cgroup v2 interface files and their values, in the same way
+// as runc does for the v1 resources in the spec.
+fn convert_resources(resources: &LinuxResources) -> Result<Vec<(String, String)>> {
+    let mut files = vec![];
+    if let Some(cpu) = resources.cpu() {
+        if let Some(shares) = cpu.shares().filter(|s| *s > 0) {
+            files.push(("cpu.weight".to_string(), cpu_shares_to_weight(shares)));
+        }
+        let quota = cpu.quota().unwrap_or_default();
+        let period = cpu.period().unwrap_or_default();
+        if quota != 0 || period != 0 {
+            let quota = if quota > 0 {
+                quota.to_string()
+            } else {
+                "max".to_string()
+            };
+            let period = if period > 0 {
+                period
+            } else {
+                DEFAULT_CPU_PERIOD
+            };
+            files.push(("cpu.max".to_string(), format!("{} {}", quota, period)));
+        }
+        if let Some(cpus) = cpu.cpus().as_ref().filter(|c| !c.is_empty()) {
+            files.push(("cpuset.cpus".to_string(), cpus.to_string()));
+        }
+        if let Some(mems) = cpu.mems().as_ref().filter(|m| !m.is_empty()) {
+            files.push(("cpuset.mems".to_string(), mems.to_string()));
+        }
+    }
+
+    if let Some(memory) = resources.memory() {
+        let limit = memory.limit().unwrap_or_default();
+        // swap is memory plus swap in v1, but only swap in v2
+        let swap = memory.swap().unwrap_or_default();
+        let swap_v2 = convert_memory_swap(swap, limit)?;
+        // swap is disabled if memory plus swap is the same as memory
+        if swap_v2 != 0 || swap > 0 {
+            files.push(("memory.swap.max".to_string(), limit_value(swap_v2)));
+        }
+        if limit != 0 {
+            files.push(("memory.max".to_string(), limit_value(limit)));
+        }
+        if let Some(reservation) = memory.reservation().filter(|r| *r != 0) {
+            files.push(("memory.low".to_string(), limit_value(reservation)));
+        }
+    }
+
+    if let Some(pids) = resources.pids() {
+        files.push(("pids.max".to_string(), limit_value(pids.limit())));
+    }
+
+    for h in resources.hugepage_limits().as_deref().unwrap_or_default() {
+        files.push((
+            format!("hugetlb.{}.max", h.page_size()),
+            limit_value(h.limit()),
+        ));
+    }
+
+    if let Some(block_io) = resources.block_io() {
+        if let Some(weight) = block_io.weight().filter(|w| *w > 0) {
+            files.push(("io.weight".to_string(), blkio_weight_to_io_weight(weight)));
+        }
+        let throttles = [
+            ("rbps", block_io.throttle_read_bps_device()),
+            ("wbps", block_io.throttle_write_bps_device()),
+            ("riops", block_io.throttle_read_iops_device()),
+            ("wiops", block_io.throttle_write_iops_device()),
+        ];
+        for (key, devices) in throttles {
+            for d in devices.as_deref().unwrap_or_default() {
+                let rate = if d.rate() > 0 {
+                    d.rate().to_string()
+                } else {
+                    "max".to_string()
+                };
+                files.push((
+                    "io.max".to_string(),
+                    format!("{}:{} {}={}", d.major(), d.minor(), key, rate),
+                ));
+            }
+        }
+    }
+
+    // the unified resources are written as they are, and override the converted ones
+    if let Some(unified) = resources.unified() {
+        for (file, value) in unified {
+            if file.contains('/') {
+                return Err(Error::InvalidArgument(format!(
+                    "invalid unified cgroup file {}",
+                    file
+                )));
+            }
+            files.push((file.to_string(), value.to_string()));
+        }
+    }
+    Ok(files)
+}
+
+// cpu.shares in [2, 262144] is mapped to cpu.weight in [1, 10000]
+fn cpu_shares_to_weight(shares: u64) -> String {
+    let shares = shares.clamp(2, 262144);
+    (1 + ((shares - 2) * 9999) / 262142).to_string()
+}
+
+// blkio.weight in [10, 1000] is mapped to io.weight in [1, 10000]
+fn blkio_weight_to_io_weight(weight: u16) -> String {
+    let weight = weight.clamp(10, 1000) as u64;
+    format!("default {}", 1 + ((weight - 10) * 9999) / 990)
+}
+
+// Convert the v1 memory+swap limit to the v2 swap limit, 0 means not to set it.
+fn convert_memory_swap(swap: i64, memory: i64) -> Result<i64> {
+    // both memory and swap are unlimited if the memory is unlimited and swap is not set
+    if memory == -1 && swap == 0 {
+        return Ok(-1);
+    }
+    if swap == -1 || swap == 0 {
+        return Ok(swap);
+    }
+    if memory <= 0 {
+        return Err(Error::InvalidArgument(
+            "unable to set swap limit without memory limit".to_string(),
+        ));
+    }
+    if swap < memory {
+        return Err(Error::InvalidArgument(format!(
+            "memory+swap limit {} should be no less than memory limit {}",
+            swap, memory
+        )));
+    }
+    Ok(swap - memory)
+}
+
+// A negative limit means no limit, which is "max" in cgroup v2
+fn limit_value(limit: i64) -> String {
+    if limit < 0 {
+        "max".to_string()
+    } else {
+        limit.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{
+        LinuxBlockIoBuilder, LinuxCpuBuilder, LinuxMemoryBuilder, LinuxPidsBuilder,
+        LinuxResourcesBuilder, LinuxThrottleDeviceBuilder,
+    };
+
+    use crate::cgroup::{convert_memory_swap, convert_resources, cpu_shares_to_weight};
+
+    #[test]
+    fn test_cpu_shares_to_weight() {
+        assert_eq!(cpu_shares_to_weight(2), "1");
+        assert_eq!(cpu_shares_to_weight(1024), "39");
+        assert_eq!(cpu_shares_to_weight(262144), "10000");
+        assert_eq!(cpu_shares_to_weight(1_000_000), "10000");
+    }
+
+    #[test]
+    fn test_convert_memory_swap() {
+        assert_eq!(convert_memory_swap(0, -1).unwrap(), -1);
+        assert_eq!(convert_memory_swap(0, 1024).unwrap(), 0);
+        assert_eq!(convert_memory_swap(-1, 1024).unwrap(), -1);
+        assert_eq!(convert_memory_swap(3072, 1024).unwrap(), 2048);
+        assert!(convert_memory_swap(1024, 0).is_err());
+        assert!(convert_memory_swap(512, 1024).is_err());
+    }
+
+    #[test]
+    fn test_convert_resources() {
+        let resources = LinuxResourcesBuilder::default()
+            .cpu(
+                LinuxCpuBuilder::default()
+                    .shares(1024u64)
+                    .quota(50000i64)
+                    .cpus("0-1")
+                    .build()
+                    .unwrap(),
+            )
+            .memory(
+                LinuxMemoryBuilder::default()
+                    .limit(1024i64)
+                    .swap(1024i64)
+                    .build()
+                    .unwrap(),
+            )
+            .pids(LinuxPidsBuilder::default().limit(-1i64).build().unwrap())
+            .block_io(
+                LinuxBlockIoBuilder::default()
+                    .weight(500u16)
+                    .throttle_read_bps_device(vec![LinuxThrottleDeviceBuilder::default()
+                        .major(8i64)
+                        .minor(0i64)
+                        .rate(1048576u64)
+                        .build()
+                        .unwrap()])
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+        let files = convert_resources(&resources).unwrap();
+        let files = files
+            .iter()
+            .map(|(f, v)| (f.as_str(), v.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            vec![
+                ("cpu.weight", "39"),
+                ("cpu.max", "50000 100000"),
+                ("cpuset.cpus", "0-1"),
+                ("memory.swap.max", "0"),
+                ("memory.max", "1024"),
+                ("pids.max", "max"),
+                ("io.weight", "default 4950"),
+                ("io.max", "8:0 rbps=1048576"),
+            ]
+        );
+    }
+}
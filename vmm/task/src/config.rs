@@ -17,11 +17,14 @@ limitations under the License.
 use containerd_shim::{io_error, Error, Result};
 use tokio::fs::read_to_string;
 
+use crate::cgroup::{CGROUP_V1, CGROUP_V2};
+
 const SHAREFS_TYPE: &str = "task.sharefs_type";
 const LOG_LEVEL: &str = "task.log_level";
 const TASK_DEBUG: &str = "task.debug";
 const ENABLE_TRACING: &str = "task.enable_tracing";
 const DEBUG_SHELL: &str = "task.debug_shell";
+const CGROUP_VERSION: &str = "task.cgroup_version";
 
 macro_rules! parse_cmdline {
     ($param:ident, $key:ident, $field:expr) => {
@@ -46,6 +49,8 @@ pub struct TaskConfig {
     pub(crate) debug: bool,
     pub(crate) enable_tracing: bool,
     pub(crate) debug_shell: String,
+    /// Version of the cgroup hierarchy mounted in guest, 1 or 2 for the unified hierarchy.
+    pub(crate) cgroup_version: u32,
 }
 
 impl Default for TaskConfig {
@@ -56,6 +61,7 @@ impl Default for TaskConfig {
             debug: false,
             enable_tracing: false,
             debug_shell: "/bin/bash".to_string(),
+            cgroup_version: CGROUP_V1,
         }
     }
 }
@@ -74,7 +80,20 @@ impl TaskConfig {
             parse_cmdline!(param, TASK_DEBUG, config.debug);
             parse_cmdline!(param, ENABLE_TRACING, config.enable_tracing);
             parse_cmdline!(param, DEBUG_SHELL, config.debug_shell, String::from);
+            parse_cmdline!(
+                param,
+                CGROUP_VERSION,
+                config.cgroup_version,
+                parse_cgroup_version
+            );
         }
         Ok(config)
     }
 }
+
+fn parse_cgroup_version(version: &str) -> u32 {
+    match version {
+        "2" | "v2" => CGROUP_V2,
+        _ => CGROUP_V1,
+    }
+}
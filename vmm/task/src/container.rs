@@ -61,9 +61,11 @@ use vmm_common::{
 };
 
 use crate::{
+    cgroup::update_resources,
     device::rescan_pci_bus,
     io::{convert_stdio, copy_io_or_console, create_io},
     sandbox::SandboxResources,
+    stats::collect_metrics,
     util::{read_io, read_storages, wait_pid},
 };
 
@@ -409,7 +411,7 @@ impl ProcessLifecycle<InitProcess> for KuasarInitLifecycle {
                 p.pid
             ));
         }
-        containerd_shim::cgroup::update_resources(p.pid as u32, resources)
+        update_resources(p.pid as u32, resources)
     }
 
     #[cfg(not(target_os = "linux"))]
@@ -427,7 +429,7 @@ impl ProcessLifecycle<InitProcess> for KuasarInitLifecycle {
                 p.pid
             ));
         }
-        containerd_shim::cgroup::collect_metrics(p.pid as u32)
+        collect_metrics(p.pid as u32).await
     }
 
     #[cfg(not(target_os = "linux"))]
@@ -546,6 +548,20 @@ impl ProcessLifecycle<ExecProcess> for KuasarExecLifecycle {
         Err(Error::Unimplemented("exec update".to_string()))
     }
 
+    #[cfg(target_os = "linux")]
+    #[instrument(skip_all)]
+    async fn stats(&self, p: &ExecProcess) -> Result<Metrics> {
+        // exec processes are in the cgroups of the container
+        if p.pid <= 0 {
+            return Err(other!(
+                "failed to collect metrics because exec process is {}",
+                p.pid
+            ));
+        }
+        collect_metrics(p.pid as u32).await
+    }
+
+    #[cfg(not(target_os = "linux"))]
     #[instrument(skip_all)]
     async fn stats(&self, _p: &ExecProcess) -> Result<Metrics> {
         Err(Error::Unimplemented("exec stats".to_string()))
@@ -0,0 +1,101 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::time::Duration;
+
+use containerd_shim::{io_error, other, Result};
+use log::{debug, warn};
+use tokio::fs::{read_dir, read_to_string, write};
+
+const SYSFS_CPU_PATH: &str = "/sys/devices/system/cpu";
+const SYSFS_MEMORY_PATH: &str = "/sys/devices/system/memory";
+// Hot plugged cpus show up in sysfs after the acpi event is handled by the guest kernel
+const ONLINE_CPU_RETRIES: u32 = 50;
+const ONLINE_CPU_INTERVAL_IN_MS: u64 = 100;
+
+/// Online the hot plugged cpus until there are `nb_cpus` cpus online.
+pub async fn online_cpus(nb_cpus: u32) -> Result<()> {
+    let mut retries = 0;
+    loop {
+        let online = online_resources(SYSFS_CPU_PATH, "cpu", "online", "0", "1").await?;
+        if online >= nb_cpus {
+            debug!("{} cpus are online", online);
+            return Ok(());
+        }
+        if retries >= ONLINE_CPU_RETRIES {
+            return Err(other!(
+                "only {} cpus are online, expected {}",
+                online,
+                nb_cpus
+            ));
+        }
+        retries += 1;
+        tokio::time::sleep(Duration::from_millis(ONLINE_CPU_INTERVAL_IN_MS)).await;
+    }
+}
+
+/// Online all the offline memory blocks.
+pub async fn online_memory() -> Result<()> {
+    let online =
+        online_resources(SYSFS_MEMORY_PATH, "memory", "state", "offline", "online").await?;
+    debug!("{} memory blocks are online", online);
+    Ok(())
+}
+
+// Write `to` into the `file` of every resource dir which is in state `from`,
+// returns the number of resources online after that.
+async fn online_resources(
+    base: &str,
+    prefix: &str,
+    file: &str,
+    from: &str,
+    to: &str,
+) -> Result<u32> {
+    let mut online = 0;
+    let mut entries = read_dir(base)
+        .await
+        .map_err(io_error!(e, "read dir {}", base))?;
+    while let Some(entry) =
+        entries
+            .next_entry()
+            .await
+            .map_err(io_error!(e, "read entry of {}", base))?
+    {
+        let name = entry.file_name().to_string_lossy().to_string();
+        match name.strip_prefix(prefix) {
+            Some(index) if !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()) => {}
+            _ => continue,
+        }
+        let state_path = entry.path().join(file);
+        // the boot cpu has no online file and can not be offline
+        let state = match read_to_string(&state_path).await {
+            Ok(s) => s,
+            Err(_) => {
+                online += 1;
+                continue;
+            }
+        };
+        if state.trim() != from {
+            online += 1;
+            continue;
+        }
+        match write(&state_path, to).await {
+            Ok(_) => online += 1,
+            Err(e) => warn!("failed to online {}: {}", state_path.display(), e),
+        }
+    }
+    Ok(online)
+}
@@ -50,26 +50,33 @@ use vmm_common::{
 };
 
 use crate::{
+    cgroup::{enable_controllers, CGROUP_V2},
     config::TaskConfig,
     debug::listen_debug_console,
     mount::{get_cgroup_mounts, PROC_CGROUPS},
     sandbox_service::SandboxService,
     task::create_task_service,
+    transfer::listen_file_transfer,
 };
 
+mod cgroup;
 mod config;
 #[cfg(not(feature = "youki"))]
 mod container;
 mod debug;
 mod device;
+mod hotplug;
 mod io;
 mod mount;
+mod net_queue;
 mod netlink;
 mod sandbox;
 mod sandbox_service;
+mod stats;
 mod stream;
 mod streaming;
 mod task;
+mod transfer;
 mod util;
 mod vsock;
 #[cfg(feature = "youki")]
@@ -149,9 +156,7 @@ lazy_static! {
 }
 
 async fn initialize() -> anyhow::Result<TaskConfig> {
-    early_init_call().await?;
-
-    let config = TaskConfig::new().await?;
+    let config = early_init_call().await?;
     trace::set_enabled(config.enable_tracing);
     init_logger(&config.log_level)?;
 
@@ -173,6 +178,10 @@ async fn initialize() -> anyhow::Result<TaskConfig> {
         if let Err(e) = listen_debug_console("vsock://-1:1025", &config.debug_shell).await {
             error!("failed to listen debug console port, {:?}", e);
         }
+        debug!("listen vsock port 1026 for file transfer");
+        if let Err(e) = listen_file_transfer("vsock://-1:1026").await {
+            error!("failed to listen file transfer port, {:?}", e);
+        }
     }
 
     late_init_call().await?;
@@ -242,13 +251,12 @@ async fn main() {
 
 // Do some initialization before everything starts.
 // Such as setting envs, preparing cgroup mounts, setting kernel paras.
-async fn early_init_call() -> Result<()> {
+async fn early_init_call() -> Result<TaskConfig> {
     // Set environment variables from ENVS vector(ordered).
     for (k, v) in ENVS.iter() {
         std::env::set_var(k, v);
     }
-    init_vm_rootfs().await?;
-    Ok(())
+    init_vm_rootfs().await
 }
 
 async fn handle_signals(signals: Signals, log_level: &str) {
@@ -355,12 +363,18 @@ lazy_static! {
     };
 }
 
-async fn init_vm_rootfs() -> Result<()> {
+async fn init_vm_rootfs() -> Result<TaskConfig> {
     let mounts = VM_ROOTFS_MOUNTS.clone();
     mount_static_mounts(mounts).await?;
-    // has to mount /proc before find cgroup mounts
-    let cgroup_mounts = get_cgroup_mounts(PROC_CGROUPS, false).await?;
+    // has to mount /proc before reading the config from the kernel cmdline
+    // and finding cgroup mounts
+    let config = TaskConfig::new().await?;
+    let unified_cgroup_hierarchy = config.cgroup_version == CGROUP_V2;
+    let cgroup_mounts = get_cgroup_mounts(PROC_CGROUPS, unified_cgroup_hierarchy).await?;
     mount_static_mounts(cgroup_mounts).await?;
+    if unified_cgroup_hierarchy {
+        enable_controllers().await?;
+    }
 
     // Set default sysctl
     for sysctl in DEFAULT_SYSCTL.iter() {
@@ -372,7 +386,7 @@ async fn init_vm_rootfs() -> Result<()> {
             .map_err(io_error!(e, "failed to write kernel parameter "))?;
     }
 
-    Ok(())
+    Ok(config)
 }
 
 // Continue to do initialization that depend on shared path.
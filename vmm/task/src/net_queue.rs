@@ -0,0 +1,170 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::path::Path;
+
+use containerd_shim::{io_error, other, Result};
+use log::debug;
+
+const SYSFS_NET_DIR: &str = "/sys/class/net";
+const ONLINE_CPUS_PATH: &str = "/sys/devices/system/cpu/online";
+
+/// Spread the packet processing of the nic over the online cpus, each queue pair is served by
+/// a group of cpus, the rx queue steers packets to its group by RPS and the cpus of the group
+/// transmit on the tx queue by XPS.
+pub(crate) async fn setup_queue_steering(ifname: &str) -> Result<()> {
+    let online = tokio::fs::read_to_string(ONLINE_CPUS_PATH)
+        .await
+        .map_err(io_error!(e, "failed to read {}", ONLINE_CPUS_PATH))?;
+    let cpus = parse_cpu_list(&online)?;
+    if cpus.len() <= 1 {
+        return Ok(());
+    }
+
+    let queues_dir = Path::new(SYSFS_NET_DIR).join(ifname).join("queues");
+    let (rx_queues, tx_queues) = count_queues(&queues_dir).await?;
+    // rps is only needed if some cpus have no rx queue of their own
+    if rx_queues > 0 && rx_queues < cpus.len() {
+        for (i, group) in cpu_groups(&cpus, rx_queues).iter().enumerate() {
+            let path = queues_dir.join(format!("rx-{}", i)).join("rps_cpus");
+            write_mask(&path, &cpu_mask(group)).await?;
+        }
+    }
+    if tx_queues > 1 {
+        for (i, group) in cpu_groups(&cpus, tx_queues).iter().enumerate() {
+            let path = queues_dir.join(format!("tx-{}", i)).join("xps_cpus");
+            write_mask(&path, &cpu_mask(group)).await?;
+        }
+    }
+    debug!(
+        "{} has {} rx and {} tx queues on {} cpus",
+        ifname,
+        rx_queues,
+        tx_queues,
+        cpus.len()
+    );
+    Ok(())
+}
+
+async fn count_queues(queues_dir: &Path) -> Result<(usize, usize)> {
+    let dir = queues_dir.display();
+    let mut entries = tokio::fs::read_dir(queues_dir).await.map_err(io_error!(
+        e,
+        "failed to read dir {}",
+        dir
+    ))?;
+    let (mut rx, mut tx) = (0, 0);
+    while let Some(entry) =
+        entries
+            .next_entry()
+            .await
+            .map_err(io_error!(e, "failed to read dir {}", dir))?
+    {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("rx-") {
+            rx += 1;
+        } else if name.starts_with("tx-") {
+            tx += 1;
+        }
+    }
+    Ok((rx, tx))
+}
+
+async fn write_mask(path: &Path, mask: &str) -> Result<()> {
+    let file = path.display();
+    tokio::fs::write(path, mask)
+        .await
+        .map_err(io_error!(e, "failed to write {} to {}", mask, file))
+}
+
+// Parse the cpu list format of sysfs like "0-3,6"
+fn parse_cpu_list(list: &str) -> Result<Vec<u32>> {
+    let mut cpus = vec![];
+    for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+        let parse = |s: &str| {
+            s.parse::<u32>()
+                .map_err(|e| other!("invalid cpu list {}: {}", list.trim(), e))
+        };
+        match range.split_once('-') {
+            Some((start, end)) => cpus.extend(parse(start)?..=parse(end)?),
+            None => cpus.push(parse(range)?),
+        }
+    }
+    Ok(cpus)
+}
+
+// Assign the cpus to the queues in turn, so that the groups differ in size by one at most
+fn cpu_groups(cpus: &[u32], queues: usize) -> Vec<Vec<u32>> {
+    let mut groups = vec![vec![]; queues];
+    for (i, cpu) in cpus.iter().enumerate() {
+        groups[i % queues].push(*cpu);
+    }
+    groups
+}
+
+// Format the cpus as the hex mask of sysfs, comma separated in 32 bits words like "1,00000003"
+fn cpu_mask(cpus: &[u32]) -> String {
+    let words = cpus.iter().max().map(|m| *m as usize / 32 + 1).unwrap_or(1);
+    let mut mask = vec![0u32; words];
+    for cpu in cpus {
+        mask[*cpu as usize / 32] |= 1 << (cpu % 32);
+    }
+    mask.iter()
+        .rev()
+        .enumerate()
+        .map(|(i, w)| {
+            if i == 0 {
+                format!("{:x}", w)
+            } else {
+                format!("{:08x}", w)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::net_queue::{cpu_groups, cpu_mask, parse_cpu_list};
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0\n").unwrap(), vec![0]);
+        assert_eq!(parse_cpu_list("0-3,6").unwrap(), vec![0, 1, 2, 3, 6]);
+        assert_eq!(parse_cpu_list("0,2-3\n").unwrap(), vec![0, 2, 3]);
+        assert!(parse_cpu_list("0-a").is_err());
+    }
+
+    #[test]
+    fn test_cpu_groups() {
+        assert_eq!(
+            cpu_groups(&[0, 1, 2, 3, 4], 2),
+            vec![vec![0, 2, 4], vec![1, 3]]
+        );
+        assert_eq!(cpu_groups(&[0, 1], 2), vec![vec![0], vec![1]]);
+    }
+
+    #[test]
+    fn test_cpu_mask() {
+        assert_eq!(cpu_mask(&[]), "0");
+        assert_eq!(cpu_mask(&[0, 1, 2, 3]), "f");
+        assert_eq!(cpu_mask(&[1, 3]), "a");
+        assert_eq!(cpu_mask(&[31]), "80000000");
+        assert_eq!(cpu_mask(&[0, 32]), "1,00000001");
+        assert_eq!(cpu_mask(&[33, 64]), "1,00000002,00000000");
+    }
+}
@@ -9,6 +9,7 @@ use std::{
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     ops::Deref,
     str::FromStr,
+    time::Duration,
 };
 
 use containerd_shim::{
@@ -18,8 +19,9 @@ use containerd_shim::{
 };
 use futures::{future, TryStreamExt};
 use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
+use log::warn;
 use netlink_packet_route::{
-    address::{AddressAttribute, AddressMessage},
+    address::{AddressAttribute, AddressHeaderFlag, AddressMessage},
     link::{LinkAttribute, LinkFlag, LinkMessage},
     route::{
         RouteAddress, RouteAttribute, RouteHeader, RouteMessage, RouteProtocol, RouteScope,
@@ -31,6 +33,12 @@ use nix::errno::Errno;
 use rtnetlink::{new_connection, IpVersion};
 use vmm_common::api::sandbox::{IPAddress, IPFamily, Interface, Route};
 
+use crate::net_queue::setup_queue_steering;
+
+// Hot attached nics may not be probed by the guest kernel yet when updating interfaces
+const LINK_WAIT_RETRIES: u32 = 50;
+const LINK_WAIT_INTERVAL_IN_MS: u64 = 100;
+
 /// Search criteria to use when looking for a link in `find_link`.
 pub enum LinkFilter<'a> {
     /// Find by link name.
@@ -87,7 +95,7 @@ impl Handle {
             // target link. filter using name or family is supported, but
             // we cannot use that to find target link.
             // let's try if hardware address filter works. -_-
-            let link = self.find_link(LinkFilter::Address(&intf.hwAddr)).await?;
+            let link = self.wait_link(&intf.hwAddr).await?;
 
             // Bring down interface if it is UP
             if link.is_up() {
@@ -107,6 +115,10 @@ impl Handle {
                     format!("invalid ip address: {}", ip_address.address)
                 ))?;
                 let mask = ip_address.mask.parse::<u8>()?;
+                // the link local address is generated by the guest kernel from the mac address
+                if is_ipv6_link_local(&ip) {
+                    continue;
+                }
 
                 self.add_addresses(
                     link.index(),
@@ -148,10 +160,31 @@ impl Handle {
                 .execute()
                 .await
                 .map_err(other_error!(e, "failed to execute netlink request"))?;
+
+            // the network still works without steering, so just warn on failure
+            if let Err(e) = setup_queue_steering(&intf.name).await {
+                warn!("failed to setup queue steering of {}: {}", intf.name, e);
+            }
         }
         Ok(())
     }
 
+    async fn wait_link(&self, hw_addr: &str) -> Result<Link> {
+        let mut retries = 0;
+        loop {
+            match self.find_link(LinkFilter::Address(hw_addr)).await {
+                Ok(link) => return Ok(link),
+                Err(e) => {
+                    if retries >= LINK_WAIT_RETRIES {
+                        return Err(e);
+                    }
+                }
+            }
+            retries += 1;
+            tokio::time::sleep(Duration::from_millis(LINK_WAIT_INTERVAL_IN_MS)).await;
+        }
+    }
+
     async fn find_link(&self, filter: LinkFilter<'_>) -> Result<Link> {
         let request = self.handle.link().get();
 
@@ -224,15 +257,20 @@ impl Handle {
         I: IntoIterator<Item = IpNetwork>,
     {
         for net in list.into_iter() {
-            self.handle
-                .address()
-                .add(index, net.ip(), net.prefix())
-                .execute()
-                .await
-                .map_err(other_error!(
-                    e,
-                    format!("Failed to add address {}", net.ip())
-                ))?;
+            let mut request = self.handle.address().add(index, net.ip(), net.prefix());
+            // the address is assigned by the cni on host, skip the duplicate address detection
+            // so that it is usable as the source of routes as soon as the link is up
+            if net.is_ipv6() {
+                request
+                    .message_mut()
+                    .header
+                    .flags
+                    .push(AddressHeaderFlag::Nodad);
+            }
+            request.execute().await.map_err(other_error!(
+                e,
+                format!("Failed to add address {}", net.ip())
+            ))?;
         }
 
         Ok(())
@@ -444,17 +482,17 @@ impl Handle {
                 continue;
             }
 
-            let index = route
-                .attributes
-                .iter()
-                .find_map(|attr| {
-                    if let RouteAttribute::Oif(v) = attr {
-                        Some(*v)
-                    } else {
-                        None
-                    }
-                })
-                .unwrap_or_default();
+            // routes without an output interface, such as multipath ones, are not set by us
+            let index = match route.attributes.iter().find_map(|attr| {
+                if let RouteAttribute::Oif(v) = attr {
+                    Some(*v)
+                } else {
+                    None
+                }
+            }) {
+                Some(index) => index,
+                None => continue,
+            };
 
             let link = self.find_link(LinkFilter::Index(index)).await?;
 
@@ -567,9 +605,9 @@ impl TryFrom<Address> for IPAddress {
 
     fn try_from(value: Address) -> Result<Self> {
         let family = if value.is_ipv6() {
-            IPFamily::v4
-        } else {
             IPFamily::v6
+        } else {
+            IPFamily::v4
         };
 
         let mut address = value.address();
@@ -622,6 +660,14 @@ impl Address {
     }
 }
 
+// fe80::/10
+fn is_ipv6_link_local(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V6(v6) => (v6.segments()[0] & 0xffc0) == 0xfe80,
+        IpAddr::V4(_) => false,
+    }
+}
+
 fn parse_mac_address(addr: &str) -> Result<[u8; 6]> {
     let mut split = addr.splitn(6, ':');
 
@@ -648,3 +694,192 @@ fn parse_mac_address(addr: &str) -> Result<[u8; 6]> {
 
     Ok(arr)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{future::Future, net::IpAddr, str::FromStr};
+
+    use containerd_shim::protos::protobuf::EnumOrUnknown;
+    use netlink_packet_route::{
+        link::LinkAttribute,
+        route::{RouteAddress, RouteAttribute, RouteFlag, RouteMessage},
+    };
+    use nix::{
+        sched::{unshare, CloneFlags},
+        unistd::Uid,
+    };
+    use rtnetlink::IpVersion;
+    use vmm_common::api::sandbox::{IPAddress, IPFamily, Interface, Route};
+
+    use super::{is_ipv6_link_local, AddressFilter, Handle, LinkFilter};
+
+    // Run the test in a private network namespace of a new thread, skip it if not root.
+    fn run_in_private_netns<F, Fut>(f: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()>,
+    {
+        if !Uid::effective().is_root() {
+            return;
+        }
+        std::thread::spawn(move || {
+            unshare(CloneFlags::CLONE_NEWNET).expect("failed to unshare network namespace");
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(f());
+        })
+        .join()
+        .unwrap();
+    }
+
+    fn ip_address(family: IPFamily, address: &str, mask: &str) -> IPAddress {
+        IPAddress {
+            family: EnumOrUnknown::from(family),
+            address: address.to_string(),
+            mask: mask.to_string(),
+            ..IPAddress::default()
+        }
+    }
+
+    fn route(family: IPFamily, dest: &str, gateway: &str, scope: u32, flags: u32) -> Route {
+        Route {
+            dest: dest.to_string(),
+            gateway: gateway.to_string(),
+            device: "eth0".to_string(),
+            scope,
+            family: EnumOrUnknown::from(family),
+            flags,
+            ..Route::default()
+        }
+    }
+
+    // Add a dummy link as the hot plugged nic, and set it up as a dual stack "eth0"
+    async fn setup_dual_stack_eth0(handle: &mut Handle) {
+        handle.enable_lo().await.unwrap();
+        handle
+            .handle
+            .link()
+            .add()
+            .dummy("dummy0".to_string())
+            .execute()
+            .await
+            .unwrap();
+        let link = handle.find_link(LinkFilter::Name("dummy0")).await.unwrap();
+        let mac = link
+            .attributes
+            .iter()
+            .find_map(|a| match a {
+                LinkAttribute::Address(m) => Some(
+                    m.iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect::<Vec<String>>()
+                        .join(":"),
+                ),
+                _ => None,
+            })
+            .unwrap();
+
+        let intf = Interface {
+            device: "eth0".to_string(),
+            name: "eth0".to_string(),
+            IPAddresses: vec![
+                ip_address(IPFamily::v4, "10.0.0.2", "24"),
+                ip_address(IPFamily::v6, "fd00::2", "64"),
+                ip_address(IPFamily::v6, "fe80::1234", "64"),
+            ],
+            mtu: 1400,
+            hwAddr: mac,
+            ..Interface::default()
+        };
+        handle.update_interfaces(vec![intf]).await.unwrap();
+    }
+
+    fn gateways(routes: &[RouteMessage]) -> Vec<String> {
+        routes
+            .iter()
+            .filter_map(|r| {
+                r.attributes.iter().find_map(|a| match a {
+                    RouteAttribute::Gateway(RouteAddress::Inet(g)) => Some(g.to_string()),
+                    RouteAttribute::Gateway(RouteAddress::Inet6(g)) => Some(g.to_string()),
+                    _ => None,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_is_ipv6_link_local() {
+        assert!(is_ipv6_link_local(&IpAddr::from_str("fe80::1").unwrap()));
+        assert!(is_ipv6_link_local(
+            &IpAddr::from_str("febf:ffff::1").unwrap()
+        ));
+        assert!(!is_ipv6_link_local(&IpAddr::from_str("fec0::1").unwrap()));
+        assert!(!is_ipv6_link_local(&IpAddr::from_str("fd00::1").unwrap()));
+        assert!(!is_ipv6_link_local(
+            &IpAddr::from_str("169.254.1.1").unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_update_dual_stack_interfaces() {
+        run_in_private_netns(|| async {
+            let mut handle = Handle::new().unwrap();
+            setup_dual_stack_eth0(&mut handle).await;
+
+            let link = handle.find_link(LinkFilter::Name("eth0")).await.unwrap();
+            assert!(link.is_up());
+            let addresses = handle
+                .list_addresses(AddressFilter::LinkIndex(link.index()))
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|a| IPAddress::try_from(a).unwrap())
+                .map(|a| (a.family.enum_value_or_default(), a.address, a.mask))
+                .collect::<Vec<_>>();
+            assert!(addresses.contains(&(IPFamily::v4, "10.0.0.2".into(), "24".into())));
+            assert!(addresses.contains(&(IPFamily::v6, "fd00::2".into(), "64".into())));
+            // the link local address is generated by the kernel rather than the one of host
+            assert!(!addresses.contains(&(IPFamily::v6, "fe80::1234".into(), "64".into())));
+        });
+    }
+
+    #[test]
+    fn test_update_dual_stack_routes() {
+        run_in_private_netns(|| async {
+            let mut handle = Handle::new().unwrap();
+            setup_dual_stack_eth0(&mut handle).await;
+
+            let onlink = u32::from(RouteFlag::Onlink);
+            let routes = vec![
+                route(IPFamily::v4, "", "10.0.0.1", 0, 0),
+                // the gateway out of the subnet, such as the one of calico
+                route(IPFamily::v4, "10.10.0.0/16", "169.254.1.1", 0, onlink),
+                route(IPFamily::v4, "10.0.0.0/24", "", 253, 0),
+                // the on-link ipv6 gateway with a link local address
+                route(IPFamily::v6, "", "fe80::1", 0, 0),
+                route(IPFamily::v6, "fd01::/64", "fd00::1", 0, 0),
+                route(IPFamily::v6, "fd00::/64", "", 0, 0),
+            ];
+            handle.update_routes(routes).await.unwrap();
+
+            let routes4 = handle.query_routes(Some(IpVersion::V4)).await.unwrap();
+            let routes6 = handle.query_routes(Some(IpVersion::V6)).await.unwrap();
+            let gateways4 = gateways(&routes4);
+            let gateways6 = gateways(&routes6);
+            assert!(gateways4.contains(&"10.0.0.1".to_string()));
+            assert!(gateways4.contains(&"169.254.1.1".to_string()));
+            assert!(gateways6.contains(&"fe80::1".to_string()));
+            assert!(gateways6.contains(&"fd00::1".to_string()));
+
+            // the routes are replaced rather than appended
+            let routes = vec![route(IPFamily::v6, "", "fe80::1", 0, 0)];
+            handle.update_routes(routes).await.unwrap();
+            let routes4 = handle.query_routes(Some(IpVersion::V4)).await.unwrap();
+            let routes6 = handle.query_routes(Some(IpVersion::V6)).await.unwrap();
+            assert!(gateways(&routes4).is_empty());
+            assert_eq!(gateways(&routes6), vec!["fe80::1".to_string()]);
+        });
+    }
+}
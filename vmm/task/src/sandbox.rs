@@ -31,7 +31,7 @@ use nix::{
 use tokio::fs::File;
 use vmm_common::{
     mount::{mount, unmount},
-    storage::{Storage, DRIVERBLKTYPE, DRIVEREPHEMERALTYPE, DRIVERSCSITYPE},
+    storage::{Storage, DRIVERBLKTYPE, DRIVEREPHEMERALTYPE, DRIVERMMIOBLKTYPE, DRIVERSCSITYPE},
     HOSTNAME_FILENAME, IPC_NAMESPACE, KUASAR_STATE_DIR, PID_NAMESPACE, SANDBOX_NS_PATH,
     UTS_NAMESPACE,
 };
@@ -89,6 +89,10 @@ impl SandboxResources {
             DRIVERBLKTYPE => {
                 self.handle_blk_storage(&mut storage).await?;
             }
+            DRIVERMMIOBLKTYPE => {
+                // virtio-mmio block devices have no pci address, source is already the device path
+                mount_storage(&storage).await?;
+            }
             _ => {
                 unimplemented!("storage driver not implemented {}", storage.driver)
             }
@@ -45,13 +45,18 @@ use vmm_common::{
         empty::Empty,
         events::Envelope,
         sandbox::{
-            CheckRequest, ExecVMProcessRequest, ExecVMProcessResponse, SetupSandboxRequest,
-            SyncClockPacket, UpdateInterfacesRequest, UpdateRoutesRequest,
+            CheckRequest, ExecVMProcessRequest, ExecVMProcessResponse, OnlineCPUMemRequest,
+            SetupSandboxRequest, SyncClockPacket, UpdateInterfacesRequest, UpdateRoutesRequest,
         },
     },
 };
 
-use crate::{netlink::Handle, sandbox::setup_sandbox, NAMESPACE};
+use crate::{
+    hotplug::{online_cpus, online_memory},
+    netlink::Handle,
+    sandbox::setup_sandbox,
+    NAMESPACE,
+};
 
 pub struct SandboxService {
     pub namespace: String,
@@ -180,6 +185,18 @@ impl api::sandbox_ttrpc::SandboxService for SandboxService {
         Ok(resp)
     }
 
+    async fn online_cpu_mem(
+        &self,
+        _ctx: &TtrpcContext,
+        req: OnlineCPUMemRequest,
+    ) -> TtrpcResult<Empty> {
+        online_cpus(req.nb_cpus).await?;
+        if !req.cpu_only {
+            online_memory().await?;
+        }
+        Ok(Empty::new())
+    }
+
     async fn get_events(&self, _ctx: &TtrpcContext, _: Empty) -> TtrpcResult<Envelope> {
         while let Some((topic, event)) = self.rx.lock().await.recv().await {
             debug!("received event {:?}", event);
@@ -0,0 +1,528 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{
+    collections::HashMap,
+    fs::{read_dir, read_to_string},
+    path::{Path, PathBuf},
+};
+
+use containerd_shim::{
+    other,
+    protos::{
+        cgroups::metrics::{
+            BlkIOEntry, BlkIOStat, CPUStat, CPUUsage, HugetlbStat, MemoryEntry, MemoryStat,
+            Metrics, NetworkStat, PidsStat, Throttle,
+        },
+        protobuf::MessageField,
+    },
+    Result,
+};
+use log::debug;
+use tokio::task::spawn_blocking;
+
+use crate::cgroup::is_cgroup2_unified_mode;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const SYS_CLASS_NET: &str = "/sys/class/net";
+const HUGEPAGE_DIR: &str = "/sys/kernel/mm/hugepages";
+const NANOS_PER_MICRO: u64 = 1000;
+
+/// Collect the cgroup metrics of the container which the process `pid` is in,
+/// together with the counters of the network interfaces in guest.
+///
+/// Metrics of cgroup v2 are also returned in the v1 format, the working set of memory
+/// is calculated from `memory.usage` and `memory.total_inactive_file` by the caller.
+pub async fn collect_metrics(pid: u32) -> Result<Metrics> {
+    // lots of small files are read, so read them all in a blocking thread
+    spawn_blocking(move || collect_metrics_blocking(pid))
+        .await
+        .map_err(|e| other!("failed to join the metrics collecting thread: {}", e))?
+}
+
+fn collect_metrics_blocking(pid: u32) -> Result<Metrics> {
+    if pid == 0 {
+        return Err(other!("failed to collect metrics of process 0"));
+    }
+    let cgroup_file = format!("/proc/{}/cgroup", pid);
+    let content = read_to_string(&cgroup_file)
+        .map_err(|e| other!("failed to read {}: {}", cgroup_file, e))?;
+    let paths = parse_cgroup_paths(&content);
+
+    let mut metrics = if is_cgroup2_unified_mode() {
+        let path = paths.get("").cloned().unwrap_or_default();
+        collect_v2(&Path::new(CGROUP_ROOT).join(path.trim_start_matches('/')))
+    } else {
+        collect_v1(&paths)
+    };
+    metrics.network = collect_network();
+    Ok(metrics)
+}
+
+// Parse /proc/<pid>/cgroup into a map from the controller to the cgroup path,
+// the path of cgroup v2 is keyed by the empty controller name.
+fn parse_cgroup_paths(content: &str) -> HashMap<String, String> {
+    let mut paths = HashMap::new();
+    for line in content.lines() {
+        let fields: Vec<&str> = line.splitn(3, ':').collect();
+        if fields.len() != 3 {
+            continue;
+        }
+        if fields[1].is_empty() {
+            paths.insert("".to_string(), fields[2].to_string());
+            continue;
+        }
+        for controller in fields[1].split(',') {
+            paths.insert(controller.to_string(), fields[2].to_string());
+        }
+    }
+    paths
+}
+
+fn collect_v1(paths: &HashMap<String, String>) -> Metrics {
+    let dir = |controller: &str| -> Option<PathBuf> {
+        let path = paths.get(controller)?;
+        let dir = Path::new(CGROUP_ROOT)
+            .join(controller)
+            .join(path.trim_start_matches('/'));
+        if dir.exists() {
+            Some(dir)
+        } else {
+            debug!("cgroup dir {} not found", dir.display());
+            None
+        }
+    };
+
+    let mut metrics = Metrics::new();
+    if let Some(dir) = dir("pids") {
+        let mut pids = PidsStat::new();
+        pids.current = read_u64(&dir.join("pids.current"));
+        pids.limit = read_u64(&dir.join("pids.max"));
+        metrics.pids = MessageField::some(pids);
+    }
+
+    if let Some(dir) = dir("cpuacct") {
+        let mut usage = CPUUsage::new();
+        usage.total = read_u64(&dir.join("cpuacct.usage"));
+        usage.user = read_u64(&dir.join("cpuacct.usage_user"));
+        usage.kernel = read_u64(&dir.join("cpuacct.usage_sys"));
+        usage.per_cpu = read_to_string(dir.join("cpuacct.usage_percpu"))
+            .unwrap_or_default()
+            .split_whitespace()
+            .filter_map(|x| x.parse().ok())
+            .collect();
+        let mut cpu = CPUStat::new();
+        cpu.usage = MessageField::some(usage);
+        if let Some(dir) = dir("cpu") {
+            let stat = read_flat_keyed(&dir.join("cpu.stat"));
+            let mut throttle = Throttle::new();
+            throttle.periods = stat_value(&stat, "nr_periods");
+            throttle.throttled_periods = stat_value(&stat, "nr_throttled");
+            throttle.throttled_time = stat_value(&stat, "throttled_time");
+            cpu.throttling = MessageField::some(throttle);
+        }
+        metrics.cpu = MessageField::some(cpu);
+    }
+
+    if let Some(dir) = dir("memory") {
+        let stat = read_flat_keyed(&dir.join("memory.stat"));
+        let mut memory = MemoryStat::new();
+        memory.cache = stat_value(&stat, "cache");
+        memory.rss = stat_value(&stat, "rss");
+        memory.rss_huge = stat_value(&stat, "rss_huge");
+        memory.mapped_file = stat_value(&stat, "mapped_file");
+        memory.dirty = stat_value(&stat, "dirty");
+        memory.writeback = stat_value(&stat, "writeback");
+        memory.pg_pg_in = stat_value(&stat, "pgpgin");
+        memory.pg_pg_out = stat_value(&stat, "pgpgout");
+        memory.pg_fault = stat_value(&stat, "pgfault");
+        memory.pg_maj_fault = stat_value(&stat, "pgmajfault");
+        memory.inactive_anon = stat_value(&stat, "inactive_anon");
+        memory.active_anon = stat_value(&stat, "active_anon");
+        memory.inactive_file = stat_value(&stat, "inactive_file");
+        memory.active_file = stat_value(&stat, "active_file");
+        memory.unevictable = stat_value(&stat, "unevictable");
+        memory.hierarchical_memory_limit = stat_value(&stat, "hierarchical_memory_limit");
+        memory.hierarchical_swap_limit = stat_value(&stat, "hierarchical_memsw_limit");
+        memory.total_cache = stat_value(&stat, "total_cache");
+        memory.total_rss = stat_value(&stat, "total_rss");
+        memory.total_rss_huge = stat_value(&stat, "total_rss_huge");
+        memory.total_mapped_file = stat_value(&stat, "total_mapped_file");
+        memory.total_dirty = stat_value(&stat, "total_dirty");
+        memory.total_writeback = stat_value(&stat, "total_writeback");
+        memory.total_pg_pg_in = stat_value(&stat, "total_pgpgin");
+        memory.total_pg_pg_out = stat_value(&stat, "total_pgpgout");
+        memory.total_pg_fault = stat_value(&stat, "total_pgfault");
+        memory.total_pg_maj_fault = stat_value(&stat, "total_pgmajfault");
+        memory.total_inactive_anon = stat_value(&stat, "total_inactive_anon");
+        memory.total_active_anon = stat_value(&stat, "total_active_anon");
+        memory.total_inactive_file = stat_value(&stat, "total_inactive_file");
+        memory.total_active_file = stat_value(&stat, "total_active_file");
+        memory.total_unevictable = stat_value(&stat, "total_unevictable");
+        memory.usage = MessageField::some(memory_entry_v1(&dir, "memory"));
+        memory.swap = MessageField::some(memory_entry_v1(&dir, "memory.memsw"));
+        memory.kernel = MessageField::some(memory_entry_v1(&dir, "memory.kmem"));
+        memory.kernel_tcp = MessageField::some(memory_entry_v1(&dir, "memory.kmem.tcp"));
+        metrics.memory = MessageField::some(memory);
+    }
+
+    if let Some(dir) = dir("blkio") {
+        let mut blkio = BlkIOStat::new();
+        blkio.io_service_bytes_recursive =
+            read_blkio_entries(&dir, "blkio.throttle.io_service_bytes_recursive");
+        blkio.io_serviced_recursive =
+            read_blkio_entries(&dir, "blkio.throttle.io_serviced_recursive");
+        blkio.io_queued_recursive = read_blkio_entries(&dir, "blkio.io_queued_recursive");
+        blkio.io_service_time_recursive =
+            read_blkio_entries(&dir, "blkio.io_service_time_recursive");
+        blkio.io_wait_time_recursive = read_blkio_entries(&dir, "blkio.io_wait_time_recursive");
+        blkio.io_merged_recursive = read_blkio_entries(&dir, "blkio.io_merged_recursive");
+        blkio.io_time_recursive = read_blkio_entries(&dir, "blkio.time_recursive");
+        blkio.sectors_recursive = read_blkio_entries(&dir, "blkio.sectors_recursive");
+        metrics.blkio = MessageField::some(blkio);
+    }
+
+    if let Some(dir) = dir("hugetlb") {
+        metrics.hugetlb = hugepage_sizes()
+            .into_iter()
+            .map(|size| {
+                let mut stat = HugetlbStat::new();
+                let prefix = format!("hugetlb.{}", size);
+                stat.usage = read_u64(&dir.join(format!("{}.usage_in_bytes", prefix)));
+                stat.max = read_u64(&dir.join(format!("{}.max_usage_in_bytes", prefix)));
+                stat.failcnt = read_u64(&dir.join(format!("{}.failcnt", prefix)));
+                stat.pagesize = size;
+                stat
+            })
+            .collect();
+    }
+    metrics
+}
+
+fn memory_entry_v1(dir: &Path, prefix: &str) -> MemoryEntry {
+    let mut entry = MemoryEntry::new();
+    entry.usage = read_u64(&dir.join(format!("{}.usage_in_bytes", prefix)));
+    entry.max = read_u64(&dir.join(format!("{}.max_usage_in_bytes", prefix)));
+    entry.limit = read_u64(&dir.join(format!("{}.limit_in_bytes", prefix)));
+    entry.failcnt = read_u64(&dir.join(format!("{}.failcnt", prefix)));
+    entry
+}
+
+fn collect_v2(dir: &Path) -> Metrics {
+    let mut metrics = Metrics::new();
+
+    let mut pids = PidsStat::new();
+    pids.current = read_u64(&dir.join("pids.current"));
+    pids.limit = read_u64(&dir.join("pids.max"));
+    metrics.pids = MessageField::some(pids);
+
+    let stat = read_flat_keyed(&dir.join("cpu.stat"));
+    let mut usage = CPUUsage::new();
+    usage.total = stat_value(&stat, "usage_usec") * NANOS_PER_MICRO;
+    usage.user = stat_value(&stat, "user_usec") * NANOS_PER_MICRO;
+    usage.kernel = stat_value(&stat, "system_usec") * NANOS_PER_MICRO;
+    let mut throttle = Throttle::new();
+    throttle.periods = stat_value(&stat, "nr_periods");
+    throttle.throttled_periods = stat_value(&stat, "nr_throttled");
+    throttle.throttled_time = stat_value(&stat, "throttled_usec") * NANOS_PER_MICRO;
+    let mut cpu = CPUStat::new();
+    cpu.usage = MessageField::some(usage);
+    cpu.throttling = MessageField::some(throttle);
+    metrics.cpu = MessageField::some(cpu);
+
+    // cgroup v2 has no hierarchical stat, the stat of a cgroup includes its children
+    let stat = read_flat_keyed(&dir.join("memory.stat"));
+    let mut memory = MemoryStat::new();
+    memory.cache = stat_value(&stat, "file");
+    memory.rss = stat_value(&stat, "anon");
+    memory.rss_huge = stat_value(&stat, "anon_thp");
+    memory.mapped_file = stat_value(&stat, "file_mapped");
+    memory.dirty = stat_value(&stat, "file_dirty");
+    memory.writeback = stat_value(&stat, "file_writeback");
+    memory.pg_fault = stat_value(&stat, "pgfault");
+    memory.pg_maj_fault = stat_value(&stat, "pgmajfault");
+    memory.inactive_anon = stat_value(&stat, "inactive_anon");
+    memory.active_anon = stat_value(&stat, "active_anon");
+    memory.inactive_file = stat_value(&stat, "inactive_file");
+    memory.active_file = stat_value(&stat, "active_file");
+    memory.unevictable = stat_value(&stat, "unevictable");
+    memory.total_cache = memory.cache;
+    memory.total_rss = memory.rss;
+    memory.total_rss_huge = memory.rss_huge;
+    memory.total_mapped_file = memory.mapped_file;
+    memory.total_dirty = memory.dirty;
+    memory.total_writeback = memory.writeback;
+    memory.total_pg_fault = memory.pg_fault;
+    memory.total_pg_maj_fault = memory.pg_maj_fault;
+    memory.total_inactive_anon = memory.inactive_anon;
+    memory.total_active_anon = memory.active_anon;
+    memory.total_inactive_file = memory.inactive_file;
+    memory.total_active_file = memory.active_file;
+    memory.total_unevictable = memory.unevictable;
+    let events = read_flat_keyed(&dir.join("memory.events"));
+    let mut usage = MemoryEntry::new();
+    usage.usage = read_u64(&dir.join("memory.current"));
+    usage.max = read_u64(&dir.join("memory.peak"));
+    usage.limit = read_u64(&dir.join("memory.max"));
+    usage.failcnt = stat_value(&events, "max");
+    memory.hierarchical_memory_limit = usage.limit;
+    memory.usage = MessageField::some(usage);
+    let mut swap = MemoryEntry::new();
+    swap.usage = read_u64(&dir.join("memory.swap.current"));
+    swap.limit = read_u64(&dir.join("memory.swap.max"));
+    memory.hierarchical_swap_limit = swap.limit;
+    memory.swap = MessageField::some(swap);
+    let mut kernel = MemoryEntry::new();
+    kernel.usage = stat_value(&stat, "kernel");
+    memory.kernel = MessageField::some(kernel);
+    let mut kernel_tcp = MemoryEntry::new();
+    kernel_tcp.usage = stat_value(&stat, "sock");
+    memory.kernel_tcp = MessageField::some(kernel_tcp);
+    metrics.memory = MessageField::some(memory);
+
+    let mut blkio = BlkIOStat::new();
+    for (major, minor, stat) in read_io_stat(&dir.join("io.stat")) {
+        let entry = |op: &str, key: &str| {
+            let mut entry = BlkIOEntry::new();
+            entry.op = op.to_string();
+            entry.major = major;
+            entry.minor = minor;
+            entry.value = stat_value(&stat, key);
+            entry
+        };
+        blkio
+            .io_service_bytes_recursive
+            .push(entry("Read", "rbytes"));
+        blkio
+            .io_service_bytes_recursive
+            .push(entry("Write", "wbytes"));
+        blkio.io_serviced_recursive.push(entry("Read", "rios"));
+        blkio.io_serviced_recursive.push(entry("Write", "wios"));
+    }
+    metrics.blkio = MessageField::some(blkio);
+
+    metrics.hugetlb = hugepage_sizes()
+        .into_iter()
+        .map(|size| {
+            let mut stat = HugetlbStat::new();
+            let prefix = format!("hugetlb.{}", size);
+            stat.usage = read_u64(&dir.join(format!("{}.current", prefix)));
+            stat.max = read_u64(&dir.join(format!("{}.max", prefix)));
+            stat.failcnt = stat_value(
+                &read_flat_keyed(&dir.join(format!("{}.events", prefix))),
+                "max",
+            );
+            stat.pagesize = size;
+            stat
+        })
+        .collect();
+    metrics
+}
+
+fn collect_network() -> Vec<NetworkStat> {
+    let entries = match read_dir(SYS_CLASS_NET) {
+        Ok(entries) => entries,
+        Err(e) => {
+            debug!("failed to read {}: {}", SYS_CLASS_NET, e);
+            return vec![];
+        }
+    };
+    let mut stats = vec![];
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name == "lo" {
+            continue;
+        }
+        let dir = entry.path().join("statistics");
+        let mut stat = NetworkStat::new();
+        stat.rx_bytes = read_u64(&dir.join("rx_bytes"));
+        stat.rx_packets = read_u64(&dir.join("rx_packets"));
+        stat.rx_errors = read_u64(&dir.join("rx_errors"));
+        stat.rx_dropped = read_u64(&dir.join("rx_dropped"));
+        stat.tx_bytes = read_u64(&dir.join("tx_bytes"));
+        stat.tx_packets = read_u64(&dir.join("tx_packets"));
+        stat.tx_errors = read_u64(&dir.join("tx_errors"));
+        stat.tx_dropped = read_u64(&dir.join("tx_dropped"));
+        stat.name = name;
+        stats.push(stat);
+    }
+    stats
+}
+
+// Page sizes like "2MB" and "1GB" as they are named in the hugetlb controller files.
+fn hugepage_sizes() -> Vec<String> {
+    let entries = match read_dir(HUGEPAGE_DIR) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .flatten()
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            let kb = name
+                .strip_prefix("hugepages-")?
+                .strip_suffix("kB")?
+                .parse::<u64>()
+                .ok()?;
+            Some(hugepage_size_name(kb))
+        })
+        .collect()
+}
+
+fn hugepage_size_name(kb: u64) -> String {
+    match kb {
+        kb if kb >= 1 << 20 && kb % (1 << 20) == 0 => format!("{}GB", kb >> 20),
+        kb if kb >= 1 << 10 && kb % (1 << 10) == 0 => format!("{}MB", kb >> 10),
+        kb => format!("{}KB", kb),
+    }
+}
+
+// "max" in cgroup files means no limit, which is u64::MAX
+fn parse_u64(s: &str) -> u64 {
+    match s.trim() {
+        "max" => u64::MAX,
+        s => s.parse().unwrap_or_default(),
+    }
+}
+
+fn read_u64(path: &Path) -> u64 {
+    read_to_string(path)
+        .map(|s| parse_u64(&s))
+        .unwrap_or_default()
+}
+
+fn read_flat_keyed(path: &Path) -> HashMap<String, u64> {
+    parse_flat_keyed(&read_to_string(path).unwrap_or_default())
+}
+
+fn parse_flat_keyed(content: &str) -> HashMap<String, u64> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(' ')?;
+            Some((key.to_string(), parse_u64(value)))
+        })
+        .collect()
+}
+
+fn stat_value(stat: &HashMap<String, u64>, key: &str) -> u64 {
+    stat.get(key).copied().unwrap_or_default()
+}
+
+fn read_blkio_entries(dir: &Path, file: &str) -> Vec<BlkIOEntry> {
+    parse_blkio_entries(&read_to_string(dir.join(file)).unwrap_or_default())
+}
+
+// Lines of blkio files are like "8:0 Read 4096", the "Total" lines have no device.
+fn parse_blkio_entries(content: &str) -> Vec<BlkIOEntry> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (device, op, value) = match fields.as_slice() {
+                [device, op, value] => (*device, *op, *value),
+                [device, value] => (*device, "", *value),
+                _ => return None,
+            };
+            let (major, minor) = device.split_once(':')?;
+            let mut entry = BlkIOEntry::new();
+            entry.major = major.parse().ok()?;
+            entry.minor = minor.parse().ok()?;
+            entry.op = op.to_string();
+            entry.value = parse_u64(value);
+            Some(entry)
+        })
+        .collect()
+}
+
+fn read_io_stat(path: &Path) -> Vec<(u64, u64, HashMap<String, u64>)> {
+    parse_io_stat(&read_to_string(path).unwrap_or_default())
+}
+
+// Lines of io.stat are like "8:0 rbytes=4096 wbytes=0 rios=1 wios=0 dbytes=0 dios=0".
+fn parse_io_stat(content: &str) -> Vec<(u64, u64, HashMap<String, u64>)> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (major, minor) = fields.next()?.split_once(':')?;
+            let stat = fields
+                .filter_map(|f| {
+                    let (key, value) = f.split_once('=')?;
+                    Some((key.to_string(), parse_u64(value)))
+                })
+                .collect();
+            Some((major.parse().ok()?, minor.parse().ok()?, stat))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stats::{
+        hugepage_size_name, parse_blkio_entries, parse_cgroup_paths, parse_flat_keyed,
+        parse_io_stat,
+    };
+
+    #[test]
+    fn test_parse_cgroup_paths() {
+        let v1 = "12:pids:/kuasar/c1\n4:cpu,cpuacct:/kuasar/c1\n1:name=systemd:/kuasar/c1\n";
+        let paths = parse_cgroup_paths(v1);
+        assert_eq!(paths.get("pids").unwrap(), "/kuasar/c1");
+        assert_eq!(paths.get("cpu").unwrap(), "/kuasar/c1");
+        assert_eq!(paths.get("cpuacct").unwrap(), "/kuasar/c1");
+        assert!(paths.get("").is_none());
+
+        let paths = parse_cgroup_paths("0::/kuasar/c1\n");
+        assert_eq!(paths.get("").unwrap(), "/kuasar/c1");
+    }
+
+    #[test]
+    fn test_parse_flat_keyed() {
+        let stat = parse_flat_keyed("usage_usec 100\nnr_periods 2\nmax max\n");
+        assert_eq!(stat.get("usage_usec"), Some(&100));
+        assert_eq!(stat.get("nr_periods"), Some(&2));
+        assert_eq!(stat.get("max"), Some(&u64::MAX));
+    }
+
+    #[test]
+    fn test_parse_blkio_entries() {
+        let entries = parse_blkio_entries("8:0 Read 4096\n8:0 Write 512\nTotal 4608\n");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].major, 8);
+        assert_eq!(entries[0].minor, 0);
+        assert_eq!(entries[0].op, "Read");
+        assert_eq!(entries[0].value, 4096);
+        assert_eq!(entries[1].op, "Write");
+    }
+
+    #[test]
+    fn test_parse_io_stat() {
+        let stats = parse_io_stat("253:16 rbytes=4096 wbytes=8192 rios=1 wios=2 dbytes=0 dios=0\n");
+        assert_eq!(stats.len(), 1);
+        let (major, minor, stat) = &stats[0];
+        assert_eq!((*major, *minor), (253, 16));
+        assert_eq!(stat.get("rbytes"), Some(&4096));
+        assert_eq!(stat.get("wios"), Some(&2));
+    }
+
+    #[test]
+    fn test_hugepage_size_name() {
+        assert_eq!(hugepage_size_name(2048), "2MB");
+        assert_eq!(hugepage_size_name(1048576), "1GB");
+        assert_eq!(hugepage_size_name(64), "64KB");
+    }
+}
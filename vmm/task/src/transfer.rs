@@ -0,0 +1,335 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+// A line based protocol to copy files between the host and guest, one file per connection:
+//
+//   GET <path>\n
+//     -> OK <size> <mode in octal>\n<size bytes of content>
+//   PUT <size> <mode in octal> <path>\n
+//     -> OK\n, then the client sends <size bytes of content>
+//     -> DONE\n after the content is written
+//
+// Any failure is replied with "ERR <message>\n" and the connection is closed.
+
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use containerd_shim::{io_error, other, Result};
+use futures::StreamExt;
+use log::{debug, error, warn};
+use tokio::{
+    fs::{metadata, remove_file, rename, set_permissions, File, OpenOptions},
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+};
+
+use crate::vsock::bind_vsock;
+
+// a request line longer than it is not a valid request
+const MAX_REQUEST_LINE_LENGTH: u64 = 8192;
+
+// distinguishes the temp files of concurrent PUTs of the same path
+static TEMP_FILE_SEQ: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, PartialEq)]
+enum Request {
+    Get { path: String },
+    Put { path: String, size: u64, mode: u32 },
+}
+
+pub async fn listen_file_transfer(addr: &str) -> Result<()> {
+    let l = bind_vsock(addr).await?;
+    tokio::spawn(async move {
+        let mut incoming = l.incoming();
+        while let Some(Ok(s)) = incoming.next().await {
+            debug!("get a file transfer request");
+            tokio::spawn(async move {
+                if let Err(e) = handle_transfer(s).await {
+                    error!("failed to transfer file {:?}", e);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+async fn handle_transfer<S: AsyncRead + AsyncWrite>(stream: S) -> Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    (&mut reader)
+        .take(MAX_REQUEST_LINE_LENGTH)
+        .read_line(&mut line)
+        .await
+        .map_err(io_error!(e, "failed to read file transfer request"))?;
+    let res = match parse_request(&line) {
+        Ok(Request::Get { path }) => send_file(&path, &mut writer).await,
+        Ok(Request::Put { path, size, mode }) => {
+            receive_file(&path, size, mode, &mut reader, &mut writer).await
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = &res {
+        // the reply may fail if the content is being sent, just close the connection then
+        let reply = format!("ERR {}\n", e.to_string().replace('\n', " "));
+        writer.write_all(reply.as_bytes()).await.unwrap_or_default();
+    }
+    writer.shutdown().await.unwrap_or_default();
+    res
+}
+
+fn parse_request(line: &str) -> Result<Request> {
+    let line = line
+        .strip_suffix('\n')
+        .ok_or_else(|| other!("file transfer request is not terminated"))?;
+    let (method, args) = line.split_once(' ').unwrap_or((line, ""));
+    match method {
+        "GET" if !args.is_empty() => Ok(Request::Get {
+            path: args.to_string(),
+        }),
+        "PUT" => {
+            let mut parts = args.splitn(3, ' ');
+            let size = parts.next().and_then(|s| s.parse().ok());
+            let mode = parts.next().and_then(|m| u32::from_str_radix(m, 8).ok());
+            let path = parts.next().filter(|p| !p.is_empty());
+            match (size, mode, path) {
+                (Some(size), Some(mode), Some(path)) => Ok(Request::Put {
+                    path: path.to_string(),
+                    size,
+                    mode: mode & 0o7777,
+                }),
+                _ => Err(other!("invalid PUT request {}", line)),
+            }
+        }
+        _ => Err(other!("invalid file transfer request {}", line)),
+    }
+}
+
+async fn send_file<W: AsyncWrite + Unpin>(path: &str, writer: &mut W) -> Result<()> {
+    let m = metadata(path)
+        .await
+        .map_err(io_error!(e, "failed to stat {}", path))?;
+    if !m.is_file() {
+        return Err(other!("{} is not a regular file", path));
+    }
+    let file = File::open(path)
+        .await
+        .map_err(io_error!(e, "failed to open {}", path))?;
+    let size = m.len();
+    let header = format!("OK {} {:o}\n", size, m.permissions().mode() & 0o7777);
+    writer
+        .write_all(header.as_bytes())
+        .await
+        .map_err(io_error!(e, "failed to reply GET {}", path))?;
+    // the file may grow while it is being sent, only the size in the header is sent
+    let sent = tokio::io::copy(&mut file.take(size), writer)
+        .await
+        .map_err(io_error!(e, "failed to send {}", path))?;
+    if sent != size {
+        return Err(other!("{} is truncated while it is being sent", path));
+    }
+    debug!("sent {} bytes of {}", sent, path);
+    Ok(())
+}
+
+async fn receive_file<R, W>(
+    path: &str,
+    size: u64,
+    mode: u32,
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    if Path::new(path).is_dir() {
+        return Err(other!("{} is a directory", path));
+    }
+    // the content is received into a temp file in the same directory and renamed to the path
+    // at last, so that a failed transfer neither leaves a partial file nor destroys the existing one
+    let temp_path = format!(
+        "{}.kuasar-cp.{}",
+        path,
+        TEMP_FILE_SEQ.fetch_add(1, Ordering::Relaxed)
+    );
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(&temp_path)
+        .await
+        .map_err(io_error!(e, "failed to create {}", temp_path))?;
+    let received = match receive_into(file, &temp_path, path, size, mode, reader, writer).await {
+        Ok(received) => received,
+        Err(e) => {
+            if let Err(re) = remove_file(&temp_path).await {
+                warn!("failed to remove {}: {}", temp_path, re);
+            }
+            return Err(e);
+        }
+    };
+    writer
+        .write_all(b"DONE\n")
+        .await
+        .map_err(io_error!(e, "failed to reply PUT {}", path))?;
+    debug!("received {} bytes of {}", received, path);
+    Ok(())
+}
+
+async fn receive_into<R, W>(
+    mut file: File,
+    temp_path: &str,
+    path: &str,
+    size: u64,
+    mode: u32,
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    writer
+        .write_all(b"OK\n")
+        .await
+        .map_err(io_error!(e, "failed to reply PUT {}", path))?;
+    let received = tokio::io::copy(&mut reader.take(size), &mut file)
+        .await
+        .map_err(io_error!(e, "failed to receive {}", path))?;
+    if received != size {
+        return Err(other!(
+            "{} is truncated, received {} of {} bytes",
+            path,
+            received,
+            size
+        ));
+    }
+    file.sync_all()
+        .await
+        .map_err(io_error!(e, "failed to sync {}", temp_path))?;
+    // the mode of a new file is masked by umask
+    set_permissions(temp_path, std::fs::Permissions::from_mode(mode))
+        .await
+        .map_err(io_error!(e, "failed to chmod {}", temp_path))?;
+    rename(temp_path, path).await.map_err(io_error!(
+        e,
+        "failed to rename {} to {}",
+        temp_path,
+        path
+    ))?;
+    Ok(received)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use temp_dir::TempDir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{handle_transfer, parse_request, Request};
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!(
+            parse_request("GET /var/log/messages\n").unwrap(),
+            Request::Get {
+                path: "/var/log/messages".to_string()
+            }
+        );
+        assert_eq!(
+            parse_request("PUT 1024 755 /usr/local/bin/my tool\n").unwrap(),
+            Request::Put {
+                path: "/usr/local/bin/my tool".to_string(),
+                size: 1024,
+                mode: 0o755,
+            }
+        );
+        assert!(parse_request("GET \n").is_err());
+        assert!(parse_request("GET /tmp/a").is_err());
+        assert!(parse_request("PUT 10 999 /tmp/a\n").is_err());
+        assert!(parse_request("PUT abc 644 /tmp/a\n").is_err());
+        assert!(parse_request("DELETE /tmp/a\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_and_put_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("file");
+        let path = path.to_str().unwrap().to_string();
+
+        let (mut client, server) = tokio::io::duplex(4096);
+        let handle = tokio::spawn(handle_transfer(server));
+        let req = format!("PUT 11 640 {}\nhello world", path);
+        client.write_all(req.as_bytes()).await.unwrap();
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).await.unwrap();
+        handle.await.unwrap().unwrap();
+        assert_eq!(reply, "OK\nDONE\n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "hello world");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o640);
+
+        let (mut client, server) = tokio::io::duplex(4096);
+        let handle = tokio::spawn(handle_transfer(server));
+        client
+            .write_all(format!("GET {}\n", path).as_bytes())
+            .await
+            .unwrap();
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).await.unwrap();
+        handle.await.unwrap().unwrap();
+        assert_eq!(reply, "OK 11 640\nhello world");
+    }
+
+    #[tokio::test]
+    async fn test_get_directory_fails() {
+        let dir = TempDir::new().unwrap();
+        let (mut client, server) = tokio::io::duplex(4096);
+        let handle = tokio::spawn(handle_transfer(server));
+        client
+            .write_all(format!("GET {}\n", dir.path().display()).as_bytes())
+            .await
+            .unwrap();
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).await.unwrap();
+        assert!(handle.await.unwrap().is_err());
+        assert!(reply.starts_with("ERR "));
+    }
+
+    #[tokio::test]
+    async fn test_put_truncated_file_fails() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("file");
+        let (mut client, server) = tokio::io::duplex(4096);
+        let handle = tokio::spawn(handle_transfer(server));
+        std::fs::write(&path, "existing").unwrap();
+        let req = format!("PUT 100 644 {}\nshort", path.display());
+        client.write_all(req.as_bytes()).await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut reply = String::new();
+        client.read_to_string(&mut reply).await.unwrap();
+        assert!(handle.await.unwrap().is_err());
+        assert!(reply.starts_with("OK\nERR "));
+        // the existing file is kept and the partial content is removed
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "existing");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
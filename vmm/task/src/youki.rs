@@ -64,9 +64,11 @@ use vmm_common::{
 };
 
 use crate::{
+    cgroup::update_resources,
     device::rescan_pci_bus,
     io::{convert_stdio, copy_io_or_console, ProcessIO},
     sandbox::SandboxResources,
+    stats::collect_metrics,
     util::{read_io, read_storages},
 };
 
@@ -325,7 +327,7 @@ impl ProcessLifecycle<InitProcess> for YoukiInitLifecycle {
                 p.pid
             ));
         }
-        containerd_shim::cgroup::update_resources(p.pid as u32, resources)
+        update_resources(p.pid as u32, resources)
     }
 
     #[cfg(not(target_os = "linux"))]
@@ -341,7 +343,7 @@ impl ProcessLifecycle<InitProcess> for YoukiInitLifecycle {
                 p.pid
             ));
         }
-        containerd_shim::cgroup::collect_metrics(p.pid as u32)
+        collect_metrics(p.pid as u32).await
     }
 
     #[cfg(not(target_os = "linux"))]
@@ -482,6 +484,19 @@ impl ProcessLifecycle<ExecProcess> for YoukiExecLifecycle {
         Err(Error::Unimplemented("exec update".to_string()))
     }
 
+    #[cfg(target_os = "linux")]
+    async fn stats(&self, p: &ExecProcess) -> Result<Metrics> {
+        // exec processes are in the cgroups of the container
+        if p.pid <= 0 {
+            return Err(other!(
+                "failed to collect metrics because exec process is {}",
+                p.pid
+            ));
+        }
+        collect_metrics(p.pid as u32).await
+    }
+
+    #[cfg(not(target_os = "linux"))]
     async fn stats(&self, _p: &ExecProcess) -> Result<Metrics> {
         Err(Error::Unimplemented("exec stats".to_string()))
     }
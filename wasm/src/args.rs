@@ -40,6 +40,24 @@ pub struct Args {
     /// Logging level for sandboxer [trace, debug, info, warn, error, fatal, panic]
     #[arg(long, value_name = "STRING")]
     pub log_level: Option<String>,
+
+    /// Cache directory of the compiled wasm modules, default is `/var/lib/kuasar-wasm/module-cache`
+    #[arg(
+        long,
+        value_name = "DIR",
+        default_value = "/var/lib/kuasar-wasm/module-cache"
+    )]
+    pub module_cache_dir: String,
+
+    /// Max size of the compiled wasm modules cache in MiB, 0 disables the cache, default is 1024
+    #[arg(long, value_name = "MiB", default_value_t = 1024)]
+    pub module_cache_size: u64,
+
+    /// Number of the dedicated threads to run the wasm modules of each container, which are
+    /// placed in the cgroup of the container, 0 runs the modules on the threads of the sandboxer.
+    /// It is only supported with cgroup v1, as threads can not be moved between cgroups in v2
+    #[arg(long, value_name = "NUM", default_value_t = 0)]
+    pub module_threads: usize,
 }
 
 #[cfg(test)]
@@ -55,5 +73,8 @@ mod tests {
         assert_eq!(args.dir, "/run/kuasar-wasm");
         assert_eq!(args.listen, "/run/wasm-sandboxer.sock");
         assert!(args.log_level.is_none());
+        assert_eq!(args.module_cache_dir, "/var/lib/kuasar-wasm/module-cache");
+        assert_eq!(args.module_cache_size, 1024);
+        assert_eq!(args.module_threads, 0);
     }
 }
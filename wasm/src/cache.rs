@@ -0,0 +1,229 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{
+    fs::{read_dir, remove_file, rename, DirBuilder, File},
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::SystemTime,
+};
+
+use containerd_shim::{io_error, Result};
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+
+// Suffix of the artifacts being written, they are never loaded or evicted.
+const TMP_SUFFIX: &str = ".tmp";
+
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The on-disk cache of the compiled modules, so that the containers of the same module do not
+/// compile it again. The artifacts are keyed by the digest of the module and the engine, and the
+/// least recently used ones are evicted if the cache grows larger than its capacity.
+#[derive(Debug, Default)]
+pub struct ModuleCache {
+    dir: PathBuf,
+    /// Max size of the artifacts in bytes, 0 means the cache is disabled.
+    capacity: u64,
+}
+
+impl ModuleCache {
+    pub fn new(dir: &str, capacity: u64) -> Self {
+        Self {
+            dir: PathBuf::from(dir),
+            capacity,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0 && !self.dir.as_os_str().is_empty()
+    }
+
+    /// Path of the artifact of `module` compiled by the engine identified by `engine_key`,
+    /// which should change with the version and the config of the engine.
+    pub fn artifact_path(&self, module: &[u8], engine_key: &str) -> PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.update(module);
+        hasher.update(engine_key.as_bytes());
+        let digest = hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        self.dir.join(digest)
+    }
+
+    /// Whether the artifact is cached, it is marked as recently used if so.
+    pub fn get(&self, path: &Path) -> bool {
+        match File::options().write(true).open(path) {
+            Ok(f) => {
+                f.set_modified(SystemTime::now()).unwrap_or_default();
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Add the artifact to the cache, `write` writes the artifact to the temp path given to it.
+    pub fn put<F>(&self, path: &Path, write: F) -> Result<()>
+    where
+        F: FnOnce(&Path) -> Result<()>,
+    {
+        let dir = self.dir.display();
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&self.dir)
+            .map_err(io_error!(e, "failed to create module cache dir {}", dir))?;
+        // the same module may be compiled by several containers at the same time
+        let tmp_path = PathBuf::from(format!(
+            "{}.{}{}",
+            path.display(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed),
+            TMP_SUFFIX
+        ));
+        if let Err(e) = write(&tmp_path) {
+            remove_file(&tmp_path).unwrap_or_default();
+            return Err(e);
+        }
+        let tmp = tmp_path.display();
+        rename(&tmp_path, path).map_err(io_error!(e, "failed to rename {}", tmp))?;
+        debug!("module artifact {} is cached", path.display());
+        self.evict();
+        Ok(())
+    }
+
+    /// Remove a broken or incompatible artifact from the cache.
+    pub fn remove(&self, path: &Path) {
+        if let Err(e) = remove_file(path) {
+            warn!("failed to remove module artifact {}: {}", path.display(), e);
+        }
+    }
+
+    // Remove the least recently used artifacts until the cache fits its capacity.
+    fn evict(&self) {
+        let entries = match read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!(
+                    "failed to read module cache dir {}: {}",
+                    self.dir.display(),
+                    e
+                );
+                return;
+            }
+        };
+        let mut artifacts = entries
+            .flatten()
+            .filter(|e| !e.file_name().to_string_lossy().ends_with(TMP_SUFFIX))
+            .filter_map(|e| {
+                let m = e.metadata().ok().filter(|m| m.is_file())?;
+                Some((m.modified().ok()?, m.len(), e.path()))
+            })
+            .collect::<Vec<_>>();
+        let mut size = artifacts.iter().map(|(_, len, _)| len).sum::<u64>();
+        artifacts.sort();
+        for (_, len, path) in artifacts {
+            if size <= self.capacity {
+                break;
+            }
+            debug!("evict module artifact {}", path.display());
+            self.remove(&path);
+            size -= len;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use tempfile::tempdir;
+
+    use crate::cache::ModuleCache;
+
+    #[test]
+    fn test_artifact_path() {
+        let cache = ModuleCache::new("/var/lib/kuasar-wasm/module-cache", 1024);
+        let path = cache.artifact_path(b"module", "engine-1");
+        assert!(path.starts_with("/var/lib/kuasar-wasm/module-cache"));
+        assert_eq!(path.file_name().unwrap().len(), 64);
+        assert_eq!(path, cache.artifact_path(b"module", "engine-1"));
+        assert_ne!(path, cache.artifact_path(b"module", "engine-2"));
+        assert_ne!(path, cache.artifact_path(b"module2", "engine-1"));
+        assert!(!ModuleCache::default().is_enabled());
+        assert!(!ModuleCache::new("/var/lib/kuasar-wasm/module-cache", 0).is_enabled());
+    }
+
+    #[test]
+    fn test_put_and_get() {
+        let tmp_dir = tempdir().unwrap();
+        let cache = ModuleCache::new(tmp_dir.path().join("cache").to_str().unwrap(), 1024);
+        let path = cache.artifact_path(b"module", "engine");
+        assert!(!cache.get(&path));
+
+        assert!(cache
+            .put(&path, |_| Err(containerd_shim::other!("failed to compile")))
+            .is_err());
+        assert!(!cache.get(&path));
+        assert_eq!(
+            std::fs::read_dir(tmp_dir.path().join("cache"))
+                .unwrap()
+                .count(),
+            0
+        );
+
+        cache
+            .put(&path, |tmp| {
+                std::fs::write(tmp, b"artifact").unwrap();
+                Ok(())
+            })
+            .unwrap();
+        assert!(cache.get(&path));
+        assert_eq!(std::fs::read(&path).unwrap(), b"artifact");
+    }
+
+    #[test]
+    fn test_evict() {
+        let tmp_dir = tempdir().unwrap();
+        let cache = ModuleCache::new(tmp_dir.path().to_str().unwrap(), 10);
+        let paths = (0..3)
+            .map(|i| cache.artifact_path(&[i], "engine"))
+            .collect::<Vec<_>>();
+        let now = SystemTime::now();
+        for (i, path) in paths.iter().enumerate() {
+            std::fs::write(path, b"1234").unwrap();
+            let f = std::fs::File::options().write(true).open(path).unwrap();
+            f.set_modified(now - Duration::from_secs(100 - i as u64))
+                .unwrap();
+        }
+        // the first one is used recently
+        assert!(cache.get(&paths[0]));
+
+        let path = cache.artifact_path(b"new", "engine");
+        cache
+            .put(&path, |tmp| {
+                std::fs::write(tmp, b"1234").unwrap();
+                Ok(())
+            })
+            .unwrap();
+        assert!(path.exists());
+        assert!(paths[0].exists());
+        assert!(!paths[1].exists());
+        assert!(!paths[2].exists());
+    }
+}
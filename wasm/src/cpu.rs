@@ -0,0 +1,319 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use cgroups_rs::{Cgroup, CgroupPid};
+use containerd_shim::{other_error, Result};
+use log::warn;
+use oci_spec::runtime::Spec;
+use tokio::{
+    runtime::Runtime,
+    task::JoinHandle,
+    time::{sleep_until, Sleep},
+};
+use wasmtime::{Engine, Trap};
+
+/// The module yields to the throttle every time slice, which is the precision of the cpu limit.
+pub const TIME_SLICE: Duration = Duration::from_millis(10);
+
+// The default cpu.cfs_period_us of the kernel
+const DEFAULT_CPU_PERIOD: u64 = 100_000;
+
+/// The cpu time the module can run in every period, from `cpu.quota` and `cpu.period` of the spec.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CpuLimit {
+    pub quota: Duration,
+    pub period: Duration,
+}
+
+impl CpuLimit {
+    pub fn from_spec(spec: &Spec) -> Option<Self> {
+        let cpu = spec
+            .linux()
+            .as_ref()?
+            .resources()
+            .as_ref()?
+            .cpu()
+            .as_ref()?;
+        // the quota is -1 or not set if the cpu is unlimited
+        let quota = cpu.quota().filter(|q| *q > 0)?;
+        let period = cpu
+            .period()
+            .filter(|p| *p > 0)
+            .unwrap_or(DEFAULT_CPU_PERIOD);
+        Some(Self {
+            quota: Duration::from_micros(quota as u64),
+            period: Duration::from_micros(period),
+        })
+    }
+}
+
+// The cpu time used by the module in the current period, the time used more than the quota is
+// charged to the next periods, so the module runs no longer than the quota on average even
+// though it is only throttled at the end of a time slice.
+struct CpuBudget {
+    limit: CpuLimit,
+    period_start: Instant,
+    used: Duration,
+}
+
+impl CpuBudget {
+    fn new(limit: CpuLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            period_start: now,
+            used: Duration::ZERO,
+        }
+    }
+
+    // Charge the cpu time used by the module, return the time it can run again if it runs out
+    // of the quota.
+    fn charge(&mut self, used: Duration, now: Instant) -> Option<Instant> {
+        self.used += used;
+        let elapsed = now.saturating_duration_since(self.period_start);
+        if elapsed >= self.limit.period {
+            let periods = (elapsed.as_nanos() / self.limit.period.as_nanos()) as u32;
+            self.period_start += self.limit.period * periods;
+            self.used = self.used.saturating_sub(self.limit.quota * periods);
+        }
+        if self.used >= self.limit.quota {
+            return Some(self.period_start + self.limit.period);
+        }
+        None
+    }
+}
+
+/// Run the module within its cpu limit, the module yields every time the epoch is increased, then
+/// it is suspended until the next period if it used up its quota. It is interrupted once it is
+/// killed, the kill signal is set by the lifecycle and increases the epoch so that it yields.
+pub struct Throttled<F> {
+    module: Pin<Box<F>>,
+    budget: Option<CpuBudget>,
+    kill_signal: Arc<AtomicU32>,
+    sleep: Option<Pin<Box<Sleep>>>,
+}
+
+impl<F> Throttled<F> {
+    pub fn new(module: F, limit: Option<CpuLimit>, kill_signal: Arc<AtomicU32>) -> Self {
+        Self {
+            module: Box::pin(module),
+            budget: limit.map(|l| CpuBudget::new(l, Instant::now())),
+            kill_signal,
+            sleep: None,
+        }
+    }
+}
+
+impl<F: Future<Output = anyhow::Result<()>>> Future for Throttled<F> {
+    type Output = anyhow::Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if this.kill_signal.load(Ordering::SeqCst) != 0 {
+            return Poll::Ready(Err(Trap::Interrupt.into()));
+        }
+        if let Some(sleep) = this.sleep.as_mut() {
+            // the throttled module may be woken up by its own yield
+            if sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.sleep = None;
+        }
+
+        let start = Instant::now();
+        let res = this.module.as_mut().poll(cx);
+        if res.is_pending() {
+            let now = Instant::now();
+            if let Some(until) = this
+                .budget
+                .as_mut()
+                .and_then(|b| b.charge(now - start, now))
+            {
+                let mut sleep = Box::pin(sleep_until(until.into()));
+                // register the timer so that the module is woken up in the next period
+                if sleep.as_mut().poll(cx).is_pending() {
+                    this.sleep = Some(sleep);
+                }
+            }
+        }
+        res
+    }
+}
+
+/// Increase the epoch of the engine every time slice so that the module yields periodically.
+pub fn spawn_epoch_ticker(engine: Engine) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TIME_SLICE);
+        loop {
+            interval.tick().await;
+            engine.increment_epoch();
+        }
+    })
+}
+
+/// A dedicated thread pool to run the module of a container, its threads are placed in the cgroup
+/// of the container so that the cpu they use is limited and accounted by the cgroup as well,
+/// rather than by the cgroup of wasm-sandboxer.
+pub struct ModuleThreadPool {
+    runtime: Option<Runtime>,
+}
+
+impl ModuleThreadPool {
+    pub fn new(threads: usize, cgroup_path: Option<String>) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(threads)
+            .max_blocking_threads(threads)
+            .thread_name("wasm-module")
+            .enable_all()
+            .on_thread_start(move || {
+                if let Some(path) = &cgroup_path {
+                    join_cgroup(path);
+                }
+            })
+            .build()
+            .map_err(other_error!(e, "failed to build module thread pool"))?;
+        Ok(Self {
+            runtime: Some(runtime),
+        })
+    }
+
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.runtime
+            .as_ref()
+            .expect("runtime is only taken when the pool is dropped")
+            .spawn(future)
+    }
+}
+
+impl Drop for ModuleThreadPool {
+    fn drop(&mut self) {
+        // a runtime can not be dropped in an async context as it blocks
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+// Only called under cgroup v1, cgroup v2 does not allow a thread to be moved into a domain
+// cgroup other than the one of its process, which is rejected when the sandboxer starts.
+fn join_cgroup(path: &str) {
+    let tid = nix::unistd::gettid().as_raw();
+    // the cgroup is created by containerd and should not be created by the module thread
+    let cgroup = Cgroup::load(
+        cgroups_rs::hierarchies::auto(),
+        path.trim_start_matches('/'),
+    );
+    if let Err(e) = cgroup.add_task(CgroupPid::from(tid as u64)) {
+        warn!(
+            "failed to add module thread {} to cgroup {}: {}",
+            tid, path, e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use oci_spec::runtime::{LinuxBuilder, LinuxCpuBuilder, LinuxResourcesBuilder, Spec};
+
+    use crate::cpu::{CpuBudget, CpuLimit};
+
+    fn spec_with_cpu(quota: i64, period: Option<u64>) -> Spec {
+        let mut cpu = LinuxCpuBuilder::default().quota(quota);
+        if let Some(period) = period {
+            cpu = cpu.period(period);
+        }
+        let resources = LinuxResourcesBuilder::default()
+            .cpu(cpu.build().unwrap())
+            .build()
+            .unwrap();
+        let linux = LinuxBuilder::default()
+            .resources(resources)
+            .build()
+            .unwrap();
+        let mut spec = Spec::default();
+        spec.set_linux(Some(linux));
+        spec
+    }
+
+    #[test]
+    fn test_cpu_limit_from_spec() {
+        assert_eq!(
+            CpuLimit::from_spec(&spec_with_cpu(50_000, Some(200_000))),
+            Some(CpuLimit {
+                quota: Duration::from_millis(50),
+                period: Duration::from_millis(200),
+            })
+        );
+        assert_eq!(
+            CpuLimit::from_spec(&spec_with_cpu(20_000, None)),
+            Some(CpuLimit {
+                quota: Duration::from_millis(20),
+                period: Duration::from_millis(100),
+            })
+        );
+        assert_eq!(CpuLimit::from_spec(&spec_with_cpu(-1, Some(100_000))), None);
+        assert_eq!(CpuLimit::from_spec(&Spec::default()), None);
+    }
+
+    #[test]
+    fn test_cpu_budget_charge() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let limit = CpuLimit {
+            quota: ms(20),
+            period: ms(100),
+        };
+        let mut budget = CpuBudget::new(limit, start);
+        assert_eq!(budget.charge(ms(10), start + ms(10)), None);
+        assert_eq!(budget.charge(ms(10), start + ms(20)), Some(start + ms(100)));
+
+        // a new period
+        assert_eq!(budget.charge(ms(0), start + ms(100)), None);
+        // the overrun is charged to the next period
+        assert_eq!(
+            budget.charge(ms(30), start + ms(130)),
+            Some(start + ms(200))
+        );
+        assert_eq!(budget.charge(ms(0), start + ms(200)), None);
+        assert_eq!(
+            budget.charge(ms(10), start + ms(210)),
+            Some(start + ms(300))
+        );
+
+        // several periods passed while the module was waiting for io
+        assert_eq!(budget.charge(ms(10), start + ms(1050)), None);
+        assert_eq!(
+            budget.charge(ms(20), start + ms(1070)),
+            Some(start + ms(1100))
+        );
+    }
+}
@@ -31,12 +31,18 @@ use nix::{
 };
 use signal_hook_tokio::Signals;
 
-use crate::sandbox::WasmSandboxer;
+use crate::{cache::ModuleCache, sandbox::WasmSandboxer};
 
 mod args;
+mod cache;
+#[cfg(feature = "wasmtime")]
+mod cpu;
 mod sandbox;
+mod state;
 mod utils;
 mod version;
+#[cfg(feature = "wasmtime")]
+mod volume;
 #[cfg(feature = "wasmedge")]
 mod wasmedge;
 #[cfg(feature = "wasmtime")]
@@ -59,14 +65,23 @@ async fn main() {
         .filter_module("wasm_sandboxer", log_level)
         .init();
 
-    // TODO: Support recovery
-
     tokio::spawn(async move {
         let signals = Signals::new([libc::SIGPIPE, libc::SIGCHLD]).expect("new signal failed");
         handle_signals(signals).await;
     });
 
-    let sandboxer = WasmSandboxer::default();
+    // the module threads are moved into the cgroups of the containers, which is only allowed
+    // for threads under cgroup v1, cgroup v2 keeps all the threads of a process in one domain
+    if args.module_threads > 0 && cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
+        error!("--module-threads is not supported with cgroup v2, set it to 0");
+        std::process::exit(1);
+    }
+
+    let module_cache = ModuleCache::new(&args.module_cache_dir, args.module_cache_size << 20);
+    let sandboxer = WasmSandboxer::new(module_cache, args.module_threads);
+    if let Err(e) = sandboxer.recover(&args.dir).await {
+        warn!("failed to recover sandboxes in {}: {}", args.dir, e);
+    }
     containerd_sandbox::run(
         "kuasar-wasm-sandboxer-wasmedge",
         &args.listen,
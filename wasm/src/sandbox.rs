@@ -14,7 +14,7 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, path::Path, sync::Arc};
 
 use anyhow::anyhow;
 use async_trait::async_trait;
@@ -31,52 +31,110 @@ use containerd_shim::{
         ttrpc::asynchronous::{Server, Service},
     },
 };
-use log::debug;
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
 use tokio::{
-    fs::create_dir_all,
+    fs::{create_dir_all, remove_file, OpenOptions},
+    io::{AsyncReadExt, AsyncWriteExt},
     sync::{mpsc::channel, Mutex, RwLock},
 };
 
 #[cfg(feature = "wasmtime")]
 use crate::wasmtime::{exec_exits, WasmtimeContainerFactory};
+use crate::{
+    cache::ModuleCache,
+    state::{TaskState, TASKS_DIR},
+};
 
 #[derive(Default)]
 pub struct WasmSandboxer {
     #[allow(clippy::type_complexity)]
     pub(crate) sandboxes: Arc<RwLock<HashMap<String, Arc<Mutex<WasmSandbox>>>>>,
+    pub(crate) module_cache: Arc<ModuleCache>,
+    pub(crate) module_threads: usize,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct WasmSandbox {
-    pub(crate) _id: String,
+    pub(crate) id: String,
     pub(crate) base_dir: String,
     pub(crate) data: SandboxData,
     pub(crate) status: SandboxStatus,
+    #[serde(skip, default)]
     pub(crate) exit_signal: Arc<ExitSignal>,
     pub(crate) containers: HashMap<String, WasmContainer>,
+    #[serde(skip)]
     pub(crate) server: Option<Server>,
+    #[serde(skip)]
+    pub(crate) module_cache: Arc<ModuleCache>,
+    #[serde(skip)]
+    pub(crate) module_threads: usize,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct WasmContainer {
     pub(crate) data: ContainerData,
 }
 
+impl WasmSandboxer {
+    pub fn new(module_cache: ModuleCache, module_threads: usize) -> Self {
+        Self {
+            sandboxes: Default::default(),
+            module_cache: Arc::new(module_cache),
+            module_threads,
+        }
+    }
+
+    pub async fn recover(&self, dir: &str) -> Result<()> {
+        let mut subs = match tokio::fs::read_dir(dir).await {
+            Ok(subs) => subs,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(Error::IO(e)),
+        };
+        while let Some(entry) = subs.next_entry().await.map_err(Error::IO)? {
+            if !entry.file_type().await.map(|t| t.is_dir()).unwrap_or(false) {
+                continue;
+            }
+            let path = Path::new(dir).join(entry.file_name());
+            let res =
+                WasmSandbox::recover(&path, self.module_cache.clone(), self.module_threads).await;
+            match res {
+                Ok(sb) => {
+                    self.sandboxes
+                        .write()
+                        .await
+                        .insert(sb.id.to_string(), Arc::new(Mutex::new(sb)));
+                }
+                Err(e) => {
+                    // keep the directory, the state in it may be needed to clean up the sandbox
+                    error!("failed to recover sandbox {:?}, {:?}", entry.file_name(), e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
 #[async_trait]
 impl Sandboxer for WasmSandboxer {
     type Sandbox = WasmSandbox;
 
     async fn create(&self, id: &str, s: SandboxOption) -> Result<()> {
         let sandbox = WasmSandbox {
-            _id: id.to_string(),
+            id: id.to_string(),
             base_dir: s.base_dir,
             data: s.sandbox,
             status: SandboxStatus::Created,
             exit_signal: Arc::new(Default::default()),
             containers: Default::default(),
             server: None,
+            module_cache: self.module_cache.clone(),
+            module_threads: self.module_threads,
         };
         create_dir_all(&sandbox.base_dir)
             .await
             .map_err(|e| anyhow!("failed to create {}, {}", sandbox.base_dir, e))?;
+        sandbox.dump().await?;
         let mut sandboxes = self.sandboxes.write().await;
         sandboxes.insert(id.to_string(), Arc::new(Mutex::new(sandbox)));
         Ok(())
@@ -90,7 +148,9 @@ impl Sandboxer for WasmSandboxer {
 
     async fn update(&self, id: &str, data: SandboxData) -> Result<()> {
         let sandbox = self.sandbox(id).await?;
-        sandbox.lock().await.data = data;
+        let mut sandbox = sandbox.lock().await;
+        sandbox.data = data;
+        sandbox.dump().await?;
         Ok(())
     }
 
@@ -135,15 +195,85 @@ impl WasmSandbox {
         let ts = time::OffsetDateTime::now_utc().unix_timestamp_nanos();
         self.status = SandboxStatus::Stopped(0, ts);
         self.exit_signal.signal();
+        self.dump().await?;
         Ok(())
     }
 
     async fn start(&mut self) -> Result<()> {
-        let task_service = self.start_task_service().await?;
+        self.start_task_server(vec![]).await?;
+        self.status = SandboxStatus::Running(0);
+        self.dump().await?;
+        Ok(())
+    }
+
+    async fn recover<P: AsRef<Path>>(
+        base_dir: P,
+        module_cache: Arc<ModuleCache>,
+        module_threads: usize,
+    ) -> Result<Self> {
+        let dump_path = base_dir.as_ref().join("sandbox.json");
+        let mut dump_file = OpenOptions::new()
+            .read(true)
+            .open(&dump_path)
+            .await
+            .map_err(Error::IO)?;
+        let mut content = vec![];
+        dump_file
+            .read_to_end(&mut content)
+            .await
+            .map_err(Error::IO)?;
+        let mut sb = serde_json::from_slice::<WasmSandbox>(content.as_slice())
+            .map_err(|e| anyhow!("failed to deserialize sandbox, {}", e))?;
+        sb.module_cache = module_cache;
+        sb.module_threads = module_threads;
+        match sb.status {
+            SandboxStatus::Running(_) => {
+                // the tasks are served by this process, so the task server has to be started again
+                let tasks = TaskState::load_all(&sb.tasks_dir()).await;
+                if let Err(e) = sb.start_task_server(tasks).await {
+                    warn!("failed to start task server of sandbox {}: {}", sb.id, e);
+                    sb.stop().await?;
+                }
+            }
+            SandboxStatus::Stopped(_, _) => sb.exit_signal.signal(),
+            _ => {}
+        }
+        Ok(sb)
+    }
+
+    async fn dump(&self) -> Result<()> {
+        let dump_data =
+            serde_json::to_vec(&self).map_err(|e| anyhow!("failed to serialize sandbox, {}", e))?;
+        let dump_path = format!("{}/sandbox.json", self.base_dir);
+        let mut dump_file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&dump_path)
+            .await
+            .map_err(Error::IO)?;
+        dump_file
+            .write_all(dump_data.as_slice())
+            .await
+            .map_err(Error::IO)?;
+        Ok(())
+    }
+
+    fn tasks_dir(&self) -> String {
+        format!("{}/{}", self.base_dir, TASKS_DIR)
+    }
+
+    // Start the task server of the sandbox, the tasks of `recovered` are added to it.
+    async fn start_task_server(&mut self, recovered: Vec<TaskState>) -> Result<()> {
+        let task_service = self.start_task_service(recovered).await?;
         let task_address = format!("unix://{}/task.sock", self.base_dir);
         self.data
             .task_address
             .clone_from(&format!("ttrpc+{}", task_address));
+        // the socket is left by the previous process if it is recovered
+        remove_file(format!("{}/task.sock", self.base_dir))
+            .await
+            .unwrap_or_default();
         let mut server = Server::new().register_service(task_service);
         server = server
             .bind(&task_address)
@@ -153,17 +283,21 @@ impl WasmSandbox {
             .await
             .map_err(|e| anyhow!("failed to start task server, {}", e))?;
         self.server = Some(server);
-        self.status = SandboxStatus::Running(0);
         Ok(())
     }
 
-    async fn start_task_service(&self) -> Result<HashMap<String, Service>>
-where {
+    async fn start_task_service(
+        &self,
+        recovered: Vec<TaskState>,
+    ) -> Result<HashMap<String, Service>> {
         let (tx, mut rx) = channel(128);
         #[cfg(feature = "wasmtime")]
         let _task_service = {
             let factory = WasmtimeContainerFactory {
                 netns: self.data.netns.clone(),
+                state_dir: self.tasks_dir(),
+                module_cache: self.module_cache.clone(),
+                module_threads: self.module_threads,
             };
 
             let task = TaskService {
@@ -174,12 +308,22 @@ where {
                 tx: tx.clone(),
             };
             exec_exits(&task).await;
+            for state in recovered {
+                match task.factory.recover(&state).await {
+                    Ok(c) => {
+                        task.containers.lock().await.insert(state.id, c);
+                    }
+                    Err(e) => warn!("failed to recover task {}: {}", state.id, e),
+                }
+            }
             create_task(Arc::new(Box::new(task)))
         };
         #[cfg(feature = "wasmedge")]
         let _task_service = {
             let mut factory = crate::wasmedge::WasmEdgeContainerFactory::default();
             factory.netns.clone_from(&self.data.netns);
+            factory.state_dir = self.tasks_dir();
+            factory.module_cache = self.module_cache.clone();
             let task = TaskService {
                 factory,
                 containers: Arc::new(Default::default()),
@@ -188,7 +332,16 @@ where {
                 tx: tx.clone(),
             };
 
+            // subscribe the exits before the processes of the recovered tasks are re-attached
             crate::wasmedge::process_exits(&task).await;
+            for state in recovered {
+                match task.factory.recover(&state).await {
+                    Ok(c) => {
+                        task.containers.lock().await.insert(state.id, c);
+                    }
+                    Err(e) => warn!("failed to recover task {}: {}", state.id, e),
+                }
+            }
             create_task(Arc::new(Box::new(task)))
         };
         tokio::spawn(async move {
@@ -223,6 +376,7 @@ impl Sandbox for WasmSandbox {
             data: option.container,
         };
         self.containers.insert(id.to_string(), container);
+        self.dump().await?;
         Ok(())
     }
 
@@ -232,6 +386,7 @@ impl Sandbox for WasmSandbox {
 
     async fn remove_container(&mut self, id: &str) -> Result<()> {
         self.containers.remove(id);
+        self.dump().await?;
         Ok(())
     }
 
@@ -249,3 +404,46 @@ impl Container for WasmContainer {
         Ok(self.data.clone())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use containerd_sandbox::{data::SandboxData, SandboxOption, SandboxStatus, Sandboxer};
+    use tempfile::tempdir;
+
+    use crate::{cache::ModuleCache, sandbox::WasmSandboxer};
+
+    #[tokio::test]
+    async fn test_dump_and_recover_sandboxes() {
+        let tmp_dir = tempdir().unwrap();
+        let dir = tmp_dir.path().to_str().unwrap().to_string();
+        let sandboxer = WasmSandboxer::new(ModuleCache::new("", 0), 1);
+        for id in ["created", "stopped"] {
+            let option = SandboxOption::new(format!("{}/{}", dir, id), SandboxData::default());
+            sandboxer.create(id, option).await.unwrap();
+        }
+        sandboxer.stop("stopped", false).await.unwrap();
+        let broken = Path::new(&dir).join("broken");
+        std::fs::create_dir(&broken).unwrap();
+        std::fs::write(broken.join("sandbox.json"), "{").unwrap();
+
+        let recovered = WasmSandboxer::new(ModuleCache::new("", 0), 1);
+        recovered.recover(&dir).await.unwrap();
+        assert_eq!(recovered.sandboxes.read().await.len(), 2);
+        let sandbox = recovered.sandbox("created").await.unwrap();
+        let sandbox = sandbox.lock().await;
+        assert_eq!(sandbox.id, "created");
+        assert_eq!(sandbox.base_dir, format!("{}/created", dir));
+        assert!(matches!(sandbox.status, SandboxStatus::Created));
+        let sandbox = recovered.sandbox("stopped").await.unwrap();
+        assert!(matches!(
+            sandbox.lock().await.status,
+            SandboxStatus::Stopped(0, _)
+        ));
+
+        // the sandbox failed to recover is kept
+        assert!(recovered.sandbox("broken").await.is_err());
+        assert!(broken.join("sandbox.json").exists());
+    }
+}
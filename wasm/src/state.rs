@@ -0,0 +1,193 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::path::Path;
+
+use containerd_shim::{api::CreateTaskRequest, io::Stdio, io_error, other, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::fs::{create_dir_all, read, read_dir, remove_file, rename, write};
+
+/// Dir of the task states in the sandbox base dir.
+pub const TASKS_DIR: &str = "tasks";
+
+/// Exit code reported for the tasks whose exit status can not be known,
+/// because their modules were run by a previous wasm-sandboxer process.
+pub const UNKNOWN_EXIT_CODE: i32 = 255;
+
+/// The state of a task saved in the sandbox dir, so that the task service can rebuild
+/// the task after wasm-sandboxer restarts.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct TaskState {
+    pub id: String,
+    pub bundle: String,
+    pub stdin: String,
+    pub stdout: String,
+    pub stderr: String,
+    pub terminal: bool,
+    /// Pid of the process running the module, 0 if the task is not started yet.
+    pub pid: i32,
+    /// Start time of the process in clock ticks after boot, to tell it from a process reusing the pid.
+    pub start_time: u64,
+}
+
+impl TaskState {
+    pub fn new(req: &CreateTaskRequest) -> Self {
+        Self {
+            id: req.id().to_string(),
+            bundle: req.bundle().to_string(),
+            stdin: req.stdin().to_string(),
+            stdout: req.stdout().to_string(),
+            stderr: req.stderr().to_string(),
+            terminal: req.terminal(),
+            pid: 0,
+            start_time: 0,
+        }
+    }
+
+    pub fn stdio(&self) -> Stdio {
+        Stdio::new(&self.stdin, &self.stdout, &self.stderr, self.terminal)
+    }
+
+    /// Record that the module of the task is run by the process `pid`.
+    pub fn started(&mut self, pid: i32) -> Result<()> {
+        self.start_time = process_start_time(pid)
+            .ok_or_else(|| other!("failed to get start time of process {}", pid))?;
+        self.pid = pid;
+        Ok(())
+    }
+
+    /// Whether the process running the module is still alive.
+    pub fn is_running(&self) -> bool {
+        self.pid > 0 && process_start_time(self.pid) == Some(self.start_time)
+    }
+
+    pub async fn save(&self, dir: &str) -> Result<()> {
+        create_dir_all(dir)
+            .await
+            .map_err(io_error!(e, "failed to create {}", dir))?;
+        let content = serde_json::to_vec(self)
+            .map_err(|e| other!("failed to serialize state of task {}: {}", self.id, e))?;
+        // write to a temp file and rename it so that a partially written state is never loaded
+        let path = Path::new(dir).join(format!("{}.json", self.id));
+        let tmp_path = path.with_extension("json.tmp");
+        let tmp = tmp_path.display();
+        write(&tmp_path, content)
+            .await
+            .map_err(io_error!(e, "failed to write {}", tmp))?;
+        rename(&tmp_path, &path)
+            .await
+            .map_err(io_error!(e, "failed to rename {}", tmp))?;
+        Ok(())
+    }
+
+    pub async fn remove(dir: &str, id: &str) -> Result<()> {
+        let path = Path::new(dir).join(format!("{}.json", id));
+        match remove_file(&path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(other!("failed to remove {}: {}", path.display(), e))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Load all the task states saved in `dir`, the broken ones are skipped.
+    pub async fn load_all(dir: &str) -> Vec<TaskState> {
+        let mut states = vec![];
+        let mut entries = match read_dir(dir).await {
+            Ok(entries) => entries,
+            Err(_) => return states,
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if path.extension().map(|x| x != "json").unwrap_or(true) {
+                continue;
+            }
+            match read(&path).await.map(|c| serde_json::from_slice(&c)) {
+                Ok(Ok(state)) => states.push(state),
+                Ok(Err(e)) => warn!("failed to parse task state {}: {}", path.display(), e),
+                Err(e) => warn!("failed to read task state {}: {}", path.display(), e),
+            }
+        }
+        states
+    }
+}
+
+pub fn process_start_time(pid: i32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    parse_start_time(&stat)
+}
+
+// The start time is the 22nd field of /proc/<pid>/stat, the command name as the 2nd field
+// is in parentheses and may contain spaces, so the fields are counted after its last ')'.
+fn parse_start_time(stat: &str) -> Option<u64> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::state::{parse_start_time, process_start_time, TaskState};
+
+    #[test]
+    fn test_parse_start_time() {
+        let stat = "1234 (wasm sandboxer) S 1 1234 1234 0 -1 4194560 2105 0 0 0 7 3 0 0 20 0 \
+                    12 0 98765 1210630144 3208 18446744073709551615";
+        assert_eq!(parse_start_time(stat), Some(98765));
+        let stat = "1234 (a) b) S 1 1234 1234 0 -1 4194560 2105 0 0 0 7 3 0 0 20 0 12 0 42 0";
+        assert_eq!(parse_start_time(stat), Some(42));
+        assert_eq!(parse_start_time("1234 (short) S 1"), None);
+    }
+
+    #[test]
+    fn test_task_state_is_running() {
+        let mut state = TaskState::default();
+        assert!(!state.is_running());
+
+        state.started(std::process::id() as i32).unwrap();
+        assert!(state.is_running());
+
+        // the pid is reused by another process
+        state.start_time += 1;
+        assert!(!state.is_running());
+        assert!(process_start_time(-1).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_save_and_load_task_states() {
+        let tmp_dir = tempdir().unwrap();
+        let dir = tmp_dir.path().join("tasks").to_str().unwrap().to_string();
+        assert!(TaskState::load_all(&dir).await.is_empty());
+
+        let state = TaskState {
+            id: "container1".to_string(),
+            bundle: "/run/bundle/container1".to_string(),
+            stdout: "/run/io/stdout".to_string(),
+            pid: 100,
+            start_time: 200,
+            ..Default::default()
+        };
+        state.save(&dir).await.unwrap();
+        std::fs::write(format!("{}/broken.json", dir), "{").unwrap();
+        assert_eq!(TaskState::load_all(&dir).await, vec![state]);
+
+        TaskState::remove(&dir, "container1").await.unwrap();
+        TaskState::remove(&dir, "container1").await.unwrap();
+        assert!(TaskState::load_all(&dir).await.is_empty());
+    }
+}
@@ -98,7 +98,6 @@ pub(crate) fn get_rootfs(spec: &Spec) -> Option<String> {
         .map(|root| root.path().display().to_string())
 }
 
-#[cfg(feature = "wasmedge")]
 pub(crate) fn get_cgroup_path(spec: &Spec) -> Option<String> {
     spec.linux()
         .as_ref()
@@ -0,0 +1,261 @@
+/*
+Copyright 2025 The Kuasar Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::{
+    fs::{canonicalize, create_dir_all, symlink_metadata, File},
+    path::{Component, Path, PathBuf},
+};
+
+use containerd_shim::{error::Error, io_error, other, Result};
+use log::{debug, warn};
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
+use oci_spec::runtime::Spec;
+
+/// A bind mount of the spec, like the volumes of hostPath, emptyDir, configMap and secret.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VolumeMount {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    pub readonly: bool,
+}
+
+/// Get the bind mounts of the spec, the parents are placed before the mounts inside them.
+pub fn get_volume_mounts(spec: &Spec) -> Vec<VolumeMount> {
+    let mut volumes = vec![];
+    for m in spec.mounts().as_deref().unwrap_or_default() {
+        let options = m.options().as_deref().unwrap_or_default();
+        let is_bind = m.typ().as_ref().map(|t| t == "bind").unwrap_or_default()
+            || options.iter().any(|o| o == "bind" || o == "rbind");
+        match m.source() {
+            Some(source) if is_bind => volumes.push(VolumeMount {
+                source: source.clone(),
+                destination: m.destination().clone(),
+                readonly: options.iter().any(|o| o == "ro"),
+            }),
+            // filesystems like proc and tmpfs are meaningless to wasi
+            _ => debug!("skip mount {} of wasm container", m.destination().display()),
+        }
+    }
+    volumes.sort_by_key(|v| v.destination.components().count());
+    volumes
+}
+
+/// Bind the volumes to their destinations in the rootfs, so that the module sees the volumes
+/// through the preopened dirs, a file is bound on a placeholder file in the rootfs as wasi can
+/// only preopen dirs. The readonly volumes are remounted readonly, as the preopened dirs of wasi
+/// are always writable.
+pub fn mount_volumes(rootfs: &str, volumes: &[VolumeMount]) -> Result<()> {
+    for (i, v) in volumes.iter().enumerate() {
+        if let Err(e) = mount_volume(Path::new(rootfs), v) {
+            umount_volumes(rootfs, &volumes[..i]);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Unmount the volumes from the rootfs, the mounts inside the others are unmounted first.
+pub fn umount_volumes(rootfs: &str, volumes: &[VolumeMount]) {
+    for v in volumes.iter().rev() {
+        let target = match target_in_rootfs(Path::new(rootfs), &v.destination) {
+            Ok(t) => t,
+            Err(_) => continue,
+        };
+        if let Err(e) = umount2(&target, MntFlags::MNT_DETACH) {
+            warn!("failed to umount volume {}: {}", target.display(), e);
+        }
+    }
+}
+
+fn mount_volume(rootfs: &Path, v: &VolumeMount) -> Result<()> {
+    let source = v.source.display();
+    let is_dir = v
+        .source
+        .metadata()
+        .map_err(io_error!(e, "failed to stat volume source {}", source))?
+        .is_dir();
+    let target = prepare_target(rootfs, &v.destination, is_dir)?;
+    debug!("bind volume {} to {}", source, target.display());
+    mount(
+        Some(&v.source),
+        &target,
+        None::<&str>,
+        MsFlags::MS_BIND | MsFlags::MS_REC,
+        None::<&str>,
+    )
+    .map_err(|e| other!("failed to bind {} to {}: {}", source, target.display(), e))?;
+    if v.readonly {
+        let flags = MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY;
+        if let Err(e) = mount(None::<&str>, &target, None::<&str>, flags, None::<&str>) {
+            umount2(&target, MntFlags::MNT_DETACH).unwrap_or_default();
+            return Err(other!(
+                "failed to remount {} readonly: {}",
+                target.display(),
+                e
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// The path of the destination in the rootfs, `..` is not allowed to escape the rootfs.
+pub fn target_in_rootfs(rootfs: &Path, destination: &Path) -> Result<PathBuf> {
+    let mut target = rootfs.to_path_buf();
+    for c in destination.components() {
+        match c {
+            Component::Normal(name) => target.push(name),
+            Component::RootDir | Component::CurDir => {}
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "invalid mount destination {}",
+                    destination.display()
+                )))
+            }
+        }
+    }
+    if target == rootfs {
+        return Err(Error::InvalidArgument(
+            "mount destination can not be the rootfs".to_string(),
+        ));
+    }
+    Ok(target)
+}
+
+// Create the mount point of the destination in the rootfs, the symlinks in the rootfs are
+// resolved and must not lead outside of it.
+fn prepare_target(rootfs: &Path, destination: &Path, is_dir: bool) -> Result<PathBuf> {
+    let target = target_in_rootfs(rootfs, destination)?;
+    let parent = target.parent().unwrap_or(rootfs);
+    let parent_dir = parent.display();
+    create_dir_all(parent).map_err(io_error!(e, "failed to create {}", parent_dir))?;
+    let real_rootfs = canonicalize(rootfs).map_err(io_error!(
+        e,
+        "failed to canonicalize {}",
+        rootfs.display()
+    ))?;
+    let real_parent =
+        canonicalize(parent).map_err(io_error!(e, "failed to canonicalize {}", parent_dir))?;
+    if !real_parent.starts_with(&real_rootfs) {
+        return Err(Error::InvalidArgument(format!(
+            "mount destination {} is out of rootfs",
+            destination.display()
+        )));
+    }
+    // the file name always exists as target is not the rootfs
+    let target = real_parent.join(target.file_name().unwrap_or_default());
+    let path = target.display();
+    match symlink_metadata(&target) {
+        Ok(m) if m.file_type().is_symlink() => {
+            return Err(Error::InvalidArgument(format!(
+                "mount destination {} is a symlink",
+                destination.display()
+            )));
+        }
+        Ok(_) => {}
+        Err(_) if is_dir => {
+            create_dir_all(&target).map_err(io_error!(e, "failed to create {}", path))?;
+        }
+        Err(_) => {
+            File::create(&target).map_err(io_error!(e, "failed to create {}", path))?;
+        }
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use oci_spec::runtime::{MountBuilder, Spec};
+    use tempfile::tempdir;
+
+    use crate::volume::{get_volume_mounts, prepare_target, target_in_rootfs, VolumeMount};
+
+    #[test]
+    fn test_get_volume_mounts() {
+        let mounts = vec![
+            MountBuilder::default()
+                .destination("/data/config")
+                .typ("bind")
+                .source("/var/lib/kubelet/pods/1/volumes/configmap")
+                .options(vec!["rbind".to_string(), "ro".to_string()])
+                .build()
+                .unwrap(),
+            MountBuilder::default()
+                .destination("/proc")
+                .typ("proc")
+                .source("proc")
+                .build()
+                .unwrap(),
+            MountBuilder::default()
+                .destination("/data")
+                .source("/var/lib/kubelet/pods/1/volumes/empty-dir")
+                .options(vec!["rbind".to_string(), "rw".to_string()])
+                .build()
+                .unwrap(),
+        ];
+        let mut spec = Spec::default();
+        spec.set_mounts(Some(mounts));
+        assert_eq!(
+            get_volume_mounts(&spec),
+            vec![
+                VolumeMount {
+                    source: "/var/lib/kubelet/pods/1/volumes/empty-dir".into(),
+                    destination: "/data".into(),
+                    readonly: false,
+                },
+                VolumeMount {
+                    source: "/var/lib/kubelet/pods/1/volumes/configmap".into(),
+                    destination: "/data/config".into(),
+                    readonly: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_target_in_rootfs() {
+        let rootfs = Path::new("/run/rootfs");
+        assert_eq!(
+            target_in_rootfs(rootfs, Path::new("/etc/hosts")).unwrap(),
+            Path::new("/run/rootfs/etc/hosts")
+        );
+        assert_eq!(
+            target_in_rootfs(rootfs, Path::new("data/./config")).unwrap(),
+            Path::new("/run/rootfs/data/config")
+        );
+        assert!(target_in_rootfs(rootfs, Path::new("/data/../../etc")).is_err());
+        assert!(target_in_rootfs(rootfs, Path::new("/")).is_err());
+    }
+
+    #[test]
+    fn test_prepare_target() {
+        let tmp_dir = tempdir().unwrap();
+        let rootfs = tmp_dir.path().join("rootfs");
+        std::fs::create_dir(&rootfs).unwrap();
+
+        let target = prepare_target(&rootfs, Path::new("/etc/hosts"), false).unwrap();
+        assert!(target.is_file());
+        let target = prepare_target(&rootfs, Path::new("/data/config"), true).unwrap();
+        assert!(target.is_dir());
+
+        // symlinks leading out of the rootfs
+        std::os::unix::fs::symlink(tmp_dir.path(), rootfs.join("escape")).unwrap();
+        assert!(prepare_target(&rootfs, Path::new("/escape/config"), true).is_err());
+        std::os::unix::fs::symlink("/etc/passwd", rootfs.join("etc/passwd")).unwrap();
+        assert!(prepare_target(&rootfs, Path::new("/etc/passwd"), false).is_err());
+    }
+}
@@ -17,8 +17,10 @@ limitations under the License.
 use std::{
     fs::OpenOptions,
     os::unix::prelude::{IntoRawFd, RawFd},
+    path::{Path, PathBuf},
     process::exit,
     sync::Arc,
+    time::Duration,
 };
 
 use cgroups_rs::{Cgroup, CgroupPid};
@@ -26,20 +28,21 @@ use containerd_shim::{
     api::{CreateTaskRequest, ExecProcessRequest, Status},
     asynchronous::{
         container::{ContainerFactory, ContainerTemplate, ProcessFactory},
-        monitor::{monitor_subscribe, monitor_unsubscribe},
+        monitor::{monitor_notify_by_pid, monitor_subscribe, monitor_unsubscribe},
         processes::{ProcessLifecycle, ProcessTemplate},
         task::TaskService,
         util::{mkdir, mount_rootfs, read_spec},
     },
     error::Error,
     io::Stdio,
+    io_error,
     monitor::{Subject, Topic},
     other, other_error,
     processes::Process,
     protos::{cgroups::metrics::Metrics, shim::oci::Options, types::task::ProcessInfo},
     ExitSignal,
 };
-use log::debug;
+use log::{debug, error, warn};
 use nix::{
     errno::Errno,
     fcntl::OFlag,
@@ -49,15 +52,25 @@ use nix::{
 };
 use oci_spec::runtime::Spec;
 use wasmedge_sdk::{
-    config::{CommonConfigOptions, ConfigBuilder, HostRegistrationConfigOptions},
+    config::{
+        CommonConfigOptions, CompilerConfigOptions, ConfigBuilder, HostRegistrationConfigOptions,
+    },
     error::WasmEdgeError,
     params,
     plugin::PluginManager,
     wasi::WasiInstance,
-    Vm, VmBuilder,
+    Compiler, CompilerOutputFormat, Vm, VmBuilder,
+};
+
+use crate::{
+    cache::ModuleCache,
+    state::{TaskState, UNKNOWN_EXIT_CODE},
+    utils::{get_args, get_cgroup_path, get_envs, get_preopens, get_rootfs},
+    version::built_info,
 };
 
-use crate::utils::{get_args, get_cgroup_path, get_envs, get_preopens, get_rootfs};
+// Interval to check whether a re-attached process is still running
+const REATTACHED_PROCESS_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 
 pub type ExecProcess = ProcessTemplate<WasmEdgeExecLifecycle>;
 pub type InitProcess = ProcessTemplate<WasmEdgeInitLifecycle>;
@@ -75,11 +88,16 @@ pub struct WasmEdgeInitLifecycle {
     prototype_vm: Vm,
     netns: String,
     _exit_signal: Arc<ExitSignal>,
+    state: TaskState,
+    state_dir: String,
+    module_cache: Arc<ModuleCache>,
 }
 
 pub struct WasmEdgeContainerFactory {
     prototype_vm: Vm,
     pub(crate) netns: String,
+    pub(crate) state_dir: String,
+    pub(crate) module_cache: Arc<ModuleCache>,
 }
 
 impl Default for WasmEdgeContainerFactory {
@@ -99,6 +117,8 @@ impl Default for WasmEdgeContainerFactory {
         Self {
             prototype_vm: vm,
             netns: "".to_string(),
+            state_dir: "".to_string(),
+            module_cache: Default::default(),
         }
     }
 }
@@ -123,6 +143,8 @@ impl ContainerFactory<WasmEdgeContainer> for WasmEdgeContainerFactory {
         let stdio = Stdio::new(req.stdin(), req.stdout(), req.stderr(), req.terminal);
         let exit_signal = Arc::new(Default::default());
         let netns = self.netns.clone();
+        let state = TaskState::new(req);
+        state.save(&self.state_dir).await?;
         let init_process = InitProcess::new(
             req.id(),
             stdio,
@@ -133,6 +155,9 @@ impl ContainerFactory<WasmEdgeContainer> for WasmEdgeContainerFactory {
                 spec,
                 prototype_vm: self.prototype_vm.clone(),
                 netns,
+                state,
+                state_dir: self.state_dir.clone(),
+                module_cache: self.module_cache.clone(),
             },
         );
         Ok(WasmEdgeContainer {
@@ -144,11 +169,79 @@ impl ContainerFactory<WasmEdgeContainer> for WasmEdgeContainerFactory {
         })
     }
 
-    async fn cleanup(&self, _ns: &str, _c: &WasmEdgeContainer) -> containerd_shim::Result<()> {
-        Ok(())
+    async fn cleanup(&self, _ns: &str, c: &WasmEdgeContainer) -> containerd_shim::Result<()> {
+        TaskState::remove(&self.state_dir, &c.id).await
+    }
+}
+
+impl WasmEdgeContainerFactory {
+    /// Rebuild the container of a task created before wasm-sandboxer restarts, the module forked
+    /// by the previous wasm-sandboxer process is re-attached if it is still running.
+    pub(crate) async fn recover(
+        &self,
+        state: &TaskState,
+    ) -> containerd_shim::Result<WasmEdgeContainer> {
+        let mut spec: Spec = read_spec(&state.bundle).await?;
+        spec.canonicalize_rootfs(&state.bundle)
+            .map_err(|e| Error::InvalidArgument(format!("could not canonicalize rootfs: {e}")))?;
+        let mut init_process = InitProcess::new(
+            &state.id,
+            state.stdio(),
+            WasmEdgeInitLifecycle {
+                _opts: Default::default(),
+                _bundle: state.bundle.to_string(),
+                _exit_signal: Arc::new(Default::default()),
+                spec,
+                prototype_vm: self.prototype_vm.clone(),
+                netns: self.netns.clone(),
+                state: state.clone(),
+                state_dir: self.state_dir.clone(),
+                module_cache: self.module_cache.clone(),
+            },
+        );
+        if state.is_running() {
+            debug!("re-attach process {} of container {}", state.pid, state.id);
+            init_process.state = Status::RUNNING;
+            init_process.pid = state.pid;
+            watch_reattached_process(state.clone());
+        } else if state.pid > 0 {
+            warn!(
+                "process {} of container {} exited while wasm-sandboxer was not running, \
+                 report it as exited with {}",
+                state.pid, state.id, UNKNOWN_EXIT_CODE
+            );
+            init_process.set_exited(UNKNOWN_EXIT_CODE).await;
+        }
+        Ok(WasmEdgeContainer {
+            id: state.id.to_string(),
+            bundle: state.bundle.to_string(),
+            init: init_process,
+            process_factory: ExecFactory {},
+            processes: Default::default(),
+        })
     }
 }
 
+// The re-attached process is not a child of this process so that it can not be waited,
+// check it periodically and report its exit with an unknown exit code after it is gone.
+fn watch_reattached_process(state: TaskState) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(REATTACHED_PROCESS_CHECK_INTERVAL).await;
+            if !state.is_running() {
+                break;
+            }
+        }
+        warn!(
+            "re-attached process {} of container {} exited, its exit code is unknown",
+            state.pid, state.id
+        );
+        monitor_notify_by_pid(state.pid, UNKNOWN_EXIT_CODE)
+            .await
+            .unwrap_or_else(|e| error!("failed to send exit event {}", e));
+    });
+}
+
 #[async_trait::async_trait]
 impl ProcessLifecycle<InitProcess> for WasmEdgeInitLifecycle {
     async fn start(&self, p: &mut InitProcess) -> containerd_shim::Result<()> {
@@ -164,6 +257,11 @@ impl ProcessLifecycle<InitProcess> for WasmEdgeInitLifecycle {
         })?;
         let mut preopens = vec![format!("/:{}", rootfs)];
         preopens.append(&mut get_preopens(spec));
+        let mut cmd = args[0].clone();
+        if let Some(stripped_cmd) = args[0].strip_prefix(std::path::MAIN_SEPARATOR) {
+            cmd = stripped_cmd.to_string();
+        }
+        let mod_path = aot_module(&p.lifecycle.module_cache, &Path::new(&rootfs).join(cmd)).await;
 
         debug!(
             "start wasm with args: {:?}, envs: {:?}, preopens: {:?}",
@@ -179,6 +277,13 @@ impl ProcessLifecycle<InitProcess> for WasmEdgeInitLifecycle {
                 let init_pid = child.as_raw();
                 p.state = Status::RUNNING;
                 p.pid = init_pid;
+                // the module keeps running even if it can not be re-attached after restart
+                let mut state = p.lifecycle.state.clone();
+                if let Err(e) = state.started(init_pid) {
+                    warn!("failed to get state of process {}: {}", init_pid, e);
+                } else if let Err(e) = state.save(&p.lifecycle.state_dir).await {
+                    warn!("failed to save state of container {}: {}", p.id, e);
+                }
             }
             ForkResult::Child => {
                 if let Some(cgroup_path) = get_cgroup_path(spec) {
@@ -232,7 +337,7 @@ impl ProcessLifecycle<InitProcess> for WasmEdgeInitLifecycle {
                         .build()
                         .unwrap();
                 }
-                match run_wasi_func(vm, args, envs, preopens, p) {
+                match run_wasi_func(vm, args, envs, preopens, &mod_path, p) {
                     Ok(_) => exit(0),
                     // TODO add a pipe? to return detailed error message
                     Err(e) => exit(e.to_exit_code()),
@@ -376,10 +481,67 @@ pub fn maybe_open_stdio(path: &str) -> Result<Option<RawFd>, std::io::Error> {
     }
 }
 
+// Get the AOT compiled module from the cache, or compile the module in the background and run it
+// by the interpreter this time. The module is compiled into the universal wasm format, which
+// falls back to the interpreter if the compiled code is not compatible with the runtime.
+async fn aot_module(cache: &Arc<ModuleCache>, mod_path: &Path) -> PathBuf {
+    if !cache.is_enabled() {
+        return mod_path.to_path_buf();
+    }
+    let module_data = match tokio::fs::read(mod_path).await {
+        Ok(data) => data,
+        // let the module fail to load in the child process
+        Err(_) => return mod_path.to_path_buf(),
+    };
+    let path = cache.artifact_path(&module_data, &engine_key());
+    if cache.get(&path) {
+        debug!("load compiled module from {}", path.display());
+        return path;
+    }
+    let (cache, module) = (cache.clone(), mod_path.to_path_buf());
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = cache.put(&path, |tmp| aot_compile(&module, tmp)) {
+            warn!(
+                "failed to cache compiled module {}: {}",
+                module.display(),
+                e
+            );
+        }
+    });
+    mod_path.to_path_buf()
+}
+
+fn aot_compile(mod_path: &Path, out_path: &Path) -> containerd_shim::Result<()> {
+    let compiler_options = CompilerConfigOptions::default().out_format(CompilerOutputFormat::Wasm);
+    let config = ConfigBuilder::new(CommonConfigOptions::default())
+        .with_compiler_config(compiler_options)
+        .build()
+        .map_err(other_error!(e, "failed to build compiler config"))?;
+    let compiler =
+        Compiler::new(Some(&config)).map_err(other_error!(e, "failed to new compiler"))?;
+    let out_dir = out_path.parent().unwrap_or(Path::new("/"));
+    let out_name = out_path.file_name().unwrap_or_default().to_string_lossy();
+    // the compiler names the output file by itself
+    let compiled = compiler
+        .compile_from_file(mod_path, out_name, out_dir)
+        .map_err(other_error!(e, "failed to compile module"))?;
+    let file = compiled.display();
+    std::fs::rename(&compiled, out_path).map_err(io_error!(e, "failed to rename {}", file))
+}
+
+// The key of the engine in the module cache, it changes with the version of wasmedge.
+fn engine_key() -> String {
+    let version = built_info::DEPENDENCIES
+        .iter()
+        .find(|(name, _)| *name == "wasmedge-sys")
+        .map(|(_, version)| *version)
+        .unwrap_or_default();
+    format!("wasmedge-{}-universal", version)
+}
+
 pub enum RunError {
     WasmEdge(Box<WasmEdgeError>),
     IO(std::io::Error),
-    NoRootInSpec,
     NoModule,
     Sys(Errno),
 }
@@ -389,7 +551,6 @@ impl RunError {
         match &self {
             RunError::WasmEdge(_e) => -100,
             RunError::IO(_e) => -101,
-            RunError::NoRootInSpec => -102,
             RunError::NoModule => -103,
             RunError::Sys(e) => -(*e as i32),
         }
@@ -401,6 +562,7 @@ fn run_wasi_func(
     args: Vec<String>,
     envs: Vec<String>,
     preopens: Vec<String>,
+    mod_path: &Path,
     p: &InitProcess,
 ) -> Result<(), RunError> {
     let netns = &*p.lifecycle.netns;
@@ -415,21 +577,8 @@ fn run_wasi_func(
         Some(envs.iter().map(|s| s as &str).collect()),
         Some(preopens.iter().map(|s| s as &str).collect()),
     );
-    let mut cmd = args[0].clone();
-    let stripped = args[0].strip_prefix(std::path::MAIN_SEPARATOR);
-    if let Some(stripped_cmd) = stripped {
-        cmd = stripped_cmd.to_string()
-    }
     let stdio = p.stdio.clone();
 
-    let rootfs = p
-        .lifecycle
-        .spec
-        .root()
-        .as_ref()
-        .ok_or(RunError::NoRootInSpec)?
-        .path();
-    let mod_path = rootfs.join(cmd);
     let vm = vm
         .register_module_from_file("main", mod_path)
         .map_err(RunError::WasmEdge)?;
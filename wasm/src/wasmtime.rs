@@ -14,8 +14,17 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::{path::Path, sync::Arc};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::Path,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
 
+use anyhow::anyhow;
 use containerd_shim::{
     api::{CreateTaskRequest, ExecProcessRequest},
     container::{ContainerFactory, ContainerTemplate, ProcessFactory},
@@ -31,12 +40,28 @@ use containerd_shim::{
     ExitSignal,
 };
 use log::{debug, trace, warn};
+use nix::libc::{SIGABRT, SIGKILL};
 use oci_spec::runtime::{LinuxResources, Spec};
-use tokio::fs::read;
-use wasmtime::{Config, Engine, Extern, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
-use wasmtime_wasi::{tokio::WasiCtxBuilder, WasiCtx};
+use tokio::{fs::read, io::AsyncWriteExt};
+use wasmtime::{
+    Config, Engine, Extern, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, Trap,
+};
+use wasmtime_wasi::{tokio::WasiCtxBuilder, I32Exit, WasiCtx};
+
+use crate::{
+    cache::ModuleCache,
+    cpu::{spawn_epoch_ticker, CpuLimit, ModuleThreadPool, Throttled},
+    state::{TaskState, UNKNOWN_EXIT_CODE},
+    utils::{get_args, get_cgroup_path, get_kv_envs, get_memory_limit, get_rootfs},
+    volume::{get_volume_mounts, mount_volumes, target_in_rootfs, umount_volumes},
+};
 
-use crate::utils::{get_args, get_kv_envs, get_memory_limit, get_rootfs};
+/// Exit code of a module terminated by a trap, the same as the wasmtime cli, as if it was aborted.
+const TRAP_EXIT_CODE: i32 = 128 + SIGABRT;
+/// Exit code of a module failed by an error of the host functions.
+const ERROR_EXIT_CODE: i32 = 1;
+/// Where kubernetes reads the termination message of a container by default.
+const TERMINATION_MESSAGE_PATH: &str = "/dev/termination-log";
 
 pub type ExecProcess = ProcessTemplate<WasmtimeExecLifecycle>;
 pub type InitProcess = ProcessTemplate<WasmtimeInitLifecycle>;
@@ -53,11 +78,21 @@ pub struct WasmtimeInitLifecycle {
     _netns: String,
     _exit_signal: Arc<ExitSignal>,
     engine: Engine,
+    state: TaskState,
+    state_dir: String,
+    // the signal of the last kill, the module is interrupted once it is set, but the signal is
+    // never delivered, the exit code tells which signal killed the module.
+    kill_signal: Arc<AtomicU32>,
+    module_cache: Arc<ModuleCache>,
+    module_threads: usize,
 }
 
 #[derive(Default)]
 pub struct WasmtimeContainerFactory {
     pub(crate) netns: String,
+    pub(crate) state_dir: String,
+    pub(crate) module_cache: Arc<ModuleCache>,
+    pub(crate) module_threads: usize,
 }
 
 struct WasmtimeContainerData {
@@ -82,11 +117,32 @@ impl ContainerFactory<WasmtimeContainer> for WasmtimeContainerFactory {
         for m in req.rootfs() {
             mount_rootfs(m, &rootfs).await?
         }
+        let volumes = get_volume_mounts(&spec);
+        mount_volumes(&rootfs, &volumes)?;
         let stdio = Stdio::new(req.stdin(), req.stdout(), req.stderr(), req.terminal);
         let exit_signal = Arc::new(Default::default());
         let netns = self.netns.clone();
-        let lifecycle =
-            WasmtimeInitLifecycle::new(&spec, exit_signal, &netns, req.bundle()).await?;
+        let state = TaskState::new(req);
+        let lifecycle = async {
+            let lifecycle = WasmtimeInitLifecycle::new(
+                &spec,
+                exit_signal,
+                &netns,
+                state.clone(),
+                &self.state_dir,
+                self.module_cache.clone(),
+                self.module_threads,
+            )
+            .await?;
+            state.save(&self.state_dir).await?;
+            Ok::<_, Error>(lifecycle)
+        }
+        .await
+        .map_err(|e| {
+            // cleanup is not called if the container is not created, so umount the volumes here
+            umount_volumes(&rootfs, &volumes);
+            e
+        })?;
         let init_process = InitProcess::new(req.id(), stdio, lifecycle);
         Ok(WasmtimeContainer {
             id: req.id.to_string(),
@@ -97,8 +153,52 @@ impl ContainerFactory<WasmtimeContainer> for WasmtimeContainerFactory {
         })
     }
 
-    async fn cleanup(&self, _ns: &str, _c: &WasmtimeContainer) -> containerd_shim::Result<()> {
-        Ok(())
+    async fn cleanup(&self, _ns: &str, c: &WasmtimeContainer) -> containerd_shim::Result<()> {
+        let spec = &c.init.lifecycle.spec;
+        if let Some(rootfs) = get_rootfs(spec) {
+            umount_volumes(&rootfs, &get_volume_mounts(spec));
+        }
+        TaskState::remove(&self.state_dir, &c.id).await
+    }
+}
+
+impl WasmtimeContainerFactory {
+    /// Rebuild the container of a task created before wasm-sandboxer restarts. A started module
+    /// was run inside the previous wasm-sandboxer process, so it is gone and can only be reported
+    /// as exited.
+    pub(crate) async fn recover(
+        &self,
+        state: &TaskState,
+    ) -> containerd_shim::Result<WasmtimeContainer> {
+        let mut spec: Spec = read_spec(&state.bundle).await?;
+        spec.canonicalize_rootfs(&state.bundle)
+            .map_err(|e| Error::InvalidArgument(format!("could not canonicalize rootfs: {e}")))?;
+        let lifecycle = WasmtimeInitLifecycle::new(
+            &spec,
+            Arc::new(Default::default()),
+            &self.netns,
+            state.clone(),
+            &self.state_dir,
+            self.module_cache.clone(),
+            self.module_threads,
+        )
+        .await?;
+        let mut init_process = InitProcess::new(&state.id, state.stdio(), lifecycle);
+        if state.pid > 0 {
+            warn!(
+                "module of container {} was run by the previous wasm-sandboxer process, \
+                 report it as exited with {}",
+                state.id, UNKNOWN_EXIT_CODE
+            );
+            init_process.set_exited(UNKNOWN_EXIT_CODE).await;
+        }
+        Ok(WasmtimeContainer {
+            id: state.id.to_string(),
+            bundle: state.bundle.to_string(),
+            init: init_process,
+            process_factory: ExecFactory {},
+            processes: Default::default(),
+        })
     }
 }
 
@@ -107,18 +207,26 @@ impl WasmtimeInitLifecycle {
         spec: &Spec,
         exit_signal: Arc<ExitSignal>,
         netns: &str,
-        bundle: &str,
+        state: TaskState,
+        state_dir: &str,
+        module_cache: Arc<ModuleCache>,
+        module_threads: usize,
     ) -> containerd_shim::Result<Self> {
         let mut config = Config::new();
         config.async_support(true).epoch_interruption(true);
         let engine = Engine::new(&config).map_err(other_error!(e, "failed to new engine"))?;
 
         let res = Self {
-            _bundle: bundle.to_string(),
+            _bundle: state.bundle.to_string(),
             spec: spec.clone(),
             _netns: netns.to_string(),
             _exit_signal: exit_signal,
             engine,
+            state,
+            state_dir: state_dir.to_string(),
+            kill_signal: Arc::new(AtomicU32::new(0)),
+            module_cache,
+            module_threads,
         };
         Ok(res)
     }
@@ -160,20 +268,25 @@ impl ProcessLifecycle<InitProcess> for WasmtimeInitLifecycle {
         }
         trace!("set rootfs {} to guest", root.display());
         if root.exists() && root.is_dir() {
-            // TODO support file mount, and filesystem mount
-            let preopen_dir = cap_std::fs::Dir::from_std_file(
-                tokio::fs::File::open(root)
-                    .await
-                    .map_err(io_error!(e, "failed to open rootfs {}", root.display()))?
-                    .into_std()
-                    .await,
-            );
             builder = builder
-                .preopened_dir(preopen_dir, "/")
+                .preopened_dir(open_preopen_dir(root).await?, "/")
                 .map_err(other_error!(e, "failed to set preopened_dir"))?;
         } else {
             warn!("rootfs should be a directory");
         }
+        // the volumes are bound into the rootfs when the container is created, the files are seen
+        // through the preopened dirs of their parents, and the dirs are preopened as well so that
+        // they are resolved to the volumes directly.
+        for v in get_volume_mounts(&self.spec) {
+            let target = target_in_rootfs(root, &v.destination)?;
+            if !target.is_dir() {
+                continue;
+            }
+            trace!("set volume {} to guest", v.destination.display());
+            builder = builder
+                .preopened_dir(open_preopen_dir(&target).await?, &v.destination)
+                .map_err(other_error!(e, "failed to set preopened_dir"))?;
+        }
         let ctx = builder.build();
 
         let mut limits_builder = StoreLimitsBuilder::new();
@@ -184,7 +297,9 @@ impl ProcessLifecycle<InitProcess> for WasmtimeInitLifecycle {
 
         let mut store = Store::new(&self.engine, WasmtimeContainerData { ctx, limiter });
         store.limiter(|x| &mut x.limiter);
-        store.set_epoch_deadline(1);
+        // the module yields when the epoch is increased by the ticker or kill, so that it can be
+        // throttled or interrupted
+        store.epoch_deadline_async_yield_and_update(1);
         debug!("start wasmtime container {}", p.id);
 
         let mut cmd = args[0].clone();
@@ -199,11 +314,12 @@ impl ProcessLifecycle<InitProcess> for WasmtimeInitLifecycle {
             module_path.display()
         ))?;
 
-        // Compilation of modules happens here, it will use rayon to parallelize the compilation tasks,
-        // rayon init as many threads as the cpu count, and each thread has a stack size of 4MB.
+        // Compilation of modules happens here if the module is not cached, it will use rayon to
+        // parallelize the compilation tasks, rayon init as many threads as the cpu count, and each
+        // thread has a stack size of 4MB.
         // if we run this on a machine with 100 cpus, 400MB memories will be consumed for these stacks
         // if it is necessary to limit this memory, set env of RAYON_NUM_THREADS to a smaller number.
-        let module = Module::new(&self.engine, module_data).map_err(other_error!(e, ""))?;
+        let module = load_module(&self.engine, module_data, &self.module_cache).await?;
         let mut linker = Linker::new(&self.engine);
         wasmtime_wasi::tokio::add_to_linker(&mut linker, |cx: &mut WasmtimeContainerData| {
             &mut cx.ctx
@@ -223,26 +339,54 @@ impl ProcessLifecycle<InitProcess> for WasmtimeInitLifecycle {
             }
         };
 
+        // the module runs in this process, record it so that the task is known as started
+        let mut state = self.state.clone();
+        state.started(std::process::id() as i32)?;
+        state.save(&self.state_dir).await?;
+
         let id_clone = p.id.clone();
+        let stderr = p.stdio.stderr.clone();
+        let spec = self.spec.clone();
+        let kill_signal = self.kill_signal.clone();
+        let pool = if self.module_threads > 0 {
+            let cgroup_path = get_cgroup_path(&self.spec);
+            Some(ModuleThreadPool::new(self.module_threads, cgroup_path)?)
+        } else {
+            None
+        };
+        let cpu_limit = CpuLimit::from_spec(&self.spec);
+        let ticker = cpu_limit
+            .as_ref()
+            .map(|_| spawn_epoch_ticker(self.engine.clone()));
+        let module = Throttled::new(
+            async move { func.call_async(&mut store, &[], &mut []).await },
+            cpu_limit,
+            kill_signal.clone(),
+        );
         tokio::spawn(async move {
-            match func.call_async(&mut store, &[], &mut []).await {
-                Ok(_) => {
-                    debug!("function of container {} finished successfully", id_clone);
-                    monitor_notify_by_exec(&id_clone, "", 0)
-                        .await
-                        .unwrap_or_default();
-                }
-                Err(e) => {
-                    debug!(
-                        "function of container {} finished with error {}",
-                        id_clone, e
-                    );
-                    // TODO add exit code for wasmtime
-                    monitor_notify_by_exec(&id_clone, "", -1)
-                        .await
-                        .unwrap_or_default();
-                }
+            let res = match &pool {
+                Some(pool) => pool
+                    .spawn(module)
+                    .await
+                    .unwrap_or_else(|e| Err(anyhow!("module task failed: {}", e))),
+                None => module.await,
+            };
+            if let Some(ticker) = ticker {
+                ticker.abort();
             }
+            drop(pool);
+            let (exit_code, reason) = exit_status(&res, kill_signal.load(Ordering::SeqCst));
+            debug!(
+                "function of container {} finished with exit code {}",
+                id_clone, exit_code
+            );
+            if let Some(reason) = reason {
+                warn!("module of container {} died: {}", id_clone, reason);
+                report_termination(&spec, &stderr, &reason).await;
+            }
+            monitor_notify_by_exec(&id_clone, "", exit_code)
+                .await
+                .unwrap_or_default();
         });
         Ok(())
     }
@@ -250,14 +394,19 @@ impl ProcessLifecycle<InitProcess> for WasmtimeInitLifecycle {
     async fn kill(
         &self,
         _p: &mut InitProcess,
-        _signal: u32,
+        signal: u32,
         _all: bool,
     ) -> containerd_shim::Result<()> {
+        self.kill_signal.store(signal, Ordering::SeqCst);
         self.engine.increment_epoch();
         Ok(())
     }
 
     async fn delete(&self, _p: &mut InitProcess) -> containerd_shim::Result<()> {
+        // interrupt the module if it is still running as if it is killed
+        self.kill_signal
+            .compare_exchange(0, SIGKILL as u32, Ordering::SeqCst, Ordering::SeqCst)
+            .unwrap_or_default();
         self.engine.increment_epoch();
         Ok(())
     }
@@ -340,6 +489,64 @@ impl ProcessFactory<ExecProcess> for ExecFactory {
     }
 }
 
+// Load the compiled module from the cache, or compile the module and add it to the cache in the
+// background so that the next containers of the module start without compilation.
+async fn load_module(
+    engine: &Engine,
+    module_data: Vec<u8>,
+    cache: &Arc<ModuleCache>,
+) -> containerd_shim::Result<Module> {
+    if !cache.is_enabled() {
+        return Module::new(engine, module_data).map_err(other_error!(e, ""));
+    }
+    let path = cache.artifact_path(&module_data, &engine_key(engine));
+    if cache.get(&path) {
+        // Safety: the artifacts are serialized by wasm-sandboxer into the cache dir that only root
+        // can access, and wasmtime checks if they are compatible with the engine before loading.
+        match unsafe { Module::deserialize_file(engine, &path) } {
+            Ok(module) => {
+                debug!("load compiled module from {}", path.display());
+                return Ok(module);
+            }
+            Err(e) => {
+                warn!("failed to load compiled module {}: {}", path.display(), e);
+                cache.remove(&path);
+            }
+        }
+    }
+    let module = Module::new(engine, module_data).map_err(other_error!(e, ""))?;
+    let (cache, compiled) = (cache.clone(), module.clone());
+    tokio::task::spawn_blocking(move || {
+        let res = cache.put(&path, |tmp| {
+            let artifact = compiled
+                .serialize()
+                .map_err(other_error!(e, "failed to serialize module"))?;
+            let file = tmp.display();
+            std::fs::write(tmp, artifact).map_err(io_error!(e, "failed to write {}", file))
+        });
+        if let Err(e) = res {
+            warn!("failed to cache compiled module: {}", e);
+        }
+    });
+    Ok(module)
+}
+
+// The key of the engine in the module cache, it changes with the version and config of wasmtime.
+fn engine_key(engine: &Engine) -> String {
+    let mut hasher = DefaultHasher::new();
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+    format!("wasmtime-{:x}", hasher.finish())
+}
+
+async fn open_preopen_dir(path: &Path) -> containerd_shim::Result<cap_std::fs::Dir> {
+    let dir = path.display();
+    let file =
+        tokio::fs::File::open(path)
+            .await
+            .map_err(io_error!(e, "failed to open dir {}", dir))?;
+    Ok(cap_std::fs::Dir::from_std_file(file.into_std().await))
+}
+
 async fn open_wasi_cap_std_file<T: AsRef<Path>>(
     path: T,
     read: bool,
@@ -368,6 +575,68 @@ async fn open_wasi_cap_std_file<T: AsRef<Path>>(
     ))
 }
 
+/// Map the result of the `_start` function to the exit code of the module, with the reason
+/// if the module did not exit by itself.
+fn exit_status(res: &anyhow::Result<()>, kill_signal: u32) -> (i32, Option<String>) {
+    let e = match res {
+        Ok(_) => return (0, None),
+        Err(e) => e,
+    };
+    // proc_exit of wasi is returned as an error to unwind the module
+    if let Some(exit) = e.downcast_ref::<I32Exit>() {
+        return (exit.0, None);
+    }
+    match e.downcast_ref::<Trap>() {
+        // the module is interrupted by kill or delete, report it as killed by the signal
+        Some(Trap::Interrupt) => {
+            let signal = if kill_signal == 0 {
+                SIGKILL
+            } else {
+                kill_signal as i32
+            };
+            (128 + signal, None)
+        }
+        Some(_) => (TRAP_EXIT_CODE, Some(format!("wasm trap: {:?}", e))),
+        None => (ERROR_EXIT_CODE, Some(format!("{:?}", e))),
+    }
+}
+
+/// Write the reason why the module died to its stderr so that it shows in the container log,
+/// and to the termination message file if it is mounted, which is shown in the pod status.
+async fn report_termination(spec: &Spec, stderr: &str, reason: &str) {
+    let msg = format!("{}\n", reason);
+    if !stderr.is_empty() {
+        // the stderr may be a fifo, do not block if no one is reading it
+        let res = match tokio::fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(nix::libc::O_NONBLOCK)
+            .open(stderr)
+            .await
+        {
+            Ok(mut f) => f.write_all(msg.as_bytes()).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            debug!("failed to write termination reason to {}: {}", stderr, e);
+        }
+    }
+    let termination_log = spec.mounts().as_ref().and_then(|mounts| {
+        mounts
+            .iter()
+            .find(|m| m.destination() == Path::new(TERMINATION_MESSAGE_PATH))
+            .and_then(|m| m.source().clone())
+    });
+    if let Some(path) = termination_log {
+        if let Err(e) = tokio::fs::write(&path, msg.as_bytes()).await {
+            warn!(
+                "failed to write termination message to {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
 pub(crate) async fn exec_exits<F>(task: &TaskService<F, WasmtimeContainer>) {
     let containers = task.containers.clone();
     let exit_signal = task.exit.clone();
@@ -400,3 +669,32 @@ pub(crate) async fn exec_exits<F>(task: &TaskService<F, WasmtimeContainer>) {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use wasmtime::Trap;
+    use wasmtime_wasi::I32Exit;
+
+    use crate::wasmtime::{exit_status, ERROR_EXIT_CODE, TRAP_EXIT_CODE};
+
+    #[test]
+    fn test_exit_status() {
+        assert_eq!(exit_status(&Ok(()), 0), (0, None));
+        assert_eq!(exit_status(&Err(I32Exit(0).into()), 0), (0, None));
+        assert_eq!(exit_status(&Err(I32Exit(3).into()), 15), (3, None));
+
+        assert_eq!(exit_status(&Err(Trap::Interrupt.into()), 9), (137, None));
+        assert_eq!(exit_status(&Err(Trap::Interrupt.into()), 15), (143, None));
+        // interrupted by delete without kill
+        assert_eq!(exit_status(&Err(Trap::Interrupt.into()), 0), (137, None));
+
+        let (code, reason) = exit_status(&Err(Trap::UnreachableCodeReached.into()), 0);
+        assert_eq!(code, TRAP_EXIT_CODE);
+        assert!(reason.unwrap().contains("unreachable"));
+
+        let (code, reason) = exit_status(&Err(anyhow!("host function failed")), 0);
+        assert_eq!(code, ERROR_EXIT_CODE);
+        assert!(reason.unwrap().contains("host function failed"));
+    }
+}